@@ -0,0 +1,78 @@
+use crate::app::{AssetBundle, RENDER_READY_VERSION};
+use std::fmt;
+
+/// Why we couldn't use a mesh or armature, or the GPU resources that we render them with.
+///
+/// Models can come from anywhere, so instead of panicking on a bad one we report what's wrong
+/// with it and draw a placeholder in its place.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AssetError {
+    /// No mesh with this name has been loaded
+    MissingMesh(String),
+    /// No armature with this name has been loaded
+    MissingArmature(String),
+    /// A mesh doesn't have vertex data that its renderer needs, ex: "uvs" or "joint weights"
+    MissingVertexData { mesh: String, data: &'static str },
+    /// A bundle that couldn't be deserialized
+    CorruptBundle { bundle: AssetBundle, reason: String },
+    /// A render-ready bundle from a newer or older `asset-pipeline` than this build can read
+    UnsupportedBundleVersion { bundle: AssetBundle, version: u32 },
+    /// An armature with more bones than this device can skin, see `ShaderSystem::bone_storage`
+    TooManyBones {
+        armature: String,
+        bone_count: usize,
+        max_bones: usize,
+    },
+    /// One of our shaders has no uniform with this name
+    MissingUniform(String),
+    /// The device rejected the bone texture for instances of this armature
+    BoneTextureUpload(String),
+    /// The device couldn't allocate a buffer, texture or framebuffer that we render with, ex:
+    /// because it ran out of memory or lost its context
+    GpuResource(&'static str),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetError::MissingMesh(mesh) => write!(f, "Missing mesh {}", mesh),
+            AssetError::MissingArmature(armature) => write!(f, "Missing armature {}", armature),
+            AssetError::MissingVertexData { mesh, data } => {
+                write!(f, "Mesh {} has no {}", mesh, data)
+            }
+            AssetError::CorruptBundle { bundle, reason } => {
+                write!(f, "Corrupt {} bundle: {}", bundle_kind(*bundle), reason)
+            }
+            AssetError::UnsupportedBundleVersion { bundle, version } => write!(
+                f,
+                "The {} bundle is render-ready version {} but we can only read version {}",
+                bundle_kind(*bundle),
+                version,
+                RENDER_READY_VERSION
+            ),
+            AssetError::TooManyBones {
+                armature,
+                bone_count,
+                max_bones,
+            } => write!(
+                f,
+                "Armature {} has {} bones but this device can only skin {}",
+                armature, bone_count, max_bones
+            ),
+            AssetError::MissingUniform(uniform) => write!(f, "Uniform '{}' not found", uniform),
+            AssetError::BoneTextureUpload(armature) => {
+                write!(f, "Could not upload the bones of armature {}", armature)
+            }
+            AssetError::GpuResource(resource) => {
+                write!(f, "This device could not allocate {}", resource)
+            }
+        }
+    }
+}
+
+fn bundle_kind(bundle: AssetBundle) -> &'static str {
+    match bundle {
+        AssetBundle::Meshes => "mesh",
+        AssetBundle::Armatures => "armature",
+    }
+}
@@ -0,0 +1,71 @@
+use blender_mesh::BlenderMesh;
+use nalgebra::{Matrix4, Point3};
+
+/// The smallest box that fits around a mesh, in the y up space that the mesh gets rendered in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshBounds {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl MeshBounds {
+    /// The bounds of a mesh that has been preprocessed.
+    ///
+    /// Skinned meshes stay z up until our skinned vertex shader turns them y up, so we turn them
+    /// here too. Their bounds are of their rest pose.
+    pub fn new(mesh: &BlenderMesh) -> MeshBounds {
+        let skinned = mesh.armature_name.is_some();
+
+        let points = mesh.vertex_positions.chunks(3).map(|position| {
+            if skinned {
+                Point3::new(position[0], position[2], -position[1])
+            } else {
+                Point3::new(position[0], position[1], position[2])
+            }
+        });
+
+        MeshBounds::around(points)
+    }
+
+    /// The box in world space around these bounds once they've been placed by `world_transform`
+    pub fn transform(&self, world_transform: &Matrix4<f32>) -> MeshBounds {
+        let (min, max) = (self.min, self.max);
+
+        let corners = (0..8).map(|corner| {
+            let x = if corner & 1 == 0 { min.x } else { max.x };
+            let y = if corner & 2 == 0 { min.y } else { max.y };
+            let z = if corner & 4 == 0 { min.z } else { max.z };
+
+            world_transform.transform_point(&Point3::new(x, y, z))
+        });
+
+        MeshBounds::around(corners)
+    }
+
+    /// A mesh without any vertices gets an empty box at its origin
+    fn around(points: impl Iterator<Item = Point3<f32>>) -> MeshBounds {
+        let mut bounds: Option<MeshBounds> = None;
+
+        for point in points {
+            bounds = Some(match bounds {
+                Some(MeshBounds { min, max }) => MeshBounds {
+                    min: Point3::new(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z)),
+                    max: Point3::new(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z)),
+                },
+                None => MeshBounds {
+                    min: point,
+                    max: point,
+                },
+            });
+        }
+
+        bounds.unwrap_or(MeshBounds {
+            min: Point3::origin(),
+            max: Point3::origin(),
+        })
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+}
@@ -1,67 +1,413 @@
 use bincode;
 use blender_armature::BlenderArmature;
 use blender_mesh::BlenderMesh;
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 
+mod asset_error;
+mod mesh_bounds;
+mod preprocess;
+pub use self::asset_error::*;
+pub use self::mesh_bounds::*;
+pub use self::preprocess::*;
+
+/// The meshes and armatures that have been downloaded so far.
+///
+/// Assets arrive in bincode bundles that get downloaded while the application is running (see
+/// `load_asset_bundle`), so anything that reads from `Assets` needs to handle a mesh or armature
+/// that isn't here yet.
 #[derive(Default)]
 pub struct Assets {
     meshes: HashMap<String, BlenderMesh>,
+    /// The bounds of every mesh in `meshes`
+    mesh_bounds: HashMap<String, MeshBounds>,
+    /// How many times each mesh in `meshes` has been inserted, see `Assets::mesh_version`
+    mesh_versions: HashMap<String, u32>,
     armatures: HashMap<String, BlenderArmature>,
+    /// Every bundle that we've started downloading, in the order that we started them
+    bundles: Vec<BundleProgress>,
 }
 
-impl Assets {
-    pub fn new() -> Assets {
-        let meshes = Assets::download_meshes();
-        let armatures = Assets::download_armatures();
+/// What a bincode bundle holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AssetBundle {
+    /// A `HashMap<String, BlenderMesh>`, like `meshes.bytes`
+    Meshes,
+    /// A `HashMap<String, BlenderArmature>`, like `armatures.bytes`
+    Armatures,
+}
 
-        Assets { meshes, armatures }
-    }
+/// How far along a bundle's download is
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundleProgress {
+    pub url: String,
+    pub loaded_bytes: u64,
+    /// None until the server tells us how large the bundle is
+    pub total_bytes: Option<u64>,
+    pub status: BundleStatus,
+}
 
-    // In a real application you would download via XHR or fetch request, but here we just
-    // included_bytes! for simplicity
-    fn download_meshes() -> HashMap<String, BlenderMesh> {
-        let meshes = include_bytes!("../../../meshes.bytes");
-        let mut meshes: HashMap<String, BlenderMesh> = bincode::deserialize(meshes).unwrap();
+#[derive(Debug, Clone, PartialEq)]
+pub enum BundleStatus {
+    Downloading,
+    /// Downloaded and added to our `Assets`
+    Loaded,
+    /// The download failed or the bundle couldn't be deserialized
+    Failed(String),
+}
 
-        for (mesh_name, mesh) in meshes.iter_mut() {
-            web_sys::console::log_1(&mesh_name.to_string().into());
+impl Assets {
+    /// Deserialize a bundle and add everything in it, replacing any meshes or armatures that
+    /// have the same names.
+    ///
+    /// Render-ready bundles from the `asset-pipeline` binary only get the transforms that their
+    /// header says haven't been applied yet, which is usually none of them.
+    pub fn insert_bundle(&mut self, bundle: AssetBundle, bytes: &[u8]) -> Result<(), AssetError> {
+        match bundle {
+            AssetBundle::Meshes => self.insert_meshes(bytes),
+            AssetBundle::Armatures => self.insert_armatures(bytes),
+        }
+    }
 
-            mesh.combine_vertex_indices();
-            mesh.triangulate();
+    fn insert_meshes(&mut self, bytes: &[u8]) -> Result<(), AssetError> {
+        let (header, mut meshes): (_, HashMap<String, BlenderMesh>) =
+            deserialize_bundle(AssetBundle::Meshes, bytes)?;
 
-            if let Some(_armature_name) = mesh.armature_name.as_ref() {
-                mesh.set_groups_per_vertex(4);
-            } else {
-                mesh.y_up();
-            }
+        for (name, mesh) in meshes.iter_mut() {
+            preprocess_mesh(mesh, &header.mesh_transforms);
+            self.mesh_bounds.insert(name.clone(), MeshBounds::new(mesh));
+            *self.mesh_versions.entry(name.clone()).or_insert(0) += 1;
         }
 
-        meshes
-    }
+        self.meshes.extend(meshes);
 
-    // In a real application you would download via XHR or fetch request, but here we just
-    // included_bytes! for simplicity
-    fn download_armatures() -> HashMap<String, BlenderArmature> {
-        let armatures = include_bytes!("../../../armatures.bytes");
-        let mut armatures: HashMap<String, BlenderArmature> =
-            bincode::deserialize(armatures).unwrap();
+        Ok(())
+    }
 
-        for (armature_name, armature) in armatures.iter_mut() {
-            web_sys::console::log_1(&armature_name.to_string().into());
+    fn insert_armatures(&mut self, bytes: &[u8]) -> Result<(), AssetError> {
+        let (header, mut armatures): (_, HashMap<String, BlenderArmature>) =
+            deserialize_bundle(AssetBundle::Armatures, bytes)?;
 
-            armature.apply_inverse_bind_poses();
-            armature.transpose_actions();
-            armature.actions_to_dual_quats();
+        for armature in armatures.values_mut() {
+            preprocess_armature(armature, &header.armature_transforms);
         }
 
-        armatures
+        self.armatures.extend(armatures);
+
+        Ok(())
     }
 
     pub fn get_mesh(&self, mesh_name: &str) -> Option<&BlenderMesh> {
         self.meshes.get(mesh_name)
     }
 
+    /// The box around a mesh in model space, see `MeshBounds::new`
+    pub fn mesh_bounds(&self, mesh_name: &str) -> Option<&MeshBounds> {
+        self.mesh_bounds.get(mesh_name)
+    }
+
+    /// Changes every time a bundle replaces the mesh, so that anything that was built from the
+    /// old mesh (ex: the renderer's vertex buffers) knows to rebuild. 0 if we don't have it.
+    pub fn mesh_version(&self, mesh_name: &str) -> u32 {
+        self.mesh_versions.get(mesh_name).cloned().unwrap_or(0)
+    }
+
     pub fn get_armature(&self, armature_name: &str) -> Option<&BlenderArmature> {
         self.armatures.get(armature_name)
     }
+
+    pub fn armature(&self, armature_name: &str) -> Result<&BlenderArmature, AssetError> {
+        self.get_armature(armature_name)
+            .ok_or_else(|| AssetError::MissingArmature(armature_name.to_string()))
+    }
+
+    /// Get a mesh, making sure that it has all of the vertex data that the `NonSkinnedMesh` or
+    /// `SkinnedMesh` renderer needs.
+    pub fn renderable_mesh(
+        &self,
+        mesh_name: &str,
+        skinned: bool,
+    ) -> Result<&BlenderMesh, AssetError> {
+        let mesh = self
+            .get_mesh(mesh_name)
+            .ok_or_else(|| AssetError::MissingMesh(mesh_name.to_string()))?;
+
+        let missing = |data| AssetError::MissingVertexData {
+            mesh: mesh_name.to_string(),
+            data,
+        };
+
+        if mesh.vertex_uvs.is_none() {
+            return Err(missing("uvs"));
+        }
+
+        if skinned {
+            if mesh.vertex_group_indices.is_none() {
+                return Err(missing("joint indices"));
+            }
+            if mesh.vertex_group_weights.is_none() {
+                return Err(missing("joint weights"));
+            }
+        }
+
+        Ok(mesh)
+    }
+
+    /// Start tracking the download of the bundle at `url`
+    pub fn start_download(&mut self, url: &str) {
+        self.bundles.push(BundleProgress {
+            url: url.to_string(),
+            loaded_bytes: 0,
+            total_bytes: None,
+            status: BundleStatus::Downloading,
+        });
+    }
+
+    /// Record how much of the bundle at `url` has arrived
+    pub fn download_progress(&mut self, url: &str, loaded_bytes: u64, total_bytes: Option<u64>) {
+        if let Some(bundle) = self.downloading_bundle(url) {
+            bundle.loaded_bytes = loaded_bytes;
+            bundle.total_bytes = total_bytes;
+        }
+    }
+
+    /// Record that the bundle at `url` was either added to our assets or failed
+    pub fn finish_download(&mut self, url: &str, result: Result<(), String>) {
+        if let Some(bundle) = self.downloading_bundle(url) {
+            bundle.status = match result {
+                Ok(()) => BundleStatus::Loaded,
+                Err(err) => BundleStatus::Failed(err),
+            };
+        }
+    }
+
+    /// The most recent download of `url` that hasn't finished yet
+    fn downloading_bundle(&mut self, url: &str) -> Option<&mut BundleProgress> {
+        self.bundles
+            .iter_mut()
+            .rev()
+            .find(|bundle| bundle.url == url && bundle.status == BundleStatus::Downloading)
+    }
+
+    pub fn bundles(&self) -> &[BundleProgress] {
+        &self.bundles
+    }
+
+    /// Whether or not any bundles are still downloading
+    pub fn is_downloading(&self) -> bool {
+        self.bundles
+            .iter()
+            .any(|bundle| bundle.status == BundleStatus::Downloading)
+    }
+}
+
+/// Deserialize a bundle along with the transforms that have already been applied to it.
+///
+/// Plain bincode bundles haven't had any transforms applied.
+fn deserialize_bundle<T: DeserializeOwned>(
+    bundle: AssetBundle,
+    bytes: &[u8],
+) -> Result<(RenderReadyHeader, T), AssetError> {
+    let corrupt = |err: bincode::Error| AssetError::CorruptBundle {
+        bundle,
+        reason: err.to_string(),
+    };
+
+    if !bytes.starts_with(RENDER_READY_MAGIC) {
+        let header = RenderReadyHeader {
+            version: RENDER_READY_VERSION,
+            mesh_transforms: vec![],
+            armature_transforms: vec![],
+        };
+        return Ok((header, bincode::deserialize(bytes).map_err(corrupt)?));
+    }
+
+    let mut reader = &bytes[RENDER_READY_MAGIC.len()..];
+
+    // The version is the header's first field, so we can check it before trusting the rest of
+    // the header to be laid out the way that we expect
+    let version: u32 = bincode::deserialize(reader).map_err(corrupt)?;
+    if version != RENDER_READY_VERSION {
+        return Err(AssetError::UnsupportedBundleVersion { bundle, version });
+    }
+
+    let header: RenderReadyHeader = bincode::deserialize_from(&mut reader).map_err(corrupt)?;
+    let assets = bincode::deserialize(reader).map_err(corrupt)?;
+
+    Ok((header, assets))
+}
+
+/// Build a render-ready bundle the same way that the `asset-pipeline` binary does, with a header
+/// that says every transform has already been applied
+#[cfg(test)]
+pub fn render_ready_bundle<T: serde::Serialize>(version: u32, assets: &T) -> Vec<u8> {
+    let header = RenderReadyHeader {
+        version,
+        mesh_transforms: MESH_TRANSFORMS.to_vec(),
+        armature_transforms: ARMATURE_TRANSFORMS.to_vec(),
+    };
+
+    let mut bundle = RENDER_READY_MAGIC.to_vec();
+    bincode::serialize_into(&mut bundle, &header).unwrap();
+    bincode::serialize_into(&mut bundle, assets).unwrap();
+    bundle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A triangle that leans towards +y, so that turning it y up moves its vertices
+    fn triangle() -> BlenderMesh {
+        BlenderMesh {
+            vertex_positions: vec![0., 0., 0., 1., 0., 0., 0., 1., 1.],
+            vertex_position_indices: vec![0, 1, 2],
+            num_vertices_in_each_face: vec![3],
+            vertex_normals: vec![0., -0.7, 0.7, 0., -0.7, 0.7, 0., -0.7, 0.7],
+            vertex_uvs: Some(vec![0., 0., 1., 0., 0., 1.]),
+            ..BlenderMesh::default()
+        }
+    }
+
+    fn meshes(mesh: BlenderMesh) -> HashMap<String, BlenderMesh> {
+        let mut meshes = HashMap::new();
+        meshes.insert("Cube".to_string(), mesh);
+        meshes
+    }
+
+    /// A render-ready bundle whose header says that only `mesh_transforms` were applied
+    fn bundle_with_transforms(
+        mesh_transforms: &[MeshTransform],
+        assets: &HashMap<String, BlenderMesh>,
+    ) -> Vec<u8> {
+        let header = RenderReadyHeader {
+            version: RENDER_READY_VERSION,
+            mesh_transforms: mesh_transforms.to_vec(),
+            armature_transforms: vec![],
+        };
+
+        let mut bundle = RENDER_READY_MAGIC.to_vec();
+        bincode::serialize_into(&mut bundle, &header).unwrap();
+        bincode::serialize_into(&mut bundle, assets).unwrap();
+        bundle
+    }
+
+    #[test]
+    fn render_ready_meshes_are_not_preprocessed_again() {
+        let bundle = render_ready_bundle(RENDER_READY_VERSION, &meshes(triangle()));
+
+        let mut assets = Assets::default();
+        assets.insert_bundle(AssetBundle::Meshes, &bundle).unwrap();
+
+        assert_eq!(assets.get_mesh("Cube"), Some(&triangle()));
+    }
+
+    #[test]
+    fn applies_the_transforms_that_the_header_does_not_list() {
+        let already_applied = [
+            MeshTransform::CombineVertexIndices,
+            MeshTransform::Triangulate,
+            MeshTransform::SetGroupsPerVertex(4),
+        ];
+        let bundle = bundle_with_transforms(&already_applied, &meshes(triangle()));
+
+        let mut assets = Assets::default();
+        assets.insert_bundle(AssetBundle::Meshes, &bundle).unwrap();
+
+        let mut y_up = triangle();
+        y_up.y_up();
+        assert_eq!(assets.get_mesh("Cube"), Some(&y_up));
+    }
+
+    #[test]
+    fn rejects_other_render_ready_versions() {
+        let bundle = render_ready_bundle(RENDER_READY_VERSION + 1, &meshes(triangle()));
+
+        let mut assets = Assets::default();
+
+        assert_eq!(
+            assets.insert_bundle(AssetBundle::Meshes, &bundle),
+            Err(AssetError::UnsupportedBundleVersion {
+                bundle: AssetBundle::Meshes,
+                version: RENDER_READY_VERSION + 1,
+            })
+        );
+        assert_eq!(assets.get_mesh("Cube"), None);
+    }
+
+    #[test]
+    fn renderable_meshes_need_the_vertex_data_that_their_renderer_buffers() {
+        let mut assets = Assets::default();
+
+        let missing = |data| {
+            Err(AssetError::MissingVertexData {
+                mesh: "Cube".to_string(),
+                data,
+            })
+        };
+
+        assert_eq!(
+            assets.renderable_mesh("Cube", false),
+            Err(AssetError::MissingMesh("Cube".to_string()))
+        );
+
+        let mut mesh = BlenderMesh {
+            vertex_uvs: None,
+            ..triangle()
+        };
+        assets.meshes.insert("Cube".to_string(), mesh.clone());
+        assert_eq!(assets.renderable_mesh("Cube", false), missing("uvs"));
+
+        mesh.vertex_uvs = triangle().vertex_uvs;
+        assets.meshes.insert("Cube".to_string(), mesh.clone());
+        assert_eq!(assets.renderable_mesh("Cube", false), Ok(&mesh));
+        assert_eq!(
+            assets.renderable_mesh("Cube", true),
+            missing("joint indices")
+        );
+
+        mesh.vertex_group_indices = Some(vec![0; 12]);
+        assets.meshes.insert("Cube".to_string(), mesh.clone());
+        assert_eq!(
+            assets.renderable_mesh("Cube", true),
+            missing("joint weights")
+        );
+
+        mesh.vertex_group_weights = Some(vec![0.25; 12]);
+        assets.meshes.insert("Cube".to_string(), mesh.clone());
+        assert_eq!(assets.renderable_mesh("Cube", true), Ok(&mesh));
+    }
+
+    /// `meshes.bytes` and `armatures.bytes` come out of the `asset-pipeline` binary, so the
+    /// browser shouldn't need to preprocess anything in them
+    #[test]
+    fn checked_in_bundles_are_render_ready() {
+        let bundles = [
+            (
+                AssetBundle::Meshes,
+                &include_bytes!("../../../meshes.bytes")[..],
+            ),
+            (
+                AssetBundle::Armatures,
+                &include_bytes!("../../../armatures.bytes")[..],
+            ),
+        ];
+
+        let mut assets = Assets::default();
+
+        for (bundle, bytes) in bundles.iter() {
+            assert!(bytes.starts_with(RENDER_READY_MAGIC));
+
+            let header: RenderReadyHeader =
+                bincode::deserialize(&bytes[RENDER_READY_MAGIC.len()..]).unwrap();
+            assert_eq!(header.mesh_transforms, MESH_TRANSFORMS.to_vec());
+            assert_eq!(header.armature_transforms, ARMATURE_TRANSFORMS.to_vec());
+
+            assets.insert_bundle(*bundle, bytes).unwrap();
+        }
+
+        assert!(assets.get_mesh("Terrain").is_some());
+        assert!(assets.get_mesh("Bird").is_some());
+        assert!(assets.get_armature("Armature.001").is_some());
+    }
 }
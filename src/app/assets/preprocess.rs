@@ -0,0 +1,101 @@
+//! Shared with the `asset-pipeline` binary through a `#[path]` module, so this file can only
+//! depend on crates and not on the rest of the application.
+
+use blender_armature::BlenderArmature;
+use blender_mesh::BlenderMesh;
+use serde::{Deserialize, Serialize};
+
+/// The first bytes of a render-ready bundle, which the `asset-pipeline` binary writes.
+///
+/// A render-ready bundle is these bytes, then a bincode `RenderReadyHeader`, then the bincode
+/// meshes or armatures. Bundles that don't start with these bytes are plain bincode and get
+/// fully preprocessed when they finish downloading.
+pub const RENDER_READY_MAGIC: &[u8] = b"WWT-RENDER-READY";
+
+/// Bumped whenever `RenderReadyHeader` or the data after it changes shape, so that we reject
+/// bundles that we don't know how to read instead of misreading them.
+pub const RENDER_READY_VERSION: u32 = 1;
+
+/// Which transforms were already applied to everything in a render-ready bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RenderReadyHeader {
+    pub version: u32,
+    pub mesh_transforms: Vec<MeshTransform>,
+    pub armature_transforms: Vec<ArmatureTransform>,
+}
+
+/// A step in getting a mesh ready for the `NonSkinnedMesh` or `SkinnedMesh` renderer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MeshTransform {
+    /// Give every vertex one index instead of separate position, normal and uv indices
+    CombineVertexIndices,
+    /// Split every face into triangles
+    Triangulate,
+    /// Give every vertex of a skinned mesh exactly this many bones
+    SetGroupsPerVertex(u8),
+    /// Turn a non-skinned mesh from Blender's z up into our y up
+    YUp,
+}
+
+/// A step in turning an armature's bones into the dual quaternions that our skinned shader
+/// blends
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ArmatureTransform {
+    ApplyInverseBindPoses,
+    TransposeActions,
+    ActionsToDualQuats,
+}
+
+/// Every transform that a mesh needs, in the order that they need to happen
+pub const MESH_TRANSFORMS: [MeshTransform; 4] = [
+    MeshTransform::CombineVertexIndices,
+    MeshTransform::Triangulate,
+    MeshTransform::SetGroupsPerVertex(4),
+    MeshTransform::YUp,
+];
+
+/// Every transform that an armature needs, in the order that they need to happen
+pub const ARMATURE_TRANSFORMS: [ArmatureTransform; 3] = [
+    ArmatureTransform::ApplyInverseBindPoses,
+    ArmatureTransform::TransposeActions,
+    ArmatureTransform::ActionsToDualQuats,
+];
+
+/// Apply every transform in `MESH_TRANSFORMS` that isn't in `already_applied`
+pub fn preprocess_mesh(mesh: &mut BlenderMesh, already_applied: &[MeshTransform]) {
+    for transform in MESH_TRANSFORMS.iter() {
+        if already_applied.contains(transform) {
+            continue;
+        }
+
+        match transform {
+            MeshTransform::CombineVertexIndices => mesh.combine_vertex_indices(),
+            MeshTransform::Triangulate => mesh.triangulate(),
+            MeshTransform::SetGroupsPerVertex(count) => {
+                if mesh.armature_name.is_some() {
+                    mesh.set_groups_per_vertex(*count);
+                }
+            }
+            MeshTransform::YUp => {
+                if mesh.armature_name.is_none() {
+                    mesh.y_up();
+                }
+            }
+        }
+    }
+}
+
+/// Apply every transform in `ARMATURE_TRANSFORMS` that isn't in `already_applied`
+pub fn preprocess_armature(armature: &mut BlenderArmature, already_applied: &[ArmatureTransform]) {
+    for transform in ARMATURE_TRANSFORMS.iter() {
+        if already_applied.contains(transform) {
+            continue;
+        }
+
+        match transform {
+            ArmatureTransform::ApplyInverseBindPoses => armature.apply_inverse_bind_poses(),
+            ArmatureTransform::TransposeActions => armature.transpose_actions(),
+            ArmatureTransform::ActionsToDualQuats => armature.actions_to_dual_quats(),
+        }
+    }
+}
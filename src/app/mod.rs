@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell, RefMut};
 use std::rc::Rc;
 
 mod store;
@@ -7,24 +7,44 @@ pub use self::store::*;
 mod assets;
 pub use self::assets::*;
 
+mod pick;
+pub use self::pick::*;
+
+/// How hard a mesh that is moving through the water's surface pushes it down every frame
+static MESH_RIPPLE_STRENGTH: f32 = 0.05;
+
 /// Used to instantiate our application
 pub struct App {
-    assets: Assets,
+    assets: RefCell<Assets>,
     pub store: Rc<RefCell<Store>>,
 }
 
 impl App {
     /// Create a new instance of our WebGL Water application
     pub fn new() -> App {
-        let assets = Assets::new();
-
         App {
-            assets,
+            assets: RefCell::new(Assets::default()),
             store: Rc::new(RefCell::new(Store::new())),
         }
     }
 
-    pub fn assets(&self) -> &Assets {
-        &self.assets
+    pub fn assets(&self) -> Ref<Assets> {
+        self.assets.borrow()
+    }
+
+    pub fn assets_mut(&self) -> RefMut<Assets> {
+        self.assets.borrow_mut()
+    }
+
+    /// Move our simulation `dt` milliseconds forwards
+    pub fn update(&self, dt: f32) {
+        let mut store = self.store.borrow_mut();
+        store.msg(&Msg::AdvanceClock(dt));
+
+        // Meshes that are moving through the water leave ripples behind them
+        let ripples = store.state.meshes_moving_through_water(&self.assets());
+        for (x, z) in ripples {
+            store.msg(&Msg::DisturbWater(x, z, MESH_RIPPLE_STRENGTH));
+        }
     }
 }
@@ -0,0 +1,201 @@
+use crate::app::{App, Ray, RayHit};
+use blender_mesh::BlenderMesh;
+use nalgebra::{Matrix4, Point3, Vector3};
+use std::cmp::Ordering;
+
+/// The thing under a pixel of the canvas
+#[derive(Debug, Clone)]
+pub struct Pick {
+    pub target: PickTarget,
+    /// Where in the world the pixel's ray hit the target
+    pub point: Point3<f32>,
+    /// The surface normal where the ray hit
+    pub normal: Vector3<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PickTarget {
+    /// The index of the water body in `State::water_bodies`
+    Water(usize),
+    /// The name of the entity in the scene, which defaults to its mesh's name
+    Mesh(String),
+}
+
+impl App {
+    /// Find the nearest water or mesh under the pixel (x, y) of the canvas, in device pixels.
+    pub fn pick(&self, x: f32, y: f32) -> Option<Pick> {
+        let store = self.store.borrow();
+        let state = &store.state;
+
+        let (width, height) = state.canvas_size();
+        let ray = state
+            .camera()
+            .screen_ray(x, y, (width as f32, height as f32));
+
+        let mut nearest: Option<(RayHit, PickTarget)> = None;
+        // A degenerate camera or a mesh with NaN positions can produce hits that aren't anywhere
+        let mut consider = |hit: Option<RayHit>, target: PickTarget| {
+            if let Some(hit) = hit.filter(|hit| hit.distance.is_finite()) {
+                let is_nearer = nearest
+                    .as_ref()
+                    .map_or(true, |(nearest_hit, _)| hit.distance < nearest_hit.distance);
+
+                if is_nearer {
+                    nearest = Some((hit, target));
+                }
+            }
+        };
+
+        for (index, water_body) in state.water_bodies().iter().enumerate() {
+            let hit = ray.intersect_water_body(water_body).map(|mut hit| {
+                hit.normal = state
+                    .water()
+                    .normal_at(hit.point.x, hit.point.z, state.clock());
+                hit
+            });
+
+            consider(hit, PickTarget::Water(index));
+        }
+
+        if state.show_scenery() {
+            let assets = self.assets();
+
+            for node in state.scene_graph().nodes() {
+                let entity = node.entity();
+
+                if let Some(mesh) = assets.get_mesh(&entity.mesh) {
+                    let hit = intersect_mesh(&ray, mesh, node.world_transform());
+                    consider(hit, PickTarget::Mesh(entity.name().to_string()));
+                }
+            }
+        }
+
+        nearest.map(|(hit, target)| Pick {
+            target,
+            point: hit.point,
+            normal: hit.normal,
+        })
+    }
+}
+
+/// Test the ray against every triangle of a mesh that has been placed in the world by
+/// `world_transform`.
+///
+/// Skinned meshes are tested in their bind pose, so an animated limb might be a little off.
+/// Triangles with an index past the mesh's last vertex are skipped.
+fn intersect_mesh(ray: &Ray, mesh: &BlenderMesh, world_transform: &Matrix4<f32>) -> Option<RayHit> {
+    let positions = &mesh.vertex_positions;
+
+    let vertex = |index: u16| {
+        let index = index as usize * 3;
+        let position = positions.get(index..index + 3)?;
+        let (x, y, z) = (position[0], position[1], position[2]);
+
+        // Skinned meshes are still in Blender's z up space, see mesh-skinned-vertex.glsl
+        let vertex = match mesh.armature_name {
+            Some(_) => Point3::new(x, z, -y),
+            None => Point3::new(x, y, z),
+        };
+
+        Some(world_transform.transform_point(&vertex))
+    };
+
+    mesh.vertex_position_indices
+        .chunks_exact(3)
+        .filter_map(|triangle| {
+            ray.intersect_triangle(
+                vertex(triangle[0])?,
+                vertex(triangle[1])?,
+                vertex(triangle[2])?,
+            )
+        })
+        .filter(|hit| hit.distance.is_finite())
+        .min_by(|a, b| {
+            a.distance
+                .partial_cmp(&b.distance)
+                .unwrap_or(Ordering::Equal)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{render_ready_bundle, AssetBundle, Msg, RENDER_READY_VERSION};
+    use std::collections::HashMap;
+
+    /// A tabletop sitting in the middle of the water, right where the camera is looking
+    const SCENE: &str = r#"{
+        "entities": [{"mesh": "Table", "transform": {"translation": [0, 1, 0]}}],
+        "waterBodies": [{"position": [0, 0], "extents": [18, 18], "height": 0}]
+    }"#;
+
+    /// A flat square, one unit wide, half a unit above its origin
+    fn tabletop() -> BlenderMesh {
+        BlenderMesh {
+            vertex_positions: vec![
+                -0.5, 0.5, -0.5, 0.5, 0.5, -0.5, 0.5, 0.5, 0.5, -0.5, 0.5, 0.5,
+            ],
+            vertex_position_indices: vec![0, 1, 2, 0, 2, 3],
+            ..BlenderMesh::default()
+        }
+    }
+
+    fn app_with_table(table: BlenderMesh) -> App {
+        let app = App::new();
+        {
+            let mut store = app.store.borrow_mut();
+            store.msg(&Msg::LoadScene(serde_json::from_str(SCENE).unwrap()));
+            store.msg(&Msg::Resize(800, 600));
+        }
+
+        let mut meshes = HashMap::new();
+        meshes.insert("Table".to_string(), table);
+        let bundle = render_ready_bundle(RENDER_READY_VERSION, &meshes);
+        app.assets_mut()
+            .insert_bundle(AssetBundle::Meshes, &bundle)
+            .unwrap();
+
+        app
+    }
+
+    #[test]
+    fn picks_the_mesh_in_front_of_the_water() {
+        let app = app_with_table(tabletop());
+
+        let pick = app.pick(400., 300.).unwrap();
+
+        assert_eq!(pick.target, PickTarget::Mesh("Table".to_string()));
+        assert!((pick.point.y - 1.5).abs() < 1e-3);
+        assert!((pick.normal - Vector3::y()).norm() < 1e-3);
+    }
+
+    #[test]
+    fn picks_the_water_when_no_mesh_is_in_the_way() {
+        let app = app_with_table(tabletop());
+
+        // The camera looks down from above, so the bottom of the screen is just below it
+        let pick = app.pick(400., 590.).unwrap();
+
+        assert_eq!(pick.target, PickTarget::Water(0));
+        assert!(pick.point.y.abs() < 1e-3);
+    }
+
+    #[test]
+    fn picks_nothing_when_the_ray_misses_everything() {
+        let app = app_with_table(tabletop());
+
+        // The top left corner of the screen looks out past the water
+        assert!(app.pick(0., 0.).is_none());
+    }
+
+    #[test]
+    fn skips_triangles_with_out_of_range_indices() {
+        let mut table = tabletop();
+        table.vertex_position_indices = vec![0, 1, 4, 0, 4, 3];
+        let app = app_with_table(table);
+
+        let pick = app.pick(400., 300.).unwrap();
+
+        assert_eq!(pick.target, PickTarget::Water(0));
+    }
+}
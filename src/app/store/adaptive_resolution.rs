@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+
+/// The render scales that we step between, from full resolution down. Stepping instead of
+/// scaling smoothly means that our framebuffers only get re-created when we cross a step.
+const SCALE_STEPS: [f32; 5] = [1.0, 0.75, 0.5, 0.35, 0.25];
+
+/// How many frames we average over before making a decision
+const FRAME_WINDOW: usize = 30;
+
+/// Frames that take longer than this are ignored, since they're usually the tab coming back from
+/// the background or a one off hitch rather than the renderer being too slow.
+const MAX_FRAME_MS: f32 = 250.;
+
+/// Scale down once our average frame takes this many times longer than our target
+const SCALE_DOWN_THRESHOLD: f32 = 1.2;
+
+/// Scale up once our average frame is within this many times our target. Frames can't finish
+/// faster than the display refreshes, so this has to be a little over 1.0.
+const SCALE_UP_THRESHOLD: f32 = 1.05;
+
+/// How long we need to hold our target before trying a larger scale
+const MIN_SCALE_UP_WAIT_MS: f32 = 2000.;
+const MAX_SCALE_UP_WAIT_MS: f32 = 60000.;
+
+/// Watches how long our frames take and picks a render scale that holds a target frame rate.
+///
+/// If scaling up makes us miss our target right away we wait twice as long before trying again,
+/// so that we don't keep bouncing between two scales. Once a scale up holds our target for as
+/// long as we waited before trying it, whatever was slowing us down has passed and we go back to
+/// the shortest wait.
+pub struct AdaptiveResolution {
+    target_frame_ms: f32,
+    frame_times: VecDeque<f32>,
+    step: usize,
+    /// Time since we last changed our scale
+    since_change_ms: f32,
+    scale_up_wait_ms: f32,
+    /// Whether our last change was a scale up
+    scaled_up_last: bool,
+}
+
+impl AdaptiveResolution {
+    pub fn new(target_fps: f32) -> AdaptiveResolution {
+        AdaptiveResolution {
+            target_frame_ms: 1000. / target_fps.max(1.),
+            frame_times: VecDeque::with_capacity(FRAME_WINDOW),
+            step: 0,
+            since_change_ms: 0.,
+            scale_up_wait_ms: MIN_SCALE_UP_WAIT_MS,
+            scaled_up_last: false,
+        }
+    }
+
+    /// How much to scale our framebuffers and water grid by, between 0.25 and 1.0
+    pub fn scale(&self) -> f32 {
+        SCALE_STEPS[self.step]
+    }
+
+    /// Record how many milliseconds the last frame took, possibly changing our scale
+    pub fn record_frame(&mut self, dt: f32) {
+        self.since_change_ms += dt;
+
+        if dt > MAX_FRAME_MS {
+            return;
+        }
+
+        if self.frame_times.len() == FRAME_WINDOW {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(dt);
+
+        if self.frame_times.len() < FRAME_WINDOW {
+            return;
+        }
+
+        let average = self.frame_times.iter().sum::<f32>() / FRAME_WINDOW as f32;
+
+        if self.scaled_up_last && self.since_change_ms >= self.scale_up_wait_ms {
+            self.scale_up_wait_ms = MIN_SCALE_UP_WAIT_MS;
+        }
+
+        if average > self.target_frame_ms * SCALE_DOWN_THRESHOLD {
+            self.scale_down();
+        } else if average <= self.target_frame_ms * SCALE_UP_THRESHOLD
+            && self.since_change_ms >= self.scale_up_wait_ms
+        {
+            self.scale_up();
+        }
+    }
+
+    fn scale_down(&mut self) {
+        if self.step == SCALE_STEPS.len() - 1 {
+            return;
+        }
+
+        // The larger scale that we just tried was too slow, so hold off longer next time
+        if self.scaled_up_last && self.since_change_ms < self.scale_up_wait_ms {
+            self.scale_up_wait_ms = (self.scale_up_wait_ms * 2.).min(MAX_SCALE_UP_WAIT_MS);
+        }
+
+        self.step += 1;
+        self.scaled_up_last = false;
+        self.changed();
+    }
+
+    fn scale_up(&mut self) {
+        if self.step == 0 {
+            return;
+        }
+
+        self.step -= 1;
+        self.scaled_up_last = true;
+        self.changed();
+    }
+
+    /// Frames from before a change don't tell us anything about our new scale
+    fn changed(&mut self) {
+        self.frame_times.clear();
+        self.since_change_ms = 0.;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET_FPS: f32 = 50.;
+    const TARGET_FRAME_MS: f32 = 20.;
+
+    /// Render frames that each take `frame_ms` for `duration_ms`
+    fn run(adaptive: &mut AdaptiveResolution, frame_ms: f32, duration_ms: f32) {
+        let mut elapsed = 0.;
+        while elapsed < duration_ms {
+            adaptive.record_frame(frame_ms);
+            elapsed += frame_ms;
+        }
+    }
+
+    /// Render frames that each take `frame_ms` until our scale changes, returning how long that
+    /// took
+    fn run_until_scale_changes(adaptive: &mut AdaptiveResolution, frame_ms: f32) -> f32 {
+        let scale = adaptive.scale();
+        let mut elapsed = 0.;
+
+        while adaptive.scale() == scale {
+            assert!(elapsed < 10. * MAX_SCALE_UP_WAIT_MS, "Scale never changed");
+            adaptive.record_frame(frame_ms);
+            elapsed += frame_ms;
+        }
+
+        elapsed
+    }
+
+    /// Scale down from full resolution
+    fn too_slow_for_full_resolution() -> AdaptiveResolution {
+        let mut adaptive = AdaptiveResolution::new(TARGET_FPS);
+        run_until_scale_changes(&mut adaptive, TARGET_FRAME_MS * 2.);
+        adaptive
+    }
+
+    #[test]
+    fn scales_down_when_too_slow() {
+        let mut adaptive = AdaptiveResolution::new(TARGET_FPS);
+
+        run(&mut adaptive, TARGET_FRAME_MS * 2., 1500.);
+        assert_eq!(adaptive.scale(), SCALE_STEPS[1]);
+
+        run(&mut adaptive, TARGET_FRAME_MS * 2., 1500.);
+        assert_eq!(adaptive.scale(), SCALE_STEPS[2]);
+    }
+
+    #[test]
+    fn ignores_hitches() {
+        let mut adaptive = AdaptiveResolution::new(TARGET_FPS);
+
+        for _ in 0..FRAME_WINDOW * 2 {
+            adaptive.record_frame(TARGET_FRAME_MS);
+            adaptive.record_frame(MAX_FRAME_MS * 2.);
+        }
+
+        assert_eq!(adaptive.scale(), 1.0);
+    }
+
+    #[test]
+    fn scales_up_after_holding_our_target() {
+        let mut adaptive = too_slow_for_full_resolution();
+
+        let waited = run_until_scale_changes(&mut adaptive, TARGET_FRAME_MS);
+
+        assert_eq!(adaptive.scale(), 1.0);
+        assert!(waited >= MIN_SCALE_UP_WAIT_MS);
+    }
+
+    /// Frames that are a little slow, but not slow enough to scale down, shouldn't scale us up
+    /// either
+    #[test]
+    fn holds_between_thresholds() {
+        let mut adaptive = too_slow_for_full_resolution();
+
+        run(&mut adaptive, TARGET_FRAME_MS * 1.1, MAX_SCALE_UP_WAIT_MS);
+
+        assert_eq!(adaptive.scale(), SCALE_STEPS[1]);
+    }
+
+    #[test]
+    fn backs_off_after_failed_scale_ups() {
+        let mut adaptive = too_slow_for_full_resolution();
+
+        let mut expected_wait = MIN_SCALE_UP_WAIT_MS;
+        for _ in 0..10 {
+            let waited = run_until_scale_changes(&mut adaptive, TARGET_FRAME_MS);
+            assert!(waited >= expected_wait);
+
+            // Full resolution is still too slow
+            run_until_scale_changes(&mut adaptive, TARGET_FRAME_MS * 2.);
+
+            expected_wait = (expected_wait * 2.).min(MAX_SCALE_UP_WAIT_MS);
+            assert_eq!(adaptive.scale_up_wait_ms, expected_wait);
+        }
+
+        assert_eq!(adaptive.scale_up_wait_ms, MAX_SCALE_UP_WAIT_MS);
+    }
+
+    #[test]
+    fn backoff_resets_once_a_scale_up_holds() {
+        let mut adaptive = too_slow_for_full_resolution();
+        for _ in 0..3 {
+            run_until_scale_changes(&mut adaptive, TARGET_FRAME_MS);
+            run_until_scale_changes(&mut adaptive, TARGET_FRAME_MS * 2.);
+        }
+        let backed_off_wait = adaptive.scale_up_wait_ms;
+        assert!(backed_off_wait > MIN_SCALE_UP_WAIT_MS);
+
+        run_until_scale_changes(&mut adaptive, TARGET_FRAME_MS);
+        run(&mut adaptive, TARGET_FRAME_MS, backed_off_wait);
+
+        assert_eq!(adaptive.scale(), 1.0);
+        assert_eq!(adaptive.scale_up_wait_ms, MIN_SCALE_UP_WAIT_MS);
+
+        // So the next time that we need to scale down, we come back up quickly
+        run_until_scale_changes(&mut adaptive, TARGET_FRAME_MS * 2.);
+        let waited = run_until_scale_changes(&mut adaptive, TARGET_FRAME_MS);
+        assert!(waited < backed_off_wait);
+    }
+}
@@ -0,0 +1,389 @@
+use crate::app::AssetError;
+use blender_armature::{ActionSettings, BlenderArmature, Bone, InterpolationSettings};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// Joint indices are u8's, so this is the most bones that an armature can have
+const MAX_JOINTS: usize = 256;
+
+/// An armature action that started playing at some time
+#[derive(Debug, Clone, PartialEq)]
+struct PlayingAction {
+    name: String,
+    /// In seconds on our `AnimationClock`
+    start_time: f32,
+}
+
+/// How far into its animation an entity is. Every animated entity has its own clock so that
+/// entities can be paused, sped up or scrubbed independently of one another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationClock {
+    /// Seconds that have played, not counting our offset
+    elapsed: f32,
+    /// Added to `elapsed`, so that entities that play the same action don't move in lockstep
+    pub offset: f32,
+    /// 2.0 plays twice as fast, 0.5 plays at half speed
+    pub rate: f32,
+    pub paused: bool,
+}
+
+impl AnimationClock {
+    pub fn new(offset: f32, rate: f32) -> AnimationClock {
+        AnimationClock {
+            elapsed: 0.,
+            offset,
+            rate,
+            paused: false,
+        }
+    }
+
+    /// The current time in seconds
+    pub fn time(&self) -> f32 {
+        self.elapsed + self.offset
+    }
+
+    /// Move forwards by `dt` milliseconds, scaled by our rate, unless we're paused
+    pub fn advance(&mut self, dt: f32) {
+        if !self.paused {
+            self.elapsed += (dt / 1000.0) * self.rate;
+        }
+    }
+
+    /// Jump to `elapsed` seconds. Our offset still gets added on top.
+    pub fn seek(&mut self, elapsed: f32) {
+        self.elapsed = elapsed;
+    }
+}
+
+/// Decides which of an entity's armature actions are playing, crossfading from one to the next
+/// whenever we transition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationController {
+    current: PlayingAction,
+    /// The action that we're fading out of, if we're in the middle of a transition
+    previous: Option<PlayingAction>,
+    blend_secs: f32,
+    pub clock: AnimationClock,
+}
+
+impl AnimationController {
+    pub fn new(action: &str, clock: AnimationClock) -> AnimationController {
+        AnimationController {
+            current: PlayingAction {
+                name: action.to_string(),
+                start_time: 0.,
+            },
+            previous: None,
+            blend_secs: 0.,
+            clock,
+        }
+    }
+
+    /// Start playing `action` now, fading out of whatever is playing over `blend_secs`. If we
+    /// were already in the middle of a transition the action that we were fading out of gets
+    /// dropped.
+    pub fn transition(&mut self, action: &str, blend_secs: f32) {
+        if self.current.name == action {
+            return;
+        }
+
+        let current = PlayingAction {
+            name: action.to_string(),
+            start_time: self.clock.time(),
+        };
+
+        self.previous = Some(std::mem::replace(&mut self.current, current));
+        self.blend_secs = blend_secs;
+        self.finish_crossfade();
+    }
+
+    /// Jump to `time` seconds into our current action, such as from the animation timeline
+    /// slider. Our clock's offset still gets added on top. Scrubbing is for inspecting the
+    /// current action's poses, so any crossfade that was still going gets finished.
+    pub fn seek(&mut self, time: f32) {
+        // Our current action started at `start_time` on our clock, which already includes
+        // our offset
+        self.clock.seek(self.current.start_time + time);
+        self.previous = None;
+    }
+
+    /// Move our clock forwards by `dt` milliseconds, see `AnimationClock::advance`
+    pub fn advance(&mut self, dt: f32) {
+        self.clock.advance(dt);
+        self.finish_crossfade();
+    }
+
+    /// Forget the action that we were fading out of once we've fully faded into the current one,
+    /// so that moving our clock backwards (ex: with a negative rate) doesn't bring it back
+    fn finish_crossfade(&mut self) {
+        if self.blend_weight() >= 1.0 {
+            self.previous = None;
+        }
+    }
+
+    /// How many seconds our current action takes to play through once, which is the time of its
+    /// last keyframe. 0.0 if the armature doesn't have the action.
+    pub fn action_duration(&self, armature: &BlenderArmature) -> f32 {
+        armature
+            .actions
+            .get(&self.current.name)
+            .map(|keyframes| keyframes.keys().map(|time| time.0).fold(0., f32::max))
+            .unwrap_or(0.)
+    }
+
+    /// How far we are into crossfading from our previous action to our current one, from 0.0 to
+    /// 1.0. Always 1.0 once the crossfade has finished, or if we never transitioned.
+    pub fn blend_weight(&self) -> f32 {
+        if self.previous.is_none() || self.blend_secs <= 0. {
+            return 1.0;
+        }
+
+        let secs_since_transition = self.clock.time() - self.current.start_time;
+        (secs_since_transition / self.blend_secs).max(0.).min(1.0)
+    }
+
+    /// Interpolate every bone in the armature named `armature_name` at the current time on our
+    /// clock. Returns each bone's dual quaternion keyed by its joint index.
+    ///
+    /// `InterpolationSettings::blend_fn` is a plain `fn` that can't know how long our crossfade
+    /// is, so we interpolate each action on its own and blend them by our `blend_weight`.
+    pub fn interpolate_bones(
+        &self,
+        armature_name: &str,
+        armature: &BlenderArmature,
+    ) -> Result<BTreeMap<u8, Bone>, AssetError> {
+        let bone_count = armature.inverse_bind_poses.len();
+        let joint_indices = (0..bone_count)
+            .map(u8::try_from)
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| AssetError::TooManyBones {
+                armature: armature_name.to_string(),
+                bone_count,
+                max_bones: MAX_JOINTS,
+            })?;
+
+        let current = self.interpolate_action(armature, &self.current, &joint_indices);
+
+        // Once we've finished blending there's no need to interpolate the previous action
+        let weight = self.blend_weight();
+        let previous = match self.previous.as_ref() {
+            Some(previous) if weight < 1.0 => previous,
+            _ => return Ok(current),
+        };
+        let previous = self.interpolate_action(armature, previous, &joint_indices);
+
+        Ok(current
+            .into_iter()
+            .map(|(joint, bone)| {
+                let bone = match previous.get(&joint) {
+                    Some(previous_bone) => blend_bones(previous_bone, &bone, weight),
+                    None => bone,
+                };
+                (joint, bone)
+            })
+            .collect())
+    }
+
+    fn interpolate_action(
+        &self,
+        armature: &BlenderArmature,
+        action: &PlayingAction,
+        joint_indices: &[u8],
+    ) -> BTreeMap<u8, Bone> {
+        let interp_opts = InterpolationSettings {
+            current_time: self.clock.time(),
+            joint_indices: joint_indices.to_vec(),
+            blend_fn: None,
+            current_action: ActionSettings::new(&action.name, action.start_time, true),
+            previous_action: None,
+        };
+
+        armature.interpolate_bones(&interp_opts)
+    }
+}
+
+/// Linearly blend two dual quaternion bones, `weight` of the way from `from` to `to`.
+///
+/// `q` and `-q` are the same rotation, so we flip `from` onto `to`'s side first in order to
+/// take the short way around.
+fn blend_bones(from: &Bone, to: &Bone, weight: f32) -> Bone {
+    match (from, to) {
+        (Bone::DualQuat(from), Bone::DualQuat(to)) => {
+            let dot: f32 = (0..4).map(|i| from[i] * to[i]).sum();
+            let sign = if dot < 0. { -1. } else { 1. };
+
+            let mut blended = [0.; 8];
+            for i in 0..8 {
+                blended[i] = from[i] * sign * (1. - weight) + to[i] * weight;
+            }
+
+            Bone::DualQuat(blended)
+        }
+        // Our bundles are always preprocessed into dual quaternions
+        _ => to.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A one bone armature that holds a different pose in each of its two actions
+    fn rig() -> BlenderArmature {
+        let rig = r#"{
+            "joint_index": {"Root": 0},
+            "inverse_bind_poses": [{"DualQuat": [1, 0, 0, 0, 0, 0, 0, 0]}],
+            "actions": {
+                "Idle": {
+                    "0.0": [{"DualQuat": [1, 0, 0, 0, 0, 0, 0, 0]}],
+                    "1.0": [{"DualQuat": [1, 0, 0, 0, 0, 0, 0, 0]}]
+                },
+                "Fly": {
+                    "0.0": [{"DualQuat": [0, 1, 0, 0, 0, 0, 0, 0]}],
+                    "1.0": [{"DualQuat": [0, 1, 0, 0, 0, 0, 0, 0]}]
+                }
+            }
+        }"#;
+
+        serde_json::from_str(rig).unwrap()
+    }
+
+    /// Playing "Idle" for a second and then crossfading into "Fly" over `blend_secs`
+    fn idle_to_fly(blend_secs: f32) -> AnimationController {
+        let mut animation = AnimationController::new("Idle", AnimationClock::new(0., 1.));
+        animation.advance(1000.);
+        animation.transition("Fly", blend_secs);
+        animation
+    }
+
+    fn root_bone(animation: &AnimationController) -> Bone {
+        animation.interpolate_bones("Rig", &rig()).unwrap()[&0].clone()
+    }
+
+    #[test]
+    fn crossfade_weight_rises_over_the_blend_duration() {
+        let mut animation = idle_to_fly(0.5);
+        assert_eq!(animation.blend_weight(), 0.);
+
+        animation.advance(250.);
+        assert_eq!(animation.blend_weight(), 0.5);
+
+        animation.advance(250.);
+        assert_eq!(animation.blend_weight(), 1.0);
+
+        animation.advance(250.);
+        assert_eq!(animation.blend_weight(), 1.0);
+    }
+
+    #[test]
+    fn transitions_without_a_blend_duration_cut_straight_to_the_next_action() {
+        let animation = idle_to_fly(0.);
+
+        assert_eq!(animation.blend_weight(), 1.0);
+        assert_eq!(
+            root_bone(&animation),
+            Bone::DualQuat([0., 1., 0., 0., 0., 0., 0., 0.])
+        );
+    }
+
+    #[test]
+    fn crossfading_blends_the_bones_of_both_actions() {
+        let mut animation = idle_to_fly(0.5);
+        animation.advance(250.);
+
+        assert_eq!(
+            root_bone(&animation),
+            Bone::DualQuat([0.5, 0.5, 0., 0., 0., 0., 0., 0.])
+        );
+    }
+
+    #[test]
+    fn finished_crossfades_only_play_the_current_action() {
+        let mut animation = idle_to_fly(0.5);
+        animation.advance(600.);
+
+        assert_eq!(
+            root_bone(&animation),
+            Bone::DualQuat([0., 1., 0., 0., 0., 0., 0., 0.])
+        );
+    }
+
+    #[test]
+    fn finished_crossfades_stay_finished_when_the_clock_runs_backwards() {
+        let mut animation = idle_to_fly(0.5);
+        animation.advance(600.);
+
+        animation.clock.rate = -1.;
+        animation.advance(500.);
+
+        assert_eq!(animation.blend_weight(), 1.0);
+        assert_eq!(
+            root_bone(&animation),
+            Bone::DualQuat([0., 1., 0., 0., 0., 0., 0., 0.])
+        );
+    }
+
+    #[test]
+    fn seeking_is_relative_to_when_the_current_action_started() {
+        let mut animation = idle_to_fly(0.5);
+        animation.seek(0.);
+
+        assert_eq!(animation.clock.time(), 1.0);
+        assert_eq!(animation.blend_weight(), 1.0);
+        assert_eq!(
+            root_bone(&animation),
+            Bone::DualQuat([0., 1., 0., 0., 0., 0., 0., 0.])
+        );
+
+        animation.seek(0.25);
+        assert_eq!(animation.clock.time(), 1.25);
+    }
+
+    #[test]
+    fn crossfades_of_different_lengths_do_not_affect_one_another() {
+        let mut short = idle_to_fly(0.5);
+        let mut long = idle_to_fly(2.0);
+        short.advance(250.);
+        long.advance(250.);
+
+        let short_bone = root_bone(&short);
+        let long_bone = root_bone(&long);
+
+        assert_eq!(
+            short_bone,
+            Bone::DualQuat([0.5, 0.5, 0., 0., 0., 0., 0., 0.])
+        );
+        assert_eq!(
+            long_bone,
+            Bone::DualQuat([0.875, 0.125, 0., 0., 0., 0., 0., 0.])
+        );
+    }
+
+    #[test]
+    fn armatures_with_more_bones_than_joint_indices_can_address_are_reported() {
+        let animation = AnimationController::new("Idle", AnimationClock::new(0., 1.));
+
+        let mut rig = rig();
+        let bone = rig.inverse_bind_poses[0].clone();
+        rig.inverse_bind_poses.resize(MAX_JOINTS, bone.clone());
+        for keyframes in rig.actions.values_mut() {
+            for bones in keyframes.values_mut() {
+                bones.resize(MAX_JOINTS, bone.clone());
+            }
+        }
+        assert_eq!(
+            animation.interpolate_bones("Rig", &rig).unwrap().len(),
+            MAX_JOINTS
+        );
+
+        rig.inverse_bind_poses.push(bone);
+        assert_eq!(
+            animation.interpolate_bones("Rig", &rig),
+            Err(AssetError::TooManyBones {
+                armature: "Rig".to_string(),
+                bone_count: MAX_JOINTS + 1,
+                max_bones: MAX_JOINTS,
+            })
+        );
+    }
+}
@@ -1,29 +1,199 @@
+use crate::app::store::Ray;
 use nalgebra::{Isometry3, Perspective3, Point3, Vector3};
+use serde::Deserialize;
 use std::f32::consts::PI;
 
+/// Projection and orbit settings that can be changed at runtime
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CameraConfig {
+    /// Vertical field of view in radians
+    pub fovy: f32,
+    /// Width / height of the viewport. This follows the canvas' size, see `Msg::Resize`.
+    #[serde(skip)]
+    pub aspect: f32,
+    /// Distance to the near clipping plane
+    pub near: f32,
+    /// Distance to the far clipping plane
+    pub far: f32,
+    /// The point that the orbit camera orbits around and looks at
+    pub orbit_target: (f32, f32, f32),
+    /// How close we can zoom into the orbit target
+    pub min_orbit_radius: f32,
+    /// How far we can zoom out from the orbit target
+    pub max_orbit_radius: f32,
+    /// How low the orbit camera can go, in radians above the horizon
+    pub min_orbit_pitch: f32,
+    /// How high the orbit camera can go, in radians above the horizon
+    pub max_orbit_pitch: f32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> CameraConfig {
+        CameraConfig {
+            fovy: PI / 3.0,
+            aspect: 1.0,
+            near: 0.1,
+            far: 50.0,
+            orbit_target: (0., 0., 0.),
+            min_orbit_radius: 5.,
+            max_orbit_radius: 30.,
+            min_orbit_pitch: 0.1,
+            max_orbit_pitch: PI / 2.1,
+        }
+    }
+}
+
+impl CameraConfig {
+    /// Make sure that the settings describe a projection that we can build and an orbit that we
+    /// can stay within, so that a bad config shows up as an error instead of a panic or a
+    /// camera that can't see anything.
+    pub fn validate(&self) -> Result<(), String> {
+        let settings = [
+            self.fovy,
+            self.aspect,
+            self.near,
+            self.far,
+            self.min_orbit_radius,
+            self.max_orbit_radius,
+            self.min_orbit_pitch,
+            self.max_orbit_pitch,
+        ];
+        if settings.iter().any(|setting| !setting.is_finite()) {
+            return Err("Every setting must be a finite number".to_string());
+        }
+
+        if self.fovy <= 0. || self.fovy >= PI {
+            return Err(format!(
+                "fovy must be between 0 and PI radians, not {}",
+                self.fovy
+            ));
+        }
+        if self.aspect <= 0. {
+            return Err(format!("aspect must be above 0, not {}", self.aspect));
+        }
+        if self.near <= 0. {
+            return Err(format!("near must be above 0, not {}", self.near));
+        }
+        if self.far <= self.near {
+            return Err(format!(
+                "far ({}) must be further away than near ({})",
+                self.far, self.near
+            ));
+        }
+        if self.min_orbit_radius > self.max_orbit_radius {
+            return Err(format!(
+                "minOrbitRadius ({}) can't be larger than maxOrbitRadius ({})",
+                self.min_orbit_radius, self.max_orbit_radius
+            ));
+        }
+        if self.min_orbit_pitch > self.max_orbit_pitch {
+            return Err(format!(
+                "minOrbitPitch ({}) can't be larger than maxOrbitPitch ({})",
+                self.min_orbit_pitch, self.max_orbit_pitch
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// How the camera moves around the scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraMode {
+    /// Orbit around the center of the scene. Drag to orbit, scroll to zoom.
+    Orbit,
+    /// Fly anywhere, including under the water. WASD to move, Space / Shift to rise and sink,
+    /// drag to look around.
+    FreeFly,
+    /// Walk along the water's surface with our eyes just above it. WASD to move, drag to look
+    /// around.
+    FirstPerson,
+}
+
+/// How high above the water our eyes are in `CameraMode::FirstPerson`
+pub static FIRST_PERSON_EYE_HEIGHT: f32 = 0.5;
+
 pub struct Camera {
+    config: CameraConfig,
     projection: Perspective3<f32>,
+    mode: CameraMode,
     left_right_radians: f32,
     up_down_radians: f32,
     orbit_radius: f32,
+    /// Where we are when we aren't orbiting
+    position: Point3<f32>,
+    /// Which way we're facing when we aren't orbiting. 0 faces down the +z axis.
+    yaw: f32,
+    /// How far we're looking up (positive) or down (negative) when we aren't orbiting
+    pitch: f32,
 }
 
 impl Camera {
     pub fn new() -> Camera {
-        let fovy = PI / 3.0;
+        let config = CameraConfig::default();
 
         Camera {
-            projection: Perspective3::new(fovy, 1.0, 0.1, 50.0),
+            projection: Perspective3::new(config.aspect, config.fovy, config.near, config.far),
+            config,
+            mode: CameraMode::Orbit,
             left_right_radians: 45.0f32.to_radians(),
             up_down_radians: 80.0f32.to_radians(),
             orbit_radius: 15.,
+            position: Point3::new(0., 0., 0.),
+            yaw: 0.,
+            pitch: 0.,
         }
     }
 
+    pub fn config(&self) -> &CameraConfig {
+        &self.config
+    }
+
+    /// Change our projection and orbit settings. Our current orbit gets pulled back within the
+    /// new limits.
+    ///
+    /// The config must be valid, see `CameraConfig::validate`.
+    pub fn set_config(&mut self, config: CameraConfig) {
+        self.projection = Perspective3::new(config.aspect, config.fovy, config.near, config.far);
+        self.config = config;
+
+        self.orbit_up_down(0.);
+        self.zoom_orbit(0.);
+    }
+
+    /// Keep our projection from stretching when the viewport changes shape
+    pub fn set_aspect(&mut self, aspect: f32) {
+        let config = CameraConfig {
+            aspect,
+            ..self.config.clone()
+        };
+        self.set_config(config);
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    /// Switch camera modes. When we leave orbit mode we start off where the orbit camera was,
+    /// looking the same way.
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if self.mode == CameraMode::Orbit && mode != CameraMode::Orbit {
+            let eye = self.get_eye_pos();
+            let forward = (self.orbit_target() - eye).normalize();
+
+            self.position = eye;
+            self.yaw = forward.x.atan2(forward.z);
+            self.pitch = forward.y.asin();
+        }
+
+        self.mode = mode;
+    }
+
     pub fn view(&self) -> [f32; 16] {
         let eye = self.get_eye_pos();
 
-        let target = Point3::new(0.0, 0.0, 0.0);
+        let target = self.get_target_pos();
 
         let view = Isometry3::look_at_rh(&eye, &target, &Vector3::y());
 
@@ -35,11 +205,14 @@ impl Camera {
         view_array
     }
 
-    pub fn view_flipped_y(&self) -> [f32; 16] {
+    /// Our view matrix mirrored across the horizontal plane at `plane_y`.
+    /// Used to render reflections off of a water surface at that height.
+    pub fn view_flipped_y(&self, plane_y: f32) -> [f32; 16] {
         let mut eye = self.get_eye_pos();
-        eye.y = -1.0 * eye.y;
+        eye.y = 2.0 * plane_y - eye.y;
 
-        let target = Point3::new(0.0, 0.0, 0.0);
+        let mut target = self.get_target_pos();
+        target.y = 2.0 * plane_y - target.y;
 
         let view = Isometry3::look_at_rh(&eye, &target, &Vector3::y());
 
@@ -51,7 +224,34 @@ impl Camera {
         view_array
     }
 
+    /// The ray that starts at the camera and passes through the pixel (x, y) of a
+    /// `viewport` that is (width, height) pixels large. (0, 0) is the top left of the viewport.
+    pub fn screen_ray(&self, x: f32, y: f32, viewport: (f32, f32)) -> Ray {
+        let ndc_x = 2.0 * x / viewport.0 - 1.0;
+        let ndc_y = 1.0 - 2.0 * y / viewport.1;
+
+        let eye = self.get_eye_pos();
+        let target = self.get_target_pos();
+        let view = Isometry3::look_at_rh(&eye, &target, &Vector3::y());
+
+        let near = self
+            .projection
+            .unproject_point(&Point3::new(ndc_x, ndc_y, -1.0));
+        let far = self
+            .projection
+            .unproject_point(&Point3::new(ndc_x, ndc_y, 1.0));
+
+        let near = view.inverse_transform_point(&near);
+        let far = view.inverse_transform_point(&far);
+
+        Ray::new(near, far - near)
+    }
+
     pub fn get_eye_pos(&self) -> Point3<f32> {
+        if self.mode != CameraMode::Orbit {
+            return self.position;
+        }
+
         let yaw = self.left_right_radians;
         let pitch = self.up_down_radians;
 
@@ -59,8 +259,31 @@ impl Camera {
         let eye_y = self.orbit_radius * pitch.sin();
         let eye_z = self.orbit_radius * yaw.cos() * pitch.cos();
 
-        Point3::new(eye_x, eye_y, eye_z)
+        self.orbit_target() + Vector3::new(eye_x, eye_y, eye_z)
+    }
+
+    fn orbit_target(&self) -> Point3<f32> {
+        let (x, y, z) = self.config.orbit_target;
+        Point3::new(x, y, z)
     }
+
+    /// The point that the camera is looking at
+    fn get_target_pos(&self) -> Point3<f32> {
+        match self.mode {
+            CameraMode::Orbit => self.orbit_target(),
+            CameraMode::FreeFly | CameraMode::FirstPerson => self.position + self.forward(),
+        }
+    }
+
+    /// The direction that we're facing when we aren't orbiting
+    fn forward(&self) -> Vector3<f32> {
+        Vector3::new(
+            self.yaw.sin() * self.pitch.cos(),
+            self.pitch.sin(),
+            self.yaw.cos() * self.pitch.cos(),
+        )
+    }
+
     pub fn projection(&self) -> [f32; 16] {
         let mut perspective_array = [0.; 16];
         perspective_array.copy_from_slice(self.projection.as_matrix().as_slice());
@@ -76,25 +299,301 @@ impl Camera {
         self.up_down_radians += delta;
 
         // Make sure:
-        // 0.1 <= radians <= PI / 2.1
+        // min_orbit_pitch <= radians <= max_orbit_pitch
         // in order to restrict the camera's up/down orbit motion
 
-        if self.up_down_radians - (PI / 2.1) > 0. {
-            self.up_down_radians = PI / 2.1;
+        if self.up_down_radians > self.config.max_orbit_pitch {
+            self.up_down_radians = self.config.max_orbit_pitch;
         }
 
-        if self.up_down_radians - 0.1 < 0. {
-            self.up_down_radians = 0.1;
+        if self.up_down_radians < self.config.min_orbit_pitch {
+            self.up_down_radians = self.config.min_orbit_pitch;
+        }
+    }
+
+    /// Turn the camera when we aren't orbiting. Positive deltas turn left and look up.
+    pub fn look(&mut self, yaw_delta: f32, pitch_delta: f32) {
+        self.yaw += yaw_delta;
+
+        // Stop just short of straight up or down, where our look at matrix would flip around
+        self.pitch = (self.pitch + pitch_delta).max(-PI / 2.1).min(PI / 2.1);
+    }
+
+    /// Move the camera relative to the way that it's facing. Does nothing while orbiting.
+    ///
+    /// In first person mode we stay level with the ground, so looking up or down doesn't change
+    /// our height and `up` is ignored.
+    pub fn fly(&mut self, forward: f32, right: f32, up: f32) {
+        let facing = match self.mode {
+            CameraMode::Orbit => return,
+            CameraMode::FreeFly => self.forward(),
+            CameraMode::FirstPerson => Vector3::new(self.yaw.sin(), 0., self.yaw.cos()),
+        };
+        let right_of_facing = facing.cross(&Vector3::y()).normalize();
+
+        self.position += facing * forward + right_of_facing * right;
+
+        if self.mode == CameraMode::FreeFly {
+            self.position.y += up;
+        }
+    }
+
+    /// In first person mode our height follows the water that we're standing in
+    pub fn set_first_person_height(&mut self, water_height: f32) {
+        if self.mode == CameraMode::FirstPerson {
+            self.position.y = water_height + FIRST_PERSON_EYE_HEIGHT;
         }
     }
 
     pub fn zoom(&mut self, zoom: f32) {
+        if self.mode != CameraMode::Orbit {
+            // Scrolling towards you (a negative zoom) zooms in, so we move forwards
+            self.fly(-zoom, 0., 0.);
+            return;
+        }
+
+        self.zoom_orbit(zoom);
+    }
+
+    fn zoom_orbit(&mut self, zoom: f32) {
         self.orbit_radius += zoom;
 
-        if self.orbit_radius > 30. {
-            self.orbit_radius = 30.;
-        } else if self.orbit_radius < 5. {
-            self.orbit_radius = 5.;
+        if self.orbit_radius > self.config.max_orbit_radius {
+            self.orbit_radius = self.config.max_orbit_radius;
+        } else if self.orbit_radius < self.config.min_orbit_radius {
+            self.orbit_radius = self.config.min_orbit_radius;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_is_valid() {
+        assert_eq!(CameraConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_configs_that_we_cannot_build_a_camera_from() {
+        // Settings that are left out keep their defaults
+        let invalid = [
+            r#"{"near": 10, "far": 10}"#,
+            r#"{"near": 10, "far": 5}"#,
+            r#"{"near": -0.1}"#,
+            r#"{"fovy": 0}"#,
+            r#"{"fovy": -1}"#,
+            r#"{"fovy": 3.1416}"#,
+            r#"{"far": 1e40}"#,
+            r#"{"minOrbitRadius": 40, "maxOrbitRadius": 30}"#,
+            r#"{"minOrbitPitch": 1, "maxOrbitPitch": 0.5}"#,
+        ];
+
+        for config in invalid.iter() {
+            let parsed: CameraConfig = serde_json::from_str(config).unwrap();
+            assert!(parsed.validate().is_err(), "{} should be invalid", config);
+        }
+
+        // JSON can't hold a NaN, and the aspect ratio always comes from the canvas
+        let invalid = [
+            CameraConfig {
+                fovy: f32::NAN,
+                ..CameraConfig::default()
+            },
+            CameraConfig {
+                aspect: 0.,
+                ..CameraConfig::default()
+            },
+        ];
+
+        for config in invalid.iter() {
+            assert!(config.validate().is_err(), "{:?} should be invalid", config);
+        }
+    }
+
+    /// The angle between two directions, in radians
+    fn angle_between(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+        a.normalize().dot(&b.normalize()).min(1.).acos()
+    }
+
+    #[test]
+    fn screen_ray_through_the_center_points_at_the_target() {
+        let camera = Camera::new();
+        let eye = camera.get_eye_pos();
+
+        let ray = camera.screen_ray(400., 300., (800., 600.));
+
+        let towards_target = camera.get_target_pos() - eye;
+        assert!(angle_between(ray.direction, towards_target) < 1e-3);
+        // The ray starts on the near plane
+        assert!(((ray.origin - eye).norm() - camera.config().near).abs() < 1e-3);
+    }
+
+    #[test]
+    fn screen_rays_through_the_edges_follow_the_field_of_view() {
+        let mut camera = Camera::new();
+        camera.set_aspect(2.);
+        let fovy = camera.config().fovy;
+
+        let center = camera.screen_ray(400., 200., (800., 400.)).direction;
+        let top = camera.screen_ray(400., 0., (800., 400.)).direction;
+        let left = camera.screen_ray(0., 200., (800., 400.)).direction;
+
+        assert!((angle_between(center, top) - fovy / 2.).abs() < 1e-3);
+        assert!(top.y > center.y);
+
+        let half_fovx = ((fovy / 2.).tan() * 2.).atan();
+        assert!((angle_between(center, left) - half_fovx).abs() < 1e-3);
+    }
+
+    fn assert_close(actual: Point3<f32>, expected: Point3<f32>) {
+        assert!(
+            (actual - expected).norm() < 1e-4,
+            "{} should be {}",
+            actual,
+            expected
+        );
+    }
+
+    /// A camera at the origin, facing down the +z axis
+    fn flying_camera(mode: CameraMode) -> Camera {
+        let mut camera = Camera::new();
+        camera.set_mode(mode);
+        camera.position = Point3::origin();
+        camera.yaw = 0.;
+        camera.pitch = 0.;
+        camera
+    }
+
+    #[test]
+    fn leaving_orbit_mode_keeps_the_view() {
+        for mode in [CameraMode::FreeFly, CameraMode::FirstPerson].iter() {
+            let mut camera = Camera::new();
+            let orbit_eye = camera.get_eye_pos();
+            let orbit_view = camera.view();
+
+            camera.set_mode(*mode);
+
+            assert_eq!(camera.mode(), *mode);
+            assert_close(camera.get_eye_pos(), orbit_eye);
+            for (flying, orbiting) in camera.view().iter().zip(orbit_view.iter()) {
+                assert!((flying - orbiting).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn returning_to_orbit_mode_goes_back_to_the_orbit() {
+        let mut camera = Camera::new();
+        let orbit_eye = camera.get_eye_pos();
+
+        camera.set_mode(CameraMode::FreeFly);
+        camera.fly(3., 1., 2.);
+        camera.set_mode(CameraMode::Orbit);
+
+        assert_close(camera.get_eye_pos(), orbit_eye);
+    }
+
+    #[test]
+    fn switching_between_flying_modes_keeps_our_position() {
+        let mut camera = flying_camera(CameraMode::FreeFly);
+        camera.fly(2., 0., 1.);
+
+        camera.set_mode(CameraMode::FirstPerson);
+
+        assert_close(camera.get_eye_pos(), Point3::new(0., 1., 2.));
+    }
+
+    #[test]
+    fn does_not_fly_while_orbiting() {
+        let mut camera = Camera::new();
+        let eye = camera.get_eye_pos();
+
+        camera.fly(3., 1., 2.);
+
+        assert_close(camera.get_eye_pos(), eye);
+    }
+
+    #[test]
+    fn flies_relative_to_the_way_that_we_are_facing() {
+        let mut camera = flying_camera(CameraMode::FreeFly);
+
+        camera.fly(2., 0., 0.);
+        assert_close(camera.get_eye_pos(), Point3::new(0., 0., 2.));
+
+        // Facing +z, our right is -x
+        camera.fly(0., 1., 0.5);
+        assert_close(camera.get_eye_pos(), Point3::new(-1., 0.5, 2.));
+
+        // A quarter turn to the left faces +x
+        camera.look(PI / 2., 0.);
+        camera.fly(1., 0., 0.);
+        assert_close(camera.get_eye_pos(), Point3::new(0., 0.5, 2.));
+    }
+
+    #[test]
+    fn free_fly_follows_our_pitch() {
+        let mut camera = flying_camera(CameraMode::FreeFly);
+        camera.look(0., PI / 4.);
+
+        camera.fly(2f32.sqrt(), 0., 0.);
+
+        assert_close(camera.get_eye_pos(), Point3::new(0., 1., 1.));
+    }
+
+    #[test]
+    fn first_person_stays_level() {
+        let mut camera = flying_camera(CameraMode::FirstPerson);
+        camera.look(0., PI / 4.);
+
+        camera.fly(2., 0., 5.);
+
+        assert_close(camera.get_eye_pos(), Point3::new(0., 0., 2.));
+    }
+
+    #[test]
+    fn first_person_height_follows_the_water() {
+        let mut camera = flying_camera(CameraMode::FirstPerson);
+        camera.set_first_person_height(3.);
+        assert_close(
+            camera.get_eye_pos(),
+            Point3::new(0., 3. + FIRST_PERSON_EYE_HEIGHT, 0.),
+        );
+
+        let mut camera = flying_camera(CameraMode::FreeFly);
+        camera.set_first_person_height(3.);
+        assert_close(camera.get_eye_pos(), Point3::origin());
+    }
+
+    #[test]
+    fn looking_stops_short_of_straight_up_or_down() {
+        let mut camera = flying_camera(CameraMode::FreeFly);
+
+        camera.look(0., 10.);
+        assert_eq!(camera.pitch, PI / 2.1);
+
+        camera.look(0., -20.);
+        assert_eq!(camera.pitch, -PI / 2.1);
+    }
+
+    #[test]
+    fn looking_turns_the_flying_camera() {
+        let mut camera = flying_camera(CameraMode::FreeFly);
+
+        camera.look(PI / 2., 0.);
+
+        assert_close(camera.get_target_pos(), Point3::new(1., 0., 0.));
+    }
+
+    #[test]
+    fn zooming_while_flying_moves_forwards() {
+        let mut camera = flying_camera(CameraMode::FreeFly);
+        let orbit_radius = camera.orbit_radius;
+
+        camera.zoom(-2.);
+
+        assert_close(camera.get_eye_pos(), Point3::new(0., 0., 2.));
+        assert_eq!(camera.orbit_radius, orbit_radius);
+    }
+}
@@ -0,0 +1,31 @@
+use std::collections::HashSet;
+
+/// The keys that are currently held down, identified by their `KeyboardEvent.code`
+/// such as "KeyW" or "Space".
+#[derive(Default)]
+pub struct Keyboard {
+    pressed: HashSet<String>,
+}
+
+impl Keyboard {
+    pub fn press(&mut self, code: &str) {
+        self.pressed.insert(code.to_string());
+    }
+
+    pub fn release(&mut self, code: &str) {
+        self.pressed.remove(code);
+    }
+
+    pub fn is_pressed(&self, code: &str) -> bool {
+        self.pressed.contains(code)
+    }
+
+    /// 1.0 if only `positive` is pressed, -1.0 if only `negative` is pressed and 0.0 otherwise
+    pub fn axis(&self, positive: &str, negative: &str) -> f32 {
+        match (self.is_pressed(positive), self.is_pressed(negative)) {
+            (true, false) => 1.0,
+            (false, true) => -1.0,
+            _ => 0.0,
+        }
+    }
+}
@@ -1,13 +1,48 @@
+use crate::app::Assets;
+use nalgebra::Vector3;
 use std::ops::Deref;
 
 mod mouse;
 use self::mouse::*;
 
 mod camera;
-use self::camera::*;
+pub use self::camera::*;
+
+mod keyboard;
+use self::keyboard::*;
 
 mod water;
-use self::water::*;
+pub use self::water::*;
+
+mod water_body;
+pub use self::water_body::*;
+
+mod ripples;
+pub use self::ripples::*;
+
+mod ray;
+pub use self::ray::*;
+
+mod quality;
+pub use self::quality::*;
+
+mod adaptive_resolution;
+use self::adaptive_resolution::*;
+
+mod scene;
+pub use self::scene::*;
+
+mod scene_graph;
+pub use self::scene_graph::*;
+
+mod motion_path;
+pub use self::motion_path::*;
+
+mod animation;
+pub use self::animation::*;
+
+/// How many units per second the camera moves when flying or walking
+static CAMERA_MOVE_SPEED: f32 = 5.0;
 
 pub struct Store {
     pub state: StateWrapper,
@@ -31,19 +66,37 @@ pub struct State {
     clock: f32,
     camera: Camera,
     mouse: Mouse,
+    keyboard: Keyboard,
     water: Water,
-    show_scenery: bool
+    water_bodies: Vec<WaterBody>,
+    scene_graph: SceneGraph,
+    canvas_size: (u32, u32),
+    /// Scales our water rendering down when frames take too long. None renders at the
+    /// resolutions in `Water`.
+    adaptive_resolution: Option<AdaptiveResolution>,
+    show_scenery: bool,
 }
 
 impl State {
     fn new() -> State {
+        let scene = Scene::default();
+
+        let mut scene_graph = SceneGraph::new(&scene);
+        scene_graph.update(0.);
+
         State {
             /// Time elapsed since the application started, in milliseconds
             clock: 0.,
             camera: Camera::new(),
             mouse: Mouse::default(),
+            keyboard: Keyboard::default(),
             water: Water::new(),
-            show_scenery: true
+            water_bodies: scene.water_bodies(),
+            scene_graph,
+            // Replaced with the canvas' real size as soon as it sends us a `Msg::Resize`
+            canvas_size: (512, 512),
+            adaptive_resolution: None,
+            show_scenery: true,
         }
     }
 
@@ -51,10 +104,142 @@ impl State {
         &self.camera
     }
 
+    /// The (width, height) of the canvas' drawing buffer in device pixels
+    pub fn canvas_size(&self) -> (u32, u32) {
+        self.canvas_size
+    }
+
+    /// How much our water rendering is scaled down to hold our target frame rate, from 0.25 to 1.0
+    pub fn render_scale(&self) -> f32 {
+        self.adaptive_resolution
+            .as_ref()
+            .map_or(1.0, |adaptive| adaptive.scale())
+    }
+
+    /// The (width, height) that our refraction framebuffer should be
+    pub fn refraction_size(&self) -> (i32, i32) {
+        let size = self.water.refraction_resolution.size(self.canvas_size);
+        scale_size(size, self.render_scale())
+    }
+
+    /// The (width, height) that our reflection framebuffer should be
+    pub fn reflection_size(&self) -> (i32, i32) {
+        let size = self.water.reflection_resolution.size(self.canvas_size);
+        scale_size(size, self.render_scale())
+    }
+
+    /// The number of quads along each side of our water grid
+    pub fn water_grid_resolution(&self) -> u16 {
+        let resolution = self.water.grid_resolution as f32 * self.render_scale();
+        (resolution.round() as u16).max(1)
+    }
+
     pub fn water(&self) -> &Water {
         &self.water
     }
 
+    /// Every body of water in the scene. Each one gets its own reflection and refraction passes.
+    pub fn water_bodies(&self) -> &[WaterBody] {
+        &self.water_bodies
+    }
+
+    /// The y coordinate of the water's surface at a world (x, z), including waves.
+    /// None if there isn't any water there.
+    pub fn water_height_at(&self, x: f32, z: f32) -> Option<f32> {
+        self.water_body_at(x, z)
+            .map(|water_body| water_body.height + self.water.height_at(x, z, self.clock))
+    }
+
+    /// The normal of the water's surface at a world (x, z).
+    /// None if there isn't any water there.
+    pub fn water_normal_at(&self, x: f32, z: f32) -> Option<Vector3<f32>> {
+        self.water_body_at(x, z)
+            .map(|_| self.water.normal_at(x, z, self.clock))
+    }
+
+    fn water_body_at(&self, x: f32, z: f32) -> Option<&WaterBody> {
+        self.water_bodies
+            .iter()
+            .find(|water_body| water_body.contains(x, z))
+    }
+
+    /// The meshes that are placed in the world, with their world matrices as of the last
+    /// `Msg::AdvanceClock`
+    pub fn scene_graph(&self) -> &SceneGraph {
+        &self.scene_graph
+    }
+
+    /// Start a ripple at a world (x, z) on whichever water is there
+    fn disturb_water(&mut self, x: f32, z: f32, strength: f32) {
+        for water_body in self.water_bodies.iter_mut() {
+            water_body.disturb(x, z, strength);
+        }
+    }
+
+    /// Move the camera with whichever of WASD, Space and Shift are held down
+    fn move_camera(&mut self, dt: f32) {
+        let keyboard = &self.keyboard;
+        let distance = CAMERA_MOVE_SPEED * dt / 1000.;
+
+        let forward = keyboard.axis("KeyW", "KeyS") * distance;
+        let right = keyboard.axis("KeyD", "KeyA") * distance;
+        let up = keyboard.axis("Space", "ShiftLeft") * distance;
+
+        self.camera.fly(forward, right, up);
+
+        if self.camera.mode() == CameraMode::FirstPerson {
+            let eye = self.camera.get_eye_pos();
+
+            if let Some(water_height) = self.water_height_at(eye.x, eye.z) {
+                self.camera.set_first_person_height(water_height);
+            }
+        }
+    }
+
+    /// The world (x, z) of every mesh that is moving through the water's surface, where it should
+    /// leave ripples behind it. Meshes that aren't moving, such as the terrain that the water
+    /// sits in, don't disturb the water even though they cross its surface.
+    pub fn meshes_moving_through_water(&self, assets: &Assets) -> Vec<(f32, f32)> {
+        let mut ripples = vec![];
+
+        for node in self.scene_graph.nodes() {
+            if !node.is_moving() {
+                continue;
+            }
+
+            let bounds = match assets.mesh_bounds(&node.entity().mesh) {
+                Some(bounds) => bounds.transform(node.world_transform()),
+                None => continue,
+            };
+            let center = bounds.center();
+
+            if let Some(water_height) = self.water_height_at(center.x, center.z) {
+                if bounds.min.y <= water_height && water_height <= bounds.max.y {
+                    ripples.push((center.x, center.z));
+                }
+            }
+        }
+
+        ripples
+    }
+
+    /// How many seconds the longest of our entities' current actions takes to play through, so
+    /// that scrubbing through it reaches every pose of every entity. None until an animated
+    /// entity's armature has arrived.
+    pub fn animation_duration(&self, assets: &Assets) -> Option<f32> {
+        self.scene_graph
+            .nodes()
+            .iter()
+            .filter_map(|node| {
+                let armature = assets.get_armature(node.entity().armature.as_ref()?)?;
+                Some(node.animation()?.action_duration(armature))
+            })
+            .fold(None, |longest, duration| match longest {
+                Some(longest) if longest >= duration => Some(longest),
+                _ => Some(duration),
+            })
+    }
+
     /// The current time in milliseconds
     pub fn clock(&self) -> f32 {
         self.clock
@@ -68,6 +253,18 @@ impl State {
         match msg {
             Msg::AdvanceClock(dt) => {
                 self.clock += dt;
+
+                if let Some(adaptive_resolution) = self.adaptive_resolution.as_mut() {
+                    adaptive_resolution.record_frame(*dt);
+                }
+
+                self.move_camera(*dt);
+
+                self.scene_graph.update(self.clock);
+                self.scene_graph.advance_animations(*dt);
+                for water_body in self.water_bodies.iter_mut() {
+                    water_body.advance_ripples(*dt);
+                }
             }
             Msg::MouseDown(x, y) => {
                 self.mouse.set_pressed(true);
@@ -86,8 +283,16 @@ impl State {
                 let x_delta = old_x as i32 - x;
                 let y_delta = y - old_y as i32;
 
-                self.camera.orbit_left_right(x_delta as f32 / 50.0);
-                self.camera.orbit_up_down(y_delta as f32 / 50.0);
+                match self.camera.mode() {
+                    CameraMode::Orbit => {
+                        self.camera.orbit_left_right(x_delta as f32 / 50.0);
+                        self.camera.orbit_up_down(y_delta as f32 / 50.0);
+                    }
+                    CameraMode::FreeFly | CameraMode::FirstPerson => {
+                        self.camera
+                            .look(x_delta as f32 / 200.0, -y_delta as f32 / 200.0);
+                    }
+                }
 
                 self.mouse.set_pos(*x, *y);
             }
@@ -109,13 +314,104 @@ impl State {
             Msg::UseRefraction(use_refraction) => {
                 self.water.use_refraction = *use_refraction;
             }
+            Msg::SetReflectionResolution(resolution) => {
+                self.water.reflection_resolution = *resolution;
+            }
+            Msg::SetRefractionResolution(resolution) => {
+                self.water.refraction_resolution = *resolution;
+            }
+            Msg::SetQuality(quality) => {
+                quality.apply(&mut self.water);
+            }
+            Msg::SetAdaptiveResolution(target_fps) => {
+                self.adaptive_resolution = target_fps.map(AdaptiveResolution::new);
+            }
             Msg::ShowScenery(show_scenery) => {
                 self.show_scenery = *show_scenery;
             }
+            Msg::AddWaterBody(water_body) => {
+                self.water_bodies.push(water_body.clone());
+            }
+            Msg::RemoveWaterBody(index) => {
+                if *index < self.water_bodies.len() {
+                    self.water_bodies.remove(*index);
+                }
+            }
+            Msg::LoadScene(scene) => {
+                self.water_bodies = scene.water_bodies();
+                self.scene_graph = SceneGraph::new(scene);
+                self.scene_graph.update(self.clock);
+            }
+            Msg::PlayAction {
+                entity,
+                action,
+                blend_secs,
+            } => {
+                for animation in self.scene_graph.animations_mut(Some(entity)) {
+                    animation.transition(action, *blend_secs);
+                }
+            }
+            Msg::PauseAnimation { entity, paused } => {
+                let entity = entity.as_ref().map(String::as_str);
+                for animation in self.scene_graph.animations_mut(entity) {
+                    animation.clock.paused = *paused;
+                }
+            }
+            Msg::SetAnimationRate { entity, rate } => {
+                let entity = entity.as_ref().map(String::as_str);
+                for animation in self.scene_graph.animations_mut(entity) {
+                    animation.clock.rate = *rate;
+                }
+            }
+            Msg::SeekAnimation { entity, time } => {
+                let entity = entity.as_ref().map(String::as_str);
+                for animation in self.scene_graph.animations_mut(entity) {
+                    animation.seek(*time);
+                }
+            }
+            Msg::SetAnimationOffset { entity, offset } => {
+                for animation in self.scene_graph.animations_mut(Some(entity)) {
+                    animation.clock.offset = *offset;
+                }
+            }
+            Msg::DisturbWater(x, z, strength) => {
+                self.disturb_water(*x, *z, *strength);
+            }
+            Msg::KeyDown(code) => {
+                self.keyboard.press(code);
+            }
+            Msg::KeyUp(code) => {
+                self.keyboard.release(code);
+            }
+            Msg::SetCameraMode(mode) => {
+                self.camera.set_mode(*mode);
+                self.move_camera(0.);
+            }
+            Msg::SetCameraConfig(config) => {
+                // An invalid config would give us a projection that panics or can't see anything
+                if config.validate().is_ok() {
+                    self.camera.set_config(config.clone());
+                }
+            }
+            Msg::Resize(width, height) => {
+                // Never let either side hit zero, ex: while the page is being laid out
+                let (width, height) = ((*width).max(1), (*height).max(1));
+
+                self.canvas_size = (width, height);
+                self.camera.set_aspect(width as f32 / height as f32);
+            }
         }
     }
 }
 
+/// Scale a (width, height), never going below one pixel
+fn scale_size((width, height): (i32, i32), scale: f32) -> (i32, i32) {
+    (
+        ((width as f32 * scale).round() as i32).max(1),
+        ((height as f32 * scale).round() as i32).max(1),
+    )
+}
+
 pub struct StateWrapper(State);
 
 impl Deref for StateWrapper {
@@ -143,5 +439,227 @@ pub enum Msg {
     SetWaveSpeed(f32),
     UseReflection(bool),
     UseRefraction(bool),
+    SetReflectionResolution(FramebufferResolution),
+    SetRefractionResolution(FramebufferResolution),
+    SetQuality(QualityPreset),
+    /// Scale our water rendering to hold this many frames per second, or None to always render at
+    /// full resolution
+    SetAdaptiveResolution(Option<f32>),
     ShowScenery(bool),
+    AddWaterBody(WaterBody),
+    /// Remove the water body at this index in `State.water_bodies`
+    RemoveWaterBody(usize),
+    /// Replace every entity and water body with the ones in this scene
+    LoadScene(Scene),
+    /// Crossfade an entity's armature from whatever it's playing into another action
+    PlayAction {
+        entity: String,
+        action: String,
+        blend_secs: f32,
+    },
+    /// Pause or resume an entity's animation. None pauses or resumes every animated entity.
+    PauseAnimation {
+        entity: Option<String>,
+        paused: bool,
+    },
+    /// Change how fast an entity's animation plays. None changes every animated entity.
+    SetAnimationRate {
+        entity: Option<String>,
+        rate: f32,
+    },
+    /// Jump an entity's animation to a time in seconds into the action that it's playing, before
+    /// its offset gets added. None jumps every animated entity.
+    SeekAnimation {
+        entity: Option<String>,
+        time: f32,
+    },
+    /// Shift an entity's animation by some number of seconds
+    SetAnimationOffset {
+        entity: String,
+        offset: f32,
+    },
+    /// Start a ripple at a world (x, z) with some strength
+    DisturbWater(f32, f32, f32),
+    /// A key was pressed. Holds the key's `KeyboardEvent.code`, such as "KeyW".
+    KeyDown(String),
+    KeyUp(String),
+    SetCameraMode(CameraMode),
+    SetCameraConfig(CameraConfig),
+    /// The canvas' drawing buffer is now (width, height) device pixels large
+    Resize(u32, u32),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{render_ready_bundle, AssetBundle, RENDER_READY_VERSION};
+    use blender_armature::BlenderArmature;
+    use blender_mesh::BlenderMesh;
+    use std::collections::HashMap;
+
+    /// A 2 x 2 x 2 box around its origin. Only its bounds matter, so it doesn't need any faces.
+    fn box_assets() -> Assets {
+        let mut meshes = HashMap::new();
+        meshes.insert(
+            "Box".to_string(),
+            BlenderMesh {
+                vertex_positions: vec![-1., -1., -1., 1., 1., 1.],
+                ..BlenderMesh::default()
+            },
+        );
+
+        let mut assets = Assets::default();
+        let bundle = render_ready_bundle(RENDER_READY_VERSION, &meshes);
+        assets.insert_bundle(AssetBundle::Meshes, &bundle).unwrap();
+        assets
+    }
+
+    /// A box placed by `entity` in a pond whose surface is at y = 0, after a few frames
+    fn box_in_pond(entity: &str) -> State {
+        let scene = format!(
+            r#"{{
+                "entities": [{}],
+                "waterBodies": [{{"position": [0, 0], "extents": [18, 18], "height": 0}}]
+            }}"#,
+            entity
+        );
+
+        let mut state = State::new();
+        state.msg(&Msg::LoadScene(serde_json::from_str(&scene).unwrap()));
+        for _ in 0..3 {
+            state.msg(&Msg::AdvanceClock(16.));
+        }
+
+        state
+    }
+
+    #[test]
+    fn still_meshes_at_the_waters_surface_do_not_ripple() {
+        let state = box_in_pond(r#"{"mesh": "Box"}"#);
+
+        assert_eq!(state.meshes_moving_through_water(&box_assets()), vec![]);
+    }
+
+    #[test]
+    fn meshes_moving_through_the_waters_surface_ripple() {
+        let state = box_in_pond(
+            r#"{"mesh": "Box", "path": {"points": [[-5, 0, 0], [5, 0, 0]], "speed": 1}}"#,
+        );
+
+        assert_eq!(state.meshes_moving_through_water(&box_assets()).len(), 1);
+    }
+
+    #[test]
+    fn meshes_moving_above_the_water_do_not_ripple() {
+        let state = box_in_pond(
+            r#"{"mesh": "Box", "path": {"points": [[-5, 5, 0], [5, 5, 0]], "speed": 1}}"#,
+        );
+
+        assert_eq!(state.meshes_moving_through_water(&box_assets()), vec![]);
+    }
+
+    /// A one bone armature whose "Short" action lasts 0.5 seconds and "Long" lasts 2 seconds
+    fn rig_assets() -> Assets {
+        let rig = r#"{
+            "joint_index": {"Root": 0},
+            "inverse_bind_poses": [{"Matrix": [1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1]}],
+            "actions": {
+                "Short": {
+                    "0.0": [{"Matrix": [1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1]}],
+                    "0.5": [{"Matrix": [1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1]}]
+                },
+                "Long": {
+                    "0.0": [{"Matrix": [1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1]}],
+                    "2.0": [{"Matrix": [1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1]}]
+                }
+            }
+        }"#;
+
+        let mut armatures: HashMap<String, BlenderArmature> = HashMap::new();
+        armatures.insert("Rig".to_string(), serde_json::from_str(rig).unwrap());
+
+        let mut assets = Assets::default();
+        let bundle = render_ready_bundle(RENDER_READY_VERSION, &armatures);
+        assets
+            .insert_bundle(AssetBundle::Armatures, &bundle)
+            .unwrap();
+        assets
+    }
+
+    #[test]
+    fn animation_duration_is_the_longest_current_action() {
+        let scene = r#"{
+            "entities": [
+                {"name": "A", "mesh": "Box", "armature": "Rig", "action": "Short"},
+                {"name": "B", "mesh": "Box", "armature": "Rig", "action": "Short"}
+            ]
+        }"#;
+
+        let mut state = State::new();
+        state.msg(&Msg::LoadScene(serde_json::from_str(scene).unwrap()));
+        assert_eq!(state.animation_duration(&rig_assets()), Some(0.5));
+
+        state.msg(&Msg::PlayAction {
+            entity: "B".to_string(),
+            action: "Long".to_string(),
+            blend_secs: 0.,
+        });
+        assert_eq!(state.animation_duration(&rig_assets()), Some(2.0));
+    }
+
+    #[test]
+    fn no_animation_duration_until_an_armature_arrives() {
+        let scene = r#"{"entities": [{"mesh": "Box", "armature": "Rig", "action": "Short"}]}"#;
+
+        let mut state = State::new();
+        state.msg(&Msg::LoadScene(serde_json::from_str(scene).unwrap()));
+
+        assert_eq!(state.animation_duration(&Assets::default()), None);
+    }
+
+    #[test]
+    fn ignores_invalid_camera_configs() {
+        let mut state = State::new();
+        let config = CameraConfig {
+            fovy: 1.,
+            ..CameraConfig::default()
+        };
+        state.msg(&Msg::SetCameraConfig(config.clone()));
+
+        state.msg(&Msg::SetCameraConfig(CameraConfig {
+            near: 10.,
+            far: 1.,
+            ..CameraConfig::default()
+        }));
+
+        assert_eq!(state.camera().config(), &config);
+    }
+
+    #[test]
+    fn the_camera_follows_the_canvas_shape() {
+        let mut state = State::new();
+
+        state.msg(&Msg::Resize(1600, 800));
+
+        assert_eq!(state.canvas_size(), (1600, 800));
+        assert_eq!(state.camera().config().aspect, 2.);
+
+        // The projection's x axis is scaled down to undo the canvas being stretched
+        let projection = state.camera().projection();
+        assert!((projection[5] / projection[0] - 2.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn canvas_sizes_are_at_least_one_pixel() {
+        let mut state = State::new();
+
+        state.msg(&Msg::Resize(0, 300));
+        assert_eq!(state.canvas_size(), (1, 300));
+        assert!((state.camera().config().aspect - 1. / 300.).abs() < 1e-7);
+
+        state.msg(&Msg::Resize(0, 0));
+        assert_eq!(state.canvas_size(), (1, 1));
+        assert_eq!(state.camera().config().aspect, 1.);
+        assert!(state.camera().projection().iter().all(|v| v.is_finite()));
+    }
 }
@@ -0,0 +1,353 @@
+use crate::app::store::{MotionPath, PathCurve, PathRepeat};
+use nalgebra::{UnitQuaternion, Vector3};
+
+/// How many straight pieces we split each span of a curve into when measuring its length
+const SAMPLES_PER_SPAN: usize = 16;
+
+/// A `MotionPath` that has been measured so that entities can travel along it at a constant
+/// speed.
+///
+/// Splines don't move at a constant speed as their parameter goes from 0 to 1, so we sample the
+/// curve and record how far along it we are at every sample. Then, to find where we are after
+/// travelling some distance, we look up the two samples around that distance and blend between
+/// their parameters.
+pub struct SplinePath {
+    points: Vec<Vector3<f32>>,
+    curve: PathCurve,
+    repeat: PathRepeat,
+    closed: bool,
+    speed: f32,
+    /// (parameter, distance along the path) at every sample, in order. A parameter of 1.5 is
+    /// halfway through the second span.
+    samples: Vec<(f32, f32)>,
+}
+
+/// Where an entity is along its path and which way it's heading
+pub struct PathSample {
+    pub position: Vector3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl PathSample {
+    /// Turns +z to face the way that we're heading. We don't turn at all if we're heading
+    /// straight up or down, since then there's no way to tell which way is up.
+    pub fn facing(&self) -> UnitQuaternion<f32> {
+        let direction = self.direction;
+
+        if direction.x.abs() < std::f32::EPSILON && direction.z.abs() < std::f32::EPSILON {
+            return UnitQuaternion::identity();
+        }
+
+        UnitQuaternion::face_towards(&direction, &Vector3::y())
+    }
+}
+
+impl SplinePath {
+    pub fn new(path: &MotionPath) -> SplinePath {
+        let points = path
+            .points
+            .iter()
+            .map(|(x, y, z)| Vector3::new(*x, *y, *z))
+            .collect();
+
+        let mut spline_path = SplinePath {
+            points,
+            curve: path.curve,
+            repeat: path.repeat,
+            closed: path.closed,
+            speed: path.speed,
+            samples: vec![],
+        };
+        spline_path.measure();
+
+        spline_path
+    }
+
+    fn measure(&mut self) {
+        let sample_count = self.span_count() * SAMPLES_PER_SPAN;
+
+        let mut distance = 0.;
+        let mut previous = self.point_at(0.);
+
+        self.samples.push((0., 0.));
+
+        for sample in 1..=sample_count {
+            let parameter = sample as f32 / SAMPLES_PER_SPAN as f32;
+            let point = self.point_at(parameter);
+
+            distance += (point - previous).norm();
+            previous = point;
+
+            self.samples.push((parameter, distance));
+        }
+    }
+
+    /// The length of the whole path
+    pub fn length(&self) -> f32 {
+        self.samples.last().map_or(0., |(_, distance)| *distance)
+    }
+
+    /// Where we are on the path after `clock` milliseconds
+    pub fn sample(&self, clock: f32) -> PathSample {
+        let length = self.length();
+
+        if length == 0. {
+            return PathSample {
+                position: self.points.first().cloned().unwrap_or_else(Vector3::zeros),
+                direction: Vector3::zeros(),
+            };
+        }
+
+        let traveled = (clock / 1000.0) * self.speed;
+
+        // Travelling backwards along the path means facing backwards too
+        let (distance, heading) = match self.repeat {
+            PathRepeat::Loop => (wrap(traveled, length), self.speed.signum()),
+            PathRepeat::PingPong => {
+                let distance = wrap(traveled, 2. * length);
+
+                if distance <= length {
+                    (distance, self.speed.signum())
+                } else {
+                    (2. * length - distance, -self.speed.signum())
+                }
+            }
+        };
+
+        let parameter = self.parameter_at_distance(distance);
+
+        PathSample {
+            position: self.point_at(parameter),
+            direction: self.tangent_at(parameter) * heading,
+        }
+    }
+
+    fn parameter_at_distance(&self, distance: f32) -> f32 {
+        // The first sample that is at least `distance` along the path
+        let after = match self
+            .samples
+            .binary_search_by(|(_, sample)| sample.partial_cmp(&distance).unwrap())
+        {
+            Ok(index) => return self.samples[index].0,
+            Err(index) => index.max(1).min(self.samples.len() - 1),
+        };
+
+        let (before_parameter, before_distance) = self.samples[after - 1];
+        let (after_parameter, after_distance) = self.samples[after];
+
+        // Repeated points leave samples with nothing between them
+        if after_distance <= before_distance {
+            return before_parameter;
+        }
+
+        let amount = (distance - before_distance) / (after_distance - before_distance);
+
+        before_parameter + (after_parameter - before_parameter) * amount
+    }
+
+    fn span_count(&self) -> usize {
+        match (self.points.len(), self.closed) {
+            (0, _) | (1, _) => 0,
+            (count, true) => count,
+            (count, false) => count - 1,
+        }
+    }
+
+    /// The four points around a span. Open paths repeat their end points.
+    fn span_points(&self, span: usize) -> [Vector3<f32>; 4] {
+        let count = self.points.len() as isize;
+        let point = |index: isize| {
+            let index = if self.closed {
+                (index % count + count) % count
+            } else {
+                index.max(0).min(count - 1)
+            };
+
+            self.points[index as usize]
+        };
+
+        let span = span as isize;
+        [
+            point(span - 1),
+            point(span),
+            point(span + 1),
+            point(span + 2),
+        ]
+    }
+
+    /// Split a parameter into the span that it's in and how far through that span it is
+    fn span_at(&self, parameter: f32) -> (usize, f32) {
+        let last_span = self.span_count().max(1) - 1;
+        let span = (parameter.floor().max(0.) as usize).min(last_span);
+
+        (span, parameter - span as f32)
+    }
+
+    fn point_at(&self, parameter: f32) -> Vector3<f32> {
+        if self.span_count() == 0 {
+            return self.points.first().cloned().unwrap_or_else(Vector3::zeros);
+        }
+
+        let (span, t) = self.span_at(parameter);
+        let [p0, p1, p2, p3] = self.span_points(span);
+
+        match self.curve {
+            PathCurve::Linear => p1 + (p2 - p1) * t,
+            PathCurve::CatmullRom => {
+                let (t2, t3) = (t * t, t * t * t);
+
+                (p1 * 2.
+                    + (p2 - p0) * t
+                    + (p0 * 2. - p1 * 5. + p2 * 4. - p3) * t2
+                    + (-p0 + p1 * 3. - p2 * 3. + p3) * t3)
+                    * 0.5
+            }
+        }
+    }
+
+    /// The direction that the path is heading in at a parameter
+    fn tangent_at(&self, parameter: f32) -> Vector3<f32> {
+        if self.span_count() == 0 {
+            return Vector3::zeros();
+        }
+
+        let (span, t) = self.span_at(parameter);
+        let [p0, p1, p2, p3] = self.span_points(span);
+
+        match self.curve {
+            PathCurve::Linear => p2 - p1,
+            PathCurve::CatmullRom => {
+                ((p2 - p0)
+                    + (p0 * 2. - p1 * 5. + p2 * 4. - p3) * (2. * t)
+                    + (-p0 + p1 * 3. - p2 * 3. + p3) * (3. * t * t))
+                    * 0.5
+            }
+        }
+    }
+}
+
+/// `value` wrapped into 0..max, even when it's negative
+fn wrap(value: f32, max: f32) -> f32 {
+    ((value % max) + max) % max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spline_path(path: &str) -> SplinePath {
+        SplinePath::new(&serde_json::from_str(path).unwrap())
+    }
+
+    /// Where we are every 10 milliseconds for `millis` milliseconds
+    fn positions(path: &SplinePath, millis: u32) -> Vec<Vector3<f32>> {
+        (0..=millis / 10)
+            .map(|step| path.sample(step as f32 * 10.).position)
+            .collect()
+    }
+
+    #[test]
+    fn catmull_rom_paths_are_travelled_at_a_constant_speed() {
+        // Unevenly spaced points, so the spline's parameter speeds up and slows down a lot
+        let path =
+            spline_path(r#"{"points": [[0, 0, 0], [1, 0, 0], [6, 0, 2], [7, 0, 9]], "speed": 2}"#);
+        let millis = (path.length() / 2. * 1000.) as u32;
+
+        // We blend linearly between our samples, so tight spans still wobble by a few percent.
+        // Without measuring, the short first span would be crossed several times faster than
+        // the long ones.
+        for step in positions(&path, millis).windows(2) {
+            let distance = (step[1] - step[0]).norm();
+            assert!((distance - 0.02).abs() < 0.003, "{}", distance);
+        }
+    }
+
+    #[test]
+    fn ping_pong_paths_turn_around_at_their_ends() {
+        let path = spline_path(
+            r#"{"points": [[0, 0, 0], [10, 0, 0]], "speed": 5, "curve": "linear", "repeat": "pingPong"}"#,
+        );
+
+        let expected = [
+            (1000., 5., 1.),
+            (2500., 7.5, -1.),
+            (3000., 5., -1.),
+            (4500., 2.5, 1.),
+        ];
+        for (clock, x, heading) in expected.iter() {
+            let sample = path.sample(*clock);
+
+            assert!((sample.position.x - x).abs() < 0.001, "{}", clock);
+            assert_eq!(sample.direction.x.signum(), *heading, "{}", clock);
+        }
+    }
+
+    #[test]
+    fn entities_face_the_way_that_they_move() {
+        for speed in [3., -3.].iter() {
+            for repeat in ["loop", "pingPong"].iter() {
+                let path = spline_path(&format!(
+                    r#"{{"points": [[0, 0, 0], [4, 0, 1], [8, 0, -1]], "speed": {}, "repeat": "{}"}}"#,
+                    speed, repeat
+                ));
+                let millis = (path.length() / 3. * 2000.) as u32;
+
+                for (step, position) in positions(&path, millis).windows(2).enumerate() {
+                    let moved = position[1] - position[0];
+
+                    // Ping pong paths cover less ground in the steps that they turn around in, and
+                    // loops jump back to the start
+                    if (moved.norm() - 0.03).abs() > 0.005 {
+                        continue;
+                    }
+
+                    let direction = path.sample(step as f32 * 10. + 5.).direction;
+                    assert!(direction.dot(&moved) > 0., "{} {} {}", speed, repeat, step);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn closed_loops_do_not_jump_at_the_seam() {
+        let path = spline_path(
+            r#"{"points": [[0, 0, 0], [4, 0, 0], [4, 0, 4], [0, 0, 4]], "speed": 4, "closed": true}"#,
+        );
+        let lap = path.length() / 4. * 1000.;
+
+        let before = path.sample(lap - 10.);
+        let seam = path.sample(lap);
+        let after = path.sample(lap + 10.);
+
+        assert!((seam.position - Vector3::zeros()).norm() < 0.001);
+        assert!((seam.position - before.position).norm() < 0.05);
+        assert!((after.position - seam.position).norm() < 0.05);
+        assert!(
+            before
+                .direction
+                .normalize()
+                .dot(&after.direction.normalize())
+                > 0.99
+        );
+    }
+
+    #[test]
+    fn paths_without_points_stay_at_the_origin() {
+        let path = spline_path(r#"{"points": [], "speed": 1}"#);
+        let sample = path.sample(1000.);
+
+        assert_eq!(path.length(), 0.);
+        assert_eq!(sample.position, Vector3::zeros());
+        assert_eq!(sample.direction, Vector3::zeros());
+    }
+
+    #[test]
+    fn paths_with_one_point_stay_on_it_without_turning() {
+        let path = spline_path(r#"{"points": [[1, 2, 3]], "speed": 1}"#);
+        let sample = path.sample(1000.);
+
+        assert_eq!(path.length(), 0.);
+        assert_eq!(sample.position, Vector3::new(1., 2., 3.));
+        assert_eq!(sample.facing(), UnitQuaternion::identity());
+    }
+}
@@ -0,0 +1,183 @@
+use crate::app::store::{FramebufferResolution, Water};
+
+/// Bundles of water rendering settings, from cheapest to best looking
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QualityPreset {
+    /// Small refractions on a coarse wave grid, without reflections, depth tint or specular
+    /// highlights
+    Low,
+    /// Small reflections and half resolution refractions with specular highlights, but without
+    /// depth tint
+    Medium,
+    /// What `Water::new` starts with
+    High,
+    /// Full resolution reflections and refractions with a dense wave grid
+    Ultra,
+}
+
+impl QualityPreset {
+    /// Overwrite every setting that this preset controls. Settings that the presets don't cover,
+    /// such as reflectivity, are left alone.
+    pub fn apply(&self, water: &mut Water) {
+        match self {
+            QualityPreset::Low => {
+                water.reflection_resolution = FramebufferResolution::Fixed(64, 64);
+                water.refraction_resolution = FramebufferResolution::CanvasScale(0.25);
+                water.use_reflection = false;
+                water.use_refraction = true;
+                water.grid_resolution = 16;
+                water.use_specular = false;
+                water.use_depth_tint = false;
+            }
+            QualityPreset::Medium => {
+                water.reflection_resolution = FramebufferResolution::CanvasScale(0.125);
+                water.refraction_resolution = FramebufferResolution::CanvasScale(0.5);
+                water.use_reflection = true;
+                water.use_refraction = true;
+                water.grid_resolution = 32;
+                water.use_specular = true;
+                water.use_depth_tint = false;
+            }
+            QualityPreset::High => {
+                water.reflection_resolution = FramebufferResolution::CanvasScale(0.25);
+                water.refraction_resolution = FramebufferResolution::CanvasScale(1.0);
+                water.use_reflection = true;
+                water.use_refraction = true;
+                water.grid_resolution = 64;
+                water.use_specular = true;
+                water.use_depth_tint = true;
+            }
+            QualityPreset::Ultra => {
+                water.reflection_resolution = FramebufferResolution::CanvasScale(1.0);
+                water.refraction_resolution = FramebufferResolution::CanvasScale(1.0);
+                water.use_reflection = true;
+                water.use_refraction = true;
+                water.grid_resolution = 128;
+                water.use_specular = true;
+                water.use_depth_tint = true;
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CANVAS: (u32, u32) = (800, 600);
+
+    /// The settings that a preset controls, with framebuffer sizes for an 800 x 600 canvas
+    #[derive(Debug, PartialEq)]
+    struct Settings {
+        reflection_size: (i32, i32),
+        refraction_size: (i32, i32),
+        use_reflection: bool,
+        use_refraction: bool,
+        grid_resolution: u16,
+        use_specular: bool,
+        use_depth_tint: bool,
+    }
+
+    fn settings(water: &Water) -> Settings {
+        Settings {
+            reflection_size: water.reflection_resolution.size(CANVAS),
+            refraction_size: water.refraction_resolution.size(CANVAS),
+            use_reflection: water.use_reflection,
+            use_refraction: water.use_refraction,
+            grid_resolution: water.grid_resolution,
+            use_specular: water.use_specular,
+            use_depth_tint: water.use_depth_tint,
+        }
+    }
+
+    #[test]
+    fn presets_control_framebuffer_sizes_and_features() {
+        let presets = [
+            (
+                QualityPreset::Low,
+                Settings {
+                    reflection_size: (64, 64),
+                    refraction_size: (200, 150),
+                    use_reflection: false,
+                    use_refraction: true,
+                    grid_resolution: 16,
+                    use_specular: false,
+                    use_depth_tint: false,
+                },
+            ),
+            (
+                QualityPreset::Medium,
+                Settings {
+                    reflection_size: (100, 75),
+                    refraction_size: (400, 300),
+                    use_reflection: true,
+                    use_refraction: true,
+                    grid_resolution: 32,
+                    use_specular: true,
+                    use_depth_tint: false,
+                },
+            ),
+            (
+                QualityPreset::High,
+                Settings {
+                    reflection_size: (200, 150),
+                    refraction_size: (800, 600),
+                    use_reflection: true,
+                    use_refraction: true,
+                    grid_resolution: 64,
+                    use_specular: true,
+                    use_depth_tint: true,
+                },
+            ),
+            (
+                QualityPreset::Ultra,
+                Settings {
+                    reflection_size: (800, 600),
+                    refraction_size: (800, 600),
+                    use_reflection: true,
+                    use_refraction: true,
+                    grid_resolution: 128,
+                    use_specular: true,
+                    use_depth_tint: true,
+                },
+            ),
+        ];
+
+        for (preset, expected) in presets.iter() {
+            // Start from a different preset so that every setting has to be overwritten
+            let mut water = Water::new();
+            match preset {
+                QualityPreset::Low => QualityPreset::Ultra.apply(&mut water),
+                _ => QualityPreset::Low.apply(&mut water),
+            }
+
+            preset.apply(&mut water);
+
+            assert_eq!(&settings(&water), expected, "{:?}", preset);
+        }
+    }
+
+    #[test]
+    fn high_is_what_the_water_starts_with() {
+        let mut water = Water::new();
+        let initial = settings(&water);
+
+        QualityPreset::Low.apply(&mut water);
+        QualityPreset::High.apply(&mut water);
+
+        assert_eq!(settings(&water), initial);
+    }
+
+    #[test]
+    fn presets_leave_other_settings_alone() {
+        let mut water = Water::new();
+        water.reflectivity = 0.9;
+        water.wave_speed = 0.2;
+
+        QualityPreset::Low.apply(&mut water);
+
+        assert_eq!(water.reflectivity, 0.9);
+        assert_eq!(water.wave_speed, 0.2);
+        assert_eq!(water.waves.len(), Water::new().waves.len());
+    }
+}
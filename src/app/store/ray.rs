@@ -0,0 +1,206 @@
+use crate::app::store::WaterBody;
+use nalgebra::{Point3, Vector3};
+
+/// A half-line through world space, such as the line under the mouse cursor.
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    /// Always normalized
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    /// The point `distance` units along the ray
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// How far along the ray it crosses the horizontal plane at `y`.
+    /// None if the ray is parallel to or pointing away from the plane.
+    pub fn distance_to_plane_y(&self, y: f32) -> Option<f32> {
+        if self.direction.y.abs() < std::f32::EPSILON {
+            return None;
+        }
+
+        let distance = (y - self.origin.y) / self.direction.y;
+
+        if distance < 0. {
+            None
+        } else {
+            Some(distance)
+        }
+    }
+
+    /// Where the ray hits a water body's resting surface, if it does.
+    /// The hit's normal points straight up, use `Water::normal_at` to account for waves.
+    pub fn intersect_water_body(&self, water_body: &WaterBody) -> Option<RayHit> {
+        let distance = self.distance_to_plane_y(water_body.height)?;
+        let point = self.at(distance);
+
+        if !water_body.contains(point.x, point.z) {
+            return None;
+        }
+
+        Some(RayHit {
+            distance,
+            point,
+            normal: Vector3::y(),
+        })
+    }
+
+    /// Where the ray hits the triangle (a, b, c), if it does. Triangles are hit from both sides and
+    /// the hit's normal faces back towards the ray's origin.
+    ///
+    /// @see https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+    pub fn intersect_triangle(
+        &self,
+        a: Point3<f32>,
+        b: Point3<f32>,
+        c: Point3<f32>,
+    ) -> Option<RayHit> {
+        let edge1 = b - a;
+        let edge2 = c - a;
+
+        let p = self.direction.cross(&edge2);
+        let determinant = edge1.dot(&p);
+
+        if determinant.abs() < std::f32::EPSILON {
+            return None;
+        }
+
+        let inverse_determinant = 1.0 / determinant;
+        let to_origin = self.origin - a;
+
+        let u = to_origin.dot(&p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = to_origin.cross(&edge1);
+        let v = self.direction.dot(&q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge2.dot(&q) * inverse_determinant;
+        if distance < 0.0 {
+            return None;
+        }
+
+        let mut normal = edge1.cross(&edge2).normalize();
+        if normal.dot(&self.direction) > 0.0 {
+            normal = -normal;
+        }
+
+        Some(RayHit {
+            distance,
+            point: self.at(distance),
+            normal,
+        })
+    }
+}
+
+/// Where a ray hit something
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+    /// How far along the ray the hit is
+    pub distance: f32,
+    pub point: Point3<f32>,
+    /// The surface normal at the hit
+    pub normal: Vector3<f32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    /// A triangle in the z = 0 plane around the origin
+    fn triangle() -> (Point3<f32>, Point3<f32>, Point3<f32>) {
+        (
+            Point3::new(-1., -1., 0.),
+            Point3::new(1., -1., 0.),
+            Point3::new(0., 1., 0.),
+        )
+    }
+
+    #[test]
+    fn hits_a_triangle_in_front_of_it() {
+        let (a, b, c) = triangle();
+        let ray = Ray::new(Point3::new(0., 0., 5.), Vector3::new(0., 0., -2.));
+
+        let hit = ray.intersect_triangle(a, b, c).unwrap();
+
+        assert_close(hit.distance, 5.);
+        assert_close((hit.point - Point3::origin()).norm(), 0.);
+        assert_eq!(hit.normal, Vector3::z());
+    }
+
+    #[test]
+    fn hits_the_back_of_a_triangle_with_a_normal_facing_the_ray() {
+        let (a, b, c) = triangle();
+        let ray = Ray::new(Point3::new(0.2, 0., -3.), Vector3::z());
+
+        let hit = ray.intersect_triangle(a, b, c).unwrap();
+
+        assert_close(hit.distance, 3.);
+        assert_close(hit.point.x, 0.2);
+        assert_eq!(hit.normal, -Vector3::z());
+    }
+
+    #[test]
+    fn misses_triangles_that_are_beside_behind_or_parallel_to_it() {
+        let (a, b, c) = triangle();
+
+        let beside = Ray::new(Point3::new(2., 2., 5.), -Vector3::z());
+        let behind = Ray::new(Point3::new(0., 0., 5.), Vector3::z());
+        let parallel = Ray::new(Point3::new(-5., 0., 0.), Vector3::x());
+
+        for ray in [beside, behind, parallel].iter() {
+            assert!(ray.intersect_triangle(a, b, c).is_none(), "{:?}", ray);
+        }
+    }
+
+    #[test]
+    fn hits_the_resting_surface_of_a_water_body() {
+        let water_body = WaterBody::new((2., 0.), (10., 10.), 1.);
+        let ray = Ray::new(Point3::new(4., 5., 0.), Vector3::new(0., -1., 1.));
+
+        let hit = ray.intersect_water_body(&water_body).unwrap();
+
+        // Four units down and four units along z
+        assert_close(hit.distance, 32.0f32.sqrt());
+        assert_close(hit.point.x, 4.);
+        assert_close(hit.point.y, 1.);
+        assert_close(hit.point.z, 4.);
+        assert_eq!(hit.normal, Vector3::y());
+    }
+
+    #[test]
+    fn misses_water_outside_of_the_body_or_away_from_the_surface() {
+        let water_body = WaterBody::new((2., 0.), (10., 10.), 1.);
+
+        let outside = Ray::new(Point3::new(20., 5., 0.), -Vector3::y());
+        let upwards = Ray::new(Point3::new(2., 5., 0.), Vector3::y());
+        let level = Ray::new(Point3::new(2., 5., 0.), Vector3::x());
+
+        for ray in [outside, upwards, level].iter() {
+            assert!(ray.intersect_water_body(&water_body).is_none(), "{:?}", ray);
+        }
+    }
+}
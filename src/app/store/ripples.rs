@@ -0,0 +1,226 @@
+/// The number of cells along each side of a water body's ripple simulation
+pub const RIPPLE_GRID_SIZE: usize = 128;
+
+/// We step at a fixed rate so that ripples travel at the same speed regardless of frame rate
+const RIPPLE_STEP_MS: f32 = 1000. / 60.;
+
+/// How much of a ripple's height survives each step. Lower values calm the water faster.
+const RIPPLE_DAMPING: f32 = 0.985;
+
+/// If we fall far behind (say the tab was in the background) we drop time instead of trying to
+/// catch up all at once.
+const MAX_STEPS_PER_ADVANCE: usize = 4;
+
+/// Scales ripple slopes before they get packed into bytes for the water shader
+const SLOPE_SCALE: f32 = 4.0;
+
+/// How many cells away from a disturbance the water gets pushed down
+const DISTURBANCE_RADIUS: i32 = 2;
+
+/// A heightfield that ripples spread across, simulated with the 2D wave equation.
+///
+/// Cells are laid out row by row. Columns run along the water's x axis and rows along its
+/// z axis, matching the water shader's texture coordinates.
+#[derive(Clone)]
+pub struct Ripples {
+    current: Vec<f32>,
+    previous: Vec<f32>,
+    unsimulated_ms: f32,
+}
+
+impl Ripples {
+    pub fn new() -> Ripples {
+        Ripples {
+            current: vec![0.; RIPPLE_GRID_SIZE * RIPPLE_GRID_SIZE],
+            previous: vec![0.; RIPPLE_GRID_SIZE * RIPPLE_GRID_SIZE],
+            unsimulated_ms: 0.,
+        }
+    }
+
+    /// Push the water down around (u, v), where u and v go from 0.0 to 1.0 across the water.
+    pub fn disturb(&mut self, u: f32, v: f32, strength: f32) {
+        let size = RIPPLE_GRID_SIZE as i32;
+        let center_col = (u * size as f32) as i32;
+        let center_row = (v * size as f32) as i32;
+
+        for row in center_row - DISTURBANCE_RADIUS..=center_row + DISTURBANCE_RADIUS {
+            for col in center_col - DISTURBANCE_RADIUS..=center_col + DISTURBANCE_RADIUS {
+                // The outermost cells never move, see `step`
+                if row < 1 || row >= size - 1 || col < 1 || col >= size - 1 {
+                    continue;
+                }
+
+                let distance =
+                    (((row - center_row).pow(2) + (col - center_col).pow(2)) as f32).sqrt();
+                let falloff = (1.0 - distance / (DISTURBANCE_RADIUS as f32 + 1.0)).max(0.0);
+
+                self.current[row as usize * RIPPLE_GRID_SIZE + col as usize] -= strength * falloff;
+            }
+        }
+    }
+
+    /// Advance the simulation by `dt` milliseconds
+    pub fn advance(&mut self, dt: f32) {
+        self.unsimulated_ms += dt;
+
+        let mut steps = 0;
+        while self.unsimulated_ms >= RIPPLE_STEP_MS {
+            self.unsimulated_ms -= RIPPLE_STEP_MS;
+
+            if steps < MAX_STEPS_PER_ADVANCE {
+                self.step();
+                steps += 1;
+            }
+        }
+    }
+
+    /// The slope of the water at every cell, packed into RGBA bytes so that it can be uploaded
+    /// as a texture. Red is the slope along x and green is the slope along z, with 128 being flat.
+    pub fn slopes_rgba(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(RIPPLE_GRID_SIZE * RIPPLE_GRID_SIZE * 4);
+
+        for row in 0..RIPPLE_GRID_SIZE {
+            for col in 0..RIPPLE_GRID_SIZE {
+                let slope_x = self.height(col + 1, row) - self.height(col.saturating_sub(1), row);
+                let slope_z = self.height(col, row + 1) - self.height(col, row.saturating_sub(1));
+
+                pixels.push(pack_slope(slope_x));
+                pixels.push(pack_slope(slope_z));
+                pixels.push(0);
+                pixels.push(255);
+            }
+        }
+
+        pixels
+    }
+
+    /// One step of the discrete 2D wave equation. Each cell accelerates towards the average of its
+    /// neighbors. Edge cells are held at zero so that ripples fade out at the shore.
+    fn step(&mut self) {
+        let size = RIPPLE_GRID_SIZE;
+
+        for row in 1..size - 1 {
+            for col in 1..size - 1 {
+                let idx = row * size + col;
+
+                let neighbors = self.current[idx - 1]
+                    + self.current[idx + 1]
+                    + self.current[idx - size]
+                    + self.current[idx + size];
+
+                self.previous[idx] = (neighbors / 2.0 - self.previous[idx]) * RIPPLE_DAMPING;
+            }
+        }
+
+        std::mem::swap(&mut self.current, &mut self.previous);
+    }
+
+    fn height(&self, col: usize, row: usize) -> f32 {
+        let col = col.min(RIPPLE_GRID_SIZE - 1);
+        let row = row.min(RIPPLE_GRID_SIZE - 1);
+
+        self.current[row * RIPPLE_GRID_SIZE + col]
+    }
+}
+
+/// -1.0..1.0 -> 0..255
+fn pack_slope(slope: f32) -> u8 {
+    let slope = (slope * SLOPE_SCALE).max(-1.0).min(1.0);
+
+    ((slope * 0.5 + 0.5) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CENTER: usize = RIPPLE_GRID_SIZE / 2;
+
+    fn advance_steps(ripples: &mut Ripples, steps: usize) {
+        for _ in 0..steps {
+            ripples.advance(RIPPLE_STEP_MS);
+        }
+    }
+
+    /// The tallest crest or deepest trough anywhere on the water
+    fn max_height(ripples: &Ripples) -> f32 {
+        ripples.current.iter().fold(0., |max, h| h.abs().max(max))
+    }
+
+    /// Every cell along the four sides of the grid
+    fn edge_heights(ripples: &Ripples) -> Vec<f32> {
+        let last = RIPPLE_GRID_SIZE - 1;
+
+        (0..RIPPLE_GRID_SIZE)
+            .flat_map(|i| vec![(i, 0), (i, last), (0, i), (last, i)])
+            .map(|(col, row)| ripples.height(col, row))
+            .collect()
+    }
+
+    #[test]
+    fn disturbances_push_the_water_down() {
+        let mut ripples = Ripples::new();
+
+        ripples.disturb(0.5, 0.5, 1.);
+
+        assert_eq!(ripples.height(CENTER, CENTER), -1.);
+        assert!(ripples.height(CENTER + 1, CENTER) < 0.);
+        assert_eq!(ripples.height(CENTER + 3, CENTER), 0.);
+    }
+
+    #[test]
+    fn disturbances_spread_outwards() {
+        let mut ripples = Ripples::new();
+        ripples.disturb(0.5, 0.5, 1.);
+
+        advance_steps(&mut ripples, 10);
+
+        // The wave front moves one cell per step in every direction
+        for (col, row) in [(CENTER + 8, CENTER), (CENTER, CENTER - 8)].iter() {
+            assert_ne!(ripples.height(*col, *row), 0.);
+        }
+        assert_eq!(ripples.height(CENTER + 20, CENTER), 0.);
+    }
+
+    #[test]
+    fn ripples_are_damped_until_the_water_is_at_rest() {
+        let mut ripples = Ripples::new();
+        ripples.disturb(0.5, 0.5, 1.);
+
+        advance_steps(&mut ripples, 100);
+        let settling = max_height(&ripples);
+        advance_steps(&mut ripples, 1500);
+
+        assert!(settling > 0.01);
+        assert!(max_height(&ripples) < 1e-5);
+    }
+
+    #[test]
+    fn drops_time_instead_of_catching_up_all_at_once() {
+        let mut stalled = Ripples::new();
+        stalled.disturb(0.5, 0.5, 1.);
+        let mut stepped = stalled.clone();
+
+        stalled.advance(RIPPLE_STEP_MS * 100.);
+        advance_steps(&mut stepped, MAX_STEPS_PER_ADVANCE);
+
+        assert_eq!(stalled.current, stepped.current);
+    }
+
+    #[test]
+    fn edges_are_held_flat() {
+        let mut ripples = Ripples::new();
+
+        // Disturbances that hang off of the edges only push down the cells inside of the water
+        for (u, v) in [(0., 0.), (1., 1.), (0., 0.5), (0.5, 1.)].iter() {
+            ripples.disturb(*u, *v, 1.);
+        }
+        assert!(edge_heights(&ripples).iter().all(|h| *h == 0.));
+        assert!(ripples.height(1, 1) < 0.);
+
+        for _ in 0..50 {
+            advance_steps(&mut ripples, 1);
+            assert!(edge_heights(&ripples).iter().all(|h| *h == 0.));
+        }
+    }
+}
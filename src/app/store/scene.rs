@@ -0,0 +1,348 @@
+use crate::app::store::{PathSample, WaterBody};
+use crate::app::Assets;
+use nalgebra::{Matrix4, UnitQuaternion, Vector3};
+use serde::Deserialize;
+
+/// The scene that we start with. Artists can lay out a new scene by editing this file, or
+/// swap scenes at runtime with `WebClient::load_scene`.
+static DEFAULT_SCENE: &str = include_str!("../../../scene.json");
+
+/// Everything that gets placed in the world, usually deserialized from JSON such as
+///
+/// ```json
+/// {
+///   "entities": [
+///     {"mesh": "Terrain"},
+///     {
+///       "name": "Bird",
+///       "mesh": "Bird",
+///       "armature": "Armature.001",
+///       "action": "Fly.001",
+///       "transform": {"translation": [0, 6, 0], "scale": [1.5, 1.5, 1.5]},
+///       "path": {"points": [[0, 0, -30], [8, 0, 0], [0, 0, 30]], "speed": 3.5},
+///       "children": [
+///         {"mesh": "Rock", "transform": {"translation": [0, -1, 0], "rotation": [0, 45, 0]}}
+///       ]
+///     }
+///   ],
+///   "waterBodies": [{"position": [0, 0], "extents": [18, 18], "height": 0}]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Scene {
+    #[serde(default)]
+    pub entities: Vec<Entity>,
+    #[serde(default)]
+    pub water_bodies: Vec<SceneWaterBody>,
+}
+
+/// A mesh placed somewhere in the scene
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entity {
+    /// What picking reports when this entity gets clicked. Defaults to the mesh's name.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The name of the mesh in `Assets`
+    pub mesh: String,
+    /// The name of the armature in `Assets` that animates this mesh. Needs an `action`.
+    #[serde(default)]
+    pub armature: Option<String>,
+    /// The armature action to loop, such as "Fly.001"
+    #[serde(default)]
+    pub action: Option<String>,
+    /// Seconds to start into the animation, so that a flock doesn't flap in lockstep
+    #[serde(default)]
+    pub animation_offset: f32,
+    /// How fast the animation plays, where 1.0 is the speed that it was made at
+    #[serde(default = "default_animation_rate")]
+    pub animation_rate: f32,
+    #[serde(default)]
+    pub transform: Transform,
+    #[serde(default)]
+    pub texture: MeshTexture,
+    /// Moves the entity along a route, relative to its transform
+    #[serde(default)]
+    pub path: Option<MotionPath>,
+    /// Entities whose transforms are relative to this one, so they move, turn and scale with it
+    #[serde(default)]
+    pub children: Vec<Entity>,
+}
+
+fn default_animation_rate() -> f32 {
+    1.0
+}
+
+/// Where an entity sits relative to its parent, or to the world if it doesn't have one.
+///
+/// Scale is applied first, then rotation, then translation.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+    pub translation: (f32, f32, f32),
+    /// Degrees around the x, y and z axis, applied in that order
+    pub rotation: (f32, f32, f32),
+    pub scale: (f32, f32, f32),
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            translation: (0., 0., 0.),
+            rotation: (0., 0., 0.),
+            scale: (1., 1., 1.),
+        }
+    }
+}
+
+/// The textures that a mesh can be drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum MeshTexture {
+    Stone,
+}
+
+impl Default for MeshTexture {
+    fn default() -> MeshTexture {
+        MeshTexture::Stone
+    }
+}
+
+/// A route through `points` that an entity travels along at `speed` units per second.
+/// Entities on a path turn so that their +z axis faces the way that they're travelling.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MotionPath {
+    pub points: Vec<(f32, f32, f32)>,
+    pub speed: f32,
+    #[serde(default)]
+    pub curve: PathCurve,
+    #[serde(default)]
+    pub repeat: PathRepeat,
+    /// Connect the last point back to the first, so that looping doesn't jump back to the start
+    #[serde(default)]
+    pub closed: bool,
+}
+
+/// How a `MotionPath` gets from one point to the next
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PathCurve {
+    /// Straight lines with sharp corners at every point
+    Linear,
+    /// A smooth curve that passes through every point
+    CatmullRom,
+}
+
+impl Default for PathCurve {
+    fn default() -> PathCurve {
+        PathCurve::CatmullRom
+    }
+}
+
+/// What happens when we reach the end of a `MotionPath`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PathRepeat {
+    /// Start over from the first point
+    Loop,
+    /// Turn around and head back to the first point
+    PingPong,
+}
+
+impl Default for PathRepeat {
+    fn default() -> PathRepeat {
+        PathRepeat::Loop
+    }
+}
+
+/// A body of water in a scene file. See `WaterBody::new`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SceneWaterBody {
+    pub position: (f32, f32),
+    pub extents: (f32, f32),
+    pub height: f32,
+}
+
+impl Scene {
+    /// Make sure that every mesh, armature and action that the scene uses exists, so that a typo
+    /// in a scene file shows up as an error instead of an entity that silently never renders.
+    ///
+    /// While asset bundles are still downloading a missing mesh or armature might just not have
+    /// arrived yet, so we only check the assets that we already have.
+    pub fn validate(&self, assets: &Assets) -> Result<(), String> {
+        for entity in self.entities.iter() {
+            entity.validate(assets)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn water_bodies(&self) -> Vec<WaterBody> {
+        self.water_bodies
+            .iter()
+            .map(|water| WaterBody::new(water.position, water.extents, water.height))
+            .collect()
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        // Checked by `default_scene_parses_and_validates`
+        serde_json::from_str(DEFAULT_SCENE).expect("Default scene")
+    }
+}
+
+impl Entity {
+    pub fn name(&self) -> &str {
+        self.name.as_ref().unwrap_or(&self.mesh)
+    }
+
+    /// Where the entity is relative to its parent, given where it is along its path
+    pub fn local_transform(&self, path_sample: Option<&PathSample>) -> Matrix4<f32> {
+        let (x, y, z) = self.transform.translation;
+        let (rx, ry, rz) = self.transform.rotation;
+        let (sx, sy, sz) = self.transform.scale;
+
+        let mut translation = Vector3::new(x, y, z);
+        let mut rotation =
+            UnitQuaternion::from_euler_angles(rx.to_radians(), ry.to_radians(), rz.to_radians());
+
+        if let Some(path_sample) = path_sample {
+            translation += path_sample.position;
+            rotation = path_sample.facing() * rotation;
+        }
+
+        Matrix4::new_translation(&translation)
+            * rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&Vector3::new(sx, sy, sz))
+    }
+
+    fn validate(&self, assets: &Assets) -> Result<(), String> {
+        let downloading = assets.is_downloading();
+
+        if assets.get_mesh(&self.mesh).is_none() && !downloading {
+            return Err(format!(
+                "Entity {} uses missing mesh {}",
+                self.name(),
+                self.mesh
+            ));
+        }
+
+        match (self.armature.as_ref(), self.action.as_ref()) {
+            (Some(armature_name), Some(action)) => match assets.get_armature(armature_name) {
+                Some(armature) if !armature.actions.contains_key(action) => {
+                    return Err(format!(
+                        "Armature {} has no action {}",
+                        armature_name, action
+                    ));
+                }
+                None if !downloading => {
+                    return Err(format!(
+                        "Entity {} uses missing armature {}",
+                        self.name(),
+                        armature_name
+                    ));
+                }
+                _ => {}
+            },
+            (None, None) => {}
+            _ => {
+                return Err(format!(
+                    "Entity {} needs both an armature and an action to be animated",
+                    self.name()
+                ));
+            }
+        }
+
+        for child in self.children.iter() {
+            child.validate(assets)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::AssetBundle;
+
+    /// The meshes and armatures that get downloaded in the browser
+    fn checked_in_assets() -> Assets {
+        let mut assets = Assets::default();
+        assets
+            .insert_bundle(AssetBundle::Meshes, include_bytes!("../../../meshes.bytes"))
+            .unwrap();
+        assets
+            .insert_bundle(
+                AssetBundle::Armatures,
+                include_bytes!("../../../armatures.bytes"),
+            )
+            .unwrap();
+        assets
+    }
+
+    fn scene(entities: &str) -> Scene {
+        serde_json::from_str(&format!(r#"{{"entities": {}}}"#, entities)).unwrap()
+    }
+
+    #[test]
+    fn default_scene_parses_and_validates() {
+        let scene = Scene::default();
+
+        assert_eq!(scene.entities.len(), 2);
+        assert_eq!(scene.water_bodies.len(), 1);
+        assert_eq!(scene.validate(&checked_in_assets()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_scenes_with_bad_references() {
+        let assets = checked_in_assets();
+
+        let bad_scenes = [
+            (
+                r#"[{"mesh": "Boulder"}]"#,
+                "Entity Boulder uses missing mesh Boulder",
+            ),
+            (
+                r#"[{"name": "Gull", "mesh": "Bird", "armature": "Wings", "action": "Fly.001"}]"#,
+                "Entity Gull uses missing armature Wings",
+            ),
+            (
+                r#"[{"mesh": "Bird", "armature": "Armature.001", "action": "Dive"}]"#,
+                "Armature Armature.001 has no action Dive",
+            ),
+            (
+                r#"[{"mesh": "Bird", "armature": "Armature.001"}]"#,
+                "Entity Bird needs both an armature and an action to be animated",
+            ),
+            (
+                r#"[{"mesh": "Terrain", "children": [{"mesh": "Boulder"}]}]"#,
+                "Entity Boulder uses missing mesh Boulder",
+            ),
+        ];
+
+        for (entities, err) in bad_scenes.iter() {
+            assert_eq!(
+                scene(entities).validate(&assets),
+                Err(err.to_string()),
+                "{}",
+                entities
+            );
+        }
+    }
+
+    #[test]
+    fn allows_assets_that_are_still_downloading() {
+        let mut assets = checked_in_assets();
+        assets.start_download("/more-meshes.bytes");
+
+        let downloading = r#"[{"mesh": "Boulder", "armature": "Wings", "action": "Dive"}]"#;
+        assert_eq!(scene(downloading).validate(&assets), Ok(()));
+
+        // Armatures that have already arrived can still be checked
+        let missing_action = r#"[{"mesh": "Bird", "armature": "Armature.001", "action": "Dive"}]"#;
+        assert!(scene(missing_action).validate(&assets).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::app::store::{AnimationClock, AnimationController, Entity, Scene, SplinePath};
+use nalgebra::Matrix4;
+
+/// Every entity in a scene, flattened so that parents always come before their children.
+///
+/// Each frame we walk the nodes in order and multiply every node's local transform by its
+/// parent's world matrix, which has already been computed by the time we reach the child.
+pub struct SceneGraph {
+    nodes: Vec<SceneNode>,
+    /// Whether or not `update` has been called yet
+    updated: bool,
+}
+
+pub struct SceneNode {
+    /// The entity without its children, which have their own nodes
+    entity: Entity,
+    /// The index of our parent in `SceneGraph.nodes`
+    parent: Option<usize>,
+    /// The entity's path, measured so that we can move along it at a constant speed
+    path: Option<SplinePath>,
+    /// Which of the armature's actions are playing. None if the entity isn't animated.
+    animation: Option<AnimationController>,
+    world_transform: Matrix4<f32>,
+    /// The world matrix from before the last update, so that we can tell whether we're moving
+    previous_world_transform: Matrix4<f32>,
+}
+
+impl SceneGraph {
+    pub fn new(scene: &Scene) -> SceneGraph {
+        let mut nodes = vec![];
+
+        for entity in scene.entities.iter() {
+            SceneGraph::add_node(&mut nodes, entity, None);
+        }
+
+        SceneGraph {
+            nodes,
+            updated: false,
+        }
+    }
+
+    fn add_node(nodes: &mut Vec<SceneNode>, entity: &Entity, parent: Option<usize>) {
+        let index = nodes.len();
+
+        nodes.push(SceneNode {
+            entity: Entity {
+                children: vec![],
+                ..entity.clone()
+            },
+            parent,
+            path: entity.path.as_ref().map(SplinePath::new),
+            animation: entity.action.as_ref().map(|action| {
+                let clock = AnimationClock::new(entity.animation_offset, entity.animation_rate);
+                AnimationController::new(action, clock)
+            }),
+            world_transform: Matrix4::identity(),
+            previous_world_transform: Matrix4::identity(),
+        });
+
+        for child in entity.children.iter() {
+            SceneGraph::add_node(nodes, child, Some(index));
+        }
+    }
+
+    /// Re-compute every node's world matrix for `clock` milliseconds
+    pub fn update(&mut self, clock: f32) {
+        for index in 0..self.nodes.len() {
+            let node = &self.nodes[index];
+
+            let path_sample = node.path.as_ref().map(|path| path.sample(clock));
+            let local_transform = node.entity.local_transform(path_sample.as_ref());
+
+            let world_transform = match node.parent {
+                Some(parent) => self.nodes[parent].world_transform * local_transform,
+                None => local_transform,
+            };
+
+            let node = &mut self.nodes[index];
+            // Nothing was moving before our first update
+            node.previous_world_transform = if self.updated {
+                node.world_transform
+            } else {
+                world_transform
+            };
+            node.world_transform = world_transform;
+        }
+
+        self.updated = true;
+    }
+
+    pub fn nodes(&self) -> &[SceneNode] {
+        &self.nodes
+    }
+
+    /// Move every animation clock forwards by `dt` milliseconds
+    pub fn advance_animations(&mut self, dt: f32) {
+        for animation in self.animations_mut(None) {
+            animation.advance(dt);
+        }
+    }
+
+    /// The animations of every entity named `entity_name`, or of every animated entity if None
+    pub fn animations_mut<'a>(
+        &'a mut self,
+        entity_name: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a mut AnimationController> + 'a {
+        self.nodes
+            .iter_mut()
+            .filter(move |node| entity_name.map_or(true, |name| node.entity.name() == name))
+            .filter_map(|node| node.animation.as_mut())
+    }
+}
+
+impl SceneNode {
+    pub fn entity(&self) -> &Entity {
+        &self.entity
+    }
+
+    pub fn animation(&self) -> Option<&AnimationController> {
+        self.animation.as_ref()
+    }
+
+    /// Transforms the entity's mesh from model space into world space
+    pub fn world_transform(&self) -> &Matrix4<f32> {
+        &self.world_transform
+    }
+
+    /// Whether or not the entity moved, turned or changed size in the last update
+    pub fn is_moving(&self) -> bool {
+        self.world_transform != self.previous_world_transform
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+
+    /// A boat that is moved, turned a quarter turn to its left and doubled in size, with a
+    /// lantern hanging off of its side
+    const BOAT: &str = r#"{
+        "entities": [{
+            "mesh": "Boat",
+            "transform": {"translation": [10, 0, 0], "rotation": [0, 90, 0], "scale": [2, 2, 2]},
+            "children": [{
+                "mesh": "Lantern",
+                "transform": {"translation": [1, 0, 0], "scale": [0.5, 0.5, 0.5]}
+            }]
+        }]
+    }"#;
+
+    /// The lantern from `BOAT`, hung from a dock instead
+    const DOCK: &str = r#"{
+        "entities": [{
+            "mesh": "Dock",
+            "transform": {"translation": [0, 5, 0]},
+            "children": [{
+                "mesh": "Lantern",
+                "transform": {"translation": [1, 0, 0], "scale": [0.5, 0.5, 0.5]}
+            }]
+        }]
+    }"#;
+
+    fn scene_graph(scene: &str) -> SceneGraph {
+        let mut scene_graph = SceneGraph::new(&serde_json::from_str(scene).unwrap());
+        scene_graph.update(0.);
+        scene_graph
+    }
+
+    /// Where a point in an entity's model space ends up in the world
+    fn world_point(scene_graph: &SceneGraph, mesh: &str, point: (f32, f32, f32)) -> Point3<f32> {
+        let node = scene_graph
+            .nodes()
+            .iter()
+            .find(|node| node.entity().mesh == mesh)
+            .unwrap();
+
+        node.world_transform()
+            .transform_point(&Point3::new(point.0, point.1, point.2))
+    }
+
+    fn assert_close(actual: Point3<f32>, expected: (f32, f32, f32)) {
+        let expected = Point3::new(expected.0, expected.1, expected.2);
+        assert!(
+            (actual - expected).norm() < 1e-4,
+            "{} should be {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn parents_come_before_their_children() {
+        let scene_graph = scene_graph(BOAT);
+
+        let nodes = scene_graph.nodes();
+        assert_eq!(nodes[0].entity().mesh, "Boat");
+        assert_eq!(nodes[1].entity().mesh, "Lantern");
+        assert!(nodes[0].entity().children.is_empty());
+    }
+
+    #[test]
+    fn scales_then_rotates_then_translates_root_nodes() {
+        let scene_graph = scene_graph(BOAT);
+
+        assert_close(
+            world_point(&scene_graph, "Boat", (0., 0., 0.)),
+            (10., 0., 0.),
+        );
+        assert_close(
+            world_point(&scene_graph, "Boat", (1., 0., 0.)),
+            (10., 0., -2.),
+        );
+        assert_close(
+            world_point(&scene_graph, "Boat", (0., 1., 0.)),
+            (10., 2., 0.),
+        );
+    }
+
+    #[test]
+    fn children_move_turn_and_scale_with_their_parent() {
+        let scene_graph = scene_graph(BOAT);
+
+        // The lantern's offset is doubled and turned along with the boat
+        assert_close(
+            world_point(&scene_graph, "Lantern", (0., 0., 0.)),
+            (10., 0., -2.),
+        );
+        // Half of the boat's doubling is undone by the lantern's own scale
+        assert_close(
+            world_point(&scene_graph, "Lantern", (1., 0., 0.)),
+            (10., 0., -3.),
+        );
+    }
+
+    #[test]
+    fn reparented_children_follow_their_new_parent() {
+        let on_boat = scene_graph(BOAT);
+        let on_dock = scene_graph(DOCK);
+
+        assert_close(
+            world_point(&on_boat, "Lantern", (0., 0., 0.)),
+            (10., 0., -2.),
+        );
+        assert_close(world_point(&on_dock, "Lantern", (0., 0., 0.)), (1., 5., 0.));
+        assert_close(
+            world_point(&on_dock, "Lantern", (1., 0., 0.)),
+            (1.5, 5., 0.),
+        );
+    }
+}
@@ -1,9 +1,41 @@
+use nalgebra::Vector3;
+use std::f32::consts::PI;
+
+/// The most waves that we can sum together. Must match MAX_WAVES in water-vertex.glsl
+pub const MAX_WAVES: usize = 4;
+
+/// Must match GRAVITY in water-vertex.glsl
+const GRAVITY: f32 = 9.8;
+
+/// Gerstner waves push the surface sideways, so the point of the surface that ends up above an
+/// (x, z) started somewhere else. This many refinement steps gets us well under a millimeter off.
+const SURFACE_SEARCH_ITERATIONS: usize = 4;
+
+/// Waves shorter than this get lengthened, since a wave's frequency is divided by its wavelength
+const MIN_WAVELENGTH: f32 = 0.01;
+
 pub struct Water {
     pub reflectivity: f32,
     pub fresnel_strength: f32,
     pub wave_speed: f32,
     pub use_reflection: bool,
     pub use_refraction: bool,
+    /// How detailed our reflections are. They can be smaller than the canvas since they get
+    /// distorted by the waves.
+    pub reflection_resolution: FramebufferResolution,
+    /// How detailed our refractions are. Due to the fresnel effect the water becomes very
+    /// transparent when you look down at it, so we want these large enough for the things below
+    /// the water to be seen clearly.
+    pub refraction_resolution: FramebufferResolution,
+    /// The number of quads along each side of the water's grid. More quads gives smoother
+    /// waves. Clamped to the renderer's `MAX_WATER_GRID_RESOLUTION`.
+    pub grid_resolution: u16,
+    /// Whether or not the sun makes highlights on the water
+    pub use_specular: bool,
+    /// Whether or not deeper water gets tinted darker
+    pub use_depth_tint: bool,
+    /// The Gerstner waves that displace the water's surface. Anything past `MAX_WAVES` is ignored.
+    pub waves: Vec<Wave>,
 }
 
 impl Water {
@@ -14,6 +46,230 @@ impl Water {
             wave_speed: 0.06,
             use_reflection: true,
             use_refraction: true,
+            reflection_resolution: FramebufferResolution::CanvasScale(0.25),
+            refraction_resolution: FramebufferResolution::CanvasScale(1.0),
+            grid_resolution: 64,
+            use_specular: true,
+            use_depth_tint: true,
+            waves: vec![
+                Wave::new(0.08, 6.0, (1.0, 0.3), 0.5),
+                Wave::new(0.05, 3.1, (-0.4, 1.0), 0.4),
+                Wave::new(0.03, 1.7, (0.7, -0.8), 0.3),
+            ],
+        }
+    }
+
+    /// The height of the water's surface above (or below) its resting height at a world (x, z).
+    /// `clock` is in milliseconds, like `State::clock`.
+    ///
+    /// This evaluates the same waves as water-vertex.glsl so that anything floating on the water
+    /// lines up with what gets rendered. Add a `WaterBody`'s height to get a world y coordinate.
+    pub fn height_at(&self, x: f32, z: f32, clock: f32) -> f32 {
+        let (rest_x, rest_z) = self.resting_position_under(x, z, clock);
+
+        self.displace(rest_x, rest_z, clock).0.y
+    }
+
+    /// The unit normal of the water's surface at a world (x, z).
+    /// `clock` is in milliseconds, like `State::clock`.
+    pub fn normal_at(&self, x: f32, z: f32, clock: f32) -> Vector3<f32> {
+        let (rest_x, rest_z) = self.resting_position_under(x, z, clock);
+
+        self.displace(rest_x, rest_z, clock).1
+    }
+
+    /// Find the point on the resting water surface that our waves carry over to (x, z)
+    fn resting_position_under(&self, x: f32, z: f32, clock: f32) -> (f32, f32) {
+        let (mut rest_x, mut rest_z) = (x, z);
+
+        for _ in 0..SURFACE_SEARCH_ITERATIONS {
+            let (displacement, _) = self.displace(rest_x, rest_z, clock);
+            rest_x = x - displacement.x;
+            rest_z = z - displacement.z;
+        }
+
+        (rest_x, rest_z)
+    }
+
+    /// Sum our Gerstner waves at a point on the resting water surface, returning how far that
+    /// point gets displaced along with the surface normal there.
+    ///
+    /// A line by line port of the wave loop in water-vertex.glsl
+    fn displace(&self, rest_x: f32, rest_z: f32, clock: f32) -> (Vector3<f32>, Vector3<f32>) {
+        let seconds = clock / 1000.;
+        let wave_count = self.waves.len().min(MAX_WAVES);
+
+        let mut displacement = Vector3::new(0., 0., 0.);
+        let mut normal = Vector3::new(0., 1., 0.);
+
+        for wave in self.waves.iter().take(wave_count) {
+            let (dir_x, dir_z) = wave.normalized_direction();
+
+            let frequency = 2.0 * PI / wave.wavelength;
+            let phase_speed = (GRAVITY / frequency).sqrt();
+            let phase = frequency * (dir_x * rest_x + dir_z * rest_z - phase_speed * seconds);
+
+            let steepness_amplitude = wave.steepness / (frequency * wave_count as f32);
+
+            displacement.x += steepness_amplitude * dir_x * phase.cos();
+            displacement.y += wave.amplitude * phase.sin();
+            displacement.z += steepness_amplitude * dir_z * phase.cos();
+
+            let frequency_amplitude = frequency * wave.amplitude;
+            normal.x -= dir_x * frequency_amplitude * phase.cos();
+            normal.y -= wave.steepness / wave_count as f32 * phase.sin();
+            normal.z -= dir_z * frequency_amplitude * phase.cos();
+        }
+
+        (displacement, normal.normalize())
+    }
+}
+
+/// A single Gerstner wave. Speed isn't configurable, waves travel at the speed of deep water
+/// waves of their wavelength.
+///
+/// Create waves with `Wave::new` so that they always have a direction and a wavelength.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wave {
+    /// How far the crests rise above (and troughs sink below) the water's resting height
+    pub amplitude: f32,
+    /// The distance between two crests. At least `MIN_WAVELENGTH`.
+    pub wavelength: f32,
+    /// The (x, z) direction that the wave travels in. Doesn't need to be normalized, but can't
+    /// be (0, 0).
+    pub direction: (f32, f32),
+    /// 0.0 is a rolling sine wave, 1.0 gives the sharpest crests
+    pub steepness: f32,
+}
+
+impl Wave {
+    /// A wavelength that is too short gets clamped to `MIN_WAVELENGTH`, and a wave without a
+    /// direction travels along +x.
+    pub fn new(amplitude: f32, wavelength: f32, direction: (f32, f32), steepness: f32) -> Wave {
+        let direction = if direction == (0., 0.) {
+            (1., 0.)
+        } else {
+            direction
+        };
+
+        Wave {
+            amplitude,
+            wavelength: wavelength.max(MIN_WAVELENGTH),
+            direction,
+            steepness,
         }
     }
+
+    /// The direction of travel as a unit vector
+    pub fn normalized_direction(&self) -> (f32, f32) {
+        let (x, z) = self.direction;
+        let length = (x * x + z * z).sqrt();
+
+        (x / length, z / length)
+    }
+}
+
+/// The size of a reflection or refraction framebuffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FramebufferResolution {
+    /// Always (width, height) pixels, no matter how large the canvas is
+    Fixed(u32, u32),
+    /// This fraction of the canvas' size, so that detail is kept as the canvas grows or shrinks
+    CanvasScale(f32),
+}
+
+impl FramebufferResolution {
+    /// The (width, height) in pixels for a canvas of `canvas_size`. Never smaller than one pixel.
+    pub fn size(&self, canvas_size: (u32, u32)) -> (i32, i32) {
+        let (width, height) = match *self {
+            FramebufferResolution::Fixed(width, height) => (width as f32, height as f32),
+            FramebufferResolution::CanvasScale(scale) => {
+                (canvas_size.0 as f32 * scale, canvas_size.1 as f32 * scale)
+            }
+        };
+
+        (
+            (width.round() as i32).max(1),
+            (height.round() as i32).max(1),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn water_with_waves(waves: Vec<Wave>) -> Water {
+        Water {
+            waves,
+            ..Water::new()
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn flat_without_amplitude() {
+        let water = water_with_waves(vec![
+            Wave::new(0., 6.0, (1.0, 0.3), 0.),
+            Wave::new(0., 3.1, (-0.4, 1.0), 0.),
+        ]);
+
+        for (x, z, clock) in [(0., 0., 0.), (2.5, -7., 1234.), (-9., 4., 60_000.)].iter() {
+            assert_close(water.height_at(*x, *z, *clock), 0.);
+            assert_eq!(water.normal_at(*x, *z, *clock), Vector3::new(0., 1., 0.));
+        }
+    }
+
+    /// Without steepness a wave doesn't push the surface sideways, so it's a plain sine wave
+    #[test]
+    fn single_wave_height_and_normal() {
+        let water = water_with_waves(vec![Wave::new(0.5, 4.0, (2.0, 0.), 0.)]);
+
+        // A quarter wavelength in is the crest
+        assert_close(water.height_at(1., 3., 0.), 0.5);
+        assert_close(water.height_at(3., 3., 0.), -0.5);
+
+        // At the start of the wave the surface rises towards +x at a slope of
+        // frequency * amplitude = (2PI / 4) * 0.5
+        let slope = PI / 4.;
+        let expected = Vector3::new(-slope, 1., 0.).normalize();
+        let normal = water.normal_at(0., 3., 0.);
+        assert_close(normal.x, expected.x);
+        assert_close(normal.y, expected.y);
+        assert_close(normal.z, expected.z);
+    }
+
+    /// The point that we find on the resting surface should get carried back over the (x, z)
+    /// that we asked about
+    #[test]
+    fn resting_position_search_converges() {
+        let water = Water::new();
+
+        for (x, z, clock) in [(0., 0., 0.), (2.5, -7., 1234.), (-9., 4., 60_000.)].iter() {
+            let (rest_x, rest_z) = water.resting_position_under(*x, *z, *clock);
+            let (displacement, _) = water.displace(rest_x, rest_z, *clock);
+
+            assert!((rest_x + displacement.x - x).abs() < 1e-3);
+            assert!((rest_z + displacement.z - z).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn waves_without_a_direction_or_wavelength_stay_finite() {
+        let wave = Wave::new(0.1, 0., (0., 0.), 0.5);
+        assert_eq!(wave.direction, (1., 0.));
+        assert_eq!(wave.wavelength, MIN_WAVELENGTH);
+
+        let water = water_with_waves(vec![wave]);
+        assert!(water.height_at(1., 1., 500.).is_finite());
+        assert!(water.normal_at(1., 1., 500.).iter().all(|n| n.is_finite()));
+    }
 }
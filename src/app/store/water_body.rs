@@ -0,0 +1,76 @@
+use crate::app::store::Ripples;
+
+/// A flat, rectangular body of water such as a pond, a lake or a segment of a river.
+///
+/// Every water body gets its own refraction and reflection passes, so bodies can sit at
+/// different heights.
+#[derive(Clone)]
+pub struct WaterBody {
+    /// The (x, z) center of the water's surface
+    pub position: (f32, f32),
+    /// The size of the water along the x and z axis
+    pub extents: (f32, f32),
+    /// The y coordinate of the water's surface
+    pub height: f32,
+    ripples: Ripples,
+}
+
+impl WaterBody {
+    pub fn new(position: (f32, f32), extents: (f32, f32), height: f32) -> WaterBody {
+        WaterBody {
+            position,
+            extents,
+            height,
+            ripples: Ripples::new(),
+        }
+    }
+
+    pub fn ripples(&self) -> &Ripples {
+        &self.ripples
+    }
+
+    /// Start a ripple at a world (x, z). Does nothing if (x, z) isn't over this water.
+    pub fn disturb(&mut self, x: f32, z: f32, strength: f32) {
+        if !self.contains(x, z) {
+            return;
+        }
+
+        let u = (x - self.position.0) / self.extents.0 + 0.5;
+        let v = (z - self.position.1) / self.extents.1 + 0.5;
+
+        self.ripples.disturb(u, v, strength);
+    }
+
+    /// Advance this water's ripples by `dt` milliseconds
+    pub fn advance_ripples(&mut self, dt: f32) {
+        self.ripples.advance(dt);
+    }
+
+    /// Whether or not a world (x, z) is within this water's extents
+    pub fn contains(&self, x: f32, z: f32) -> bool {
+        let half_x = self.extents.0 / 2.;
+        let half_z = self.extents.1 / 2.;
+
+        (x - self.position.0).abs() <= half_x && (z - self.position.1).abs() <= half_z
+    }
+
+    /// Used when rendering refraction. Clips away everything that is above the water.
+    ///
+    /// Clip planes are stored as (normal.x, normal.y, normal.z, distance). Anything whose
+    /// dot product with the plane is negative gets clipped.
+    pub fn refraction_clip_plane(&self) -> [f32; 4] {
+        [0., -1., 0., self.height]
+    }
+
+    /// Used when rendering reflection. Clips away everything that is below the water.
+    pub fn reflection_clip_plane(&self) -> [f32; 4] {
+        [0., 1., 0., -self.height]
+    }
+}
+
+impl Default for WaterBody {
+    /// The 18x18 pond in the middle of our terrain
+    fn default() -> WaterBody {
+        WaterBody::new((0., 0.), (18., 18.), 0.)
+    }
+}
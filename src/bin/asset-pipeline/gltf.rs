@@ -0,0 +1,1179 @@
+//! Imports glTF 2.0 files (`.gltf` or `.glb`) as the same meshes and armatures that our JSON
+//! exports hold, so that models can come from tools other than Blender.
+//!
+//! Imported meshes and armatures come out ready to render:
+//!
+//! - Every primitive in a mesh gets merged into one triangle list with one index per vertex.
+//! - Non-skinned meshes keep glTF's y up. Skinned meshes get turned into Blender's z up, since
+//!   `mesh-skinned-vertex.glsl` turns them back after skinning.
+//! - Every vertex of a skinned mesh has four joints, straight from `JOINTS_0` and `WEIGHTS_0`.
+//! - Every animation gets sampled at each of its keyframe times into one dual quaternion per
+//!   joint, with the joint's inverse bind matrix already applied. Dual quaternions can't scale,
+//!   so any scale that's left over after the inverse bind matrix gets dropped.
+//!
+//! A non-skinned mesh that only one node uses gets that node's transform applied, so props come
+//! out where they were placed. A mesh that several nodes use stays in its own space, to be
+//! placed once per node with the scene file's transforms. Skinned meshes are posed by their
+//! joints, so like in glTF their node's transform is ignored. Materials, textures, cameras and
+//! morph targets are ignored.
+
+use nalgebra::{
+    Isometry3, Matrix3, Matrix4, Quaternion, Rotation3, Translation3, UnitQuaternion, Vector3,
+    Vector4,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const GLB_MAGIC: &[u8] = b"glTF";
+const GLB_JSON_CHUNK: u32 = 0x4E4F_534A;
+const GLB_BIN_CHUNK: u32 = 0x004E_4942;
+
+/// The parts of a glTF document that we import
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default)]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+    #[serde(default)]
+    meshes: Vec<Mesh>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    skins: Vec<Skin>,
+    #[serde(default)]
+    animations: Vec<Animation>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    sparse: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct Buffer {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Mesh {
+    name: Option<String>,
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Deserialize)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    mode: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    name: Option<String>,
+    #[serde(default)]
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    skin: Option<usize>,
+    matrix: Option<[f32; 16]>,
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Skin {
+    name: Option<String>,
+    inverse_bind_matrices: Option<usize>,
+    joints: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+struct Animation {
+    name: Option<String>,
+    channels: Vec<Channel>,
+    samplers: Vec<Sampler>,
+}
+
+#[derive(Deserialize)]
+struct Channel {
+    sampler: usize,
+    target: Target,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    node: Option<usize>,
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct Sampler {
+    input: usize,
+    output: usize,
+    #[serde(default = "default_interpolation")]
+    interpolation: String,
+}
+
+fn default_interpolation() -> String {
+    "LINEAR".to_string()
+}
+
+/// A node's translation, rotation and scale
+#[derive(Clone, Copy)]
+struct Trs {
+    translation: Vector3<f32>,
+    rotation: UnitQuaternion<f32>,
+    scale: Vector3<f32>,
+}
+
+struct Gltf {
+    doc: Document,
+    buffers: Vec<Vec<u8>>,
+    /// The parent of every node, if it has one
+    parents: Vec<Option<usize>>,
+}
+
+/// Import a glTF file as an export, ex: `{"meshes": {...}, "armatures": {...}}`
+pub fn import(path: &Path) -> Result<Value, String> {
+    Gltf::load(path)?.import()
+}
+
+impl Gltf {
+    fn load(path: &Path) -> Result<Gltf, String> {
+        let bytes = fs::read(path).map_err(|err| err.to_string())?;
+
+        let (json, mut glb_bin) = if bytes.starts_with(GLB_MAGIC) {
+            parse_glb(&bytes)?
+        } else {
+            (bytes, None)
+        };
+
+        let doc: Document = serde_json::from_slice(&json).map_err(|err| err.to_string())?;
+
+        let mut buffers = vec![];
+        for buffer in doc.buffers.iter() {
+            let bytes = match buffer.uri.as_ref() {
+                // A .glb's first buffer is its binary chunk
+                None => glb_bin
+                    .take()
+                    .ok_or_else(|| "A buffer has no uri".to_string())?,
+                Some(uri) if uri.starts_with("data:") => {
+                    let base64 = uri
+                        .find(";base64,")
+                        .map(|start| &uri[start + ";base64,".len()..])
+                        .ok_or_else(|| "Only base64 data uris are supported".to_string())?;
+                    decode_base64(base64)?
+                }
+                Some(uri) => {
+                    let bin = path.with_file_name(uri);
+                    fs::read(&bin).map_err(|err| format!("{}: {}", bin.display(), err))?
+                }
+            };
+
+            buffers.push(bytes);
+        }
+
+        Gltf::new(doc, buffers)
+    }
+
+    fn new(doc: Document, buffers: Vec<Vec<u8>>) -> Result<Gltf, String> {
+        let mut parents = vec![None; doc.nodes.len()];
+        for (parent, node) in doc.nodes.iter().enumerate() {
+            for child in node.children.iter() {
+                *parents
+                    .get_mut(*child)
+                    .ok_or_else(|| format!("Node {} has a missing child", parent))? = Some(parent);
+            }
+        }
+
+        Ok(Gltf {
+            doc,
+            buffers,
+            parents,
+        })
+    }
+
+    /// Every mesh and armature in the document, as an export
+    fn import(&self) -> Result<Value, String> {
+        // The skin that each mesh is drawn with, if any of the nodes that use it are skinned,
+        // and every node that uses it
+        let mut mesh_skins = vec![None; self.doc.meshes.len()];
+        let mut mesh_nodes = vec![vec![]; self.doc.meshes.len()];
+        for (index, node) in self.doc.nodes.iter().enumerate() {
+            let mesh = match node.mesh {
+                Some(mesh) if mesh < self.doc.meshes.len() => mesh,
+                Some(_) => return Err(format!("Node {} uses a missing mesh", index)),
+                None => continue,
+            };
+            mesh_nodes[mesh].push(index);
+
+            let skin = match node.skin {
+                Some(skin) if skin < self.doc.skins.len() => skin,
+                Some(_) => return Err(format!("Node {} uses a missing skin", index)),
+                None => continue,
+            };
+
+            // Our meshes only have one armature, so the mesh can't be drawn with both
+            match mesh_skins[mesh] {
+                Some(other_skin) if other_skin != skin => {
+                    return Err(format!(
+                        "Mesh {} is skinned by both {} and {}",
+                        self.mesh_name(mesh),
+                        self.armature_name(other_skin),
+                        self.armature_name(skin)
+                    ));
+                }
+                _ => mesh_skins[mesh] = Some(skin),
+            }
+        }
+
+        let world_transforms = self.world_transforms(&self.rest_pose());
+
+        let mut meshes = Map::new();
+        for (index, skin) in mesh_skins.iter().enumerate() {
+            let name = self.mesh_name(index);
+            if meshes.contains_key(&name) {
+                return Err(format!("More than one mesh is named {}", name));
+            }
+
+            let mesh = match skin {
+                Some(skin) => self.mesh(index, Some(self.armature_name(*skin)), None)?,
+                None => {
+                    let transform = match mesh_nodes[index].as_slice() {
+                        [node] => Some(&world_transforms[*node]),
+                        _ => None,
+                    };
+                    self.mesh(index, None, transform)?
+                }
+            };
+            meshes.insert(name, mesh);
+        }
+
+        let mut armatures = Map::new();
+        for index in 0..self.doc.skins.len() {
+            let name = self.armature_name(index);
+            if armatures.contains_key(&name) {
+                return Err(format!("More than one skin is named {}", name));
+            }
+
+            armatures.insert(name, self.armature(index)?);
+        }
+
+        Ok(json!({ "meshes": meshes, "armatures": armatures }))
+    }
+
+    fn mesh_name(&self, mesh: usize) -> String {
+        self.doc.meshes[mesh]
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("Mesh{}", mesh))
+    }
+
+    fn armature_name(&self, skin: usize) -> String {
+        self.doc.skins[skin]
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("Armature{}", skin))
+    }
+
+    /// A `BlenderMesh`, as JSON, with the node `transform` that places it applied
+    fn mesh(
+        &self,
+        index: usize,
+        armature_name: Option<String>,
+        transform: Option<&Matrix4<f32>>,
+    ) -> Result<Value, String> {
+        let name = self.mesh_name(index);
+        let skinned = armature_name.is_some();
+
+        let mut positions = vec![];
+        let mut normals = vec![];
+        let mut uvs = vec![];
+        let mut joints = vec![];
+        let mut weights = vec![];
+        let mut indices = vec![];
+        let mut has_uvs = true;
+
+        for primitive in self.doc.meshes[index].primitives.iter() {
+            // 4 is TRIANGLES, which is also what a primitive without a mode is drawn as
+            if primitive.mode.unwrap_or(4) != 4 {
+                return Err(format!(
+                    "Mesh {} has a primitive that isn't triangles",
+                    name
+                ));
+            }
+
+            let attribute = |attribute: &str| match primitive.attributes.get(attribute) {
+                Some(accessor) => self.accessor(*accessor).map(Some),
+                None => Ok(None),
+            };
+            let required = |attribute: &str| match primitive.attributes.get(attribute) {
+                Some(accessor) => self.accessor(*accessor),
+                None => Err(format!("Mesh {} has no {}", name, attribute)),
+            };
+
+            let first_vertex = positions.len() / 3;
+
+            let primitive_positions = required("POSITION")?;
+            let vertex_count = primitive_positions.len() / 3;
+            positions.extend(primitive_positions);
+            normals.extend(required("NORMAL")?);
+
+            match attribute("TEXCOORD_0")? {
+                // glTF's uvs start at the top left of the texture, Blender's at the bottom left
+                Some(primitive_uvs) => uvs.extend(
+                    primitive_uvs
+                        .chunks(2)
+                        .flat_map(|uv| vec![uv[0], 1.0 - uv[1]]),
+                ),
+                None => has_uvs = false,
+            };
+
+            if skinned {
+                joints.extend(required("JOINTS_0")?);
+                weights.extend(required("WEIGHTS_0")?);
+            }
+
+            match primitive.indices {
+                Some(accessor) => indices.extend(
+                    self.accessor(accessor)?
+                        .into_iter()
+                        .map(|index| first_vertex + index as usize),
+                ),
+                None => indices.extend(first_vertex..first_vertex + vertex_count),
+            };
+        }
+
+        if positions.len() / 3 > std::u16::MAX as usize + 1 {
+            return Err(format!(
+                "Mesh {} has more vertices than fit in 16 bit indices",
+                name
+            ));
+        }
+        if joints.iter().any(|joint| *joint > 255.0) {
+            return Err(format!("Mesh {} uses more than 256 joints", name));
+        }
+
+        if let Some(transform) = transform {
+            positions = transform_positions(transform, &positions);
+            normals = transform_normals(transform, &normals);
+
+            // A mirroring transform turns every triangle inside out
+            if transform.determinant() < 0.0 {
+                indices
+                    .chunks_mut(3)
+                    .for_each(|triangle| triangle.swap(1, 2));
+            }
+        }
+
+        if skinned {
+            positions = z_up(&positions);
+            normals = z_up(&normals);
+        }
+
+        let (min_corner, max_corner) = bounding_box(&positions);
+        let indices: Vec<u16> = indices.into_iter().map(|index| index as u16).collect();
+        let vertex_count = positions.len() / 3;
+
+        let uvs = if has_uvs { Some(uvs) } else { None };
+        let faces = vec![3u8; indices.len() / 3];
+
+        let (joints, weights, groups) = if skinned {
+            let joints: Vec<u8> = joints.into_iter().map(|joint| joint as u8).collect();
+            (Some(joints), Some(weights), Some(vec![4u8; vertex_count]))
+        } else {
+            (None, None, None)
+        };
+
+        Ok(json!({
+            "vertex_positions": positions,
+            "vertex_position_indices": indices,
+            "num_vertices_in_each_face": faces,
+            "vertex_normals": normals,
+            "vertex_normal_indices": null,
+            "vertex_uvs": uvs,
+            "vertex_uv_indices": null,
+            "texture_name": null,
+            "armature_name": armature_name,
+            "vertex_group_indices": joints,
+            "vertex_group_weights": weights,
+            "num_groups_for_each_vertex": groups,
+            "bounding_box": {"min_corner": min_corner, "max_corner": max_corner},
+        }))
+    }
+
+    /// A `BlenderArmature`, as JSON, with an action for every animation that moves its joints
+    fn armature(&self, index: usize) -> Result<Value, String> {
+        let skin = &self.doc.skins[index];
+
+        let inverse_bind_matrices: Vec<Matrix4<f32>> = match skin.inverse_bind_matrices {
+            Some(accessor) => self
+                .accessor(accessor)?
+                .chunks(16)
+                .map(Matrix4::from_column_slice)
+                .collect(),
+            None => vec![Matrix4::identity(); skin.joints.len()],
+        };
+        if inverse_bind_matrices.len() != skin.joints.len() {
+            return Err(format!(
+                "Skin {} needs one inverse bind matrix per joint",
+                self.armature_name(index)
+            ));
+        }
+
+        let mut joint_index = Map::new();
+        for (index, joint) in skin.joints.iter().enumerate() {
+            let name = self
+                .node(*joint)?
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("Joint{}", joint));
+            joint_index.insert(name, json!(index));
+        }
+
+        let inverse_bind_poses: Vec<Value> = inverse_bind_matrices
+            .iter()
+            .map(|matrix| json!({ "Matrix": matrix.as_slice() }))
+            .collect();
+
+        let rest_pose = self.rest_pose();
+
+        let mut actions = Map::new();
+        for (index, animation) in self.doc.animations.iter().enumerate() {
+            let moves_skin = animation.channels.iter().any(|channel| {
+                channel
+                    .target
+                    .node
+                    .map_or(false, |node| skin.joints.contains(&node))
+            });
+            if !moves_skin {
+                continue;
+            }
+
+            let name = animation
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("Action{}", index));
+
+            let mut keyframes = Map::new();
+            for time in self.keyframe_times(animation)? {
+                let pose = self.sample(animation, &rest_pose, time)?;
+                let world_transforms = self.world_transforms(&pose);
+
+                let bones: Vec<Value> = skin
+                    .joints
+                    .iter()
+                    .zip(inverse_bind_matrices.iter())
+                    .map(|(joint, inverse_bind_matrix)| {
+                        let skinning = world_transforms[*joint] * inverse_bind_matrix;
+                        json!({ "DualQuat": dual_quat(&z_up_isometry(&skinning)) })
+                    })
+                    .collect();
+
+                keyframes.insert(keyframe_key(time), Value::Array(bones));
+            }
+
+            actions.insert(name, Value::Object(keyframes));
+        }
+
+        Ok(json!({
+            "joint_index": joint_index,
+            "inverse_bind_poses": inverse_bind_poses,
+            "actions": actions,
+        }))
+    }
+
+    fn node(&self, node: usize) -> Result<&Node, String> {
+        self.doc
+            .nodes
+            .get(node)
+            .ok_or_else(|| format!("Missing node {}", node))
+    }
+
+    /// Every node's own transform, before any animation
+    fn rest_pose(&self) -> Vec<Trs> {
+        self.doc
+            .nodes
+            .iter()
+            .map(|node| match node.matrix {
+                Some(matrix) => decompose(&Matrix4::from_column_slice(&matrix)),
+                None => Trs {
+                    translation: node.translation.map_or(Vector3::zeros(), Vector3::from),
+                    rotation: node.rotation.map_or(UnitQuaternion::identity(), |q| {
+                        UnitQuaternion::new_normalize(Quaternion::new(q[3], q[0], q[1], q[2]))
+                    }),
+                    scale: node.scale.map_or(Vector3::repeat(1.0), Vector3::from),
+                },
+            })
+            .collect()
+    }
+
+    /// Every time that any of the animation's channels has a keyframe, in seconds. Times that
+    /// would share a `keyframe_key` are merged into the first of them.
+    fn keyframe_times(&self, animation: &Animation) -> Result<Vec<f32>, String> {
+        let mut times = vec![];
+        for channel in animation.channels.iter() {
+            let sampler = sampler(animation, channel)?;
+            let sampler_times = self.accessor(sampler.input)?;
+
+            if sampler_times.iter().any(|time| !time.is_finite()) {
+                return Err(format!(
+                    "Animation sampler {} has a keyframe time that isn't a number",
+                    channel.sampler
+                ));
+            }
+
+            times.extend(sampler_times);
+        }
+
+        // None of the times are NaN, so they all compare
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        times.dedup_by(|a, b| keyframe_key(*a) == keyframe_key(*b));
+
+        Ok(times)
+    }
+
+    /// Every node's transform `time` seconds into the animation
+    fn sample(
+        &self,
+        animation: &Animation,
+        rest_pose: &[Trs],
+        time: f32,
+    ) -> Result<Vec<Trs>, String> {
+        let mut pose = rest_pose.to_vec();
+
+        for channel in animation.channels.iter() {
+            let node = match channel.target.node {
+                Some(node) => node,
+                None => continue,
+            };
+            let components = match channel.target.path.as_str() {
+                "translation" | "scale" => 3,
+                "rotation" => 4,
+                // Morph target weights
+                _ => continue,
+            };
+
+            let sampler = sampler(animation, channel)?;
+            let times = self.accessor(sampler.input)?;
+            let values = self.accessor(sampler.output)?;
+
+            let stride = match sampler.interpolation.as_str() {
+                "CUBICSPLINE" => components * 3,
+                _ => components,
+            };
+            if times.is_empty() || values.len() != times.len() * stride {
+                return Err(format!(
+                    "Animation sampler {} doesn't have one value per keyframe",
+                    channel.sampler
+                ));
+            }
+
+            let value = interpolate(&times, &values, components, &sampler.interpolation, time);
+
+            let trs = pose
+                .get_mut(node)
+                .ok_or_else(|| format!("Missing node {}", node))?;
+            match channel.target.path.as_str() {
+                "translation" => trs.translation = Vector3::new(value[0], value[1], value[2]),
+                "scale" => trs.scale = Vector3::new(value[0], value[1], value[2]),
+                _ => {
+                    trs.rotation = UnitQuaternion::new_normalize(Quaternion::new(
+                        value[3], value[0], value[1], value[2],
+                    ))
+                }
+            }
+        }
+
+        Ok(pose)
+    }
+
+    /// Every node's transform in the world, given every node's transform relative to its parent
+    fn world_transforms(&self, pose: &[Trs]) -> Vec<Matrix4<f32>> {
+        let mut world_transforms = vec![None; pose.len()];
+
+        for node in 0..pose.len() {
+            self.world_transform(node, pose, &mut world_transforms);
+        }
+
+        world_transforms.into_iter().map(Option::unwrap).collect()
+    }
+
+    fn world_transform(
+        &self,
+        node: usize,
+        pose: &[Trs],
+        world_transforms: &mut Vec<Option<Matrix4<f32>>>,
+    ) -> Matrix4<f32> {
+        if let Some(world_transform) = world_transforms[node] {
+            return world_transform;
+        }
+
+        let trs = &pose[node];
+        let local_transform = Matrix4::new_translation(&trs.translation)
+            * trs.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&trs.scale);
+
+        let world_transform = match self.parents[node] {
+            Some(parent) => self.world_transform(parent, pose, world_transforms) * local_transform,
+            None => local_transform,
+        };
+
+        world_transforms[node] = Some(world_transform);
+        world_transform
+    }
+
+    /// Every component of every element of an accessor, as floats. Normalized integers get
+    /// turned into 0.0 to 1.0 (or -1.0 to 1.0), everything else keeps its value.
+    fn accessor(&self, index: usize) -> Result<Vec<f32>, String> {
+        let accessor = self
+            .doc
+            .accessors
+            .get(index)
+            .ok_or_else(|| format!("Missing accessor {}", index))?;
+
+        if accessor.sparse.is_some() {
+            return Err(format!(
+                "Accessor {} is sparse, which isn't supported",
+                index
+            ));
+        }
+
+        let components = match accessor.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" | "MAT2" => 4,
+            "MAT3" => 9,
+            "MAT4" => 16,
+            kind => return Err(format!("Accessor {} has unknown type {}", index, kind)),
+        };
+        let component_size = match accessor.component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            other => {
+                return Err(format!(
+                    "Accessor {} has unknown component type {}",
+                    index, other
+                ))
+            }
+        };
+
+        let view = match accessor.buffer_view {
+            Some(view) => view,
+            // An accessor without a buffer view is all zeros
+            None => return Ok(vec![0.0; accessor.count * components]),
+        };
+        let view = self
+            .doc
+            .buffer_views
+            .get(view)
+            .ok_or_else(|| format!("Missing buffer view {}", view))?;
+        let buffer = self
+            .buffers
+            .get(view.buffer)
+            .ok_or_else(|| format!("Missing buffer {}", view.buffer))?;
+
+        let stride = view.byte_stride.unwrap_or(components * component_size);
+        let start = view.byte_offset + accessor.byte_offset;
+
+        let mut values = Vec::with_capacity(accessor.count * components);
+        for element in 0..accessor.count {
+            for component in 0..components {
+                let offset = start + element * stride + component * component_size;
+                let bytes = buffer
+                    .get(offset..offset + component_size)
+                    .ok_or_else(|| format!("Accessor {} runs past the end of its buffer", index))?;
+
+                values.push(read_component(
+                    bytes,
+                    accessor.component_type,
+                    accessor.normalized,
+                ));
+            }
+        }
+
+        Ok(values)
+    }
+}
+
+fn sampler<'a>(animation: &'a Animation, channel: &Channel) -> Result<&'a Sampler, String> {
+    animation
+        .samplers
+        .get(channel.sampler)
+        .ok_or_else(|| format!("Missing animation sampler {}", channel.sampler))
+}
+
+fn read_component(bytes: &[u8], component_type: u32, normalized: bool) -> f32 {
+    let little_endian_u16 = || u16::from(bytes[0]) | u16::from(bytes[1]) << 8;
+    let little_endian_u32 = || {
+        u32::from(bytes[0])
+            | u32::from(bytes[1]) << 8
+            | u32::from(bytes[2]) << 16
+            | u32::from(bytes[3]) << 24
+    };
+
+    match (component_type, normalized) {
+        (5120, false) => f32::from(bytes[0] as i8),
+        (5120, true) => (f32::from(bytes[0] as i8) / 127.0).max(-1.0),
+        (5121, false) => f32::from(bytes[0]),
+        (5121, true) => f32::from(bytes[0]) / 255.0,
+        (5122, false) => f32::from(little_endian_u16() as i16),
+        (5122, true) => (f32::from(little_endian_u16() as i16) / 32767.0).max(-1.0),
+        (5123, false) => f32::from(little_endian_u16()),
+        (5123, true) => f32::from(little_endian_u16()) / 65535.0,
+        (5125, _) => little_endian_u32() as f32,
+        _ => f32::from_bits(little_endian_u32()),
+    }
+}
+
+/// A channel's value at `time`, given its keyframe `times` and the `components` wide `values`
+/// at each of them
+fn interpolate(
+    times: &[f32],
+    values: &[f32],
+    components: usize,
+    interpolation: &str,
+    time: f32,
+) -> Vec<f32> {
+    // Cubic spline keyframes are an in tangent, a value and then an out tangent
+    let cubic = interpolation == "CUBICSPLINE";
+    let stride = if cubic { components * 3 } else { components };
+    let value = |keyframe: usize, part: usize| {
+        let start = keyframe * stride + part * components;
+        &values[start..start + components]
+    };
+    let value_part = if cubic { 1 } else { 0 };
+
+    let next = times.iter().position(|keyframe| *keyframe > time);
+    let (previous, next) = match next {
+        Some(0) => return value(0, value_part).to_vec(),
+        Some(next) => (next - 1, next),
+        None => return value(times.len() - 1, value_part).to_vec(),
+    };
+
+    let duration = times[next] - times[previous];
+    let t = (time - times[previous]) / duration;
+
+    let mut interpolated: Vec<f32> = match interpolation {
+        "STEP" => value(previous, 0).to_vec(),
+        "CUBICSPLINE" => {
+            let (t2, t3) = (t * t, t * t * t);
+            (0..components)
+                .map(|c| {
+                    (2. * t3 - 3. * t2 + 1.) * value(previous, 1)[c]
+                        + (t3 - 2. * t2 + t) * duration * value(previous, 2)[c]
+                        + (-2. * t3 + 3. * t2) * value(next, 1)[c]
+                        + (t3 - t2) * duration * value(next, 0)[c]
+                })
+                .collect()
+        }
+        _ => {
+            let (a, mut b) = (value(previous, 0).to_vec(), value(next, 0).to_vec());
+
+            // Take the short way around when blending rotations
+            let dot: f32 = a.iter().zip(b.iter()).map(|(a, b)| a * b).sum();
+            if components == 4 && dot < 0.0 {
+                b.iter_mut().for_each(|b| *b = -*b);
+            }
+
+            a.iter()
+                .zip(b.iter())
+                .map(|(a, b)| a + (b - a) * t)
+                .collect()
+        }
+    };
+
+    if components == 4 {
+        let length = interpolated.iter().map(|c| c * c).sum::<f32>().sqrt();
+        interpolated.iter_mut().for_each(|c| *c /= length);
+    }
+
+    interpolated
+}
+
+/// Split a transform into its translation, rotation and scale, ignoring any shear
+fn decompose(matrix: &Matrix4<f32>) -> Trs {
+    let column = |c: usize| Vector3::new(matrix[(0, c)], matrix[(1, c)], matrix[(2, c)]);
+    let (x, y, z) = (column(0), column(1), column(2));
+    let scale = Vector3::new(x.norm(), y.norm(), z.norm());
+
+    let rotation = Matrix3::from_columns(&[x / scale.x, y / scale.y, z / scale.z]);
+    let rotation =
+        UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(rotation));
+
+    Trs {
+        translation: column(3),
+        rotation,
+        scale,
+    }
+}
+
+/// The key of an action's keyframe at `time` seconds. Like Blender's exporter we round keyframe
+/// times to 6 decimal places, ex: "0.458333" or "1.0", instead of relying on every float
+/// printing back out the same way.
+fn keyframe_key(time: f32) -> String {
+    let key = format!("{:.6}", time);
+    let key = key.trim_end_matches('0');
+
+    if key.ends_with('.') {
+        format!("{}0", key)
+    } else {
+        key.to_string()
+    }
+}
+
+/// Move positions by a node's transform
+fn transform_positions(transform: &Matrix4<f32>, positions: &[f32]) -> Vec<f32> {
+    positions
+        .chunks(3)
+        .flat_map(|p| {
+            let p = transform * Vector4::new(p[0], p[1], p[2], 1.0);
+            vec![p.x, p.y, p.z]
+        })
+        .collect()
+}
+
+/// Turn normals by a node's transform. Normals get multiplied by the inverse transpose so that
+/// they stay perpendicular to surfaces that are scaled unevenly.
+fn transform_normals(transform: &Matrix4<f32>, normals: &[f32]) -> Vec<f32> {
+    let normal_matrix = Matrix3::from_fn(|row, column| transform[(row, column)])
+        .try_inverse()
+        .unwrap_or_else(Matrix3::identity)
+        .transpose();
+
+    normals
+        .chunks(3)
+        .flat_map(|n| {
+            let normal = Vector3::new(n[0], n[1], n[2]);
+            let n = (normal_matrix * normal)
+                .try_normalize(0.0)
+                .unwrap_or(normal);
+            vec![n.x, n.y, n.z]
+        })
+        .collect()
+}
+
+/// The rotation and translation of a skinning transform, after turning it from y up to z up
+fn z_up_isometry(skinning: &Matrix4<f32>) -> Isometry3<f32> {
+    let trs = decompose(skinning);
+    let skinning = Isometry3::from_parts(Translation3::from(trs.translation), trs.rotation);
+
+    // Rotating 90 degrees around x takes y up to z up
+    let y_up_to_z_up = Isometry3::rotation(Vector3::x() * std::f32::consts::FRAC_PI_2);
+
+    y_up_to_z_up * skinning * y_up_to_z_up.inverse()
+}
+
+/// The [rotation, translation] dual quaternion that `mesh-skinned-vertex.glsl` blends, with
+/// each quaternion stored w first
+fn dual_quat(isometry: &Isometry3<f32>) -> [f32; 8] {
+    let rotation = isometry.rotation.quaternion();
+    let t = isometry.translation.vector;
+    let translation = Quaternion::new(0.0, t.x, t.y, t.z) * rotation * 0.5;
+
+    [
+        rotation.w,
+        rotation.i,
+        rotation.j,
+        rotation.k,
+        translation.w,
+        translation.i,
+        translation.j,
+        translation.k,
+    ]
+}
+
+/// Turn y up positions or normals into Blender's z up
+fn z_up(vectors: &[f32]) -> Vec<f32> {
+    vectors
+        .chunks(3)
+        .flat_map(|v| vec![v[0], -v[2], v[1]])
+        .collect()
+}
+
+fn bounding_box(positions: &[f32]) -> ([f32; 3], [f32; 3]) {
+    let mut min = [std::f32::INFINITY; 3];
+    let mut max = [std::f32::NEG_INFINITY; 3];
+
+    for position in positions.chunks(3) {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(position[axis]);
+            max[axis] = max[axis].max(position[axis]);
+        }
+    }
+
+    (min, max)
+}
+
+/// Split a .glb into its JSON chunk and its binary chunk, if it has one
+fn parse_glb(bytes: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>), String> {
+    let u32_at = |offset: usize| {
+        bytes.get(offset..offset + 4).map(|b| {
+            u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16 | u32::from(b[3]) << 24
+        })
+    };
+
+    if u32_at(4) != Some(2) {
+        return Err("Only version 2 .glb files are supported".to_string());
+    }
+
+    let mut json = None;
+    let mut bin = None;
+
+    // Chunks start after the 12 byte header, each with its length and type
+    let mut offset = 12;
+    while let (Some(length), Some(kind)) = (u32_at(offset), u32_at(offset + 4)) {
+        let start = offset + 8;
+        let end = start + length as usize;
+        let chunk = bytes
+            .get(start..end)
+            .ok_or_else(|| "A .glb chunk runs past the end of the file".to_string())?
+            .to_vec();
+
+        match kind {
+            GLB_JSON_CHUNK => json = Some(chunk),
+            GLB_BIN_CHUNK => bin = Some(chunk),
+            // Extensions can add their own chunks
+            _ => {}
+        }
+
+        offset = end;
+    }
+
+    let json = json.ok_or_else(|| "The .glb has no JSON chunk".to_string())?;
+    Ok((json, bin))
+}
+
+fn decode_base64(base64: &str) -> Result<Vec<u8>, String> {
+    let sextet = |c: u8| match c {
+        b'A'..=b'Z' => Ok(c - b'A'),
+        b'a'..=b'z' => Ok(c - b'a' + 26),
+        b'0'..=b'9' => Ok(c - b'0' + 52),
+        b'+' => Ok(62),
+        b'/' => Ok(63),
+        _ => Err(format!("Invalid base64 character {}", c as char)),
+    };
+
+    let base64 = base64.trim_end_matches('=').as_bytes();
+    let mut bytes = Vec::with_capacity(base64.len() * 3 / 4);
+
+    for chunk in base64.chunks(4) {
+        let mut bits = 0u32;
+        for (index, c) in chunk.iter().enumerate() {
+            bits |= u32::from(sextet(*c)?) << (18 - 6 * index);
+        }
+
+        let decoded = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        bytes.extend_from_slice(&decoded[..chunk.len() - 1]);
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(file: &str) -> String {
+        format!(
+            "{}/src/bin/asset-pipeline/fixtures/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            file
+        )
+    }
+
+    fn floats(value: &Value) -> Vec<f32> {
+        value
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|float| float.as_f64().unwrap() as f32)
+            .collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(
+            actual.len(),
+            expected.len(),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn little_endian(float: f32) -> Vec<u8> {
+        let bits = float.to_bits();
+        vec![
+            bits as u8,
+            (bits >> 8) as u8,
+            (bits >> 16) as u8,
+            (bits >> 24) as u8,
+        ]
+    }
+
+    /// A joint that's keyframed at each of `times`
+    fn animated_joint(times: &[f32]) -> Gltf {
+        let mut buffer = vec![];
+        for time in times.iter() {
+            buffer.extend(little_endian(*time));
+        }
+        for _ in times.iter() {
+            for component in [0.0, 0.0, 0.0, 1.0].iter() {
+                buffer.extend(little_endian(*component));
+            }
+        }
+
+        let doc = json!({
+            "nodes": [{"name": "Joint"}],
+            "skins": [{"joints": [0]}],
+            "animations": [{
+                "channels": [{"sampler": 0, "target": {"node": 0, "path": "rotation"}}],
+                "samplers": [{"input": 0, "output": 1}]
+            }],
+            "accessors": [
+                {"bufferView": 0, "componentType": 5126, "count": times.len(), "type": "SCALAR"},
+                {"bufferView": 1, "componentType": 5126, "count": times.len(), "type": "VEC4"}
+            ],
+            "bufferViews": [
+                {"buffer": 0, "byteOffset": 0},
+                {"buffer": 0, "byteOffset": times.len() * 4}
+            ],
+            "buffers": [{}]
+        });
+
+        Gltf::new(serde_json::from_value(doc).unwrap(), vec![buffer]).unwrap()
+    }
+
+    #[test]
+    fn imports_a_skinned_mesh_and_its_animation() {
+        let export = import(Path::new(&fixture("rigged-triangle.gltf"))).unwrap();
+
+        let triangle = &export["meshes"]["Triangle"];
+        assert_eq!(triangle["armature_name"], "Rig");
+        // Skinned meshes get turned z up
+        assert_close(
+            &floats(&triangle["vertex_positions"]),
+            &[-0.5, 0., 0., 0.5, 0., 0., 0., 0., 1.],
+        );
+        assert_close(
+            &floats(&triangle["vertex_group_indices"]),
+            &[0., 0., 0., 0., 0., 0., 0., 0., 1., 0., 0., 0.],
+        );
+
+        let rig = &export["armatures"]["Rig"];
+        assert_eq!(rig["joint_index"], json!({"Root": 0, "Tip": 1}));
+
+        let wave = rig["actions"]["Wave"].as_object().unwrap();
+        let keys: Vec<&String> = wave.keys().collect();
+        assert_eq!(keys, vec!["0.0", "1.0"]);
+
+        let identity = [1., 0., 0., 0., 0., 0., 0., 0.];
+        for bone in wave["0.0"].as_array().unwrap() {
+            assert_close(&floats(&bone["DualQuat"]), &identity);
+        }
+
+        // By the end the tip has turned 90 degrees around the root's tip
+        let bones = wave["1.0"].as_array().unwrap();
+        assert_close(&floats(&bones[0]["DualQuat"]), &identity);
+        let tip = floats(&bones[1]["DualQuat"]);
+        assert_close(&tip[..1], &[std::f32::consts::FRAC_1_SQRT_2]);
+    }
+
+    #[test]
+    fn imports_a_glb_like_the_gltf_that_it_was_packed_from() {
+        let gltf = import(Path::new(&fixture("rigged-triangle.gltf"))).unwrap();
+        let glb = import(Path::new(&fixture("rigged-triangle.glb"))).unwrap();
+
+        assert_eq!(glb, gltf);
+    }
+
+    #[test]
+    fn places_props_with_their_node_transform() {
+        let export = import(Path::new(&fixture("rigged-triangle.gltf"))).unwrap();
+
+        // The prop node is moved 2 along x and doubled in size. Props stay y up.
+        let prop = &export["meshes"]["Prop"];
+        assert_eq!(prop["armature_name"], Value::Null);
+        assert_close(
+            &floats(&prop["vertex_positions"]),
+            &[1., 0., 0., 3., 0., 0., 2., 2., 0.],
+        );
+        assert_close(
+            &floats(&prop["vertex_normals"]),
+            &[0., 0., 1., 0., 0., 1., 0., 0., 1.],
+        );
+    }
+
+    #[test]
+    fn keyframes_that_round_to_the_same_time_are_merged() {
+        let gltf = animated_joint(&[1.0, 0.0, 0.458_333_3, 0.458_333_34]);
+
+        let armature = gltf.armature(0).unwrap();
+        let keys: Vec<&String> = armature["actions"]["Action0"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect();
+
+        assert_eq!(keys, vec!["0.0", "0.458333", "1.0"]);
+    }
+
+    #[test]
+    fn rejects_keyframe_times_that_are_not_numbers() {
+        let gltf = animated_joint(&[0.0, std::f32::NAN]);
+
+        assert!(gltf.armature(0).unwrap_err().contains("isn't a number"));
+    }
+
+    #[test]
+    fn rejects_meshes_that_more_than_one_skin_uses() {
+        let doc = json!({
+            "nodes": [
+                {"mesh": 0, "skin": 0},
+                {"mesh": 0, "skin": 1},
+                {"name": "Joint"}
+            ],
+            "meshes": [{"name": "Body", "primitives": []}],
+            "skins": [{"name": "Small", "joints": [2]}, {"name": "Large", "joints": [2]}]
+        });
+        let gltf = Gltf::new(serde_json::from_value(doc).unwrap(), vec![]).unwrap();
+
+        assert_eq!(
+            gltf.import().unwrap_err(),
+            "Mesh Body is skinned by both Small and Large"
+        );
+    }
+}
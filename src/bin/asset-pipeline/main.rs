@@ -0,0 +1,224 @@
+//! Turns the JSON that `blender_mesh` and `blender_armature` export, and glTF 2.0 files, into the
+//! `meshes.bytes` and `armatures.bytes` bundles that the web client downloads.
+//!
+//! Meshes and armatures get preprocessed here, ahead of time, and written as render-ready
+//! bundles whose header lists every transform that we applied. That way the browser doesn't do
+//! any geometry processing when it starts up. No Blender needed.
+//!
+//! ```sh
+//! # Regenerate the bundles that the demo downloads
+//! cargo run --bin asset-pipeline -- --out-dir . exports/terrain.json exports/bird.json
+//! # Or write them somewhere else
+//! cargo run --bin asset-pipeline -- --out-dir dist exports/*.json
+//! ```
+//!
+//! There's no default `--out-dir`, so that running the pipeline from the wrong directory can't
+//! scatter bundles around or overwrite the checked in ones by accident.
+//!
+//! Each export is a JSON object with the meshes and armatures from one `.blend` file, keyed by
+//! name. Either one can be left out.
+//!
+//! ```json
+//! {
+//!   "meshes": {"Bird": {"vertex_positions": [0.0, 1.0, 2.0], ...}},
+//!   "armatures": {"Armature.001": {"joint_index": {"Torso": 0}, ...}}
+//! }
+//! ```
+//!
+//! `.gltf` and `.glb` files can be passed in alongside the JSON exports, see `gltf.rs`.
+
+use blender_armature::BlenderArmature;
+use blender_mesh::BlenderMesh;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+mod gltf;
+
+#[path = "../../app/assets/preprocess.rs"]
+mod preprocess;
+use self::preprocess::*;
+
+/// The meshes and armatures exported from one `.blend` file, or imported from one glTF file
+#[derive(Deserialize)]
+struct Export {
+    #[serde(default)]
+    meshes: HashMap<String, BlenderMesh>,
+    #[serde(default)]
+    armatures: HashMap<String, BlenderArmature>,
+    /// glTF imports come out with every transform already applied
+    #[serde(skip)]
+    render_ready: bool,
+}
+
+fn main() {
+    if let Err(err) = run(std::env::args().skip(1).collect()) {
+        eprintln!("asset-pipeline: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut out_dir = None;
+    let mut exports = vec![];
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out-dir" => {
+                let dir = args
+                    .next()
+                    .ok_or_else(|| "--out-dir needs a directory".to_string())?;
+                out_dir = Some(PathBuf::from(dir));
+            }
+            _ => exports.push(PathBuf::from(arg)),
+        }
+    }
+
+    let out_dir = match out_dir {
+        Some(out_dir) if !exports.is_empty() => out_dir,
+        _ => return Err("Usage: asset-pipeline --out-dir DIR EXPORT.json...".to_string()),
+    };
+
+    // BTreeMaps so that the same exports always produce the same bundles. They serialize exactly
+    // like the HashMaps that the web client deserializes them into.
+    let mut meshes = BTreeMap::new();
+    let mut armatures = BTreeMap::new();
+
+    for path in exports.iter() {
+        let export = read_export(path)?;
+
+        let (mesh_transforms, armature_transforms): (&[_], &[_]) = if export.render_ready {
+            (&MESH_TRANSFORMS, &ARMATURE_TRANSFORMS)
+        } else {
+            (&[], &[])
+        };
+
+        for (name, mut mesh) in export.meshes {
+            if meshes.contains_key(&name) {
+                return Err(format!("More than one export has a mesh named {}", name));
+            }
+
+            preprocess_mesh(&mut mesh, mesh_transforms);
+            meshes.insert(name, mesh);
+        }
+
+        for (name, mut armature) in export.armatures {
+            if armatures.contains_key(&name) {
+                return Err(format!(
+                    "More than one export has an armature named {}",
+                    name
+                ));
+            }
+
+            preprocess_armature(&mut armature, armature_transforms);
+            armatures.insert(name, armature);
+        }
+    }
+
+    fs::create_dir_all(&out_dir).map_err(|err| format!("{}: {}", out_dir.display(), err))?;
+
+    let header = RenderReadyHeader {
+        version: RENDER_READY_VERSION,
+        mesh_transforms: MESH_TRANSFORMS.to_vec(),
+        armature_transforms: ARMATURE_TRANSFORMS.to_vec(),
+    };
+
+    write_bundle(&out_dir.join("meshes.bytes"), &header, &meshes)?;
+    write_bundle(&out_dir.join("armatures.bytes"), &header, &armatures)?;
+
+    println!(
+        "Wrote {} meshes and {} armatures to {}",
+        meshes.len(),
+        armatures.len(),
+        out_dir.display()
+    );
+
+    Ok(())
+}
+
+fn read_export(path: &Path) -> Result<Export, String> {
+    let export = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("gltf") | Some("glb") => gltf::import(path)
+            .and_then(|export| serde_json::from_value(export).map_err(|err| err.to_string()))
+            .map(|export| Export {
+                render_ready: true,
+                ..export
+            }),
+        _ => fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|err| err.to_string())),
+    };
+
+    export.map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Write a render-ready bundle, which the web client will know not to preprocess again
+fn write_bundle<T: Serialize>(
+    path: &Path,
+    header: &RenderReadyHeader,
+    assets: &T,
+) -> Result<(), String> {
+    let mut bytes = RENDER_READY_MAGIC.to_vec();
+    bincode::serialize_into(&mut bytes, header).map_err(|err| err.to_string())?;
+    bincode::serialize_into(&mut bytes, assets).map_err(|err| err.to_string())?;
+
+    let mut file = File::create(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    file.write_all(&bytes)
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+
+    fn path(file: &str) -> String {
+        format!("{}/{}", env!("CARGO_MANIFEST_DIR"), file)
+    }
+
+    /// Read back a bundle that we wrote, making sure that it's render-ready
+    fn read_bundle<T: DeserializeOwned>(path: &Path) -> T {
+        let bytes = fs::read(path).unwrap();
+        assert!(bytes.starts_with(RENDER_READY_MAGIC));
+
+        let mut bytes = &bytes[RENDER_READY_MAGIC.len()..];
+        let header: RenderReadyHeader = bincode::deserialize_from(&mut bytes).unwrap();
+        assert_eq!(header.version, RENDER_READY_VERSION);
+
+        bincode::deserialize_from(&mut bytes).unwrap()
+    }
+
+    #[test]
+    fn bundles_the_checked_in_exports() {
+        let out_dir = path("target/asset-pipeline-test");
+
+        run(vec![
+            "--out-dir".to_string(),
+            out_dir.clone(),
+            path("exports/terrain.json"),
+            path("exports/bird.json"),
+        ])
+        .unwrap();
+
+        let out_dir = Path::new(&out_dir);
+
+        let meshes: HashMap<String, BlenderMesh> = read_bundle(&out_dir.join("meshes.bytes"));
+        let mut mesh_names: Vec<&String> = meshes.keys().collect();
+        mesh_names.sort();
+        assert_eq!(mesh_names, vec!["Bird", "Terrain"]);
+
+        let armatures: HashMap<String, BlenderArmature> =
+            read_bundle(&out_dir.join("armatures.bytes"));
+        let armature_names: Vec<&String> = armatures.keys().collect();
+        assert_eq!(armature_names, vec!["Armature.001"]);
+    }
+
+    #[test]
+    fn needs_an_out_dir() {
+        assert!(run(vec![path("exports/terrain.json")]).is_err());
+        assert!(run(vec!["--out-dir".to_string()]).is_err());
+    }
+}
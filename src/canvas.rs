@@ -1,5 +1,7 @@
 use crate::app::App;
+use crate::app::CameraMode;
 use crate::app::Msg;
+use crate::app::PickTarget;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
@@ -9,8 +11,11 @@ use web_sys::*;
 
 pub static APP_DIV_ID: &'static str = "webgl-water-tutorial";
 
-pub static CANVAS_WIDTH: i32 = 512;
-pub static CANVAS_HEIGHT: i32 = 512;
+/// If the page doesn't give our container a height, the canvas is at least this tall
+static MIN_CANVAS_CSS_HEIGHT: &'static str = "512px";
+
+/// How hard clicking on the water pushes it down
+static CLICK_RIPPLE_STRENGTH: f32 = 0.5;
 
 pub fn create_webgl_context(app: Rc<App>) -> Result<WebGlRenderingContext, JsValue> {
     let canvas = init_canvas(app)?;
@@ -29,8 +34,24 @@ fn init_canvas(app: Rc<App>) -> Result<HtmlCanvasElement, JsValue> {
 
     let canvas: HtmlCanvasElement = document.create_element("canvas").unwrap().dyn_into()?;
 
-    canvas.set_width(CANVAS_WIDTH as u32);
-    canvas.set_height(CANVAS_HEIGHT as u32);
+    // The canvas fills a wrapper that takes up whatever width our controls leave over and the
+    // height of our container. The canvas is absolutely positioned so that its drawing buffer
+    // size never feeds back into the layout, see `resize_canvas`.
+    let canvas_wrapper: HtmlElement = document.create_element("div")?.dyn_into()?;
+    let wrapper_style = canvas_wrapper.style();
+    wrapper_style.set_property("position", "relative")?;
+    wrapper_style.set_property("flex", "1 1 0")?;
+    wrapper_style.set_property("min-width", "0")?;
+    wrapper_style.set_property("min-height", MIN_CANVAS_CSS_HEIGHT)?;
+
+    let canvas_style = canvas.style();
+    canvas_style.set_property("position", "absolute")?;
+    canvas_style.set_property("left", "0")?;
+    canvas_style.set_property("top", "0")?;
+    canvas_style.set_property("width", "100%")?;
+    canvas_style.set_property("height", "100%")?;
+
+    canvas_wrapper.append_child(&canvas)?;
 
     attach_mouse_down_handler(&canvas, Rc::clone(&app))?;
     attach_mouse_up_handler(&canvas, Rc::clone(&app))?;
@@ -41,6 +62,9 @@ fn init_canvas(app: Rc<App>) -> Result<HtmlCanvasElement, JsValue> {
     attach_touch_move_handler(&canvas, Rc::clone(&app))?;
     attach_touch_end_handler(&canvas, Rc::clone(&app))?;
 
+    attach_key_down_handler(&window, Rc::clone(&app))?;
+    attach_key_up_handler(&window, Rc::clone(&app))?;
+
     let app_div: HtmlElement = match document.get_element_by_id(APP_DIV_ID) {
         Some(container) => container.dyn_into()?,
         None => {
@@ -51,16 +75,51 @@ fn init_canvas(app: Rc<App>) -> Result<HtmlCanvasElement, JsValue> {
     };
 
     app_div.style().set_property("display", "flex")?;
-    app_div.append_child(&canvas)?;
+    app_div.append_child(&canvas_wrapper)?;
 
     Ok(canvas)
 }
 
+/// Match the canvas' drawing buffer to the size that it is displayed at, so that we render one
+/// pixel per device pixel. Cheap enough to call every frame, which lets us follow our container
+/// and the device pixel ratio (ex: the page being zoomed) without listening for resizes.
+///
+/// We only send a `Msg::Resize` when the size changed. The canvas' drawing buffer might already
+/// be the right size before our state knows about it, so we compare against our state.
+pub fn resize_canvas(canvas: &HtmlCanvasElement, app: &App) {
+    let pixel_ratio = window().unwrap().device_pixel_ratio();
+
+    // A canvas that is hidden or hasn't been laid out yet has no size, but our state never goes
+    // below one pixel
+    let width = ((canvas.client_width() as f64 * pixel_ratio).round() as u32).max(1);
+    let height = ((canvas.client_height() as f64 * pixel_ratio).round() as u32).max(1);
+
+    if canvas.width() != width || canvas.height() != height {
+        canvas.set_width(width);
+        canvas.set_height(height);
+    }
+
+    let mut store = app.store.borrow_mut();
+    if store.state.canvas_size() != (width, height) {
+        store.msg(&Msg::Resize(width, height));
+    }
+}
+
+/// Mouse and touch events are in CSS pixels, while our canvas' drawing buffer and
+/// `State::canvas_size` are in device pixels
+pub fn css_to_device_pixels(x: f32, y: f32) -> (f32, f32) {
+    let pixel_ratio = window().unwrap().device_pixel_ratio() as f32;
+
+    (x * pixel_ratio, y * pixel_ratio)
+}
+
 fn attach_mouse_down_handler(canvas: &HtmlCanvasElement, app: Rc<App>) -> Result<(), JsValue> {
     let handler = move |event: web_sys::MouseEvent| {
-        let x = event.client_x();
-        let y = event.client_y();
+        // Relative to the canvas so that we can tell what was clicked on
+        let x = event.offset_x();
+        let y = event.offset_y();
         app.store.borrow_mut().msg(&Msg::MouseDown(x, y));
+        ripple_water_under_pixel(&app, x, y);
     };
 
     let handler = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
@@ -87,8 +146,8 @@ fn attach_mouse_up_handler(canvas: &HtmlCanvasElement, app: Rc<App>) -> Result<(
 fn attach_mouse_move_handler(canvas: &HtmlCanvasElement, app: Rc<App>) -> Result<(), JsValue> {
     let handler = move |event: web_sys::MouseEvent| {
         event.prevent_default();
-        let x = event.client_x();
-        let y = event.client_y();
+        let x = event.offset_x();
+        let y = event.offset_y();
         app.store.borrow_mut().msg(&Msg::MouseMove(x, y));
     };
 
@@ -116,11 +175,13 @@ fn attach_mouse_wheel_handler(canvas: &HtmlCanvasElement, app: Rc<App>) -> Resul
 }
 
 fn attach_touch_start_handler(canvas: &HtmlCanvasElement, app: Rc<App>) -> Result<(), JsValue> {
+    let canvas_clone = canvas.clone();
+
     let handler = move |event: web_sys::TouchEvent| {
         let touch = event.touches().item(0).expect("First Touch");
-        let x = touch.client_x();
-        let y = touch.client_y();
+        let (x, y) = touch_pos_in_canvas(&canvas_clone, &touch);
         app.store.borrow_mut().msg(&Msg::MouseDown(x, y));
+        ripple_water_under_pixel(&app, x, y);
     };
 
     let handler = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
@@ -131,11 +192,12 @@ fn attach_touch_start_handler(canvas: &HtmlCanvasElement, app: Rc<App>) -> Resul
 }
 
 fn attach_touch_move_handler(canvas: &HtmlCanvasElement, app: Rc<App>) -> Result<(), JsValue> {
+    let canvas_clone = canvas.clone();
+
     let handler = move |event: web_sys::TouchEvent| {
         event.prevent_default();
         let touch = event.touches().item(0).expect("First Touch");
-        let x = touch.client_x();
-        let y = touch.client_y();
+        let (x, y) = touch_pos_in_canvas(&canvas_clone, &touch);
         app.store.borrow_mut().msg(&Msg::MouseMove(x, y));
     };
 
@@ -159,3 +221,55 @@ fn attach_touch_end_handler(canvas: &HtmlCanvasElement, app: Rc<App>) -> Result<
 
     Ok(())
 }
+
+fn attach_key_down_handler(window: &Window, app: Rc<App>) -> Result<(), JsValue> {
+    let handler = move |event: web_sys::KeyboardEvent| {
+        // Don't scroll the page while Space is being used to fly upwards
+        let flying = app.store.borrow().state.camera().mode() == CameraMode::FreeFly;
+        if flying && event.code() == "Space" {
+            event.prevent_default();
+        }
+
+        app.store.borrow_mut().msg(&Msg::KeyDown(event.code()));
+    };
+
+    let handler = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
+    window.add_event_listener_with_callback("keydown", handler.as_ref().unchecked_ref())?;
+    handler.forget();
+
+    Ok(())
+}
+
+fn attach_key_up_handler(window: &Window, app: Rc<App>) -> Result<(), JsValue> {
+    let handler = move |event: web_sys::KeyboardEvent| {
+        app.store.borrow_mut().msg(&Msg::KeyUp(event.code()));
+    };
+
+    let handler = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
+    window.add_event_listener_with_callback("keyup", handler.as_ref().unchecked_ref())?;
+    handler.forget();
+
+    Ok(())
+}
+
+/// Touches only know where they are on the page, so we offset them by where the canvas is
+fn touch_pos_in_canvas(canvas: &HtmlCanvasElement, touch: &Touch) -> (i32, i32) {
+    let rect = canvas.get_bounding_client_rect();
+
+    (
+        touch.client_x() - rect.left() as i32,
+        touch.client_y() - rect.top() as i32,
+    )
+}
+
+/// Touching the water starts a ripple
+fn ripple_water_under_pixel(app: &App, x: i32, y: i32) {
+    let (x, y) = css_to_device_pixels(x as f32, y as f32);
+
+    if let Some(pick) = app.pick(x, y) {
+        if let PickTarget::Water(_) = pick.target {
+            let msg = Msg::DisturbWater(pick.point.x, pick.point.z, CLICK_RIPPLE_STRENGTH);
+            app.store.borrow_mut().msg(&msg);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use crate::app::CameraMode;
+use crate::app::QualityPreset;
 use crate::canvas::APP_DIV_ID;
 use crate::App;
 use crate::Msg;
@@ -9,6 +11,17 @@ use web_sys::window;
 use web_sys::Element;
 use web_sys::HtmlElement;
 use web_sys::HtmlInputElement;
+use web_sys::HtmlSelectElement;
+
+static USE_REFRACTION_CHECKBOX_ID: &'static str = "use-refraction";
+static USE_REFLECTION_CHECKBOX_ID: &'static str = "use-reflection";
+
+static PAUSE_ANIMATIONS_CHECKBOX_ID: &'static str = "pause-animations";
+
+/// The frame rate that the adaptive resolution checkbox tries to hold
+static ADAPTIVE_RESOLUTION_TARGET_FPS: f32 = 60.;
+
+static ANIMATION_TIMELINE_SLIDER_ID: &'static str = "animation-timeline";
 
 pub fn append_controls(app: Rc<App>) -> Result<(), JsValue> {
     let window = window().unwrap();
@@ -25,6 +38,20 @@ pub fn append_controls(app: Rc<App>) -> Result<(), JsValue> {
     controls.style().set_property("padding-left", "5px")?;
     let controls: Element = controls.dyn_into()?;
 
+    // Quality
+    {
+        let app = Rc::clone(&app);
+        let quality_control = create_quality_control(app)?;
+        controls.append_child(&quality_control)?;
+    }
+
+    // Adaptive Resolution
+    {
+        let app = Rc::clone(&app);
+        let adaptive_resolution_control = create_adaptive_resolution_checkbox(app)?;
+        controls.append_child(&adaptive_resolution_control)?;
+    }
+
     // Reflectivity
     {
         let app = Rc::clone(&app);
@@ -67,9 +94,109 @@ pub fn append_controls(app: Rc<App>) -> Result<(), JsValue> {
         controls.append_child(&show_scenery_control)?;
     }
 
+    // Camera Mode
+    {
+        let app = Rc::clone(&app);
+        let camera_mode_control = create_camera_mode_control(app)?;
+        controls.append_child(&camera_mode_control)?;
+    }
+
+    // Pause Animations
+    {
+        let app = Rc::clone(&app);
+        let pause_animations_control = create_pause_animations_checkbox(app)?;
+        controls.append_child(&pause_animations_control)?;
+    }
+
+    // Animation Speed
+    {
+        let app = Rc::clone(&app);
+        let animation_speed_control = create_animation_speed_control(app)?;
+        controls.append_child(&animation_speed_control)?;
+    }
+
+    // Animation Timeline
+    {
+        let app = Rc::clone(&app);
+        let animation_timeline_control = create_animation_timeline_control(app)?;
+        controls.append_child(&animation_timeline_control)?;
+    }
+
     Ok(())
 }
 
+fn create_quality_control(app: Rc<App>) -> Result<HtmlElement, JsValue> {
+    let handler = move |event: web_sys::Event| {
+        let select_elem: HtmlSelectElement = event.target().unwrap().dyn_into().unwrap();
+        let quality = match select_elem.value().as_str() {
+            "low" => QualityPreset::Low,
+            "medium" => QualityPreset::Medium,
+            "ultra" => QualityPreset::Ultra,
+            _ => QualityPreset::High,
+        };
+
+        app.store.borrow_mut().msg(&Msg::SetQuality(quality));
+
+        // The low preset turns reflection off and the others turn it back on, so keep the
+        // reflection and refraction checkboxes in sync with whatever the preset chose
+        let store = app.store.borrow();
+        let water = store.state.water();
+        set_checkbox(USE_REFRACTION_CHECKBOX_ID, water.use_refraction);
+        set_checkbox(USE_REFLECTION_CHECKBOX_ID, water.use_reflection);
+    };
+    let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
+
+    let quality_control = Dropdown {
+        options: &[
+            ("low", "Low"),
+            ("medium", "Medium"),
+            ("high", "High"),
+            ("ultra", "Ultra"),
+        ],
+        start: "high",
+        label: "Quality",
+        closure,
+    }
+    .create_element()?;
+
+    Ok(quality_control)
+}
+
+fn create_adaptive_resolution_checkbox(app: Rc<App>) -> Result<HtmlElement, JsValue> {
+    let handler = move |event: web_sys::Event| {
+        let input_elem: HtmlInputElement = event.target().unwrap().dyn_into().unwrap();
+        let target_fps = if input_elem.checked() {
+            Some(ADAPTIVE_RESOLUTION_TARGET_FPS)
+        } else {
+            None
+        };
+
+        app.store
+            .borrow_mut()
+            .msg(&Msg::SetAdaptiveResolution(target_fps));
+    };
+    let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
+
+    let adaptive_resolution_control = Checkbox {
+        id: "adaptive-resolution",
+        start_checked: false,
+        label: "Adaptive Resolution",
+        closure,
+    }
+    .create_element()?;
+
+    Ok(adaptive_resolution_control)
+}
+
+fn set_checkbox(id: &str, checked: bool) {
+    let document = window().unwrap().document().unwrap();
+
+    if let Some(checkbox) = document.get_element_by_id(id) {
+        let checkbox: HtmlInputElement = checkbox.dyn_into().unwrap();
+        checkbox.set_checked(checked);
+    }
+}
+
 fn create_reflectivity_control(app: Rc<App>) -> Result<HtmlElement, JsValue> {
     let handler = move |event: web_sys::Event| {
         let input_elem: HtmlInputElement = event.target().unwrap().dyn_into().unwrap();
@@ -82,6 +209,7 @@ fn create_reflectivity_control(app: Rc<App>) -> Result<HtmlElement, JsValue> {
     let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
 
     let reflectivity_control = Slider {
+        id: None,
         min: 0.0,
         max: 1.0,
         step: 0.1,
@@ -104,6 +232,7 @@ fn create_fresnel_control(app: Rc<App>) -> Result<HtmlElement, JsValue> {
     let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
 
     let fresnel_control = Slider {
+        id: None,
         min: 0.0,
         max: 10.0,
         step: 0.1,
@@ -126,6 +255,7 @@ fn create_wave_speed_control(app: Rc<App>) -> Result<HtmlElement, JsValue> {
     let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
 
     let wave_speed_control = Slider {
+        id: None,
         min: 0.0,
         max: 0.15,
         step: 0.01,
@@ -150,6 +280,7 @@ fn create_use_refraction_checkbox(app: Rc<App>) -> Result<HtmlElement, JsValue>
     let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
 
     let use_refraction_control = Checkbox {
+        id: USE_REFRACTION_CHECKBOX_ID,
         start_checked: true,
         label: "Use Refraction",
         closure,
@@ -171,6 +302,7 @@ fn create_use_reflection_checkbox(app: Rc<App>) -> Result<HtmlElement, JsValue>
     let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
 
     let use_reflection_control = Checkbox {
+        id: USE_REFLECTION_CHECKBOX_ID,
         start_checked: true,
         label: "Use Reflection",
         closure,
@@ -190,6 +322,7 @@ fn create_show_scenery_control(app: Rc<App>) -> Result<HtmlElement, JsValue> {
     let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
 
     let show_scenery_control = Checkbox {
+        id: "show-scenery",
         start_checked: true,
         label: "Show Scenery",
         closure,
@@ -199,7 +332,141 @@ fn create_show_scenery_control(app: Rc<App>) -> Result<HtmlElement, JsValue> {
     Ok(show_scenery_control)
 }
 
+fn create_camera_mode_control(app: Rc<App>) -> Result<HtmlElement, JsValue> {
+    let handler = move |event: web_sys::Event| {
+        let select_elem: HtmlSelectElement = event.target().unwrap().dyn_into().unwrap();
+        let camera_mode = match select_elem.value().as_str() {
+            "free-fly" => CameraMode::FreeFly,
+            "first-person" => CameraMode::FirstPerson,
+            _ => CameraMode::Orbit,
+        };
+
+        app.store.borrow_mut().msg(&Msg::SetCameraMode(camera_mode));
+    };
+    let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
+
+    let camera_mode_control = Dropdown {
+        options: &[
+            ("orbit", "Orbit"),
+            ("free-fly", "Free Fly (WASD, Space, Shift)"),
+            ("first-person", "First Person (WASD)"),
+        ],
+        start: "orbit",
+        label: "Camera",
+        closure,
+    }
+    .create_element()?;
+
+    Ok(camera_mode_control)
+}
+
+fn create_pause_animations_checkbox(app: Rc<App>) -> Result<HtmlElement, JsValue> {
+    let handler = move |event: web_sys::Event| {
+        let input_elem: HtmlInputElement = event.target().unwrap().dyn_into().unwrap();
+        let paused = input_elem.checked();
+
+        app.store.borrow_mut().msg(&Msg::PauseAnimation {
+            entity: None,
+            paused,
+        });
+    };
+    let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
+
+    let pause_animations_control = Checkbox {
+        id: PAUSE_ANIMATIONS_CHECKBOX_ID,
+        start_checked: false,
+        label: "Pause Animations",
+        closure,
+    }
+    .create_element()?;
+
+    Ok(pause_animations_control)
+}
+
+fn create_animation_speed_control(app: Rc<App>) -> Result<HtmlElement, JsValue> {
+    let handler = move |event: web_sys::Event| {
+        let input_elem: HtmlInputElement = event.target().unwrap().dyn_into().unwrap();
+        let rate = match input_elem.value().parse() {
+            Ok(rate) => rate,
+            Err(_) => return,
+        };
+
+        app.store
+            .borrow_mut()
+            .msg(&Msg::SetAnimationRate { entity: None, rate });
+    };
+    let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
+
+    let animation_speed_control = Slider {
+        id: None,
+        min: 0.0,
+        max: 3.0,
+        step: 0.1,
+        start: 1.0,
+        label: "Animation Speed",
+        closure,
+    }
+    .create_element()?;
+
+    Ok(animation_speed_control)
+}
+
+fn create_animation_timeline_control(app: Rc<App>) -> Result<HtmlElement, JsValue> {
+    let handler = move |event: web_sys::Event| {
+        let input_elem: HtmlInputElement = event.target().unwrap().dyn_into().unwrap();
+        let time = match input_elem.value().parse() {
+            Ok(time) => time,
+            Err(_) => return,
+        };
+
+        // Scrubbing is for inspecting poses, so we hold still wherever the slider is let go
+        let mut store = app.store.borrow_mut();
+        store.msg(&Msg::PauseAnimation {
+            entity: None,
+            paused: true,
+        });
+        store.msg(&Msg::SeekAnimation { entity: None, time });
+
+        set_checkbox(PAUSE_ANIMATIONS_CHECKBOX_ID, true);
+    };
+    let closure = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
+
+    // Our armatures are still downloading, so `update_animation_timeline` sets the real max once
+    // they arrive
+    let animation_timeline_control = Slider {
+        id: Some(ANIMATION_TIMELINE_SLIDER_ID),
+        min: 0.0,
+        max: 0.0,
+        step: 0.01,
+        start: 0.0,
+        label: "Animation Timeline",
+        closure,
+    }
+    .create_element()?;
+
+    Ok(animation_timeline_control)
+}
+
+/// Let the animation timeline slider scrub through exactly as long as the longest action that
+/// our entities are playing, which changes as armatures arrive and actions get played
+pub fn update_animation_timeline(app: &App) {
+    let duration = match app.store.borrow().state.animation_duration(&app.assets()) {
+        Some(duration) => format!("{}", duration),
+        None => return,
+    };
+
+    let document = window().unwrap().document().unwrap();
+
+    if let Some(slider) = document.get_element_by_id(ANIMATION_TIMELINE_SLIDER_ID) {
+        let slider: HtmlInputElement = slider.dyn_into().unwrap();
+        if slider.max() != duration {
+            slider.set_max(&duration);
+        }
+    }
+}
+
 struct Slider {
+    id: Option<&'static str>,
     min: f32,
     max: f32,
     step: f32,
@@ -215,6 +482,9 @@ impl Slider {
 
         let slider: HtmlInputElement = document.create_element("input")?.dyn_into()?;
         slider.set_type("range");
+        if let Some(id) = self.id {
+            slider.set_id(id);
+        }
         slider.set_min(&format!("{}", self.min));
         slider.set_max(&format!("{}", self.max));
         slider.set_step(&format!("{}", self.step));
@@ -239,6 +509,7 @@ impl Slider {
 }
 
 struct Checkbox {
+    id: &'static str,
     start_checked: bool,
     label: &'static str,
     closure: Closure<dyn FnMut(web_sys::Event)>,
@@ -251,6 +522,7 @@ impl Checkbox {
 
         let checkbox: HtmlInputElement = document.create_element("input")?.dyn_into()?;
         checkbox.set_type("checkbox");
+        checkbox.set_id(self.id);
         checkbox.set_checked(self.start_checked);
 
         let closure = self.closure;
@@ -273,3 +545,44 @@ impl Checkbox {
         Ok(container)
     }
 }
+
+struct Dropdown {
+    /// (value, label) pairs
+    options: &'static [(&'static str, &'static str)],
+    start: &'static str,
+    label: &'static str,
+    closure: Closure<dyn FnMut(web_sys::Event)>,
+}
+
+impl Dropdown {
+    fn create_element(self) -> Result<HtmlElement, JsValue> {
+        let window = window().unwrap();
+        let document = window.document().unwrap();
+
+        let select: HtmlSelectElement = document.create_element("select")?.dyn_into()?;
+
+        for (value, label) in self.options.iter() {
+            let option = document.create_element("option")?;
+            option.set_attribute("value", value)?;
+            option.set_inner_html(label);
+            select.append_child(&option)?;
+        }
+        select.set_value(self.start);
+
+        let closure = self.closure;
+        select.set_onchange(Some(closure.as_ref().unchecked_ref()));
+        closure.forget();
+
+        let label = document.create_element("div")?;
+        label.set_inner_html(self.label);
+
+        let container = document.create_element("div")?;
+        container.append_child(&label)?;
+        container.append_child(&select)?;
+
+        let container: HtmlElement = container.dyn_into()?;
+        container.style().set_property("margin-bottom", "15px")?;
+
+        Ok(container)
+    }
+}
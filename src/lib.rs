@@ -21,15 +21,19 @@ pub(in crate) use self::app::*;
 use self::canvas::*;
 use self::controls::*;
 use self::render::*;
+use crate::load_asset_bundle::load_asset_bundle;
 use crate::load_texture_img::load_texture_image;
 use console_error_panic_hook;
+use std::collections::HashSet;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
 use web_sys::*;
 
 mod app;
 mod canvas;
 mod controls;
+mod load_asset_bundle;
 mod load_texture_img;
 mod render;
 mod shader;
@@ -38,30 +42,44 @@ mod shader;
 #[wasm_bindgen]
 pub struct WebClient {
     app: Rc<App>,
-    gl: Rc<WebGlRenderingContext>,
-    renderer: WebRenderer,
+    canvas: HtmlCanvasElement,
+    gl: FrameCapture<WebGlRenderingContext>,
+    renderer: WebRenderer<FrameCapture<WebGlRenderingContext>>,
+    /// So that a broken asset gets logged once instead of every frame
+    logged_asset_errors: HashSet<AssetError>,
 }
 #[wasm_bindgen]
 impl WebClient {
-    /// Create a new web client
+    /// Create a new web client. Fails with a message that the page can show if we can't render on
+    /// this device, ex: because it is missing a WebGL extension that we need.
     #[wasm_bindgen(constructor)]
-    pub fn new() -> WebClient {
+    pub fn new() -> Result<WebClient, JsValue> {
         console_error_panic_hook::set_once();
 
         let app = Rc::new(App::new());
 
-        let gl = Rc::new(create_webgl_context(Rc::clone(&app)).unwrap());
-        append_controls(Rc::clone(&app)).expect("Append controls");
+        let gl = Rc::new(create_webgl_context(Rc::clone(&app))?);
+        append_controls(Rc::clone(&app))?;
 
-        let renderer = WebRenderer::new(&gl);
+        let canvas: HtmlCanvasElement = gl.canvas().unwrap().dyn_into().unwrap();
 
-        WebClient { app, gl, renderer }
+        let gl = FrameCapture::new(gl);
+        let renderer = WebRenderer::new(&gl, &app.store.borrow().state)
+            .map_err(|err| JsValue::from_str(&err))?;
+
+        Ok(WebClient {
+            app,
+            canvas,
+            gl,
+            renderer,
+            logged_asset_errors: HashSet::new(),
+        })
     }
 
     /// Start our WebGL Water application. `index.html` will call this function in order
     /// to begin rendering.
     pub fn start(&self) -> Result<(), JsValue> {
-        let gl = &self.gl;
+        let gl = self.gl.inner();
 
         load_texture_image(
             Rc::clone(gl),
@@ -79,17 +97,272 @@ impl WebClient {
             TextureUnit::Stone,
         );
 
+        self.load_meshes("/meshes.bytes");
+        self.load_armatures("/armatures.bytes");
+
         Ok(())
     }
 
+    /// Download a bincode bundle of meshes, such as `meshes.bytes`. Entities that use a mesh
+    /// don't get rendered until it arrives.
+    pub fn load_meshes(&self, url: &str) {
+        load_asset_bundle(Rc::clone(&self.app), url, AssetBundle::Meshes);
+    }
+
+    /// Download a bincode bundle of armatures, such as `armatures.bytes`
+    pub fn load_armatures(&self, url: &str) {
+        load_asset_bundle(Rc::clone(&self.app), url, AssetBundle::Armatures);
+    }
+
+    /// How far along our asset bundle downloads are, as JSON such as
+    /// `{"loadedBytes": 1024, "totalBytes": 4096, "done": false, "errors": []}`.
+    /// Bundles whose size the server didn't tell us aren't counted in `totalBytes`.
+    pub fn asset_progress(&self) -> String {
+        let assets = self.app.assets();
+        let bundles = assets.bundles();
+
+        let loaded_bytes: u64 = bundles.iter().map(|bundle| bundle.loaded_bytes).sum();
+        let total_bytes: u64 = bundles.iter().filter_map(|bundle| bundle.total_bytes).sum();
+
+        let errors: Vec<String> = bundles
+            .iter()
+            .filter_map(|bundle| match &bundle.status {
+                BundleStatus::Failed(err) => Some(format!("{}: {}", bundle.url, err)),
+                _ => None,
+            })
+            .collect();
+
+        let progress = serde_json::json!({
+            "loadedBytes": loaded_bytes,
+            "totalBytes": total_bytes,
+            "done": !assets.is_downloading(),
+            "errors": errors,
+        });
+
+        progress.to_string()
+    }
+
     /// Update our simulation
     pub fn update(&self, dt: f32) {
-        self.app.store.borrow_mut().msg(&Msg::AdvanceClock(dt));
+        self.app.update(dt);
+        update_animation_timeline(&self.app);
+    }
+
+    /// Add a body of water centered at (x, z) with its surface at y = `height`
+    pub fn add_water_body(&self, x: f32, z: f32, x_extent: f32, z_extent: f32, height: f32) {
+        let water_body = WaterBody::new((x, z), (x_extent, z_extent), height);
+        self.app
+            .store
+            .borrow_mut()
+            .msg(&Msg::AddWaterBody(water_body));
+    }
+
+    /// Remove the water body that was added at this index. The default scene's pond is index 0.
+    pub fn remove_water_body(&self, index: usize) {
+        self.app
+            .store
+            .borrow_mut()
+            .msg(&Msg::RemoveWaterBody(index));
+    }
+
+    /// The y coordinate of the water's surface at a world (x, z), or undefined if there is no
+    /// water there. Used to float objects on top of our waves.
+    pub fn water_height_at(&self, x: f32, z: f32) -> Option<f32> {
+        self.app.store.borrow().state.water_height_at(x, z)
+    }
+
+    /// The [x, y, z] normal of the water's surface at a world (x, z), or an empty array if there
+    /// is no water there.
+    pub fn water_normal_at(&self, x: f32, z: f32) -> Vec<f32> {
+        match self.app.store.borrow().state.water_normal_at(x, z) {
+            Some(normal) => vec![normal.x, normal.y, normal.z],
+            None => vec![],
+        }
+    }
+
+    /// Start a ripple on the water at a world (x, z). Call this when objects that the
+    /// application doesn't know about splash into the water.
+    pub fn disturb_water(&self, x: f32, z: f32, strength: f32) {
+        self.app
+            .store
+            .borrow_mut()
+            .msg(&Msg::DisturbWater(x, z, strength));
+    }
+
+    /// Render reflections at a fixed `width` x `height` pixels, no matter how large the canvas is
+    pub fn set_reflection_resolution(&self, width: u32, height: u32) {
+        let resolution = FramebufferResolution::Fixed(width, height);
+        self.app
+            .store
+            .borrow_mut()
+            .msg(&Msg::SetReflectionResolution(resolution));
+    }
+
+    /// Render reflections at `scale` times the canvas' size, ex: 0.25 for a quarter of the size
+    pub fn set_reflection_scale(&self, scale: f32) {
+        let resolution = FramebufferResolution::CanvasScale(scale);
+        self.app
+            .store
+            .borrow_mut()
+            .msg(&Msg::SetReflectionResolution(resolution));
+    }
+
+    /// Render refractions at a fixed `width` x `height` pixels, no matter how large the canvas is
+    pub fn set_refraction_resolution(&self, width: u32, height: u32) {
+        let resolution = FramebufferResolution::Fixed(width, height);
+        self.app
+            .store
+            .borrow_mut()
+            .msg(&Msg::SetRefractionResolution(resolution));
+    }
+
+    /// Render refractions at `scale` times the canvas' size, ex: 1.0 for full resolution
+    pub fn set_refraction_scale(&self, scale: f32) {
+        let resolution = FramebufferResolution::CanvasScale(scale);
+        self.app
+            .store
+            .borrow_mut()
+            .msg(&Msg::SetRefractionResolution(resolution));
+    }
+
+    /// Automatically lower the resolution of our water rendering whenever we can't hold
+    /// `target_fps`, raising it back up when we can. Pass undefined to turn this off.
+    pub fn set_adaptive_resolution(&self, target_fps: Option<f32>) {
+        self.app
+            .store
+            .borrow_mut()
+            .msg(&Msg::SetAdaptiveResolution(target_fps));
+    }
+
+    /// Change the camera's projection and orbit settings from JSON such as
+    /// `{"fovy": 1.0, "far": 100.0, "orbitTarget": [0, 0, 0], "maxOrbitRadius": 40.0}`.
+    /// Settings that are left out go back to their defaults. The aspect ratio always follows the
+    /// canvas. Fails without changing anything if the settings don't make sense, ex: a `near`
+    /// that isn't closer than `far`.
+    pub fn set_camera_config(&self, config: &str) -> Result<(), JsValue> {
+        let mut config: CameraConfig = serde_json::from_str(config)
+            .map_err(|err| JsValue::from_str(&format!("Invalid camera config: {}", err)))?;
+
+        let mut store = self.app.store.borrow_mut();
+        config.aspect = store.state.camera().config().aspect;
+
+        config
+            .validate()
+            .map_err(|err| JsValue::from_str(&format!("Invalid camera config: {}", err)))?;
+
+        store.msg(&Msg::SetCameraConfig(config));
+
+        Ok(())
+    }
+
+    /// Replace every entity and body of water with the ones in a JSON scene, in the same format
+    /// as `scene.json`. Fails without changing anything if the scene can't be parsed or uses a
+    /// mesh, armature or action that we don't have. While asset bundles are still downloading
+    /// only the assets that have already arrived get checked.
+    pub fn load_scene(&self, scene: &str) -> Result<(), JsValue> {
+        let scene: Scene = serde_json::from_str(scene)
+            .map_err(|err| JsValue::from_str(&format!("Invalid scene: {}", err)))?;
+
+        scene
+            .validate(&self.app.assets())
+            .map_err(|err| JsValue::from_str(&format!("Invalid scene: {}", err)))?;
+
+        self.app.store.borrow_mut().msg(&Msg::LoadScene(scene));
+
+        Ok(())
+    }
+
+    /// Crossfade an animated entity into another one of its armature's actions over
+    /// `blend_secs`, ex: `play_action("Bird", "Glide", 0.3)`
+    pub fn play_action(&self, entity: &str, action: &str, blend_secs: f32) -> Result<(), JsValue> {
+        let mut store = self.app.store.borrow_mut();
+
+        let armature_name = store
+            .state
+            .scene_graph()
+            .nodes()
+            .iter()
+            .filter(|node| node.entity().name() == entity)
+            .find_map(|node| node.entity().armature.clone())
+            .ok_or_else(|| JsValue::from_str(&format!("No animated entity named {}", entity)))?;
+
+        let has_action = self
+            .app
+            .assets()
+            .get_armature(&armature_name)
+            .map_or(false, |armature| armature.actions.contains_key(action));
+        if !has_action {
+            return Err(JsValue::from_str(&format!(
+                "Armature {} has no action {}",
+                armature_name, action
+            )));
+        }
+
+        store.msg(&Msg::PlayAction {
+            entity: entity.to_string(),
+            action: action.to_string(),
+            blend_secs,
+        });
+
+        Ok(())
+    }
+
+    /// Shift an entity's animation by `offset` seconds, ex: to keep the birds in a flock from
+    /// flapping in lockstep
+    pub fn set_animation_offset(&self, entity: &str, offset: f32) {
+        self.app.store.borrow_mut().msg(&Msg::SetAnimationOffset {
+            entity: entity.to_string(),
+            offset,
+        });
+    }
+
+    /// Find what is under the pixel (x, y) of our canvas, in CSS pixels like a `MouseEvent`'s
+    /// `offsetX` and `offsetY`. Returns JSON such as
+    /// `{"mesh": "Terrain", "water": null, "point": [x, y, z], "normal": [x, y, z]}`
+    /// or undefined if there is nothing there.
+    pub fn pick(&self, x: f32, y: f32) -> Option<String> {
+        let (x, y) = css_to_device_pixels(x, y);
+        let pick = self.app.pick(x, y)?;
+
+        let (mesh, water) = match pick.target {
+            PickTarget::Mesh(mesh_name) => (Some(mesh_name), None),
+            PickTarget::Water(index) => (None, Some(index)),
+        };
+
+        let pick = serde_json::json!({
+            "mesh": mesh,
+            "water": water,
+            "point": [pick.point.x, pick.point.y, pick.point.z],
+            "normal": [pick.normal.x, pick.normal.y, pick.normal.z],
+        });
+
+        Some(pick.to_string())
     }
 
     /// Render the scene. `index.html` will call this once every requestAnimationFrame
     pub fn render(&mut self) {
+        resize_canvas(&self.canvas, &self.app);
+
         self.renderer
             .render(&self.gl, &self.app.store.borrow().state, &self.app.assets());
+
+        for err in self.renderer.asset_errors().iter() {
+            if self.logged_asset_errors.insert(err.clone()) {
+                console::error_1(&format!("{}. Rendering a placeholder instead.", err).into());
+            }
+        }
+    }
+
+    /// Render the scene while recording every GL call that gets made.
+    ///
+    /// Returns the frame's commands as JSON so that frames from two different builds can
+    /// be diffed.
+    pub fn capture_frame(&mut self) -> Result<String, JsValue> {
+        self.gl.start_capture();
+        self.render();
+
+        self.gl
+            .finish_capture_json()
+            .map_err(|err| JsValue::from_str(&format!("Could not serialize frame: {}", err)))
     }
 }
@@ -0,0 +1,123 @@
+use crate::app::{App, AssetBundle};
+use js_sys::{ArrayBuffer, Uint8Array, WebAssembly};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{console, ProgressEvent, XmlHttpRequest, XmlHttpRequestResponseType};
+
+/// Download a bincode bundle of meshes or armatures and add them to our `Assets` once it arrives.
+///
+/// Entities that use assets that haven't arrived yet don't get rendered, so the scene fills in
+/// as bundles finish downloading. `WebClient::asset_progress` reports how far along we are.
+pub fn load_asset_bundle(app: Rc<App>, url: &str, bundle: AssetBundle) {
+    app.assets_mut().start_download(url);
+
+    let xhr = Rc::new(XmlHttpRequest::new().unwrap());
+
+    let onprogress = {
+        let app = Rc::clone(&app);
+        let url = url.to_string();
+
+        move |event: ProgressEvent| {
+            let total_bytes = if event.length_computable() {
+                Some(event.total() as u64)
+            } else {
+                None
+            };
+
+            app.assets_mut()
+                .download_progress(&url, event.loaded() as u64, total_bytes);
+        }
+    };
+    let onprogress = Closure::wrap(Box::new(onprogress) as Box<dyn FnMut(_)>);
+
+    let onload = {
+        let app = Rc::clone(&app);
+        let url = url.to_string();
+        let xhr = Rc::clone(&xhr);
+
+        move || {
+            let mut assets = app.assets_mut();
+
+            let result = match response_bytes(&xhr) {
+                Ok(bytes) => assets
+                    .insert_bundle(bundle, &bytes)
+                    .map_err(|err| err.to_string()),
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = result.as_ref() {
+                console::error_1(&format!("{}: {}", url, err).into());
+            }
+
+            assets.finish_download(&url, result);
+        }
+    };
+    let onload = Closure::wrap(Box::new(onload) as Box<dyn FnMut()>);
+
+    // A request that never loads has to finish its download too, otherwise `is_downloading`
+    // stays true and the entities waiting on the bundle get skipped forever
+    let onerror = download_failed(Rc::clone(&app), url, "Could not download");
+    let onabort = download_failed(Rc::clone(&app), url, "Aborted the download of");
+    let ontimeout = download_failed(app, url, "Timed out downloading");
+
+    xhr.set_response_type(XmlHttpRequestResponseType::Arraybuffer);
+    xhr.set_onprogress(Some(onprogress.as_ref().unchecked_ref()));
+    xhr.set_onload(Some(onload.as_ref().unchecked_ref()));
+    xhr.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+    xhr.set_onabort(Some(onabort.as_ref().unchecked_ref()));
+    xhr.set_ontimeout(Some(ontimeout.as_ref().unchecked_ref()));
+
+    xhr.open("GET", url).unwrap();
+    xhr.send().unwrap();
+
+    onprogress.forget();
+    onload.forget();
+    onerror.forget();
+    onabort.forget();
+    ontimeout.forget();
+}
+
+/// A request handler that reports a download that ended without a response, ex: "Could not
+/// download" when the connection drops
+fn download_failed(app: Rc<App>, url: &str, reason: &'static str) -> Closure<dyn FnMut()> {
+    let url = url.to_string();
+
+    let onfailed = move || {
+        let err = format!("{} {}", reason, url);
+        console::error_1(&err.clone().into());
+
+        app.assets_mut().finish_download(&url, Err(err));
+    };
+
+    Closure::wrap(Box::new(onfailed) as Box<dyn FnMut()>)
+}
+
+/// The bytes that a finished request downloaded, or an error if the server didn't send any
+fn response_bytes(xhr: &XmlHttpRequest) -> Result<Vec<u8>, String> {
+    let status = xhr.status().unwrap_or(0);
+    if status < 200 || status >= 300 {
+        return Err(format!(
+            "{} {}",
+            status,
+            xhr.status_text().unwrap_or_default()
+        ));
+    }
+
+    let response: ArrayBuffer = xhr
+        .response()
+        .ok()
+        .and_then(|response| response.dyn_into().ok())
+        .ok_or_else(|| "The response was not an ArrayBuffer".to_string())?;
+    let response = Uint8Array::new(&response);
+
+    // Copy the whole response into our wasm memory at once instead of one byte at a time
+    let mut bytes = vec![0; response.length() as usize];
+    let memory = wasm_bindgen::memory()
+        .dyn_into::<WebAssembly::Memory>()
+        .unwrap()
+        .buffer();
+    Uint8Array::new(&memory).set(&response, bytes.as_mut_ptr() as u32);
+
+    Ok(bytes)
+}
@@ -1,61 +1,98 @@
-// Reflection texture can be smaller since it gets distorted by the waves.
-pub static REFLECTION_TEXTURE_WIDTH: i32 = 128;
-pub static REFLECTION_TEXTURE_HEIGHT: i32 = 128;
-
-// Due to the fresnel effect when you look above the water it becomes very transparent,
-// so we want a larger texture for refraction so that the objects below the water can
-// be seen clearly.
-pub static REFRACTION_TEXTURE_WIDTH: i32 = 512;
-pub static REFRACTION_TEXTURE_HEIGHT: i32 = 512;
-
+use crate::app::AssetError;
+use crate::render::GlContext;
 use crate::render::TextureUnit;
 use crate::render::WebRenderer;
 use wasm_bindgen::JsValue;
 use web_sys::WebGlRenderingContext as GL;
-use web_sys::*;
 
-pub struct Framebuffer {
-    pub framebuffer: Option<WebGlFramebuffer>,
-    pub color_texture: Option<WebGlTexture>,
-    pub depth_texture: Option<WebGlTexture>,
+pub struct Framebuffer<G: GlContext> {
+    pub framebuffer: Option<G::Framebuffer>,
+    pub color_texture: Option<G::Texture>,
+    pub depth_texture: Option<G::Texture>,
+    pub depth_renderbuffer: Option<G::Renderbuffer>,
+    pub width: i32,
+    pub height: i32,
 }
 
-impl WebRenderer {
+impl<G: GlContext> Framebuffer<G> {
+    /// Release the framebuffer and everything that is attached to it
+    pub fn delete(&self, gl: &G) {
+        gl.delete_framebuffer(self.framebuffer.as_ref());
+        gl.delete_texture(self.color_texture.as_ref());
+        gl.delete_texture(self.depth_texture.as_ref());
+        gl.delete_renderbuffer(self.depth_renderbuffer.as_ref());
+    }
+}
+
+impl<G: GlContext> WebRenderer<G> {
+    /// Create the framebuffer that we render what's under the water into, releasing everything
+    /// that we allocated for it if the device can't fit it
     pub(in crate::render) fn create_refraction_framebuffer(
-        gl: &WebGlRenderingContext,
-    ) -> Result<Framebuffer, JsValue> {
-        let framebuffer = gl.create_framebuffer();
-        gl.bind_framebuffer(GL::FRAMEBUFFER, framebuffer.as_ref());
+        gl: &G,
+        (width, height): (i32, i32),
+    ) -> Result<Framebuffer<G>, AssetError> {
+        let framebuffer = Framebuffer {
+            framebuffer: gl.create_framebuffer(),
+            color_texture: gl.create_texture(),
+            depth_texture: gl.create_texture(),
+            depth_renderbuffer: None,
+            width,
+            height,
+        };
+
+        let allocated = framebuffer.framebuffer.is_some()
+            && framebuffer.color_texture.is_some()
+            && framebuffer.depth_texture.is_some();
+        let attached =
+            allocated && WebRenderer::attach_refraction_textures(gl, &framebuffer).is_ok();
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+
+        if attached {
+            Ok(framebuffer)
+        } else {
+            framebuffer.delete(gl);
+            Err(AssetError::GpuResource("the refraction framebuffer"))
+        }
+    }
+
+    fn attach_refraction_textures(gl: &G, framebuffer: &Framebuffer<G>) -> Result<(), JsValue> {
+        let Framebuffer {
+            color_texture,
+            depth_texture,
+            width,
+            height,
+            ..
+        } = framebuffer;
+
+        gl.bind_framebuffer(GL::FRAMEBUFFER, framebuffer.framebuffer.as_ref());
 
         gl.active_texture(TextureUnit::Refraction.TEXTURE_N());
-        let color_texture = gl.create_texture();
         gl.bind_texture(GL::TEXTURE_2D, color_texture.as_ref());
 
         gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::LINEAR as i32);
         gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::LINEAR as i32);
-        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+        gl.tex_image_2d(
             GL::TEXTURE_2D,
             0,
             GL::RGBA as i32,
-            REFRACTION_TEXTURE_WIDTH,
-            REFRACTION_TEXTURE_HEIGHT,
+            *width,
+            *height,
             0,
             GL::RGBA as u32,
             GL::UNSIGNED_BYTE,
             None,
         )?;
 
-        let depth_texture = gl.create_texture();
         gl.active_texture(TextureUnit::RefractionDepth.TEXTURE_N());
         gl.bind_texture(GL::TEXTURE_2D, depth_texture.as_ref());
         gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::LINEAR as i32);
         gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::LINEAR as i32);
-        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+        gl.tex_image_2d(
             GL::TEXTURE_2D,
             0,
             GL::DEPTH_COMPONENT as i32,
-            REFRACTION_TEXTURE_WIDTH,
-            REFRACTION_TEXTURE_HEIGHT,
+            *width,
+            *height,
             0,
             GL::DEPTH_COMPONENT as u32,
             GL::UNSIGNED_SHORT,
@@ -78,47 +115,69 @@ impl WebRenderer {
             0,
         );
 
+        Ok(())
+    }
+
+    /// Create the framebuffer that we render what's above the water into, releasing everything
+    /// that we allocated for it if the device can't fit it
+    pub(in crate::render) fn create_reflection_framebuffer(
+        gl: &G,
+        (width, height): (i32, i32),
+    ) -> Result<Framebuffer<G>, AssetError> {
+        let framebuffer = Framebuffer {
+            framebuffer: gl.create_framebuffer(),
+            color_texture: gl.create_texture(),
+            depth_texture: None,
+            depth_renderbuffer: gl.create_renderbuffer(),
+            width,
+            height,
+        };
+
+        let allocated = framebuffer.framebuffer.is_some()
+            && framebuffer.color_texture.is_some()
+            && framebuffer.depth_renderbuffer.is_some();
+        let attached =
+            allocated && WebRenderer::attach_reflection_buffers(gl, &framebuffer).is_ok();
+        gl.bind_renderbuffer(GL::RENDERBUFFER, None);
         gl.bind_framebuffer(GL::FRAMEBUFFER, None);
 
-        Ok(Framebuffer {
-            framebuffer,
-            color_texture,
-            depth_texture,
-        })
+        if attached {
+            Ok(framebuffer)
+        } else {
+            framebuffer.delete(gl);
+            Err(AssetError::GpuResource("the reflection framebuffer"))
+        }
     }
 
-    pub(in crate::render) fn create_reflection_framebuffer(
-        gl: &WebGlRenderingContext,
-    ) -> Result<Framebuffer, JsValue> {
-        let framebuffer = gl.create_framebuffer();
-        gl.bind_framebuffer(GL::FRAMEBUFFER, framebuffer.as_ref());
+    fn attach_reflection_buffers(gl: &G, framebuffer: &Framebuffer<G>) -> Result<(), JsValue> {
+        let Framebuffer {
+            color_texture,
+            depth_renderbuffer,
+            width,
+            height,
+            ..
+        } = framebuffer;
 
-        let color_texture = gl.create_texture();
+        gl.bind_framebuffer(GL::FRAMEBUFFER, framebuffer.framebuffer.as_ref());
 
         gl.active_texture(TextureUnit::Reflection.TEXTURE_N());
         gl.bind_texture(GL::TEXTURE_2D, color_texture.as_ref());
         gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::LINEAR as i32);
         gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::LINEAR as i32);
-        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+        gl.tex_image_2d(
             GL::TEXTURE_2D,
             0,
             GL::RGBA as i32,
-            REFLECTION_TEXTURE_WIDTH,
-            REFLECTION_TEXTURE_HEIGHT,
+            *width,
+            *height,
             0,
             GL::RGBA as u32,
             GL::UNSIGNED_BYTE,
             None,
         )?;
 
-        let renderbuffer = gl.create_renderbuffer();
-        gl.bind_renderbuffer(GL::RENDERBUFFER, renderbuffer.as_ref());
-        gl.renderbuffer_storage(
-            GL::RENDERBUFFER,
-            GL::DEPTH_COMPONENT16,
-            REFLECTION_TEXTURE_WIDTH,
-            REFLECTION_TEXTURE_HEIGHT,
-        );
+        gl.bind_renderbuffer(GL::RENDERBUFFER, depth_renderbuffer.as_ref());
+        gl.renderbuffer_storage(GL::RENDERBUFFER, GL::DEPTH_COMPONENT16, *width, *height);
 
         gl.framebuffer_texture_2d(
             GL::FRAMEBUFFER,
@@ -131,16 +190,9 @@ impl WebRenderer {
             GL::FRAMEBUFFER,
             GL::DEPTH_ATTACHMENT,
             GL::RENDERBUFFER,
-            renderbuffer.as_ref(),
+            depth_renderbuffer.as_ref(),
         );
 
-        gl.bind_renderbuffer(GL::RENDERBUFFER, None);
-        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
-
-        Ok(Framebuffer {
-            framebuffer,
-            color_texture,
-            depth_texture: None,
-        })
+        Ok(())
     }
 }
@@ -0,0 +1,716 @@
+use crate::render::{GlCommand, GlContext, RecordedUniform, UniformValue};
+use crate::shader::ShaderKind;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+use wasm_bindgen::JsValue;
+use web_sys::WebGlRenderingContext as GL;
+
+/// Wraps another `GlContext` and, while a capture is in progress, records every call that is
+/// made against it as a `GlCommand`.
+///
+/// Every object that gets created through a `FrameCapture` is given an id in creation order.
+/// Since our renderer creates its objects in the same order every time, the ids (and so the
+/// captured frames) can be diffed between builds.
+///
+/// ```ignore
+/// gl.start_capture();
+/// renderer.render(&gl, &state, &assets);
+/// let frame = gl.finish_capture();
+/// ```
+pub struct FrameCapture<G: GlContext> {
+    gl: Rc<G>,
+    capturing: Cell<bool>,
+    commands: RefCell<Vec<GlCommand>>,
+    next_id: Cell<u32>,
+}
+
+/// An object that was created through a `FrameCapture`
+pub struct Captured<T> {
+    id: u32,
+    handle: T,
+}
+
+/// A uniform location that was retrieved through a `FrameCapture`
+#[derive(Clone)]
+pub struct CapturedUniform<L> {
+    uniform: RecordedUniform,
+    location: L,
+}
+
+impl<G: GlContext> FrameCapture<G> {
+    /// Wrap a context. Nothing is recorded until `start_capture` is called.
+    pub fn new(gl: Rc<G>) -> FrameCapture<G> {
+        FrameCapture {
+            gl,
+            capturing: Cell::new(false),
+            commands: RefCell::new(vec![]),
+            next_id: Cell::new(0),
+        }
+    }
+
+    /// The context that we're wrapping. Calls made directly against it aren't captured.
+    pub fn inner(&self) -> &Rc<G> {
+        &self.gl
+    }
+
+    /// Start recording commands, discarding any that were previously captured
+    pub fn start_capture(&self) {
+        self.commands.borrow_mut().clear();
+        self.capturing.set(true);
+    }
+
+    /// Stop recording and return everything that was captured since `start_capture`
+    pub fn finish_capture(&self) -> Vec<GlCommand> {
+        self.capturing.set(false);
+        self.commands.replace(vec![])
+    }
+
+    /// Stop recording and return everything that was captured since `start_capture` as pretty
+    /// printed JSON, so that frames from two different builds can be diffed
+    pub fn finish_capture_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(&self.finish_capture())
+    }
+
+    fn record(&self, command: GlCommand) {
+        if self.capturing.get() {
+            self.commands.borrow_mut().push(command);
+        }
+    }
+
+    fn capture<T>(&self, handle: Option<T>) -> Option<Captured<T>> {
+        let id = self.next_id.get() + 1;
+        self.next_id.set(id);
+
+        handle.map(|handle| Captured { id, handle })
+    }
+
+    /// Record a uniform upload. The value is only built while capturing so that we don't
+    /// allocate every frame.
+    fn uniform(
+        &self,
+        location: Option<&CapturedUniform<G::UniformLocation>>,
+        value: impl FnOnce() -> UniformValue,
+    ) {
+        if !self.capturing.get() {
+            return;
+        }
+
+        self.record(GlCommand::Uniform {
+            location: location.map(|location| location.uniform.clone()),
+            value: value(),
+        });
+    }
+}
+
+fn id<T>(captured: Option<&Captured<T>>) -> Option<u32> {
+    captured.map(|captured| captured.id)
+}
+
+fn handle<T>(captured: Option<&Captured<T>>) -> Option<&T> {
+    captured.map(|captured| &captured.handle)
+}
+
+impl<G: GlContext> GlContext for FrameCapture<G> {
+    type Shader = Captured<G::Shader>;
+    type Program = Captured<G::Program>;
+    type UniformLocation = CapturedUniform<G::UniformLocation>;
+    type Buffer = Captured<G::Buffer>;
+    type Texture = Captured<G::Texture>;
+    type Framebuffer = Captured<G::Framebuffer>;
+    type Renderbuffer = Captured<G::Renderbuffer>;
+    type Vao = Captured<G::Vao>;
+
+    fn marker(&self, label: fmt::Arguments) {
+        if self.capturing.get() {
+            self.record(GlCommand::Marker(label.to_string()));
+        }
+    }
+
+    fn enable_extension(&self, name: &str) -> bool {
+        self.gl.enable_extension(name)
+    }
+
+    fn get_parameter_i32(&self, pname: u32) -> i32 {
+        self.gl.get_parameter_i32(pname)
+    }
+
+    fn create_vertex_array(&self) -> Option<Self::Vao> {
+        self.capture(self.gl.create_vertex_array())
+    }
+
+    fn bind_vertex_array(&self, vao: Option<&Self::Vao>) {
+        self.record(GlCommand::BindVertexArray(id(vao)));
+        self.gl.bind_vertex_array(handle(vao));
+    }
+
+    fn create_shader(&self, shader_type: u32) -> Option<Self::Shader> {
+        self.capture(self.gl.create_shader(shader_type))
+    }
+
+    fn shader_source(&self, shader: &Self::Shader, source: &str) {
+        self.gl.shader_source(&shader.handle, source);
+    }
+
+    fn compile_shader(&self, shader: &Self::Shader) {
+        self.gl.compile_shader(&shader.handle);
+    }
+
+    fn shader_compiled(&self, shader: &Self::Shader) -> bool {
+        self.gl.shader_compiled(&shader.handle)
+    }
+
+    fn get_shader_info_log(&self, shader: &Self::Shader) -> Option<String> {
+        self.gl.get_shader_info_log(&shader.handle)
+    }
+
+    fn create_program(&self) -> Option<Self::Program> {
+        self.capture(self.gl.create_program())
+    }
+
+    fn attach_shader(&self, program: &Self::Program, shader: &Self::Shader) {
+        self.gl.attach_shader(&program.handle, &shader.handle);
+    }
+
+    fn identify_program(&self, program: &Self::Program, shader_kind: ShaderKind) {
+        self.gl.identify_program(&program.handle, shader_kind);
+    }
+
+    fn link_program(&self, program: &Self::Program) {
+        self.gl.link_program(&program.handle);
+    }
+
+    fn program_linked(&self, program: &Self::Program) -> bool {
+        self.gl.program_linked(&program.handle)
+    }
+
+    fn get_program_info_log(&self, program: &Self::Program) -> Option<String> {
+        self.gl.get_program_info_log(&program.handle)
+    }
+
+    fn use_program(&self, program: Option<&Self::Program>) {
+        self.record(GlCommand::UseProgram(id(program)));
+        self.gl.use_program(handle(program));
+    }
+
+    fn get_uniform_location(
+        &self,
+        program: &Self::Program,
+        name: &str,
+    ) -> Option<Self::UniformLocation> {
+        let location = self.gl.get_uniform_location(&program.handle, name)?;
+
+        Some(CapturedUniform {
+            uniform: RecordedUniform {
+                program: program.id,
+                name: name.to_string(),
+            },
+            location,
+        })
+    }
+
+    fn get_attrib_location(&self, program: &Self::Program, name: &str) -> i32 {
+        self.gl.get_attrib_location(&program.handle, name)
+    }
+
+    fn create_buffer(&self) -> Option<Self::Buffer> {
+        self.capture(self.gl.create_buffer())
+    }
+
+    fn bind_buffer(&self, target: u32, buffer: Option<&Self::Buffer>) {
+        self.record(GlCommand::BindBuffer {
+            target,
+            buffer: id(buffer),
+        });
+        self.gl.bind_buffer(target, handle(buffer));
+    }
+
+    fn buffer_data_f32(&self, target: u32, data: &[f32], usage: u32) {
+        self.record(GlCommand::BufferData {
+            target,
+            byte_len: data.len() * 4,
+            usage,
+        });
+        self.gl.buffer_data_f32(target, data, usage);
+    }
+
+    fn buffer_data_u8(&self, target: u32, data: &[u8], usage: u32) {
+        self.record(GlCommand::BufferData {
+            target,
+            byte_len: data.len(),
+            usage,
+        });
+        self.gl.buffer_data_u8(target, data, usage);
+    }
+
+    fn buffer_data_u16(&self, target: u32, data: &[u16], usage: u32) {
+        self.record(GlCommand::BufferData {
+            target,
+            byte_len: data.len() * 2,
+            usage,
+        });
+        self.gl.buffer_data_u16(target, data, usage);
+    }
+
+    fn enable_vertex_attrib_array(&self, index: u32) {
+        self.record(GlCommand::EnableVertexAttribArray(index));
+        self.gl.enable_vertex_attrib_array(index);
+    }
+
+    fn vertex_attrib_pointer_with_i32(
+        &self,
+        index: u32,
+        size: i32,
+        type_: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    ) {
+        self.record(GlCommand::VertexAttribPointer {
+            index,
+            size,
+            type_,
+            normalized,
+            stride,
+            offset,
+        });
+        self.gl
+            .vertex_attrib_pointer_with_i32(index, size, type_, normalized, stride, offset);
+    }
+
+    fn vertex_attrib_divisor_angle(&self, index: u32, divisor: u32) {
+        self.record(GlCommand::VertexAttribDivisor { index, divisor });
+        self.gl.vertex_attrib_divisor_angle(index, divisor);
+    }
+
+    fn create_texture(&self) -> Option<Self::Texture> {
+        self.capture(self.gl.create_texture())
+    }
+
+    fn delete_texture(&self, texture: Option<&Self::Texture>) {
+        self.record(GlCommand::DeleteTexture(id(texture)));
+        self.gl.delete_texture(handle(texture));
+    }
+
+    fn active_texture(&self, texture: u32) {
+        self.record(GlCommand::ActiveTexture(texture));
+        self.gl.active_texture(texture);
+    }
+
+    fn bind_texture(&self, target: u32, texture: Option<&Self::Texture>) {
+        self.record(GlCommand::BindTexture {
+            target,
+            texture: id(texture),
+        });
+        self.gl.bind_texture(target, handle(texture));
+    }
+
+    fn tex_parameteri(&self, target: u32, pname: u32, param: i32) {
+        self.record(GlCommand::TexParameteri {
+            target,
+            pname,
+            param,
+        });
+        self.gl.tex_parameteri(target, pname, param);
+    }
+
+    fn tex_image_2d(
+        &self,
+        target: u32,
+        level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        border: i32,
+        format: u32,
+        type_: u32,
+        pixels: Option<&[u8]>,
+    ) -> Result<(), JsValue> {
+        self.record(GlCommand::TexImage2d {
+            target,
+            level,
+            internal_format,
+            width,
+            height,
+            format,
+            type_,
+        });
+        self.gl.tex_image_2d(
+            target,
+            level,
+            internal_format,
+            width,
+            height,
+            border,
+            format,
+            type_,
+            pixels,
+        )
+    }
+
+    fn tex_image_2d_f32(
+        &self,
+        target: u32,
+        level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        border: i32,
+        format: u32,
+        pixels: &[f32],
+    ) -> Result<(), JsValue> {
+        self.record(GlCommand::TexImage2d {
+            target,
+            level,
+            internal_format,
+            width,
+            height,
+            format,
+            type_: GL::FLOAT,
+        });
+        self.gl.tex_image_2d_f32(
+            target,
+            level,
+            internal_format,
+            width,
+            height,
+            border,
+            format,
+            pixels,
+        )
+    }
+
+    fn create_framebuffer(&self) -> Option<Self::Framebuffer> {
+        self.capture(self.gl.create_framebuffer())
+    }
+
+    fn delete_framebuffer(&self, framebuffer: Option<&Self::Framebuffer>) {
+        self.record(GlCommand::DeleteFramebuffer(id(framebuffer)));
+        self.gl.delete_framebuffer(handle(framebuffer));
+    }
+
+    fn bind_framebuffer(&self, target: u32, framebuffer: Option<&Self::Framebuffer>) {
+        self.record(GlCommand::BindFramebuffer {
+            target,
+            framebuffer: id(framebuffer),
+        });
+        self.gl.bind_framebuffer(target, handle(framebuffer));
+    }
+
+    fn framebuffer_texture_2d(
+        &self,
+        target: u32,
+        attachment: u32,
+        textarget: u32,
+        texture: Option<&Self::Texture>,
+        level: i32,
+    ) {
+        self.record(GlCommand::FramebufferTexture2d {
+            attachment,
+            texture: id(texture),
+        });
+        self.gl
+            .framebuffer_texture_2d(target, attachment, textarget, handle(texture), level);
+    }
+
+    fn create_renderbuffer(&self) -> Option<Self::Renderbuffer> {
+        self.capture(self.gl.create_renderbuffer())
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: Option<&Self::Renderbuffer>) {
+        self.record(GlCommand::DeleteRenderbuffer(id(renderbuffer)));
+        self.gl.delete_renderbuffer(handle(renderbuffer));
+    }
+
+    fn bind_renderbuffer(&self, target: u32, renderbuffer: Option<&Self::Renderbuffer>) {
+        self.record(GlCommand::BindRenderbuffer {
+            target,
+            renderbuffer: id(renderbuffer),
+        });
+        self.gl.bind_renderbuffer(target, handle(renderbuffer));
+    }
+
+    fn renderbuffer_storage(&self, target: u32, internal_format: u32, width: i32, height: i32) {
+        self.record(GlCommand::RenderbufferStorage {
+            internal_format,
+            width,
+            height,
+        });
+        self.gl
+            .renderbuffer_storage(target, internal_format, width, height);
+    }
+
+    fn framebuffer_renderbuffer(
+        &self,
+        target: u32,
+        attachment: u32,
+        renderbuffer_target: u32,
+        renderbuffer: Option<&Self::Renderbuffer>,
+    ) {
+        self.record(GlCommand::FramebufferRenderbuffer {
+            attachment,
+            renderbuffer: id(renderbuffer),
+        });
+        self.gl.framebuffer_renderbuffer(
+            target,
+            attachment,
+            renderbuffer_target,
+            handle(renderbuffer),
+        );
+    }
+
+    fn uniform1i(&self, location: Option<&Self::UniformLocation>, x: i32) {
+        self.uniform(location, || UniformValue::Int(x));
+        self.gl
+            .uniform1i(location.map(|location| &location.location), x);
+    }
+
+    fn uniform1f(&self, location: Option<&Self::UniformLocation>, x: f32) {
+        self.uniform(location, || UniformValue::Float(x));
+        self.gl
+            .uniform1f(location.map(|location| &location.location), x);
+    }
+
+    fn uniform2f(&self, location: Option<&Self::UniformLocation>, x: f32, y: f32) {
+        self.uniform(location, || UniformValue::Floats(vec![x, y]));
+        self.gl
+            .uniform2f(location.map(|location| &location.location), x, y);
+    }
+
+    fn uniform4f(&self, location: Option<&Self::UniformLocation>, x: f32, y: f32, z: f32, w: f32) {
+        self.uniform(location, || UniformValue::Floats(vec![x, y, z, w]));
+        self.gl
+            .uniform4f(location.map(|location| &location.location), x, y, z, w);
+    }
+
+    fn uniform3fv_with_f32_array(
+        &self,
+        location: Option<&Self::UniformLocation>,
+        data: &mut [f32],
+    ) {
+        self.uniform(location, || UniformValue::Floats(data.to_vec()));
+        self.gl
+            .uniform3fv_with_f32_array(location.map(|location| &location.location), data);
+    }
+
+    fn uniform4fv_with_f32_array(
+        &self,
+        location: Option<&Self::UniformLocation>,
+        data: &mut [f32],
+    ) {
+        self.uniform(location, || UniformValue::Floats(data.to_vec()));
+        self.gl
+            .uniform4fv_with_f32_array(location.map(|location| &location.location), data);
+    }
+
+    fn uniform_matrix4fv_with_f32_array(
+        &self,
+        location: Option<&Self::UniformLocation>,
+        transpose: bool,
+        data: &mut [f32],
+    ) {
+        self.uniform(location, || UniformValue::Mat4 {
+            transpose,
+            values: data.to_vec(),
+        });
+        self.gl.uniform_matrix4fv_with_f32_array(
+            location.map(|location| &location.location),
+            transpose,
+            data,
+        );
+    }
+
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        self.record(GlCommand::Viewport {
+            x,
+            y,
+            width,
+            height,
+        });
+        self.gl.viewport(x, y, width, height);
+    }
+
+    fn clear_color(&self, red: f32, green: f32, blue: f32, alpha: f32) {
+        self.record(GlCommand::ClearColor([red, green, blue, alpha]));
+        self.gl.clear_color(red, green, blue, alpha);
+    }
+
+    fn clear(&self, mask: u32) {
+        self.record(GlCommand::Clear(mask));
+        self.gl.clear(mask);
+    }
+
+    fn enable(&self, cap: u32) {
+        self.record(GlCommand::Enable(cap));
+        self.gl.enable(cap);
+    }
+
+    fn disable(&self, cap: u32) {
+        self.record(GlCommand::Disable(cap));
+        self.gl.disable(cap);
+    }
+
+    fn blend_func(&self, sfactor: u32, dfactor: u32) {
+        self.record(GlCommand::BlendFunc { sfactor, dfactor });
+        self.gl.blend_func(sfactor, dfactor);
+    }
+
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32) {
+        self.record(GlCommand::DrawArrays { mode, first, count });
+        self.gl.draw_arrays(mode, first, count);
+    }
+
+    fn draw_elements_with_i32(&self, mode: u32, count: i32, type_: u32, offset: i32) {
+        self.record(GlCommand::DrawElements {
+            mode,
+            count,
+            type_,
+            offset,
+        });
+        self.gl.draw_elements_with_i32(mode, count, type_, offset);
+    }
+
+    fn draw_elements_instanced_angle_with_i32(
+        &self,
+        mode: u32,
+        count: i32,
+        type_: u32,
+        offset: i32,
+        primcount: i32,
+    ) {
+        self.record(GlCommand::DrawElementsInstanced {
+            mode,
+            count,
+            type_,
+            offset,
+            primcount,
+        });
+        self.gl
+            .draw_elements_instanced_angle_with_i32(mode, count, type_, offset, primcount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::recording::RecordingContext;
+    use super::*;
+    use serde_json::json;
+
+    fn frame_capture() -> FrameCapture<RecordingContext> {
+        FrameCapture::new(Rc::new(RecordingContext::new()))
+    }
+
+    #[test]
+    fn only_records_while_capturing() {
+        let gl = frame_capture();
+
+        gl.clear(GL::COLOR_BUFFER_BIT);
+        gl.start_capture();
+        gl.enable(GL::DEPTH_TEST);
+        let frame = gl.finish_capture();
+        gl.disable(GL::DEPTH_TEST);
+
+        assert_eq!(frame, vec![GlCommand::Enable(GL::DEPTH_TEST)]);
+        assert_eq!(gl.finish_capture(), vec![]);
+
+        // Calls are still passed along to the context that we're wrapping
+        assert_eq!(
+            gl.inner().take_commands(),
+            vec![
+                GlCommand::Clear(GL::COLOR_BUFFER_BIT),
+                GlCommand::Enable(GL::DEPTH_TEST),
+                GlCommand::Disable(GL::DEPTH_TEST),
+            ]
+        );
+    }
+
+    #[test]
+    fn starting_a_capture_discards_the_previous_one() {
+        let gl = frame_capture();
+
+        gl.start_capture();
+        gl.enable(GL::BLEND);
+        gl.start_capture();
+        gl.disable(GL::BLEND);
+
+        assert_eq!(gl.finish_capture(), vec![GlCommand::Disable(GL::BLEND)]);
+    }
+
+    #[test]
+    fn objects_are_numbered_in_the_order_that_they_were_created() {
+        let gl = frame_capture();
+
+        // Objects that were created before the capture started still get their ids
+        let program = gl.create_program().unwrap();
+        let buffer = gl.create_buffer().unwrap();
+        let location = gl.get_uniform_location(&program, "model");
+
+        gl.start_capture();
+        let texture = gl.create_texture().unwrap();
+        gl.use_program(Some(&program));
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(&buffer));
+        gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
+        gl.uniform1f(location.as_ref(), 0.5);
+        gl.use_program(None);
+
+        assert_eq!(
+            gl.finish_capture(),
+            vec![
+                GlCommand::UseProgram(Some(1)),
+                GlCommand::BindBuffer {
+                    target: GL::ARRAY_BUFFER,
+                    buffer: Some(2),
+                },
+                GlCommand::BindTexture {
+                    target: GL::TEXTURE_2D,
+                    texture: Some(3),
+                },
+                GlCommand::Uniform {
+                    location: Some(RecordedUniform {
+                        program: 1,
+                        name: "model".to_string(),
+                    }),
+                    value: UniformValue::Float(0.5),
+                },
+                GlCommand::UseProgram(None),
+            ]
+        );
+    }
+
+    #[test]
+    fn serializes_captured_frames_to_json() {
+        let gl = frame_capture();
+        let program = gl.create_program().unwrap();
+        let location = gl.get_uniform_location(&program, "clipPlane");
+
+        gl.start_capture();
+        gl.marker(format_args!("Water {}", 0));
+        gl.use_program(Some(&program));
+        gl.uniform4f(location.as_ref(), 0., 1., 0., 2.);
+        gl.draw_elements_with_i32(GL::TRIANGLES, 6, GL::UNSIGNED_SHORT, 0);
+        let frame = gl.finish_capture_json().unwrap();
+
+        let expected = json!([
+            {"Marker": "Water 0"},
+            {"UseProgram": 1},
+            {"Uniform": {
+                "location": {"program": 1, "name": "clipPlane"},
+                "value": {"Floats": [0.0, 1.0, 0.0, 2.0]}
+            }},
+            {"DrawElements": {
+                "mode": GL::TRIANGLES,
+                "count": 6,
+                "type_": GL::UNSIGNED_SHORT,
+                "offset": 0
+            }}
+        ]);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&frame).unwrap(),
+            expected
+        );
+
+        // Frames can be read back in, ex: to diff them
+        let commands: Vec<GlCommand> = serde_json::from_str(&frame).unwrap();
+        assert_eq!(commands.len(), 4);
+    }
+}
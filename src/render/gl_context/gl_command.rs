@@ -0,0 +1,137 @@
+//! The commands that `FrameCapture` and our `RecordingContext` record.
+
+use serde::{Deserialize, Serialize};
+
+/// A uniform location within a recorded shader program
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedUniform {
+    pub program: u32,
+    pub name: String,
+}
+
+/// A call that was recorded by a `FrameCapture` or a `RecordingContext`.
+///
+/// Calls that only create objects or query state aren't recorded since they don't affect
+/// what ends up getting drawn.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GlCommand {
+    /// A label from `GlContext::marker`
+    Marker(String),
+    BindVertexArray(Option<u32>),
+    UseProgram(Option<u32>),
+    BindBuffer {
+        target: u32,
+        buffer: Option<u32>,
+    },
+    BufferData {
+        target: u32,
+        /// The size of the uploaded data in bytes
+        byte_len: usize,
+        usage: u32,
+    },
+    EnableVertexAttribArray(u32),
+    VertexAttribPointer {
+        index: u32,
+        size: i32,
+        type_: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    },
+    VertexAttribDivisor {
+        index: u32,
+        divisor: u32,
+    },
+    ActiveTexture(u32),
+    BindTexture {
+        target: u32,
+        texture: Option<u32>,
+    },
+    TexParameteri {
+        target: u32,
+        pname: u32,
+        param: i32,
+    },
+    DeleteTexture(Option<u32>),
+    TexImage2d {
+        target: u32,
+        level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        format: u32,
+        type_: u32,
+    },
+    BindFramebuffer {
+        target: u32,
+        framebuffer: Option<u32>,
+    },
+    DeleteFramebuffer(Option<u32>),
+    FramebufferTexture2d {
+        attachment: u32,
+        texture: Option<u32>,
+    },
+    BindRenderbuffer {
+        target: u32,
+        renderbuffer: Option<u32>,
+    },
+    DeleteRenderbuffer(Option<u32>),
+    RenderbufferStorage {
+        internal_format: u32,
+        width: i32,
+        height: i32,
+    },
+    FramebufferRenderbuffer {
+        attachment: u32,
+        renderbuffer: Option<u32>,
+    },
+    Uniform {
+        location: Option<RecordedUniform>,
+        value: UniformValue,
+    },
+    Viewport {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+    ClearColor([f32; 4]),
+    Clear(u32),
+    Enable(u32),
+    Disable(u32),
+    BlendFunc {
+        sfactor: u32,
+        dfactor: u32,
+    },
+    DrawArrays {
+        mode: u32,
+        first: i32,
+        count: i32,
+    },
+    DrawElements {
+        mode: u32,
+        count: i32,
+        type_: u32,
+        offset: i32,
+    },
+    DrawElementsInstanced {
+        mode: u32,
+        count: i32,
+        type_: u32,
+        offset: i32,
+        primcount: i32,
+    },
+}
+
+/// The value that was uploaded in a `GlCommand::Uniform`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum UniformValue {
+    Int(i32),
+    Float(f32),
+    /// vec2 / vec3 / vec4 uniforms
+    Floats(Vec<f32>),
+    Mat4 {
+        transpose: bool,
+        values: Vec<f32>,
+    },
+}
@@ -0,0 +1,204 @@
+//! Everything that our renderer needs from a graphics API.
+//!
+//! `WebRenderer`, our `ShaderSystem` and all of our `Render` implementations are generic over
+//! a `GlContext` instead of talking to `web_sys::WebGlRenderingContext` directly. In the browser
+//! we use the real WebGL context, but natively (ex: in `cargo test`) we can swap in a context
+//! that doesn't need a GPU.
+//!
+//! The method names mirror WebGL's so that code using a `GlContext` reads the same as code that
+//! uses the `WebGlRenderingContext`. The only exceptions are methods where `web_sys` needs to
+//! know about JavaScript types (buffering data, uploading textures, vertex array objects).
+
+pub use self::frame_capture::*;
+pub use self::gl_command::*;
+use crate::shader::ShaderKind;
+use std::fmt;
+use wasm_bindgen::JsValue;
+
+mod frame_capture;
+mod gl_command;
+#[cfg(test)]
+mod recording;
+// The software context only runs natively since it reads and writes PNGs
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod software;
+mod web_gl;
+
+/// A graphics context that our renderer can issue draw calls against.
+///
+/// GL enums (`GL::TRIANGLES`, `GL::FRAMEBUFFER`, ...) are shared between all implementations,
+/// so we keep using the constants on `web_sys::WebGlRenderingContext`.
+pub trait GlContext {
+    /// A compiled vertex or fragment shader
+    type Shader;
+    /// A linked shader program
+    type Program;
+    /// The location of a uniform within a shader program
+    type UniformLocation: Clone;
+    /// A vertex or index buffer
+    type Buffer;
+    /// A texture
+    type Texture;
+    /// A framebuffer
+    type Framebuffer;
+    /// A renderbuffer
+    type Renderbuffer;
+    /// A vertex array object
+    type Vao;
+
+    /// Label the commands that follow, ex: `gl.marker(format_args!("render {}", mesh_name))`.
+    ///
+    /// This is a no-op unless the context is recording commands, so it's cheap to call
+    /// every frame.
+    fn marker(&self, _label: fmt::Arguments) {}
+
+    /// Say which of our shaders a program is about to be linked from.
+    ///
+    /// WebGL runs whatever GLSL it was given so this is a no-op, but a context that runs ports
+    /// of our shaders (ex: the `SoftwareContext`) needs to know which port to run.
+    fn identify_program(&self, _program: &Self::Program, _shader_kind: ShaderKind) {}
+
+    /// Enable an extension, returning false if it isn't supported.
+    fn enable_extension(&self, name: &str) -> bool;
+
+    /// Look up an integer limit of the device, ex: `GL::MAX_VERTEX_UNIFORM_VECTORS`
+    fn get_parameter_i32(&self, pname: u32) -> i32;
+
+    /// Create a vertex array object (`OES_vertex_array_object` in WebGL 1)
+    fn create_vertex_array(&self) -> Option<Self::Vao>;
+    /// Bind a vertex array object, or unbind with `None`
+    fn bind_vertex_array(&self, vao: Option<&Self::Vao>);
+
+    fn create_shader(&self, shader_type: u32) -> Option<Self::Shader>;
+    fn shader_source(&self, shader: &Self::Shader, source: &str);
+    fn compile_shader(&self, shader: &Self::Shader);
+    /// Whether or not the last `compile_shader` call for this shader succeeded
+    fn shader_compiled(&self, shader: &Self::Shader) -> bool;
+    fn get_shader_info_log(&self, shader: &Self::Shader) -> Option<String>;
+
+    fn create_program(&self) -> Option<Self::Program>;
+    fn attach_shader(&self, program: &Self::Program, shader: &Self::Shader);
+    fn link_program(&self, program: &Self::Program);
+    /// Whether or not the last `link_program` call for this program succeeded
+    fn program_linked(&self, program: &Self::Program) -> bool;
+    fn get_program_info_log(&self, program: &Self::Program) -> Option<String>;
+    fn use_program(&self, program: Option<&Self::Program>);
+
+    fn get_uniform_location(
+        &self,
+        program: &Self::Program,
+        name: &str,
+    ) -> Option<Self::UniformLocation>;
+    fn get_attrib_location(&self, program: &Self::Program, name: &str) -> i32;
+
+    fn create_buffer(&self) -> Option<Self::Buffer>;
+    fn bind_buffer(&self, target: u32, buffer: Option<&Self::Buffer>);
+    /// Upload f32 data to the buffer that is bound to `target`
+    fn buffer_data_f32(&self, target: u32, data: &[f32], usage: u32);
+    /// Upload u8 data to the buffer that is bound to `target`
+    fn buffer_data_u8(&self, target: u32, data: &[u8], usage: u32);
+    /// Upload u16 data to the buffer that is bound to `target`
+    fn buffer_data_u16(&self, target: u32, data: &[u16], usage: u32);
+    fn enable_vertex_attrib_array(&self, index: u32);
+    fn vertex_attrib_pointer_with_i32(
+        &self,
+        index: u32,
+        size: i32,
+        type_: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    );
+    /// Make an attribute advance once every `divisor` instances instead of once per vertex
+    /// (`ANGLE_instanced_arrays` in WebGL 1)
+    fn vertex_attrib_divisor_angle(&self, index: u32, divisor: u32);
+
+    fn create_texture(&self) -> Option<Self::Texture>;
+    fn delete_texture(&self, texture: Option<&Self::Texture>);
+    fn active_texture(&self, texture: u32);
+    fn bind_texture(&self, target: u32, texture: Option<&Self::Texture>);
+    fn tex_parameteri(&self, target: u32, pname: u32, param: i32);
+    /// Allocate the currently bound texture, optionally filling it with `pixels`
+    #[allow(clippy::too_many_arguments)]
+    fn tex_image_2d(
+        &self,
+        target: u32,
+        level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        border: i32,
+        format: u32,
+        type_: u32,
+        pixels: Option<&[u8]>,
+    ) -> Result<(), JsValue>;
+    /// Allocate the currently bound texture and fill it with `GL::FLOAT` pixels
+    /// (`OES_texture_float` in WebGL 1)
+    #[allow(clippy::too_many_arguments)]
+    fn tex_image_2d_f32(
+        &self,
+        target: u32,
+        level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        border: i32,
+        format: u32,
+        pixels: &[f32],
+    ) -> Result<(), JsValue>;
+
+    fn create_framebuffer(&self) -> Option<Self::Framebuffer>;
+    fn delete_framebuffer(&self, framebuffer: Option<&Self::Framebuffer>);
+    fn bind_framebuffer(&self, target: u32, framebuffer: Option<&Self::Framebuffer>);
+    fn framebuffer_texture_2d(
+        &self,
+        target: u32,
+        attachment: u32,
+        textarget: u32,
+        texture: Option<&Self::Texture>,
+        level: i32,
+    );
+    fn create_renderbuffer(&self) -> Option<Self::Renderbuffer>;
+    fn delete_renderbuffer(&self, renderbuffer: Option<&Self::Renderbuffer>);
+    fn bind_renderbuffer(&self, target: u32, renderbuffer: Option<&Self::Renderbuffer>);
+    fn renderbuffer_storage(&self, target: u32, internal_format: u32, width: i32, height: i32);
+    fn framebuffer_renderbuffer(
+        &self,
+        target: u32,
+        attachment: u32,
+        renderbuffer_target: u32,
+        renderbuffer: Option<&Self::Renderbuffer>,
+    );
+
+    fn uniform1i(&self, location: Option<&Self::UniformLocation>, x: i32);
+    fn uniform1f(&self, location: Option<&Self::UniformLocation>, x: f32);
+    fn uniform2f(&self, location: Option<&Self::UniformLocation>, x: f32, y: f32);
+    fn uniform4f(&self, location: Option<&Self::UniformLocation>, x: f32, y: f32, z: f32, w: f32);
+    fn uniform3fv_with_f32_array(&self, location: Option<&Self::UniformLocation>, data: &mut [f32]);
+    fn uniform4fv_with_f32_array(&self, location: Option<&Self::UniformLocation>, data: &mut [f32]);
+    fn uniform_matrix4fv_with_f32_array(
+        &self,
+        location: Option<&Self::UniformLocation>,
+        transpose: bool,
+        data: &mut [f32],
+    );
+
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32);
+    fn clear_color(&self, red: f32, green: f32, blue: f32, alpha: f32);
+    fn clear(&self, mask: u32);
+    fn enable(&self, cap: u32);
+    fn disable(&self, cap: u32);
+    fn blend_func(&self, sfactor: u32, dfactor: u32);
+
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32);
+    fn draw_elements_with_i32(&self, mode: u32, count: i32, type_: u32, offset: i32);
+    /// Draw `primcount` instances of the bound elements (`ANGLE_instanced_arrays` in WebGL 1)
+    fn draw_elements_instanced_angle_with_i32(
+        &self,
+        mode: u32,
+        count: i32,
+        type_: u32,
+        offset: i32,
+        primcount: i32,
+    );
+}
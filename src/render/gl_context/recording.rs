@@ -0,0 +1,1131 @@
+use crate::render::{GlCommand, GlContext, RecordedUniform, UniformValue};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use wasm_bindgen::JsValue;
+use web_sys::WebGlRenderingContext as GL;
+
+/// A `GlContext` that records every call instead of talking to a GPU.
+///
+/// This lets us run the renderer natively (ex: `cargo test` on Linux) and then assert on
+/// the draw calls, uniform uploads and framebuffer binds that it would have issued.
+///
+/// Handles (programs, buffers, textures, ...) are just incrementing ids.
+#[derive(Default)]
+pub struct RecordingContext {
+    commands: RefCell<Vec<GlCommand>>,
+    /// The data of every `GL::DYNAMIC_DRAW` f32 upload (ex: our per-instance attributes), since
+    /// `GlCommand::BufferData` only records how large an upload was
+    dynamic_f32_uploads: RefCell<Vec<Vec<f32>>>,
+    next_handle: Cell<u32>,
+    /// Extensions that `enable_extension` reports as unsupported
+    missing_extensions: Vec<&'static str>,
+    /// Limits that `get_parameter_i32` reports instead of its defaults
+    parameters: HashMap<u32, i32>,
+    /// Shaders whose source contains this fail to compile
+    rejected_source: Option<&'static str>,
+    /// The shaders that we pretended to fail to compile, see `rejecting_shaders`
+    rejected_shaders: RefCell<Vec<u32>>,
+    /// Whether texture uploads fail, see `fail_texture_uploads`
+    failing_texture_uploads: Cell<bool>,
+}
+
+impl RecordingContext {
+    /// Create a new RecordingContext with no recorded commands
+    pub fn new() -> RecordingContext {
+        RecordingContext::default()
+    }
+
+    /// Create a RecordingContext for a device that doesn't support `extension`
+    pub fn without_extension(extension: &'static str) -> RecordingContext {
+        RecordingContext {
+            missing_extensions: vec![extension],
+            ..RecordingContext::default()
+        }
+    }
+
+    /// Create a RecordingContext for a device with different limits, ex:
+    /// `[(GL::MAX_VERTEX_ATTRIBS, 8)]`
+    pub fn with_parameters(parameters: &[(u32, i32)]) -> RecordingContext {
+        RecordingContext {
+            parameters: parameters.iter().cloned().collect(),
+            ..RecordingContext::default()
+        }
+    }
+
+    /// Fail to compile every shader whose source contains `source`, like a driver that can't
+    /// handle something in it
+    pub fn rejecting_shaders(self, source: &'static str) -> RecordingContext {
+        RecordingContext {
+            rejected_source: Some(source),
+            ..self
+        }
+    }
+
+    /// Fail every texture upload from now on, like a device that has run out of memory
+    pub fn fail_texture_uploads(&self) {
+        self.failing_texture_uploads.set(true);
+    }
+
+    fn texture_upload_result(&self) -> Result<(), JsValue> {
+        if self.failing_texture_uploads.get() {
+            Err(JsValue::NULL)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Remove and return all of the commands that have been recorded so far.
+    /// Useful for only looking at the commands from a single frame.
+    pub fn take_commands(&self) -> Vec<GlCommand> {
+        self.commands.replace(vec![])
+    }
+
+    /// Remove and return the data of every `GL::DYNAMIC_DRAW` f32 upload so far
+    pub fn take_dynamic_f32_uploads(&self) -> Vec<Vec<f32>> {
+        self.dynamic_f32_uploads.replace(vec![])
+    }
+
+    fn record(&self, command: GlCommand) {
+        self.commands.borrow_mut().push(command);
+    }
+
+    fn new_handle(&self) -> u32 {
+        let handle = self.next_handle.get() + 1;
+        self.next_handle.set(handle);
+        handle
+    }
+
+    fn uniform(&self, location: Option<&RecordedUniform>, value: UniformValue) {
+        self.record(GlCommand::Uniform {
+            location: location.cloned(),
+            value,
+        });
+    }
+}
+
+impl GlContext for RecordingContext {
+    type Shader = u32;
+    type Program = u32;
+    type UniformLocation = RecordedUniform;
+    type Buffer = u32;
+    type Texture = u32;
+    type Framebuffer = u32;
+    type Renderbuffer = u32;
+    type Vao = u32;
+
+    fn marker(&self, label: fmt::Arguments) {
+        self.record(GlCommand::Marker(label.to_string()));
+    }
+
+    fn enable_extension(&self, name: &str) -> bool {
+        !self.missing_extensions.contains(&name)
+    }
+
+    /// The smallest limits that every WebGL implementation guarantees, except for vertex
+    /// attributes since our instanced mesh shaders need more than the guaranteed 8
+    fn get_parameter_i32(&self, pname: u32) -> i32 {
+        if let Some(value) = self.parameters.get(&pname) {
+            return *value;
+        }
+
+        match pname {
+            GL::MAX_VERTEX_UNIFORM_VECTORS => 128,
+            GL::MAX_VERTEX_ATTRIBS => 16,
+            GL::MAX_TEXTURE_SIZE => 64,
+            _ => 0,
+        }
+    }
+
+    fn create_vertex_array(&self) -> Option<u32> {
+        Some(self.new_handle())
+    }
+
+    fn bind_vertex_array(&self, vao: Option<&u32>) {
+        self.record(GlCommand::BindVertexArray(vao.cloned()));
+    }
+
+    fn create_shader(&self, _shader_type: u32) -> Option<u32> {
+        Some(self.new_handle())
+    }
+
+    fn shader_source(&self, shader: &u32, source: &str) {
+        if let Some(rejected_source) = self.rejected_source {
+            if source.contains(rejected_source) {
+                self.rejected_shaders.borrow_mut().push(*shader);
+            }
+        }
+    }
+
+    fn compile_shader(&self, _shader: &u32) {}
+
+    fn shader_compiled(&self, shader: &u32) -> bool {
+        !self.rejected_shaders.borrow().contains(shader)
+    }
+
+    fn get_shader_info_log(&self, _shader: &u32) -> Option<String> {
+        None
+    }
+
+    fn create_program(&self) -> Option<u32> {
+        Some(self.new_handle())
+    }
+
+    fn attach_shader(&self, _program: &u32, _shader: &u32) {}
+
+    fn link_program(&self, _program: &u32) {}
+
+    fn program_linked(&self, _program: &u32) -> bool {
+        true
+    }
+
+    fn get_program_info_log(&self, _program: &u32) -> Option<String> {
+        None
+    }
+
+    fn use_program(&self, program: Option<&u32>) {
+        self.record(GlCommand::UseProgram(program.cloned()));
+    }
+
+    fn get_uniform_location(&self, program: &u32, name: &str) -> Option<RecordedUniform> {
+        Some(RecordedUniform {
+            program: *program,
+            name: name.to_string(),
+        })
+    }
+
+    fn get_attrib_location(&self, _program: &u32, _name: &str) -> i32 {
+        0
+    }
+
+    fn create_buffer(&self) -> Option<u32> {
+        Some(self.new_handle())
+    }
+
+    fn bind_buffer(&self, target: u32, buffer: Option<&u32>) {
+        self.record(GlCommand::BindBuffer {
+            target,
+            buffer: buffer.cloned(),
+        });
+    }
+
+    fn buffer_data_f32(&self, target: u32, data: &[f32], usage: u32) {
+        self.record(GlCommand::BufferData {
+            target,
+            byte_len: data.len() * 4,
+            usage,
+        });
+
+        if usage == GL::DYNAMIC_DRAW {
+            self.dynamic_f32_uploads.borrow_mut().push(data.to_vec());
+        }
+    }
+
+    fn buffer_data_u8(&self, target: u32, data: &[u8], usage: u32) {
+        self.record(GlCommand::BufferData {
+            target,
+            byte_len: data.len(),
+            usage,
+        });
+    }
+
+    fn buffer_data_u16(&self, target: u32, data: &[u16], usage: u32) {
+        self.record(GlCommand::BufferData {
+            target,
+            byte_len: data.len() * 2,
+            usage,
+        });
+    }
+
+    fn enable_vertex_attrib_array(&self, index: u32) {
+        self.record(GlCommand::EnableVertexAttribArray(index));
+    }
+
+    fn vertex_attrib_pointer_with_i32(
+        &self,
+        index: u32,
+        size: i32,
+        type_: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    ) {
+        self.record(GlCommand::VertexAttribPointer {
+            index,
+            size,
+            type_,
+            normalized,
+            stride,
+            offset,
+        });
+    }
+
+    fn vertex_attrib_divisor_angle(&self, index: u32, divisor: u32) {
+        self.record(GlCommand::VertexAttribDivisor { index, divisor });
+    }
+
+    fn create_texture(&self) -> Option<u32> {
+        Some(self.new_handle())
+    }
+
+    fn delete_texture(&self, texture: Option<&u32>) {
+        self.record(GlCommand::DeleteTexture(texture.cloned()));
+    }
+
+    fn active_texture(&self, texture: u32) {
+        self.record(GlCommand::ActiveTexture(texture));
+    }
+
+    fn bind_texture(&self, target: u32, texture: Option<&u32>) {
+        self.record(GlCommand::BindTexture {
+            target,
+            texture: texture.cloned(),
+        });
+    }
+
+    fn tex_parameteri(&self, target: u32, pname: u32, param: i32) {
+        self.record(GlCommand::TexParameteri {
+            target,
+            pname,
+            param,
+        });
+    }
+
+    fn tex_image_2d(
+        &self,
+        target: u32,
+        level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        _border: i32,
+        format: u32,
+        type_: u32,
+        _pixels: Option<&[u8]>,
+    ) -> Result<(), JsValue> {
+        self.record(GlCommand::TexImage2d {
+            target,
+            level,
+            internal_format,
+            width,
+            height,
+            format,
+            type_,
+        });
+
+        self.texture_upload_result()
+    }
+
+    fn tex_image_2d_f32(
+        &self,
+        target: u32,
+        level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        _border: i32,
+        format: u32,
+        _pixels: &[f32],
+    ) -> Result<(), JsValue> {
+        self.record(GlCommand::TexImage2d {
+            target,
+            level,
+            internal_format,
+            width,
+            height,
+            format,
+            type_: GL::FLOAT,
+        });
+
+        self.texture_upload_result()
+    }
+
+    fn create_framebuffer(&self) -> Option<u32> {
+        Some(self.new_handle())
+    }
+
+    fn delete_framebuffer(&self, framebuffer: Option<&u32>) {
+        self.record(GlCommand::DeleteFramebuffer(framebuffer.cloned()));
+    }
+
+    fn bind_framebuffer(&self, target: u32, framebuffer: Option<&u32>) {
+        self.record(GlCommand::BindFramebuffer {
+            target,
+            framebuffer: framebuffer.cloned(),
+        });
+    }
+
+    fn framebuffer_texture_2d(
+        &self,
+        _target: u32,
+        attachment: u32,
+        _textarget: u32,
+        texture: Option<&u32>,
+        _level: i32,
+    ) {
+        self.record(GlCommand::FramebufferTexture2d {
+            attachment,
+            texture: texture.cloned(),
+        });
+    }
+
+    fn create_renderbuffer(&self) -> Option<u32> {
+        Some(self.new_handle())
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: Option<&u32>) {
+        self.record(GlCommand::DeleteRenderbuffer(renderbuffer.cloned()));
+    }
+
+    fn bind_renderbuffer(&self, target: u32, renderbuffer: Option<&u32>) {
+        self.record(GlCommand::BindRenderbuffer {
+            target,
+            renderbuffer: renderbuffer.cloned(),
+        });
+    }
+
+    fn renderbuffer_storage(&self, _target: u32, internal_format: u32, width: i32, height: i32) {
+        self.record(GlCommand::RenderbufferStorage {
+            internal_format,
+            width,
+            height,
+        });
+    }
+
+    fn framebuffer_renderbuffer(
+        &self,
+        _target: u32,
+        attachment: u32,
+        _renderbuffer_target: u32,
+        renderbuffer: Option<&u32>,
+    ) {
+        self.record(GlCommand::FramebufferRenderbuffer {
+            attachment,
+            renderbuffer: renderbuffer.cloned(),
+        });
+    }
+
+    fn uniform1i(&self, location: Option<&RecordedUniform>, x: i32) {
+        self.uniform(location, UniformValue::Int(x));
+    }
+
+    fn uniform1f(&self, location: Option<&RecordedUniform>, x: f32) {
+        self.uniform(location, UniformValue::Float(x));
+    }
+
+    fn uniform2f(&self, location: Option<&RecordedUniform>, x: f32, y: f32) {
+        self.uniform(location, UniformValue::Floats(vec![x, y]));
+    }
+
+    fn uniform4f(&self, location: Option<&RecordedUniform>, x: f32, y: f32, z: f32, w: f32) {
+        self.uniform(location, UniformValue::Floats(vec![x, y, z, w]));
+    }
+
+    fn uniform3fv_with_f32_array(&self, location: Option<&RecordedUniform>, data: &mut [f32]) {
+        self.uniform(location, UniformValue::Floats(data.to_vec()));
+    }
+
+    fn uniform4fv_with_f32_array(&self, location: Option<&RecordedUniform>, data: &mut [f32]) {
+        self.uniform(location, UniformValue::Floats(data.to_vec()));
+    }
+
+    fn uniform_matrix4fv_with_f32_array(
+        &self,
+        location: Option<&RecordedUniform>,
+        transpose: bool,
+        data: &mut [f32],
+    ) {
+        self.uniform(
+            location,
+            UniformValue::Mat4 {
+                transpose,
+                values: data.to_vec(),
+            },
+        );
+    }
+
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        self.record(GlCommand::Viewport {
+            x,
+            y,
+            width,
+            height,
+        });
+    }
+
+    fn clear_color(&self, red: f32, green: f32, blue: f32, alpha: f32) {
+        self.record(GlCommand::ClearColor([red, green, blue, alpha]));
+    }
+
+    fn clear(&self, mask: u32) {
+        self.record(GlCommand::Clear(mask));
+    }
+
+    fn enable(&self, cap: u32) {
+        self.record(GlCommand::Enable(cap));
+    }
+
+    fn disable(&self, cap: u32) {
+        self.record(GlCommand::Disable(cap));
+    }
+
+    fn blend_func(&self, sfactor: u32, dfactor: u32) {
+        self.record(GlCommand::BlendFunc { sfactor, dfactor });
+    }
+
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32) {
+        self.record(GlCommand::DrawArrays { mode, first, count });
+    }
+
+    fn draw_elements_with_i32(&self, mode: u32, count: i32, type_: u32, offset: i32) {
+        self.record(GlCommand::DrawElements {
+            mode,
+            count,
+            type_,
+            offset,
+        });
+    }
+
+    fn draw_elements_instanced_angle_with_i32(
+        &self,
+        mode: u32,
+        count: i32,
+        type_: u32,
+        offset: i32,
+        primcount: i32,
+    ) {
+        self.record(GlCommand::DrawElementsInstanced {
+            mode,
+            count,
+            type_,
+            offset,
+            primcount,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::RENDER_READY_VERSION;
+    use crate::app::{render_ready_bundle, AssetBundle, AssetError, Assets, Msg, Scene, Store};
+    use crate::render::{placeholder_mesh, MeshRenderOpts, NonSkinnedMesh, Render, TextureUnit};
+    use crate::render::{VertexBuffers, WebRenderer, INSTANCE_FLOATS};
+    use crate::shader::{BoneStorage, ShaderKind};
+    use std::collections::HashMap;
+
+    /// A cube hovering above a pond
+    const SCENE: &str = r#"{
+        "entities": [{"mesh": "Cube", "transform": {"translation": [0, 2, 0]}}],
+        "waterBodies": [{"position": [0, 0], "extents": [18, 18], "height": 0}]
+    }"#;
+
+    fn cube_scene() -> (Store, Assets) {
+        let mut store = Store::new();
+        let scene: Scene = serde_json::from_str(SCENE).unwrap();
+        store.msg(&Msg::LoadScene(scene));
+
+        let mut meshes = HashMap::new();
+        meshes.insert("Cube".to_string(), placeholder_mesh());
+
+        let mut assets = Assets::default();
+        let bundle = render_ready_bundle(RENDER_READY_VERSION, &meshes);
+        assets.insert_bundle(AssetBundle::Meshes, &bundle).unwrap();
+
+        (store, assets)
+    }
+
+    /// Render one frame, returning only the commands from that frame
+    fn render_frame(
+        gl: &RecordingContext,
+        renderer: &mut WebRenderer<RecordingContext>,
+        store: &Store,
+        assets: &Assets,
+    ) -> Vec<GlCommand> {
+        gl.take_commands();
+        renderer.render(gl, &store.state, assets);
+        gl.take_commands()
+    }
+
+    /// The framebuffer binds, program changes and draw calls in a frame
+    fn passes(commands: &[GlCommand]) -> Vec<GlCommand> {
+        commands
+            .iter()
+            .filter(|command| {
+                matches!(
+                    command,
+                    GlCommand::BindFramebuffer { .. }
+                        | GlCommand::UseProgram(_)
+                        | GlCommand::DrawArrays { .. }
+                        | GlCommand::DrawElements { .. }
+                        | GlCommand::DrawElementsInstanced { .. }
+                )
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Every value that was uploaded to one of a program's uniforms, in order
+    fn uniform_values(commands: &[GlCommand], program: u32, name: &str) -> Vec<UniformValue> {
+        commands
+            .iter()
+            .filter_map(|command| match command {
+                GlCommand::Uniform {
+                    location: Some(location),
+                    value,
+                } if location.program == program && location.name == name => Some(value.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn renders_refraction_and_reflection_before_the_water_that_uses_them() {
+        let (store, assets) = cube_scene();
+        let gl = RecordingContext::new();
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+
+        let commands = render_frame(&gl, &mut renderer, &store, &assets);
+
+        let program = |kind| Some(renderer.shader_sys.get_shader(&kind).unwrap().program);
+        let bind = |framebuffer| GlCommand::BindFramebuffer {
+            target: GL::FRAMEBUFFER,
+            framebuffer,
+        };
+        let draw_cube = GlCommand::DrawElementsInstanced {
+            mode: GL::TRIANGLES,
+            count: 36,
+            type_: GL::UNSIGNED_SHORT,
+            offset: 0,
+            primcount: 1,
+        };
+        let draw_quad = GlCommand::DrawArrays {
+            mode: GL::TRIANGLES,
+            first: 0,
+            count: 6,
+        };
+
+        let grid_resolution = store.state.water_grid_resolution() as i32;
+        let draw_water = GlCommand::DrawElements {
+            mode: GL::TRIANGLES,
+            count: grid_resolution * grid_resolution * 6,
+            type_: GL::UNSIGNED_SHORT,
+            offset: 0,
+        };
+
+        let expected = vec![
+            bind(renderer.refraction_framebuffer.framebuffer),
+            GlCommand::UseProgram(program(ShaderKind::NonSkinnedMesh)),
+            draw_cube.clone(),
+            bind(renderer.reflection_framebuffer.framebuffer),
+            draw_cube.clone(),
+            bind(None),
+            GlCommand::UseProgram(program(ShaderKind::Water)),
+            draw_water,
+            GlCommand::UseProgram(program(ShaderKind::NonSkinnedMesh)),
+            draw_cube,
+            GlCommand::UseProgram(program(ShaderKind::TexturedQuad)),
+            draw_quad.clone(),
+            draw_quad,
+        ];
+
+        assert_eq!(passes(&commands), expected);
+    }
+
+    #[test]
+    fn clips_each_pass_and_places_the_mesh_with_its_world_matrix() {
+        let (store, assets) = cube_scene();
+        let gl = RecordingContext::new();
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+
+        let commands = render_frame(&gl, &mut renderer, &store, &assets);
+
+        let mesh_shader = renderer.shader_sys.get_shader(&ShaderKind::NonSkinnedMesh);
+        let mesh_program = mesh_shader.unwrap().program;
+
+        let water_body = &store.state.water_bodies()[0];
+        let clip_planes: Vec<UniformValue> = vec![
+            water_body.refraction_clip_plane().to_vec(),
+            water_body.reflection_clip_plane().to_vec(),
+            vec![0., 1., 0., 1000000.],
+        ]
+        .into_iter()
+        .map(UniformValue::Floats)
+        .collect();
+        assert_eq!(
+            uniform_values(&commands, mesh_program, "clipPlane"),
+            clip_planes
+        );
+
+        // Each instance's model matrix comes first in the instance buffer
+        let instances = gl.take_dynamic_f32_uploads();
+        assert_eq!(instances.len(), 3);
+        for instance in instances {
+            assert_eq!(instance.len(), INSTANCE_FLOATS);
+            assert_eq!(instance[12..15], [0., 2., 0.]);
+        }
+    }
+
+    #[test]
+    fn draws_entities_that_share_a_mesh_in_one_call() {
+        let (mut store, assets) = cube_scene();
+        let scene = r#"{
+            "entities": [
+                {"mesh": "Cube", "transform": {"translation": [0, 2, 0]}},
+                {"mesh": "Cube", "transform": {"translation": [3, 2, 0]}},
+                {"mesh": "Cube", "transform": {"translation": [6, 2, 0]}}
+            ],
+            "waterBodies": []
+        }"#;
+        store.msg(&Msg::LoadScene(serde_json::from_str(scene).unwrap()));
+
+        let gl = RecordingContext::new();
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+        let commands = render_frame(&gl, &mut renderer, &store, &assets);
+
+        let draw_cubes = GlCommand::DrawElementsInstanced {
+            mode: GL::TRIANGLES,
+            count: 36,
+            type_: GL::UNSIGNED_SHORT,
+            offset: 0,
+            primcount: 3,
+        };
+        assert_eq!(
+            passes(&commands)
+                .into_iter()
+                .filter(|command| matches!(command, GlCommand::DrawElementsInstanced { .. }))
+                .collect::<Vec<GlCommand>>(),
+            vec![draw_cubes]
+        );
+
+        let instances = gl.take_dynamic_f32_uploads();
+        assert_eq!(instances.len(), 1);
+
+        let translations: Vec<&[f32]> = instances[0]
+            .chunks(INSTANCE_FLOATS)
+            .map(|instance| &instance[12..15])
+            .collect();
+        assert_eq!(translations, vec![[0., 2., 0.], [3., 2., 0.], [6., 2., 0.]]);
+    }
+
+    #[test]
+    fn reuses_vertex_arrays_after_the_first_frame() {
+        let (store, assets) = cube_scene();
+        let gl = RecordingContext::new();
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+
+        let first_frame = render_frame(&gl, &mut renderer, &store, &assets);
+        let second_frame = render_frame(&gl, &mut renderer, &store, &assets);
+
+        // Only the per-instance attributes get uploaded every frame
+        let uploads = |commands: &[GlCommand]| {
+            commands
+                .iter()
+                .filter(|command| {
+                    matches!(
+                        command,
+                        GlCommand::BufferData {
+                            usage: GL::STATIC_DRAW,
+                            ..
+                        }
+                    )
+                })
+                .count()
+        };
+
+        assert!(uploads(&first_frame) > 0);
+        assert_eq!(uploads(&second_frame), 0);
+        assert_eq!(passes(&first_frame), passes(&second_frame));
+    }
+
+    #[test]
+    fn rebuffers_meshes_that_a_later_bundle_replaces() {
+        let (store, mut assets) = cube_scene();
+        let gl = RecordingContext::new();
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+
+        let first_frame = render_frame(&gl, &mut renderer, &store, &assets);
+
+        // A new version of the cube that only has its first triangle
+        let mut triangle = placeholder_mesh();
+        triangle.vertex_position_indices.truncate(3);
+
+        let mut meshes = HashMap::new();
+        meshes.insert("Cube".to_string(), triangle);
+        let bundle = render_ready_bundle(RENDER_READY_VERSION, &meshes);
+        assets.insert_bundle(AssetBundle::Meshes, &bundle).unwrap();
+
+        let commands = render_frame(&gl, &mut renderer, &store, &assets);
+
+        let index_uploads: Vec<usize> = commands
+            .iter()
+            .filter_map(|command| match command {
+                GlCommand::BufferData {
+                    target: GL::ELEMENT_ARRAY_BUFFER,
+                    byte_len,
+                    ..
+                } => Some(*byte_len),
+                _ => None,
+            })
+            .collect();
+
+        // Three u16 indices, uploaded once and then reused by the later passes
+        assert_eq!(index_uploads, vec![6]);
+
+        // The new version gets uploaded into the old version's buffers instead of leaking them
+        let refilled = filled_buffers(&commands);
+        assert_eq!(refilled.len(), 4);
+        let first_filled = filled_buffers(&first_frame);
+        assert!(refilled.iter().all(|buffer| first_filled.contains(buffer)));
+    }
+
+    /// The buffers that `GL::STATIC_DRAW` data got uploaded into
+    fn filled_buffers(commands: &[GlCommand]) -> Vec<u32> {
+        let mut bound = HashMap::new();
+        let mut filled = vec![];
+
+        for command in commands {
+            match command {
+                GlCommand::BindBuffer {
+                    target,
+                    buffer: Some(buffer),
+                } => {
+                    bound.insert(*target, *buffer);
+                }
+                GlCommand::BufferData {
+                    target,
+                    usage: GL::STATIC_DRAW,
+                    ..
+                } => filled.push(bound[target]),
+                _ => {}
+            }
+        }
+
+        filled
+    }
+
+    /// A flock of `birds` flying birds, whose armature we pad with bones that no vertex uses
+    /// until it has `bone_count` of them
+    fn flock_scene(birds: usize, bone_count: usize) -> (Store, Assets) {
+        let bird = r#"{"mesh": "Bird", "armature": "Armature.001", "action": "Fly.001"}"#;
+        let scene = format!(
+            r#"{{"entities": [{}], "waterBodies": []}}"#,
+            vec![bird; birds].join(",")
+        );
+
+        let mut store = Store::new();
+        store.msg(&Msg::LoadScene(serde_json::from_str(&scene).unwrap()));
+
+        let mut assets = Assets::default();
+        let meshes = include_bytes!("../../../meshes.bytes");
+        let armatures = include_bytes!("../../../armatures.bytes");
+        assets.insert_bundle(AssetBundle::Meshes, meshes).unwrap();
+        assets
+            .insert_bundle(AssetBundle::Armatures, armatures)
+            .unwrap();
+
+        let mut rig = assets.armature("Armature.001").unwrap().clone();
+        let bone = rig.inverse_bind_poses[0].clone();
+        rig.inverse_bind_poses.resize(bone_count, bone);
+        for keyframes in rig.actions.values_mut() {
+            for bones in keyframes.values_mut() {
+                let pose = bones[0].clone();
+                bones.resize(bone_count, pose);
+            }
+        }
+
+        let mut armatures = HashMap::new();
+        armatures.insert("Armature.001".to_string(), rig);
+        let bundle = render_ready_bundle(RENDER_READY_VERSION, &armatures);
+        assets
+            .insert_bundle(AssetBundle::Armatures, &bundle)
+            .unwrap();
+
+        (store, assets)
+    }
+
+    #[test]
+    fn draws_a_placeholder_for_rigs_that_this_device_cannot_skin() {
+        // Give the bird more bones than fit in our uniforms. RecordingContext doesn't have any
+        // vertex texture units, so we can't fall back to a bone texture.
+        let (store, assets) = flock_scene(1, 60);
+
+        let gl = RecordingContext::new();
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+        let commands = render_frame(&gl, &mut renderer, &store, &assets);
+
+        assert_eq!(
+            *renderer.asset_errors(),
+            vec![AssetError::TooManyBones {
+                armature: "Armature.001".to_string(),
+                bone_count: 60,
+                max_bones: 59,
+            }]
+        );
+
+        let mesh_program = renderer.shader_sys.get_shader(&ShaderKind::NonSkinnedMesh);
+        let placeholder = GlCommand::DrawElementsInstanced {
+            mode: GL::TRIANGLES,
+            count: 36,
+            type_: GL::UNSIGNED_SHORT,
+            offset: 0,
+            primcount: 1,
+        };
+        assert_eq!(
+            passes(&commands)[..2],
+            [
+                GlCommand::UseProgram(Some(mesh_program.unwrap().program)),
+                placeholder,
+            ]
+        );
+    }
+
+    /// A device that can read bones from a float texture in its vertex shaders
+    fn bone_texture_context() -> RecordingContext {
+        RecordingContext::with_parameters(&[(GL::MAX_VERTEX_TEXTURE_IMAGE_UNITS, 4)])
+    }
+
+    #[test]
+    fn splits_flocks_across_as_many_bone_textures_as_they_need() {
+        // Our 64 texel wide bone texture fits 32 birds' bones
+        let (store, assets) = flock_scene(40, 60);
+        let gl = bone_texture_context();
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+        let commands = render_frame(&gl, &mut renderer, &store, &assets);
+
+        assert_eq!(*renderer.asset_errors(), vec![]);
+
+        let bone_textures: Vec<(i32, i32)> = commands
+            .iter()
+            .filter_map(|command| match command {
+                GlCommand::TexImage2d {
+                    width,
+                    height,
+                    type_: GL::FLOAT,
+                    ..
+                } => Some((*width, *height)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(bone_textures, vec![(64, 60), (16, 60)]);
+
+        let flock_sizes: Vec<i32> = commands
+            .iter()
+            .filter_map(|command| match command {
+                GlCommand::DrawElementsInstanced { primcount, .. } => Some(*primcount),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(flock_sizes, vec![32, 8]);
+    }
+
+    #[test]
+    fn draws_a_placeholder_for_rigs_with_more_bones_than_a_texture_is_tall() {
+        let (store, assets) = flock_scene(1, 65);
+        let gl = bone_texture_context();
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+        render_frame(&gl, &mut renderer, &store, &assets);
+
+        assert_eq!(
+            *renderer.asset_errors(),
+            vec![AssetError::TooManyBones {
+                armature: "Armature.001".to_string(),
+                bone_count: 65,
+                max_bones: 64,
+            }]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_a_bone_texture_when_the_driver_rejects_our_bone_uniforms() {
+        let (store, assets) = flock_scene(1, 10);
+        let gl = bone_texture_context().rejecting_shaders("#define BONE_COUNT");
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+        let commands = render_frame(&gl, &mut renderer, &store, &assets);
+
+        assert_eq!(*renderer.asset_errors(), vec![]);
+
+        let texture_shader = ShaderKind::SkinnedMesh(BoneStorage::Texture);
+        let texture_program = renderer.shader_sys.get_shader(&texture_shader).unwrap();
+        assert!(passes(&commands).contains(&GlCommand::UseProgram(Some(texture_program.program))));
+    }
+
+    #[test]
+    fn draws_a_placeholder_when_the_driver_rejects_every_skinned_shader() {
+        let (store, assets) = flock_scene(1, 10);
+        let gl = RecordingContext::new().rejecting_shaders("#define BONE_COUNT");
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+        render_frame(&gl, &mut renderer, &store, &assets);
+
+        assert_eq!(
+            *renderer.asset_errors(),
+            vec![AssetError::TooManyBones {
+                armature: "Armature.001".to_string(),
+                bone_count: 10,
+                max_bones: 9,
+            }]
+        );
+    }
+
+    #[test]
+    fn fails_without_room_for_our_framebuffers() {
+        let (store, _) = cube_scene();
+        let gl = RecordingContext::new();
+        gl.fail_texture_uploads();
+
+        match WebRenderer::new(&gl, &store.state) {
+            Err(err) => assert!(err.contains("refraction framebuffer")),
+            Ok(_) => panic!("Created a renderer without framebuffers"),
+        }
+    }
+
+    #[test]
+    fn keeps_rendering_when_the_device_runs_out_of_texture_memory() {
+        let (mut store, assets) = cube_scene();
+        let gl = RecordingContext::new();
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+
+        // Resizing the canvas resizes our framebuffers
+        store.msg(&Msg::Resize(123, 77));
+        gl.fail_texture_uploads();
+        let commands = render_frame(&gl, &mut renderer, &store, &assets);
+
+        assert_eq!(
+            *renderer.asset_errors(),
+            vec![
+                AssetError::GpuResource("the refraction framebuffer"),
+                AssetError::GpuResource("the reflection framebuffer"),
+                AssetError::GpuResource("the ripple texture"),
+            ]
+        );
+
+        // The cube still gets drawn into the canvas
+        assert!(commands
+            .iter()
+            .any(|command| matches!(command, GlCommand::DrawElementsInstanced { .. })));
+    }
+
+    #[test]
+    fn reports_meshes_without_the_vertex_data_that_their_renderer_buffers() {
+        let (store, _) = cube_scene();
+        let gl = RecordingContext::new();
+        let renderer = WebRenderer::new(&gl, &store.state).unwrap();
+
+        let mut mesh = placeholder_mesh();
+        mesh.vertex_uvs = None;
+
+        let opts = MeshRenderOpts {
+            texture: TextureUnit::Stone,
+            clip_plane: [0., 1., 0., 1000000.],
+            flip_camera_y: None,
+        };
+        let non_skinned_mesh = NonSkinnedMesh {
+            name: "Cube",
+            mesh: &mesh,
+            shader: renderer
+                .shader_sys
+                .get_shader(&ShaderKind::NonSkinnedMesh)
+                .unwrap(),
+            instances: &[],
+            instance_buffer: renderer.instance_buffer.as_ref(),
+            opts: &opts,
+        };
+
+        gl.take_commands();
+
+        assert_eq!(
+            non_skinned_mesh.buffer_attributes(&gl, &mut VertexBuffers::default()),
+            Err(AssetError::MissingVertexData {
+                mesh: "Cube".to_string(),
+                data: "uvs",
+            })
+        );
+        // Nothing gets half buffered
+        assert_eq!(gl.take_commands(), vec![]);
+    }
+
+    #[test]
+    fn fails_without_depth_textures() {
+        let (store, _) = cube_scene();
+        let gl = RecordingContext::without_extension("WEBGL_depth_texture");
+
+        match WebRenderer::new(&gl, &store.state) {
+            Err(err) => assert!(err.contains("WEBGL_depth_texture")),
+            Ok(_) => panic!("Created a renderer without depth textures"),
+        }
+    }
+
+    /// Render three cubes on a device that can't draw instances, returning the frame's
+    /// commands and the mesh shader's program
+    fn render_cubes_without_instancing(gl: &RecordingContext) -> (Vec<GlCommand>, u32) {
+        let (mut store, assets) = cube_scene();
+        let scene = r#"{
+            "entities": [
+                {"mesh": "Cube", "transform": {"translation": [0, 2, 0]}},
+                {"mesh": "Cube", "transform": {"translation": [3, 2, 0]}},
+                {"mesh": "Cube", "transform": {"translation": [6, 2, 0]}}
+            ],
+            "waterBodies": []
+        }"#;
+        store.msg(&Msg::LoadScene(serde_json::from_str(scene).unwrap()));
+
+        let mut renderer = WebRenderer::new(gl, &store.state).unwrap();
+        let commands = render_frame(gl, &mut renderer, &store, &assets);
+        assert_eq!(*renderer.asset_errors(), vec![]);
+
+        let mesh_shader = renderer.shader_sys.get_shader(&ShaderKind::NonSkinnedMesh);
+        (commands, mesh_shader.unwrap().program)
+    }
+
+    /// Check that each cube was drawn on its own, after uploading its model matrix
+    fn assert_drew_each_cube(gl: &RecordingContext, commands: &[GlCommand], program: u32) {
+        let draw_cube = GlCommand::DrawElements {
+            mode: GL::TRIANGLES,
+            count: 36,
+            type_: GL::UNSIGNED_SHORT,
+            offset: 0,
+        };
+        assert_eq!(
+            passes(commands)
+                .into_iter()
+                .filter(|command| matches!(
+                    command,
+                    GlCommand::DrawElements { .. } | GlCommand::DrawElementsInstanced { .. }
+                ))
+                .collect::<Vec<GlCommand>>(),
+            vec![draw_cube.clone(), draw_cube.clone(), draw_cube]
+        );
+
+        let translations: Vec<Vec<f32>> = uniform_values(commands, program, "model")
+            .into_iter()
+            .map(|model| match model {
+                UniformValue::Mat4 { values, .. } => values[12..15].to_vec(),
+                other => panic!("Expected a model matrix, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            translations,
+            vec![vec![0., 2., 0.], vec![3., 2., 0.], vec![6., 2., 0.]]
+        );
+        assert_eq!(uniform_values(commands, program, "normalMatrix").len(), 3);
+
+        // There's no instance buffer to fill
+        assert_eq!(gl.take_dynamic_f32_uploads(), Vec::<Vec<f32>>::new());
+    }
+
+    #[test]
+    fn draws_entities_one_at_a_time_without_instanced_arrays() {
+        let gl = RecordingContext::without_extension("ANGLE_instanced_arrays");
+        let (commands, program) = render_cubes_without_instancing(&gl);
+
+        assert_drew_each_cube(&gl, &commands, program);
+    }
+
+    #[test]
+    fn draws_entities_one_at_a_time_with_only_the_guaranteed_vertex_attributes() {
+        let gl = RecordingContext::with_parameters(&[(GL::MAX_VERTEX_ATTRIBS, 8)]);
+        let (commands, program) = render_cubes_without_instancing(&gl);
+
+        assert_drew_each_cube(&gl, &commands, program);
+    }
+}
@@ -0,0 +1,126 @@
+use png::HasParameters;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// An 8 bit per channel RGBA image with its rows stored from top to bottom, the same way that
+/// a PNG stores them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    /// `width * height * 4` bytes
+    pub pixels: Vec<u8>,
+}
+
+/// How much two images differ. See `RgbaImage::compare`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageDiff {
+    /// The number of pixels that have at least one channel that differs by more than the
+    /// tolerance
+    pub differing_pixels: usize,
+    /// The largest difference between any two channels
+    pub max_channel_difference: u8,
+}
+
+impl RgbaImage {
+    /// Create a transparent black image
+    pub fn new(width: u32, height: u32) -> RgbaImage {
+        RgbaImage {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    /// Read an 8 bit RGB or RGBA PNG file, such as one of our golden images.
+    pub fn read_png<P: AsRef<Path>>(path: P) -> io::Result<RgbaImage> {
+        let decoder = png::Decoder::new(File::open(path)?);
+        let (info, mut reader) = decoder.read_info().map_err(invalid_data)?;
+
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf).map_err(invalid_data)?;
+
+        if info.bit_depth != png::BitDepth::Eight {
+            return Err(invalid_data("Only 8 bit PNGs are supported"));
+        }
+
+        let pixels = match info.color_type {
+            png::ColorType::RGBA => buf,
+            png::ColorType::RGB => buf
+                .chunks(3)
+                .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            color_type => {
+                return Err(invalid_data(format!(
+                    "Unsupported PNG color type {:?}",
+                    color_type
+                )))
+            }
+        };
+
+        Ok(RgbaImage {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    /// Write this image to an RGBA PNG file. Useful for updating a golden image or for looking
+    /// at a frame that didn't match.
+    pub fn write_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(invalid_data)?;
+        writer
+            .write_image_data(&self.pixels)
+            .map_err(invalid_data)?;
+
+        Ok(())
+    }
+
+    /// Compare this image against another one, pixel by pixel.
+    ///
+    /// Floating point math on a GPU won't exactly match our software rasterizer, so pixels
+    /// where no channel differs by more than `channel_tolerance` are considered to be the same.
+    ///
+    /// Images with different dimensions are considered to differ in every pixel.
+    pub fn compare(&self, other: &RgbaImage, channel_tolerance: u8) -> ImageDiff {
+        if self.width != other.width || self.height != other.height {
+            return ImageDiff {
+                differing_pixels: (self.width * self.height).max(other.width * other.height)
+                    as usize,
+                max_channel_difference: 255,
+            };
+        }
+
+        let mut diff = ImageDiff {
+            differing_pixels: 0,
+            max_channel_difference: 0,
+        };
+
+        for (ours, theirs) in self.pixels.chunks(4).zip(other.pixels.chunks(4)) {
+            let max_difference = ours
+                .iter()
+                .zip(theirs.iter())
+                .map(|(a, b)| (*a as i16 - *b as i16).unsigned_abs() as u8)
+                .max()
+                .unwrap();
+
+            if max_difference > channel_tolerance {
+                diff.differing_pixels += 1;
+            }
+            diff.max_channel_difference = diff.max_channel_difference.max(max_difference);
+        }
+
+        diff
+    }
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
@@ -0,0 +1,1197 @@
+//! A `GlContext` that renders on the CPU.
+//!
+//! This lets us run every pass in `WebRenderer::render` natively, without a browser or a GPU,
+//! and compare the resulting frame against golden PNGs. Our GLSL shaders are ported to Rust in
+//! `shaders.rs`, and programs run the port of the `ShaderKind` that they were identified as (see
+//! `GlContext::identify_program`).
+//!
+//! Only the subset of WebGL that our renderer uses is supported: `GL::TRIANGLES`, `GL::LESS`
+//! depth testing, `GL::REPEAT` texture wrapping and RGBA / depth textures.
+
+pub use self::image::*;
+use self::rasterizer::*;
+use self::shaders::*;
+use self::texture::Texture;
+use crate::render::{GlContext, TextureUnit, UniformValue};
+use crate::shader::ShaderKind;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use wasm_bindgen::JsValue;
+use web_sys::WebGlRenderingContext as GL;
+
+mod image;
+mod rasterizer;
+mod shaders;
+mod texture;
+
+/// WebGL guarantees at least 8 texture units
+const MAX_TEXTURE_UNITS: usize = 8;
+
+/// A software rasterizer that implements `GlContext`.
+///
+/// ```ignore
+/// let (width, height) = state.canvas_size();
+/// let gl = SoftwareContext::new(width as i32, height as i32);
+/// gl.upload_image(TextureUnit::Dudv, &RgbaImage::read_png("./dudvmap.png")?);
+/// gl.upload_image(TextureUnit::NormalMap, &RgbaImage::read_png("./normalmap.png")?);
+/// gl.upload_image(TextureUnit::Stone, &RgbaImage::read_png("./stone-texture.png")?);
+///
+/// let mut renderer = WebRenderer::new(&gl, &state)?;
+/// renderer.render(&gl, &state, &assets);
+///
+/// let frame = gl.read_pixels();
+/// let diff = frame.compare(&RgbaImage::read_png("./screenshot.png")?, 2);
+/// ```
+pub struct SoftwareContext {
+    state: RefCell<SoftwareState>,
+    /// Extensions that `enable_extension` reports as unsupported
+    missing_extensions: Vec<&'static str>,
+}
+
+#[derive(Default)]
+struct SoftwareState {
+    next_handle: u32,
+
+    programs: HashMap<u32, Program>,
+    current_program: Option<u32>,
+
+    buffers: HashMap<u32, Vec<u8>>,
+    array_buffer: Option<u32>,
+    vaos: HashMap<u32, VertexArray>,
+    /// Used when no vertex array object is bound
+    default_vao: VertexArray,
+    current_vao: Option<u32>,
+
+    textures: HashMap<u32, Texture>,
+    active_texture_unit: usize,
+    texture_units: [Option<u32>; MAX_TEXTURE_UNITS],
+
+    framebuffers: HashMap<u32, Framebuffer>,
+    current_framebuffer: Option<u32>,
+    renderbuffers: HashMap<u32, Texture>,
+    current_renderbuffer: Option<u32>,
+    /// The canvas' drawing buffer
+    default_color: Texture,
+    default_depth: Texture,
+
+    viewport: Viewport,
+    clear_color: [f32; 4],
+    depth_test: bool,
+    blend: bool,
+    blend_func: (u32, u32),
+}
+
+#[derive(Default)]
+struct Program {
+    attached_shaders: Vec<u32>,
+    /// Which of our shaders the program was identified as before linking
+    identified_as: Option<ShaderKind>,
+    /// `None` until the program is successfully linked
+    shader_kind: Option<ShaderKind>,
+    uniforms: Uniforms,
+}
+
+#[derive(Default, Clone)]
+struct VertexArray {
+    attributes: [Option<AttribPointer>; MAX_VERTEX_ATTRIBS],
+    enabled: [bool; MAX_VERTEX_ATTRIBS],
+    /// How many instances each attribute's value is used for, or 0 to advance it every vertex
+    divisors: [u32; MAX_VERTEX_ATTRIBS],
+    element_buffer: Option<u32>,
+}
+
+/// WebGL only guarantees 8 vertex attributes, but our instanced mesh shaders need more (see
+/// `INSTANCED_VERTEX_ATTRIBS`) so we support as many as most devices do
+const MAX_VERTEX_ATTRIBS: usize = 16;
+
+#[derive(Clone, Copy)]
+struct AttribPointer {
+    buffer: u32,
+    size: i32,
+    type_: u32,
+    normalized: bool,
+    stride: i32,
+    offset: i32,
+}
+
+#[derive(Default)]
+struct Framebuffer {
+    color: Option<u32>,
+    depth: Option<DepthAttachment>,
+}
+
+#[derive(Clone, Copy)]
+enum DepthAttachment {
+    Texture(u32),
+    Renderbuffer(u32),
+}
+
+impl SoftwareContext {
+    /// Create a context whose drawing buffer is `width` x `height` pixels.
+    ///
+    /// Like our `create_webgl_context`, depth testing starts off enabled.
+    pub fn new(width: i32, height: i32) -> SoftwareContext {
+        let (width, height) = (width as usize, height as usize);
+
+        let state = SoftwareState {
+            default_color: Texture::new(width, height, [0., 0., 0., 0.]),
+            default_depth: Texture::new(width, height, [1., 1., 1., 1.]),
+            viewport: Viewport {
+                x: 0,
+                y: 0,
+                width: width as i32,
+                height: height as i32,
+            },
+            depth_test: true,
+            blend_func: (GL::ONE, GL::ZERO),
+            ..SoftwareState::default()
+        };
+
+        SoftwareContext {
+            state: RefCell::new(state),
+            missing_extensions: vec![],
+        }
+    }
+
+    /// Create a context for a device that doesn't support `extension`
+    pub fn without_extension(width: i32, height: i32, extension: &'static str) -> SoftwareContext {
+        SoftwareContext {
+            missing_extensions: vec![extension],
+            ..SoftwareContext::new(width, height)
+        }
+    }
+
+    /// Upload an image to a texture unit, the same way that `load_texture_image` does in the
+    /// browser.
+    pub fn upload_image(&self, texture_unit: TextureUnit, image: &RgbaImage) {
+        let texture = self.create_texture();
+
+        self.active_texture(texture_unit.TEXTURE_N());
+        self.bind_texture(GL::TEXTURE_2D, texture.as_ref());
+
+        self.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::NEAREST as i32);
+        self.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::NEAREST as i32);
+
+        // UNPACK_FLIP_Y_WEBGL
+        let row_len = image.width as usize * 4;
+        let pixels: Vec<u8> = image
+            .pixels
+            .chunks(row_len)
+            .rev()
+            .flat_map(|row| row.iter().cloned())
+            .collect();
+
+        self.tex_image_2d(
+            GL::TEXTURE_2D,
+            0,
+            GL::RGBA as i32,
+            image.width as i32,
+            image.height as i32,
+            0,
+            GL::RGBA,
+            GL::UNSIGNED_BYTE,
+            Some(&pixels),
+        )
+        .unwrap();
+    }
+
+    /// Read the drawing buffer, ex: after calling `WebRenderer::render`.
+    pub fn read_pixels(&self) -> RgbaImage {
+        let state = self.state.borrow();
+        let color = &state.default_color;
+
+        let mut image = RgbaImage::new(color.width as u32, color.height as u32);
+
+        // GL's first row is the bottom of the image, but our image's first row is the top
+        for (idx, texel) in color.texels.iter().enumerate() {
+            let (x, y) = (idx % color.width, idx / color.width);
+            let start = ((color.height - 1 - y) * color.width + x) * 4;
+
+            for (channel, value) in texel.iter().enumerate() {
+                image.pixels[start + channel] = (value * 255.).round() as u8;
+            }
+        }
+
+        image
+    }
+
+    fn new_handle(&self) -> u32 {
+        let mut state = self.state.borrow_mut();
+        state.next_handle += 1;
+        state.next_handle
+    }
+
+    fn uniform(&self, location: Option<&String>, value: UniformValue) {
+        let location = match location {
+            Some(location) => location,
+            None => return,
+        };
+
+        let mut state = self.state.borrow_mut();
+        let current_program = state.current_program.expect("No program in use");
+
+        state
+            .programs
+            .get_mut(&current_program)
+            .unwrap()
+            .uniforms
+            .insert(location.clone(), value);
+    }
+
+    fn buffer_data(&self, target: u32, bytes: Vec<u8>) {
+        let mut state = self.state.borrow_mut();
+
+        let buffer = match target {
+            GL::ELEMENT_ARRAY_BUFFER => state.vao().element_buffer,
+            _ => state.array_buffer,
+        };
+
+        state
+            .buffers
+            .insert(buffer.expect("No buffer bound to target"), bytes);
+    }
+
+    /// Draw the `instance`th instance of the vertices at `indices`
+    fn draw(&self, mode: u32, indices: impl Iterator<Item = usize>, instance: usize) {
+        assert_eq!(mode, GL::TRIANGLES, "Only GL::TRIANGLES is supported");
+
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+
+        let program = &state.programs[&state.current_program.expect("No program in use")];
+        let shader_kind = program.shader_kind.expect("Program was not linked");
+        let shader = create_shader(shader_kind, &program.uniforms, &state.texture_units());
+        let attribute_count = attribute_names(shader_kind).len();
+
+        let vao = state.vao().clone();
+
+        let mut shaded: HashMap<usize, ShadedVertex> = HashMap::new();
+        let mut vertices = vec![];
+
+        for index in indices {
+            let vertex = shaded.entry(index).or_insert_with(|| {
+                let attributes: Vec<[f32; 4]> = (0..attribute_count)
+                    .map(|attrib| state.read_attribute(&vao, attrib, index, instance))
+                    .collect();
+
+                let mut varyings = vec![0.; shader.varying_count()];
+                let position = shader.vertex(&attributes, &mut varyings);
+
+                ShadedVertex { position, varyings }
+            });
+
+            vertices.push(vertex.clone());
+        }
+
+        // Move our render target out of the state so that we can write to it while sampling
+        // from the other textures.
+        let mut color = state
+            .color_attachment()
+            .map(std::mem::take)
+            .unwrap_or_default();
+        let mut depth = state.depth_attachment().map(std::mem::take);
+
+        {
+            let textures = state.texture_units();
+
+            let mut target = RenderTarget {
+                color: &mut color,
+                depth: depth.as_mut(),
+                viewport: state.viewport,
+                depth_test: state.depth_test,
+                blend: if state.blend {
+                    Some(state.blend_func)
+                } else {
+                    None
+                },
+            };
+
+            draw_triangles(&mut target, &*shader, &textures, &vertices);
+        }
+
+        if let Some(attachment) = state.color_attachment() {
+            *attachment = color;
+        }
+        if let (Some(attachment), Some(depth)) = (state.depth_attachment(), depth) {
+            *attachment = depth;
+        }
+    }
+}
+
+impl SoftwareState {
+    fn vao(&mut self) -> &mut VertexArray {
+        match self.current_vao {
+            Some(vao) => self.vaos.get_mut(&vao).unwrap(),
+            None => &mut self.default_vao,
+        }
+    }
+
+    /// The color buffer of the bound framebuffer
+    fn color_attachment(&mut self) -> Option<&mut Texture> {
+        let framebuffer = match self.current_framebuffer {
+            Some(framebuffer) => &self.framebuffers[&framebuffer],
+            None => return Some(&mut self.default_color),
+        };
+
+        match framebuffer.color {
+            Some(texture) => self.textures.get_mut(&texture),
+            None => None,
+        }
+    }
+
+    /// The depth buffer of the bound framebuffer
+    fn depth_attachment(&mut self) -> Option<&mut Texture> {
+        let framebuffer = match self.current_framebuffer {
+            Some(framebuffer) => &self.framebuffers[&framebuffer],
+            None => return Some(&mut self.default_depth),
+        };
+
+        match framebuffer.depth {
+            Some(DepthAttachment::Texture(texture)) => self.textures.get_mut(&texture),
+            Some(DepthAttachment::Renderbuffer(renderbuffer)) => {
+                self.renderbuffers.get_mut(&renderbuffer)
+            }
+            None => None,
+        }
+    }
+
+    /// The texture that is bound to each texture unit
+    fn texture_units(&self) -> TextureUnits<'_> {
+        TextureUnits {
+            units: self
+                .texture_units
+                .iter()
+                .map(|texture| texture.and_then(|texture| self.textures.get(&texture)))
+                .collect(),
+        }
+    }
+
+    fn bound_texture(&mut self) -> &mut Texture {
+        let texture = self.texture_units[self.active_texture_unit].expect("No texture bound");
+        self.textures.get_mut(&texture).unwrap()
+    }
+
+    /// Read a vertex attribute, filling in missing components with (0, 0, 0, 1) like GL does.
+    fn read_attribute(
+        &self,
+        vao: &VertexArray,
+        attrib: usize,
+        vertex: usize,
+        instance: usize,
+    ) -> [f32; 4] {
+        let mut value = [0., 0., 0., 1.];
+
+        let pointer = match (vao.enabled[attrib], vao.attributes[attrib]) {
+            (true, Some(pointer)) => pointer,
+            _ => return value,
+        };
+
+        // Per-instance attributes advance once every `divisor` instances instead of every vertex
+        let element = match vao.divisors[attrib] {
+            0 => vertex,
+            divisor => instance / divisor as usize,
+        };
+
+        let bytes = &self.buffers[&pointer.buffer];
+
+        let component_size = match pointer.type_ {
+            GL::FLOAT => 4,
+            GL::UNSIGNED_SHORT => 2,
+            _ => 1,
+        };
+        let stride = match pointer.stride {
+            0 => pointer.size as usize * component_size,
+            stride => stride as usize,
+        };
+        let start = pointer.offset as usize + element * stride;
+
+        for (component, component_value) in value.iter_mut().take(pointer.size as usize).enumerate()
+        {
+            let idx = start + component * component_size;
+
+            *component_value = match pointer.type_ {
+                GL::FLOAT => {
+                    let mut float = [0; 4];
+                    float.copy_from_slice(&bytes[idx..idx + 4]);
+                    f32::from_le_bytes(float)
+                }
+                GL::UNSIGNED_SHORT => {
+                    let short = u16::from_le_bytes([bytes[idx], bytes[idx + 1]]) as f32;
+                    if pointer.normalized {
+                        short / 65535.
+                    } else {
+                        short
+                    }
+                }
+                _ => {
+                    let byte = bytes[idx] as f32;
+                    if pointer.normalized {
+                        byte / 255.
+                    } else {
+                        byte
+                    }
+                }
+            };
+        }
+
+        value
+    }
+}
+
+impl GlContext for SoftwareContext {
+    type Shader = u32;
+    type Program = u32;
+    /// Uniforms are looked up by name in the program that is in use
+    type UniformLocation = String;
+    type Buffer = u32;
+    type Texture = u32;
+    type Framebuffer = u32;
+    type Renderbuffer = u32;
+    type Vao = u32;
+
+    fn enable_extension(&self, name: &str) -> bool {
+        let supported = matches!(
+            name,
+            "WEBGL_depth_texture"
+                | "OES_vertex_array_object"
+                | "OES_texture_float"
+                | "ANGLE_instanced_arrays"
+        );
+
+        supported && !self.missing_extensions.contains(&name)
+    }
+
+    /// WebGL's minimum number of vertex uniforms. Unlike a lot of devices we can sample textures
+    /// in our vertex shaders, and we have more vertex attributes and larger textures than WebGL
+    /// guarantees.
+    fn get_parameter_i32(&self, pname: u32) -> i32 {
+        match pname {
+            GL::MAX_VERTEX_UNIFORM_VECTORS => 128,
+            GL::MAX_VERTEX_TEXTURE_IMAGE_UNITS => MAX_TEXTURE_UNITS as i32,
+            GL::MAX_VERTEX_ATTRIBS => MAX_VERTEX_ATTRIBS as i32,
+            GL::MAX_TEXTURE_SIZE => 4096,
+            _ => 0,
+        }
+    }
+
+    fn create_vertex_array(&self) -> Option<u32> {
+        let vao = self.new_handle();
+        self.state
+            .borrow_mut()
+            .vaos
+            .insert(vao, VertexArray::default());
+        Some(vao)
+    }
+
+    fn bind_vertex_array(&self, vao: Option<&u32>) {
+        self.state.borrow_mut().current_vao = vao.cloned();
+    }
+
+    fn create_shader(&self, _shader_type: u32) -> Option<u32> {
+        Some(self.new_handle())
+    }
+
+    /// Programs run our Rust ports instead of their GLSL
+    fn shader_source(&self, _shader: &u32, _source: &str) {}
+
+    fn compile_shader(&self, _shader: &u32) {}
+
+    fn shader_compiled(&self, _shader: &u32) -> bool {
+        true
+    }
+
+    fn get_shader_info_log(&self, _shader: &u32) -> Option<String> {
+        None
+    }
+
+    fn create_program(&self) -> Option<u32> {
+        let program = self.new_handle();
+        self.state
+            .borrow_mut()
+            .programs
+            .insert(program, Program::default());
+        Some(program)
+    }
+
+    fn attach_shader(&self, program: &u32, shader: &u32) {
+        let mut state = self.state.borrow_mut();
+        state
+            .programs
+            .get_mut(program)
+            .unwrap()
+            .attached_shaders
+            .push(*shader);
+    }
+
+    fn identify_program(&self, program: &u32, shader_kind: ShaderKind) {
+        let mut state = self.state.borrow_mut();
+        state.programs.get_mut(program).unwrap().identified_as = Some(shader_kind);
+    }
+
+    fn link_program(&self, program: &u32) {
+        let mut state = self.state.borrow_mut();
+        let program = state.programs.get_mut(program).unwrap();
+
+        // A vertex and a fragment shader
+        if program.attached_shaders.len() == 2 {
+            program.shader_kind = program.identified_as;
+        }
+    }
+
+    fn program_linked(&self, program: &u32) -> bool {
+        self.state.borrow().programs[program].shader_kind.is_some()
+    }
+
+    fn get_program_info_log(&self, program: &u32) -> Option<String> {
+        if self.program_linked(program) {
+            return None;
+        }
+
+        Some("The program was not identified as one of our shaders".to_string())
+    }
+
+    fn use_program(&self, program: Option<&u32>) {
+        self.state.borrow_mut().current_program = program.cloned();
+    }
+
+    fn get_uniform_location(&self, _program: &u32, name: &str) -> Option<String> {
+        Some(name.to_string())
+    }
+
+    fn get_attrib_location(&self, program: &u32, name: &str) -> i32 {
+        let state = self.state.borrow();
+        let shader_kind = match state.programs[program].shader_kind {
+            Some(shader_kind) => shader_kind,
+            None => return -1,
+        };
+
+        attribute_names(shader_kind)
+            .iter()
+            .position(|attrib| *attrib == name)
+            .map(|location| location as i32)
+            .unwrap_or(-1)
+    }
+
+    fn create_buffer(&self) -> Option<u32> {
+        Some(self.new_handle())
+    }
+
+    fn bind_buffer(&self, target: u32, buffer: Option<&u32>) {
+        let mut state = self.state.borrow_mut();
+
+        match target {
+            GL::ELEMENT_ARRAY_BUFFER => state.vao().element_buffer = buffer.cloned(),
+            _ => state.array_buffer = buffer.cloned(),
+        };
+    }
+
+    fn buffer_data_f32(&self, target: u32, data: &[f32], _usage: u32) {
+        self.buffer_data(
+            target,
+            data.iter()
+                .flat_map(|float| float.to_le_bytes().to_vec())
+                .collect(),
+        );
+    }
+
+    fn buffer_data_u8(&self, target: u32, data: &[u8], _usage: u32) {
+        self.buffer_data(target, data.to_vec());
+    }
+
+    fn buffer_data_u16(&self, target: u32, data: &[u16], _usage: u32) {
+        self.buffer_data(
+            target,
+            data.iter()
+                .flat_map(|short| short.to_le_bytes().to_vec())
+                .collect(),
+        );
+    }
+
+    fn enable_vertex_attrib_array(&self, index: u32) {
+        self.state.borrow_mut().vao().enabled[index as usize] = true;
+    }
+
+    fn vertex_attrib_pointer_with_i32(
+        &self,
+        index: u32,
+        size: i32,
+        type_: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    ) {
+        let mut state = self.state.borrow_mut();
+        let buffer = state.array_buffer.expect("No GL::ARRAY_BUFFER bound");
+
+        state.vao().attributes[index as usize] = Some(AttribPointer {
+            buffer,
+            size,
+            type_,
+            normalized,
+            stride,
+            offset,
+        });
+    }
+
+    fn vertex_attrib_divisor_angle(&self, index: u32, divisor: u32) {
+        self.state.borrow_mut().vao().divisors[index as usize] = divisor;
+    }
+
+    fn create_texture(&self) -> Option<u32> {
+        let texture = self.new_handle();
+        self.state
+            .borrow_mut()
+            .textures
+            .insert(texture, Texture::default());
+        Some(texture)
+    }
+
+    /// Like WebGL, deleting a texture unbinds it from every texture unit
+    fn delete_texture(&self, texture: Option<&u32>) {
+        let texture = match texture {
+            Some(texture) => *texture,
+            None => return,
+        };
+
+        let mut state = self.state.borrow_mut();
+        state.textures.remove(&texture);
+
+        for unit in state.texture_units.iter_mut() {
+            if *unit == Some(texture) {
+                *unit = None;
+            }
+        }
+    }
+
+    fn active_texture(&self, texture: u32) {
+        self.state.borrow_mut().active_texture_unit = (texture - GL::TEXTURE0) as usize;
+    }
+
+    fn bind_texture(&self, _target: u32, texture: Option<&u32>) {
+        let mut state = self.state.borrow_mut();
+        let unit = state.active_texture_unit;
+        state.texture_units[unit] = texture.cloned();
+    }
+
+    fn tex_parameteri(&self, _target: u32, pname: u32, param: i32) {
+        if pname == GL::TEXTURE_MAG_FILTER {
+            self.state.borrow_mut().bound_texture().mag_filter = param as u32;
+        }
+    }
+
+    fn tex_image_2d(
+        &self,
+        _target: u32,
+        _level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        _border: i32,
+        _format: u32,
+        _type_: u32,
+        pixels: Option<&[u8]>,
+    ) -> Result<(), JsValue> {
+        let mut state = self.state.borrow_mut();
+        let texture = state.bound_texture();
+
+        let mut allocated = Texture::new(width as usize, height as usize, [0., 0., 0., 0.]);
+        allocated.mag_filter = texture.mag_filter;
+
+        if let Some(pixels) = pixels {
+            if internal_format != GL::RGBA as i32 {
+                return Err("Only GL::RGBA pixels can be uploaded".into());
+            }
+
+            for (texel, rgba) in allocated.texels.iter_mut().zip(pixels.chunks(4)) {
+                for channel in 0..4 {
+                    texel[channel] = rgba[channel] as f32 / 255.;
+                }
+            }
+        }
+
+        *texture = allocated;
+
+        Ok(())
+    }
+
+    fn tex_image_2d_f32(
+        &self,
+        _target: u32,
+        _level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        _border: i32,
+        _format: u32,
+        pixels: &[f32],
+    ) -> Result<(), JsValue> {
+        if internal_format != GL::RGBA as i32 {
+            return Err("Only GL::RGBA pixels can be uploaded".into());
+        }
+
+        let mut state = self.state.borrow_mut();
+        let texture = state.bound_texture();
+
+        let mut allocated = Texture::new(width as usize, height as usize, [0., 0., 0., 0.]);
+        allocated.mag_filter = texture.mag_filter;
+
+        for (texel, rgba) in allocated.texels.iter_mut().zip(pixels.chunks(4)) {
+            texel.copy_from_slice(rgba);
+        }
+
+        *texture = allocated;
+
+        Ok(())
+    }
+
+    fn create_framebuffer(&self) -> Option<u32> {
+        let framebuffer = self.new_handle();
+        self.state
+            .borrow_mut()
+            .framebuffers
+            .insert(framebuffer, Framebuffer::default());
+        Some(framebuffer)
+    }
+
+    /// Deleting the bound framebuffer goes back to drawing to the canvas
+    fn delete_framebuffer(&self, framebuffer: Option<&u32>) {
+        let framebuffer = match framebuffer {
+            Some(framebuffer) => *framebuffer,
+            None => return,
+        };
+
+        let mut state = self.state.borrow_mut();
+        state.framebuffers.remove(&framebuffer);
+
+        if state.current_framebuffer == Some(framebuffer) {
+            state.current_framebuffer = None;
+        }
+    }
+
+    fn bind_framebuffer(&self, _target: u32, framebuffer: Option<&u32>) {
+        self.state.borrow_mut().current_framebuffer = framebuffer.cloned();
+    }
+
+    fn framebuffer_texture_2d(
+        &self,
+        _target: u32,
+        attachment: u32,
+        _textarget: u32,
+        texture: Option<&u32>,
+        _level: i32,
+    ) {
+        let mut state = self.state.borrow_mut();
+        let current_framebuffer = state.current_framebuffer.expect("No framebuffer bound");
+        let framebuffer = state.framebuffers.get_mut(&current_framebuffer).unwrap();
+
+        match attachment {
+            GL::COLOR_ATTACHMENT0 => framebuffer.color = texture.cloned(),
+            GL::DEPTH_ATTACHMENT => {
+                framebuffer.depth = texture.cloned().map(DepthAttachment::Texture)
+            }
+            _ => {}
+        };
+    }
+
+    fn create_renderbuffer(&self) -> Option<u32> {
+        let renderbuffer = self.new_handle();
+        self.state
+            .borrow_mut()
+            .renderbuffers
+            .insert(renderbuffer, Texture::default());
+        Some(renderbuffer)
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: Option<&u32>) {
+        let renderbuffer = match renderbuffer {
+            Some(renderbuffer) => *renderbuffer,
+            None => return,
+        };
+
+        let mut state = self.state.borrow_mut();
+        state.renderbuffers.remove(&renderbuffer);
+
+        if state.current_renderbuffer == Some(renderbuffer) {
+            state.current_renderbuffer = None;
+        }
+    }
+
+    fn bind_renderbuffer(&self, _target: u32, renderbuffer: Option<&u32>) {
+        self.state.borrow_mut().current_renderbuffer = renderbuffer.cloned();
+    }
+
+    fn renderbuffer_storage(&self, _target: u32, _internal_format: u32, width: i32, height: i32) {
+        let mut state = self.state.borrow_mut();
+        let renderbuffer = state.current_renderbuffer.expect("No renderbuffer bound");
+
+        state.renderbuffers.insert(
+            renderbuffer,
+            Texture::new(width as usize, height as usize, [1., 1., 1., 1.]),
+        );
+    }
+
+    fn framebuffer_renderbuffer(
+        &self,
+        _target: u32,
+        attachment: u32,
+        _renderbuffer_target: u32,
+        renderbuffer: Option<&u32>,
+    ) {
+        let mut state = self.state.borrow_mut();
+        let current_framebuffer = state.current_framebuffer.expect("No framebuffer bound");
+        let framebuffer = state.framebuffers.get_mut(&current_framebuffer).unwrap();
+
+        if attachment == GL::DEPTH_ATTACHMENT {
+            framebuffer.depth = renderbuffer.cloned().map(DepthAttachment::Renderbuffer);
+        }
+    }
+
+    fn uniform1i(&self, location: Option<&String>, x: i32) {
+        self.uniform(location, UniformValue::Int(x));
+    }
+
+    fn uniform1f(&self, location: Option<&String>, x: f32) {
+        self.uniform(location, UniformValue::Float(x));
+    }
+
+    fn uniform2f(&self, location: Option<&String>, x: f32, y: f32) {
+        self.uniform(location, UniformValue::Floats(vec![x, y]));
+    }
+
+    fn uniform4f(&self, location: Option<&String>, x: f32, y: f32, z: f32, w: f32) {
+        self.uniform(location, UniformValue::Floats(vec![x, y, z, w]));
+    }
+
+    fn uniform3fv_with_f32_array(&self, location: Option<&String>, data: &mut [f32]) {
+        self.uniform(location, UniformValue::Floats(data.to_vec()));
+    }
+
+    fn uniform4fv_with_f32_array(&self, location: Option<&String>, data: &mut [f32]) {
+        self.uniform(location, UniformValue::Floats(data.to_vec()));
+    }
+
+    fn uniform_matrix4fv_with_f32_array(
+        &self,
+        location: Option<&String>,
+        transpose: bool,
+        data: &mut [f32],
+    ) {
+        self.uniform(
+            location,
+            UniformValue::Mat4 {
+                transpose,
+                values: data.to_vec(),
+            },
+        );
+    }
+
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        self.state.borrow_mut().viewport = Viewport {
+            x,
+            y,
+            width,
+            height,
+        };
+    }
+
+    fn clear_color(&self, red: f32, green: f32, blue: f32, alpha: f32) {
+        self.state.borrow_mut().clear_color = [red, green, blue, alpha];
+    }
+
+    fn clear(&self, mask: u32) {
+        let mut state = self.state.borrow_mut();
+        let clear_color = state.clear_color;
+
+        if mask & GL::COLOR_BUFFER_BIT != 0 {
+            if let Some(color) = state.color_attachment() {
+                color
+                    .texels
+                    .iter_mut()
+                    .for_each(|texel| *texel = clear_color);
+            }
+        }
+
+        if mask & GL::DEPTH_BUFFER_BIT != 0 {
+            if let Some(depth) = state.depth_attachment() {
+                depth.texels.iter_mut().for_each(|texel| *texel = [1.; 4]);
+            }
+        }
+    }
+
+    fn enable(&self, cap: u32) {
+        let mut state = self.state.borrow_mut();
+        match cap {
+            GL::DEPTH_TEST => state.depth_test = true,
+            GL::BLEND => state.blend = true,
+            _ => {}
+        };
+    }
+
+    fn disable(&self, cap: u32) {
+        let mut state = self.state.borrow_mut();
+        match cap {
+            GL::DEPTH_TEST => state.depth_test = false,
+            GL::BLEND => state.blend = false,
+            _ => {}
+        };
+    }
+
+    fn blend_func(&self, sfactor: u32, dfactor: u32) {
+        self.state.borrow_mut().blend_func = (sfactor, dfactor);
+    }
+
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32) {
+        self.draw(mode, (first as usize)..(first + count) as usize, 0);
+    }
+
+    fn draw_elements_with_i32(&self, mode: u32, count: i32, type_: u32, offset: i32) {
+        self.draw_elements_instanced_angle_with_i32(mode, count, type_, offset, 1);
+    }
+
+    fn draw_elements_instanced_angle_with_i32(
+        &self,
+        mode: u32,
+        count: i32,
+        type_: u32,
+        offset: i32,
+        primcount: i32,
+    ) {
+        assert_eq!(type_, GL::UNSIGNED_SHORT, "Only u16 indices are supported");
+
+        let indices: Vec<usize> = {
+            let mut state = self.state.borrow_mut();
+            let element_buffer = state.vao().element_buffer.expect("No index buffer bound");
+            let bytes = &state.buffers[&element_buffer];
+
+            let start = offset as usize;
+            let end = start + count as usize * 2;
+
+            bytes[start..end]
+                .chunks(2)
+                .map(|short| u16::from_le_bytes([short[0], short[1]]) as usize)
+                .collect()
+        };
+
+        for instance in 0..primcount as usize {
+            self.draw(mode, indices.iter().cloned(), instance);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::RENDER_READY_VERSION;
+    use crate::app::{render_ready_bundle, AssetBundle, Assets, Msg, Scene, Store};
+    use crate::render::WebRenderer;
+
+    /// Pixels whose channels all differ by no more than this match their golden pixel
+    const CHANNEL_TOLERANCE: u8 = 2;
+
+    /// How many pixels can differ from a golden image, to allow for the last bit of float math
+    /// differing between platforms
+    const MAX_DIFFERING_PIXELS: usize = 64;
+
+    /// The default scene's terrain with the bird hovering over the water, at the start of its
+    /// animation so that it's posed exactly as it was keyframed
+    const BIRD_SCENE: &str = r#"{
+        "entities": [
+            {"mesh": "Terrain"},
+            {
+                "mesh": "Bird",
+                "armature": "Armature.001",
+                "action": "Fly.001",
+                "transform": {"translation": [0, 4, 0]}
+            }
+        ],
+        "waterBodies": [{"position": [0, 0], "extents": [18, 18], "height": 0}]
+    }"#;
+
+    fn path(file: &str) -> String {
+        format!("{}/{}", env!("CARGO_MANIFEST_DIR"), file)
+    }
+
+    /// The meshes and armatures that the browser downloads
+    fn bundled_assets() -> Assets {
+        let mut assets = Assets::default();
+        assets
+            .insert_bundle(
+                AssetBundle::Meshes,
+                include_bytes!("../../../../meshes.bytes"),
+            )
+            .unwrap();
+        assets
+            .insert_bundle(
+                AssetBundle::Armatures,
+                include_bytes!("../../../../armatures.bytes"),
+            )
+            .unwrap();
+        assets
+    }
+
+    /// Render the first frame of a scene with the assets and textures that the browser downloads
+    fn render_first_frame(scene: Option<Scene>) -> RgbaImage {
+        render_first_frame_with_assets(scene, &bundled_assets())
+    }
+
+    /// Render the first frame of a scene with the textures that the browser downloads
+    fn render_first_frame_with_assets(scene: Option<Scene>, assets: &Assets) -> RgbaImage {
+        render_first_frame_on(SoftwareContext::new, scene, assets)
+    }
+
+    /// Render the first frame of a scene on a device that can't draw instances
+    fn render_first_frame_without_instancing(scene: Option<Scene>, assets: &Assets) -> RgbaImage {
+        let without_instancing = |width, height| {
+            SoftwareContext::without_extension(width, height, "ANGLE_instanced_arrays")
+        };
+
+        render_first_frame_on(without_instancing, scene, assets)
+    }
+
+    /// Render the first frame of a scene on the context that `create_context` creates for our
+    /// canvas size
+    fn render_first_frame_on(
+        create_context: impl Fn(i32, i32) -> SoftwareContext,
+        scene: Option<Scene>,
+        assets: &Assets,
+    ) -> RgbaImage {
+        let mut store = Store::new();
+        store.msg(&Msg::Resize(256, 256));
+        if let Some(scene) = scene {
+            store.msg(&Msg::LoadScene(scene));
+        }
+
+        let (width, height) = store.state.canvas_size();
+        let gl = create_context(width as i32, height as i32);
+        for (texture_unit, image) in [
+            (TextureUnit::Dudv, "dudvmap.png"),
+            (TextureUnit::NormalMap, "normalmap.png"),
+            (TextureUnit::Stone, "stone-texture.png"),
+        ]
+        .iter()
+        {
+            gl.upload_image(*texture_unit, &RgbaImage::read_png(path(image)).unwrap());
+        }
+
+        let mut renderer = WebRenderer::new(&gl, &store.state).unwrap();
+        renderer.render(&gl, &store.state, assets);
+        assert_eq!(*renderer.asset_errors(), vec![]);
+
+        gl.read_pixels()
+    }
+
+    /// Compare a frame against its golden image.
+    ///
+    /// Run with `UPDATE_GOLDEN_IMAGES=1` to overwrite the golden image after an intentional
+    /// change to what gets rendered.
+    fn assert_matches_golden(frame: &RgbaImage, file: &str) {
+        let golden = path(&format!("golden/{}", file));
+
+        if std::env::var("UPDATE_GOLDEN_IMAGES").is_ok() {
+            frame.write_png(&golden).unwrap();
+        }
+
+        let diff = frame.compare(&RgbaImage::read_png(&golden).unwrap(), CHANNEL_TOLERANCE);
+        if diff.differing_pixels > MAX_DIFFERING_PIXELS {
+            // So that the failed frame can be looked at next to the golden one
+            let actual = path(&format!("target/{}", file));
+            frame.write_png(&actual).unwrap();
+            panic!("{} does not match {}: {:?}", actual, golden, diff);
+        }
+    }
+
+    #[test]
+    fn default_scene_matches_golden_image() {
+        assert_matches_golden(&render_first_frame(None), "default-scene.png");
+    }
+
+    #[test]
+    fn skinned_bird_matches_golden_image() {
+        let scene = serde_json::from_str(BIRD_SCENE).unwrap();
+        assert_matches_golden(&render_first_frame(Some(scene)), "bird.png");
+    }
+
+    /// Two birds flying side by side
+    const FLOCK: &str = r#"{
+        "entities": [
+            {
+                "mesh": "Bird",
+                "armature": "Armature.001",
+                "action": "Fly.001",
+                "transform": {"translation": [-3, 4, 0]}
+            },
+            {
+                "mesh": "Bird",
+                "armature": "Armature.001",
+                "action": "Fly.001",
+                "transform": {"translation": [3, 4, 0]}
+            }
+        ],
+        "waterBodies": []
+    }"#;
+
+    /// Our bundled assets with the bird's armature padded with bones that no vertex uses until
+    /// they no longer fit in our uniforms, so that birds get skinned from a bone texture
+    fn texture_bone_assets() -> Assets {
+        let mut texture_bones = bundled_assets();
+        let mut rig = texture_bones.armature("Armature.001").unwrap().clone();
+        let bone = rig.inverse_bind_poses[0].clone();
+        rig.inverse_bind_poses.resize(60, bone);
+        for keyframes in rig.actions.values_mut() {
+            for bones in keyframes.values_mut() {
+                let pose = bones[0].clone();
+                bones.resize(60, pose);
+            }
+        }
+
+        let mut armatures = std::collections::HashMap::new();
+        armatures.insert("Armature.001".to_string(), rig);
+        let bundle = render_ready_bundle(RENDER_READY_VERSION, &armatures);
+        texture_bones
+            .insert_bundle(AssetBundle::Armatures, &bundle)
+            .unwrap();
+
+        texture_bones
+    }
+
+    #[test]
+    fn flocks_skinned_from_a_bone_texture_match_ones_skinned_from_uniforms() {
+        let expected =
+            render_first_frame_with_assets(serde_json::from_str(FLOCK).ok(), &bundled_assets());
+        let actual = render_first_frame_with_assets(
+            serde_json::from_str(FLOCK).ok(),
+            &texture_bone_assets(),
+        );
+
+        let diff = actual.compare(&expected, CHANNEL_TOLERANCE);
+        assert!(diff.differing_pixels <= MAX_DIFFERING_PIXELS, "{:?}", diff);
+    }
+
+    #[test]
+    fn default_scene_drawn_without_instancing_matches_golden_image() {
+        let frame = render_first_frame_without_instancing(None, &bundled_assets());
+        assert_matches_golden(&frame, "default-scene.png");
+    }
+
+    #[test]
+    fn skinned_bird_drawn_without_instancing_matches_golden_image() {
+        let scene = serde_json::from_str(BIRD_SCENE).ok();
+        let frame = render_first_frame_without_instancing(scene, &bundled_assets());
+        assert_matches_golden(&frame, "bird.png");
+    }
+
+    #[test]
+    fn flocks_skinned_from_a_bone_texture_without_instancing_match_instanced_flocks() {
+        let expected = render_first_frame_with_assets(
+            serde_json::from_str(FLOCK).ok(),
+            &texture_bone_assets(),
+        );
+        let actual = render_first_frame_without_instancing(
+            serde_json::from_str(FLOCK).ok(),
+            &texture_bone_assets(),
+        );
+
+        let diff = actual.compare(&expected, CHANNEL_TOLERANCE);
+        assert!(diff.differing_pixels <= MAX_DIFFERING_PIXELS, "{:?}", diff);
+    }
+}
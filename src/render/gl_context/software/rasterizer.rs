@@ -0,0 +1,254 @@
+use super::shaders::{SoftwareShader, TextureUnits};
+use super::texture::Texture;
+use web_sys::WebGlRenderingContext as GL;
+
+/// A vertex after it has been through the vertex shader
+#[derive(Debug, Clone)]
+pub struct ShadedVertex {
+    /// gl_Position
+    pub position: [f32; 4],
+    pub varyings: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Where a draw call's fragments end up and the fixed function state that gets applied to them
+pub struct RenderTarget<'a> {
+    pub color: &'a mut Texture,
+    pub depth: Option<&'a mut Texture>,
+    pub viewport: Viewport,
+    pub depth_test: bool,
+    /// (sfactor, dfactor) when `GL::BLEND` is enabled
+    pub blend: Option<(u32, u32)>,
+}
+
+/// A vertex in window coordinates with its varyings pre-divided by w for perspective correct
+/// interpolation.
+struct WindowVertex {
+    x: f32,
+    y: f32,
+    z: f32,
+    inv_w: f32,
+    varyings_over_w: Vec<f32>,
+}
+
+/// Rasterize `GL::TRIANGLES`, where every three vertices make up one triangle.
+pub fn draw_triangles(
+    target: &mut RenderTarget,
+    shader: &dyn SoftwareShader,
+    textures: &TextureUnits,
+    vertices: &[ShadedVertex],
+) {
+    for triangle in vertices.chunks(3) {
+        if triangle.len() < 3 {
+            break;
+        }
+
+        let polygon = clip_against_near_plane(triangle);
+        if polygon.len() < 3 {
+            continue;
+        }
+
+        let polygon: Vec<WindowVertex> = polygon
+            .iter()
+            .map(|vertex| to_window(vertex, target.viewport))
+            .collect();
+
+        // Our clipped polygon is convex, so we can fan it out into triangles
+        for idx in 1..polygon.len() - 1 {
+            rasterize_triangle(
+                target,
+                shader,
+                textures,
+                [&polygon[0], &polygon[idx], &polygon[idx + 1]],
+            );
+        }
+    }
+}
+
+/// Clip a triangle against the near plane (z = -w) so that we never divide by a w that is zero
+/// or negative. The other clip planes are handled by only rasterizing pixels within the viewport
+/// and discarding fragments with a depth outside of [0, 1].
+fn clip_against_near_plane(triangle: &[ShadedVertex]) -> Vec<ShadedVertex> {
+    let distance = |vertex: &ShadedVertex| vertex.position[2] + vertex.position[3];
+
+    if triangle.iter().all(|vertex| distance(vertex) >= 0.) {
+        return triangle.to_vec();
+    }
+
+    let mut clipped = vec![];
+
+    for idx in 0..triangle.len() {
+        let current = &triangle[idx];
+        let next = &triangle[(idx + 1) % triangle.len()];
+
+        let current_distance = distance(current);
+        let next_distance = distance(next);
+
+        if current_distance >= 0. {
+            clipped.push(current.clone());
+        }
+
+        if (current_distance >= 0.) != (next_distance >= 0.) {
+            let t = current_distance / (current_distance - next_distance);
+            clipped.push(lerp_vertex(current, next, t));
+        }
+    }
+
+    clipped
+}
+
+fn lerp_vertex(a: &ShadedVertex, b: &ShadedVertex, t: f32) -> ShadedVertex {
+    let lerp = |a: f32, b: f32| a + (b - a) * t;
+
+    let mut position = [0.; 4];
+    for (idx, component) in position.iter_mut().enumerate() {
+        *component = lerp(a.position[idx], b.position[idx]);
+    }
+
+    ShadedVertex {
+        position,
+        varyings: a
+            .varyings
+            .iter()
+            .zip(b.varyings.iter())
+            .map(|(a, b)| lerp(*a, *b))
+            .collect(),
+    }
+}
+
+/// Perspective divide and viewport transform
+fn to_window(vertex: &ShadedVertex, viewport: Viewport) -> WindowVertex {
+    let [x, y, z, w] = vertex.position;
+    let inv_w = 1. / w;
+
+    let (ndc_x, ndc_y, ndc_z) = (x * inv_w, y * inv_w, z * inv_w);
+
+    WindowVertex {
+        x: (ndc_x + 1.) / 2. * viewport.width as f32 + viewport.x as f32,
+        y: (ndc_y + 1.) / 2. * viewport.height as f32 + viewport.y as f32,
+        z: (ndc_z + 1.) / 2.,
+        inv_w,
+        varyings_over_w: vertex.varyings.iter().map(|v| v * inv_w).collect(),
+    }
+}
+
+fn edge(a: &WindowVertex, b: &WindowVertex, x: f32, y: f32) -> f32 {
+    (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+}
+
+fn rasterize_triangle(
+    target: &mut RenderTarget,
+    shader: &dyn SoftwareShader,
+    textures: &TextureUnits,
+    [v0, v1, v2]: [&WindowVertex; 3],
+) {
+    let area = edge(v0, v1, v2.x, v2.y);
+    if area == 0. || !area.is_finite() {
+        return;
+    }
+
+    let viewport = target.viewport;
+
+    let min_x = viewport.x.max(0);
+    let min_y = viewport.y.max(0);
+    let max_x = (viewport.x + viewport.width).min(target.color.width as i32);
+    let max_y = (viewport.y + viewport.height).min(target.color.height as i32);
+
+    let min_x = min_x.max(v0.x.min(v1.x).min(v2.x).floor() as i32);
+    let min_y = min_y.max(v0.y.min(v1.y).min(v2.y).floor() as i32);
+    let max_x = max_x.min(v0.x.max(v1.x).max(v2.x).ceil() as i32);
+    let max_y = max_y.min(v0.y.max(v1.y).max(v2.y).ceil() as i32);
+
+    let mut varyings = vec![0.; v0.varyings_over_w.len()];
+
+    for py in min_y..max_y {
+        for px in min_x..max_x {
+            // Sample at the pixel's center
+            let (x, y) = (px as f32 + 0.5, py as f32 + 0.5);
+
+            let w0 = edge(v1, v2, x, y) / area;
+            let w1 = edge(v2, v0, x, y) / area;
+            let w2 = edge(v0, v1, x, y) / area;
+
+            if w0 < 0. || w1 < 0. || w2 < 0. {
+                continue;
+            }
+
+            let z = w0 * v0.z + w1 * v1.z + w2 * v2.z;
+            if !(0. ..=1.).contains(&z) {
+                continue;
+            }
+
+            let (px, py) = (px as usize, py as usize);
+
+            if target.depth_test {
+                if let Some(depth) = target.depth.as_ref() {
+                    if z >= depth.texels[py * depth.width + px][0] {
+                        continue;
+                    }
+                }
+            }
+
+            let inv_w = w0 * v0.inv_w + w1 * v1.inv_w + w2 * v2.inv_w;
+            for (idx, varying) in varyings.iter_mut().enumerate() {
+                *varying = (w0 * v0.varyings_over_w[idx]
+                    + w1 * v1.varyings_over_w[idx]
+                    + w2 * v2.varyings_over_w[idx])
+                    / inv_w;
+            }
+
+            let color = match shader.fragment([x, y, z, inv_w], &varyings, textures) {
+                Some(color) => color,
+                None => continue,
+            };
+
+            let dst = target.color.texel_mut(px, py);
+            let color = match target.blend {
+                Some((sfactor, dfactor)) => blend(color, *dst, sfactor, dfactor),
+                None => color,
+            };
+            *dst = to_rgba8(color);
+
+            if target.depth_test {
+                if let Some(depth) = target.depth.as_mut() {
+                    *depth.texel_mut(px, py) = [z, z, z, 1.];
+                }
+            }
+        }
+    }
+}
+
+fn blend(src: [f32; 4], dst: [f32; 4], sfactor: u32, dfactor: u32) -> [f32; 4] {
+    let factor = |factor: u32| match factor {
+        GL::ZERO => 0.,
+        GL::SRC_ALPHA => src[3],
+        GL::ONE_MINUS_SRC_ALPHA => 1. - src[3],
+        GL::DST_ALPHA => dst[3],
+        GL::ONE_MINUS_DST_ALPHA => 1. - dst[3],
+        _ => 1.,
+    };
+    let (sfactor, dfactor) = (factor(sfactor), factor(dfactor));
+
+    let mut blended = [0.; 4];
+    for idx in 0..4 {
+        blended[idx] = src[idx] * sfactor + dst[idx] * dfactor;
+    }
+    blended
+}
+
+/// Our color attachments are all `GL::RGBA` + `GL::UNSIGNED_BYTE`, so we clamp and quantize
+/// colors the same way that the GPU would when writing them.
+fn to_rgba8(color: [f32; 4]) -> [f32; 4] {
+    let mut quantized = [0.; 4];
+    for idx in 0..4 {
+        quantized[idx] = (color[idx].max(0.).min(1.) * 255.).round() / 255.;
+    }
+    quantized
+}
@@ -0,0 +1,892 @@
+//! Rust ports of the GLSL shaders in `src/shader`.
+//!
+//! Each port follows its GLSL file line by line so that a change to one of our shaders can be
+//! mirrored here. If you edit a `.glsl` file, edit its port too or the golden images will stop
+//! matching what the browser renders.
+
+use super::texture::Texture;
+use crate::app::MAX_WAVES;
+use crate::render::UniformValue;
+use crate::shader::*;
+use nalgebra::{Matrix4, Vector2, Vector3, Vector4};
+use std::collections::HashMap;
+
+/// The uniforms that have been uploaded to a shader program, keyed by name.
+pub type Uniforms = HashMap<String, UniformValue>;
+
+/// A vertex + fragment shader pair that the rasterizer can run.
+pub trait SoftwareShader {
+    /// How many floats the vertex shader writes to `varyings`
+    fn varying_count(&self) -> usize;
+
+    /// Run the vertex shader, returning `gl_Position`.
+    ///
+    /// `attributes` are indexed by attribute location, see `attribute_names`.
+    fn vertex(&self, attributes: &[[f32; 4]], varyings: &mut [f32]) -> [f32; 4];
+
+    /// Run the fragment shader, returning `gl_FragColor` or `None` if the fragment was discarded.
+    fn fragment(
+        &self,
+        frag_coord: [f32; 4],
+        varyings: &[f32],
+        textures: &TextureUnits,
+    ) -> Option<[f32; 4]>;
+}
+
+/// The textures that are bound to each texture unit while drawing
+pub struct TextureUnits<'a> {
+    pub units: Vec<Option<&'a Texture>>,
+}
+
+impl<'a> TextureUnits<'a> {
+    /// `texture2D(sampler, uv)`. Unbound texture units sample as opaque black, like WebGL's
+    /// incomplete textures.
+    fn texture_2d(&self, sampler: i32, uv: Vector2<f32>) -> Vector4<f32> {
+        let texel = self
+            .units
+            .get(sampler as usize)
+            .and_then(|texture| *texture)
+            .map(|texture| texture.sample([uv.x, uv.y]))
+            .unwrap_or([0., 0., 0., 1.]);
+
+        Vector4::from_column_slice(&texel)
+    }
+}
+
+/// The attributes of each shader. An attribute's location is its index in this list, and a mat4
+/// attribute takes up four locations (one per column) so it is listed four times.
+pub fn attribute_names(shader_kind: ShaderKind) -> &'static [&'static str] {
+    match shader_kind {
+        ShaderKind::Water => &["position"],
+        ShaderKind::NonSkinnedMesh => &[
+            "position",
+            "normal",
+            "uvs",
+            "model",
+            "model",
+            "model",
+            "model",
+            "normalMatrix",
+            "normalMatrix",
+            "normalMatrix",
+            "normalMatrix",
+        ],
+        ShaderKind::SkinnedMesh(BoneStorage::Uniforms(_)) => &[
+            "position",
+            "normal",
+            "uvs",
+            "jointIndices",
+            "jointWeights",
+            "model",
+            "model",
+            "model",
+            "model",
+            "normalMatrix",
+            "normalMatrix",
+            "normalMatrix",
+            "normalMatrix",
+        ],
+        // Only the bone texture needs to know which instance we are
+        ShaderKind::SkinnedMesh(BoneStorage::Texture) => &[
+            "position",
+            "normal",
+            "uvs",
+            "jointIndices",
+            "jointWeights",
+            "model",
+            "model",
+            "model",
+            "model",
+            "normalMatrix",
+            "normalMatrix",
+            "normalMatrix",
+            "normalMatrix",
+            "instanceIndex",
+        ],
+        ShaderKind::TexturedQuad => &["vertexData"],
+    }
+}
+
+/// Create the port of a shader using the uniforms that are currently uploaded to it. Only the
+/// vertex shaders that sample textures need `textures`, fragment shaders are given them when
+/// they run.
+pub fn create_shader(
+    shader_kind: ShaderKind,
+    uniforms: &Uniforms,
+    textures: &TextureUnits,
+) -> Box<dyn SoftwareShader> {
+    match shader_kind {
+        ShaderKind::Water => Box::new(WaterShader::new(uniforms)),
+        ShaderKind::NonSkinnedMesh => Box::new(NonSkinnedMeshShader::new(uniforms)),
+        ShaderKind::SkinnedMesh(bone_storage) => {
+            Box::new(SkinnedMeshShader::new(uniforms, textures, bone_storage))
+        }
+        ShaderKind::TexturedQuad => Box::new(TexturedQuadShader::new(uniforms)),
+    }
+}
+
+fn sunlight_dir() -> Vector3<f32> {
+    Vector3::new(-1.0, -1.0, 0.5).normalize()
+}
+
+/// GLSL's `reflect`
+fn reflect(incident: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32> {
+    incident - normal * 2.0 * normal.dot(&incident)
+}
+
+/// GLSL's `mix`
+fn mix(a: Vector4<f32>, b: Vector4<f32>, t: f32) -> Vector4<f32> {
+    a * (1.0 - t) + b * t
+}
+
+/// A mat4 attribute, whose columns are at the four locations starting at `location`
+fn mat4_attribute(attributes: &[[f32; 4]], location: usize) -> Matrix4<f32> {
+    Matrix4::from_fn(|row, column| attributes[location + column][row])
+}
+
+fn int_uniform(uniforms: &Uniforms, name: &str) -> i32 {
+    match uniforms.get(name) {
+        Some(UniformValue::Int(value)) => *value,
+        _ => 0,
+    }
+}
+
+fn float_uniform(uniforms: &Uniforms, name: &str) -> f32 {
+    match uniforms.get(name) {
+        Some(UniformValue::Float(value)) => *value,
+        _ => 0.,
+    }
+}
+
+fn vec2_uniform(uniforms: &Uniforms, name: &str) -> Vector2<f32> {
+    match uniforms.get(name) {
+        Some(UniformValue::Floats(values)) if values.len() >= 2 => {
+            Vector2::from_column_slice(&values[0..2])
+        }
+        _ => Vector2::zeros(),
+    }
+}
+
+fn vec3_uniform(uniforms: &Uniforms, name: &str) -> Vector3<f32> {
+    match uniforms.get(name) {
+        Some(UniformValue::Floats(values)) if values.len() >= 3 => {
+            Vector3::from_column_slice(&values[0..3])
+        }
+        _ => Vector3::zeros(),
+    }
+}
+
+fn vec4_uniform(uniforms: &Uniforms, name: &str) -> Vector4<f32> {
+    match uniforms.get(name) {
+        Some(UniformValue::Floats(values)) if values.len() >= 4 => {
+            Vector4::from_column_slice(&values[0..4])
+        }
+        _ => Vector4::zeros(),
+    }
+}
+
+/// The `model` and `normalMatrix` uniforms that replace our per-instance attributes on devices
+/// that draw one instance at a time
+fn model_uniforms(uniforms: &Uniforms) -> Option<(Matrix4<f32>, Matrix4<f32>)> {
+    uniforms.get("model").map(|_| {
+        (
+            mat4_uniform(uniforms, "model"),
+            mat4_uniform(uniforms, "normalMatrix"),
+        )
+    })
+}
+
+fn mat4_uniform(uniforms: &Uniforms, name: &str) -> Matrix4<f32> {
+    match uniforms.get(name) {
+        Some(UniformValue::Mat4 { transpose, values }) if values.len() == 16 => {
+            let matrix = Matrix4::from_column_slice(values);
+            if *transpose {
+                matrix.transpose()
+            } else {
+                matrix
+            }
+        }
+        _ => Matrix4::zeros(),
+    }
+}
+
+fn vec2_varying(varyings: &[f32], start: usize) -> Vector2<f32> {
+    Vector2::from_column_slice(&varyings[start..start + 2])
+}
+
+fn vec3_varying(varyings: &[f32], start: usize) -> Vector3<f32> {
+    Vector3::from_column_slice(&varyings[start..start + 3])
+}
+
+fn vec4_varying(varyings: &[f32], start: usize) -> Vector4<f32> {
+    Vector4::from_column_slice(&varyings[start..start + 4])
+}
+
+fn write_varying(varyings: &mut [f32], start: usize, values: &[f32]) {
+    varyings[start..start + values.len()].copy_from_slice(values);
+}
+
+/// water-vertex.glsl and water-fragment.glsl
+struct WaterShader {
+    perspective: Matrix4<f32>,
+    model: Matrix4<f32>,
+    view: Matrix4<f32>,
+    camera_pos: Vector3<f32>,
+    refraction_texture: i32,
+    reflection_texture: i32,
+    dudv_texture: i32,
+    normal_map: i32,
+    water_depth_texture: i32,
+    ripple_texture: i32,
+    dudv_offset: f32,
+    water_reflectivity: f32,
+    fresnel_strength: f32,
+    use_specular: bool,
+    use_depth_tint: bool,
+    camera_near: f32,
+    camera_far: f32,
+    waves: Vec<WaterWave>,
+    seconds: f32,
+}
+
+/// The `Wave` struct in water-vertex.glsl
+struct WaterWave {
+    amplitude: f32,
+    wavelength: f32,
+    direction: Vector2<f32>,
+    steepness: f32,
+}
+
+// Varyings: clipSpace (4), textureCoords (2), fromFragmentToCamera (3), waveNormal (3),
+// tileCoords (2)
+const WATER_CLIP_SPACE: usize = 0;
+const WATER_TEXTURE_COORDS: usize = 4;
+const WATER_FROM_FRAGMENT_TO_CAMERA: usize = 6;
+const WATER_WAVE_NORMAL: usize = 9;
+const WATER_TILE_COORDS: usize = 12;
+
+impl WaterShader {
+    fn new(uniforms: &Uniforms) -> WaterShader {
+        let wave_count = (int_uniform(uniforms, "waveCount").max(0) as usize).min(MAX_WAVES);
+        let waves = (0..wave_count)
+            .map(|idx| {
+                let field = |name: &str| format!("waves[{}].{}", idx, name);
+
+                WaterWave {
+                    amplitude: float_uniform(uniforms, &field("amplitude")),
+                    wavelength: float_uniform(uniforms, &field("wavelength")),
+                    direction: vec2_uniform(uniforms, &field("direction")),
+                    steepness: float_uniform(uniforms, &field("steepness")),
+                }
+            })
+            .collect();
+
+        WaterShader {
+            perspective: mat4_uniform(uniforms, "perspective"),
+            model: mat4_uniform(uniforms, "model"),
+            view: mat4_uniform(uniforms, "view"),
+            camera_pos: vec3_uniform(uniforms, "cameraPos"),
+            refraction_texture: int_uniform(uniforms, "refractionTexture"),
+            reflection_texture: int_uniform(uniforms, "reflectionTexture"),
+            dudv_texture: int_uniform(uniforms, "dudvTexture"),
+            normal_map: int_uniform(uniforms, "normalMap"),
+            water_depth_texture: int_uniform(uniforms, "waterDepthTexture"),
+            ripple_texture: int_uniform(uniforms, "rippleTexture"),
+            dudv_offset: float_uniform(uniforms, "dudvOffset"),
+            water_reflectivity: float_uniform(uniforms, "waterReflectivity"),
+            fresnel_strength: float_uniform(uniforms, "fresnelStrength"),
+            use_specular: int_uniform(uniforms, "useSpecular") != 0,
+            use_depth_tint: int_uniform(uniforms, "useDepthTint") != 0,
+            camera_near: float_uniform(uniforms, "cameraNear"),
+            camera_far: float_uniform(uniforms, "cameraFar"),
+            waves,
+            seconds: float_uniform(uniforms, "seconds"),
+        }
+    }
+
+    fn get_normal(
+        &self,
+        textures: &TextureUnits,
+        texture_coords: Vector2<f32>,
+        wave_normal: Vector3<f32>,
+        tile_coords: Vector2<f32>,
+    ) -> Vector3<f32> {
+        let normal_map_color = textures.texture_2d(self.normal_map, texture_coords);
+        let make_normal_point_upwards_more = 2.6;
+
+        let normal = Vector3::new(
+            normal_map_color.x * 2.0 - 1.0,
+            normal_map_color.z * make_normal_point_upwards_more,
+            normal_map_color.y * 2.0 - 1.0,
+        )
+        .normalize();
+
+        let normal = (normal + wave_normal.normalize() - Vector3::y()).normalize();
+
+        let ripple_slope = textures.texture_2d(self.ripple_texture, tile_coords).xy() * 2.0
+            - Vector2::new(1.0, 1.0);
+
+        (normal - Vector3::new(ripple_slope.x, 0.0, ripple_slope.y)).normalize()
+    }
+}
+
+impl SoftwareShader for WaterShader {
+    fn varying_count(&self) -> usize {
+        14
+    }
+
+    fn vertex(&self, attributes: &[[f32; 4]], varyings: &mut [f32]) -> [f32; 4] {
+        let position = Vector2::new(attributes[0][0], attributes[0][1]);
+        let tiling = 4.0;
+        let gravity = 9.8;
+
+        let mut world_position = self.model * Vector4::new(position.x, 0.0, position.y, 1.0);
+
+        let resting_position = Vector2::new(world_position.x, world_position.z);
+        let mut displacement: Vector3<f32> = Vector3::zeros();
+        let mut normal = Vector3::new(0.0, 1.0, 0.0);
+        let wave_count = self.waves.len() as f32;
+
+        for wave in self.waves.iter() {
+            let frequency = 2.0 * std::f32::consts::PI / wave.wavelength;
+            let phase_speed = (gravity / frequency).sqrt();
+            let phase =
+                frequency * (wave.direction.dot(&resting_position) - phase_speed * self.seconds);
+
+            let steepness_amplitude = wave.steepness / (frequency * wave_count);
+
+            displacement.x += steepness_amplitude * wave.direction.x * phase.cos();
+            displacement.y += wave.amplitude * phase.sin();
+            displacement.z += steepness_amplitude * wave.direction.y * phase.cos();
+
+            let frequency_amplitude = frequency * wave.amplitude;
+            normal.x -= wave.direction.x * frequency_amplitude * phase.cos();
+            normal.y -= wave.steepness / wave_count * phase.sin();
+            normal.z -= wave.direction.y * frequency_amplitude * phase.cos();
+        }
+
+        world_position.x += displacement.x;
+        world_position.y += displacement.y;
+        world_position.z += displacement.z;
+        let wave_normal = normal.normalize();
+
+        let clip_space = self.perspective * self.view * world_position;
+
+        let tile_coords = position + Vector2::new(0.5, 0.5);
+        let texture_coords = tile_coords * tiling;
+
+        let from_fragment_to_camera = self.camera_pos - world_position.xyz();
+
+        write_varying(varyings, WATER_CLIP_SPACE, clip_space.as_slice());
+        write_varying(varyings, WATER_TEXTURE_COORDS, texture_coords.as_slice());
+        write_varying(
+            varyings,
+            WATER_FROM_FRAGMENT_TO_CAMERA,
+            from_fragment_to_camera.as_slice(),
+        );
+        write_varying(varyings, WATER_WAVE_NORMAL, wave_normal.as_slice());
+        write_varying(varyings, WATER_TILE_COORDS, tile_coords.as_slice());
+
+        [clip_space.x, clip_space.y, clip_space.z, clip_space.w]
+    }
+
+    fn fragment(
+        &self,
+        frag_coord: [f32; 4],
+        varyings: &[f32],
+        textures: &TextureUnits,
+    ) -> Option<[f32; 4]> {
+        let clip_space = vec4_varying(varyings, WATER_CLIP_SPACE);
+        let texture_coords = vec2_varying(varyings, WATER_TEXTURE_COORDS);
+        let from_fragment_to_camera = vec3_varying(varyings, WATER_FROM_FRAGMENT_TO_CAMERA);
+        let wave_normal = vec3_varying(varyings, WATER_WAVE_NORMAL);
+        let tile_coords = vec2_varying(varyings, WATER_TILE_COORDS);
+
+        let water_distortion_strength = 0.03;
+        let shine_damper = 20.0;
+        let shallow_water_color = Vector4::new(0.0, 0.1, 0.3, 1.0);
+        let deep_water_color = Vector4::new(0.0, 0.1, 0.2, 1.0);
+
+        let ndc = (clip_space.xy() / clip_space.w) / 2.0 + Vector2::new(0.5, 0.5);
+
+        let mut refract_tex_coords = Vector2::new(ndc.x, ndc.y);
+        let mut reflect_tex_coords = Vector2::new(ndc.x, -ndc.y);
+
+        let near = self.camera_near;
+        let far = self.camera_far;
+
+        let camera_to_first_thing_behind_water = textures
+            .texture_2d(self.water_depth_texture, refract_tex_coords)
+            .x;
+        let camera_to_first_thing_under_water = 2.0 * near * far
+            / (far + near - (2.0 * camera_to_first_thing_behind_water - 1.0) * (far - near));
+
+        let camera_to_water_depth = frag_coord[2];
+        let camera_to_water_distance =
+            2.0 * near * far / (far + near - (2.0 * camera_to_water_depth - 1.0) * (far - near));
+
+        let angled_water_depth = camera_to_first_thing_under_water - camera_to_water_distance;
+
+        let distorted_tex_coords = textures
+            .texture_2d(
+                self.dudv_texture,
+                Vector2::new(texture_coords.x + self.dudv_offset, texture_coords.y),
+            )
+            .xy()
+            * 0.1;
+        let distorted_tex_coords = texture_coords
+            + Vector2::new(
+                distorted_tex_coords.x,
+                distorted_tex_coords.y + self.dudv_offset,
+            );
+
+        let total_distortion = (textures
+            .texture_2d(self.dudv_texture, distorted_tex_coords)
+            .xy()
+            * 2.0
+            - Vector2::new(1.0, 1.0))
+            * water_distortion_strength;
+
+        refract_tex_coords += total_distortion;
+        reflect_tex_coords += total_distortion;
+
+        refract_tex_coords.x = refract_tex_coords.x.max(0.001).min(0.999);
+        refract_tex_coords.y = refract_tex_coords.y.max(0.001).min(0.999);
+        reflect_tex_coords.x = reflect_tex_coords.x.max(0.001).min(0.999);
+        reflect_tex_coords.y = reflect_tex_coords.y.max(-0.999).min(-0.001);
+
+        let reflect_color = textures.texture_2d(self.reflection_texture, reflect_tex_coords);
+
+        let refract_color = textures.texture_2d(self.refraction_texture, refract_tex_coords);
+
+        let refract_color = if self.use_depth_tint {
+            mix(
+                refract_color,
+                deep_water_color,
+                (angled_water_depth / 10.0).max(0.0).min(1.0),
+            )
+        } else {
+            refract_color
+        };
+
+        let to_camera = from_fragment_to_camera.normalize();
+
+        let normal = self.get_normal(textures, distorted_tex_coords, wave_normal, tile_coords);
+
+        // GLSL's pow is undefined for negative bases. We treat water that's facing away from the
+        // camera as fully reflective instead of producing a NaN.
+        let refractive_factor = to_camera.dot(&normal).max(0.0);
+        let refractive_factor = refractive_factor.powf(self.fresnel_strength);
+
+        let specular_highlights = if self.use_specular {
+            let reflected_light = reflect(sunlight_dir(), normal);
+            let specular = reflected_light.dot(&to_camera).max(0.0);
+            let specular = specular.powf(shine_damper);
+            Vector3::new(1.0, 1.0, 1.0) * specular * self.water_reflectivity
+        } else {
+            Vector3::new(0.0, 0.0, 0.0)
+        };
+
+        let frag_color = mix(reflect_color, refract_color, refractive_factor);
+        let frag_color = mix(frag_color, shallow_water_color, 0.2)
+            + Vector4::new(
+                specular_highlights.x,
+                specular_highlights.y,
+                specular_highlights.z,
+                0.0,
+            );
+
+        Some([frag_color.x, frag_color.y, frag_color.z, frag_color.w])
+    }
+}
+
+/// The lighting that is shared by mesh-non-skinned-fragment.glsl and
+/// mesh-skinned-fragment.glsl
+fn lit_mesh_color(
+    textures: &TextureUnits,
+    mesh_texture: i32,
+    normal: Vector3<f32>,
+    uvs: Vector2<f32>,
+    from_fragment_to_camera: Vector3<f32>,
+) -> [f32; 4] {
+    let shininess = 0.4;
+    let sunlight_color = Vector3::new(1.0, 1.0, 1.0);
+    let sunlight_dir = sunlight_dir();
+
+    let ambient = Vector3::new(0.24725, 0.1995, 0.0745);
+
+    let normal = normal.normalize();
+    let diff = normal.dot(&-sunlight_dir).max(0.0);
+    let diffuse = sunlight_color * diff;
+
+    let reflect_dir = reflect(-sunlight_dir, normal);
+    let spec = from_fragment_to_camera
+        .normalize()
+        .dot(&reflect_dir)
+        .max(0.0)
+        .powf(32.0);
+    let specular = Vector3::new(0.628281, 0.555802, 0.366065) * shininess * spec;
+
+    let lighting = ambient + diffuse + specular;
+    let lighting = Vector4::new(lighting.x, lighting.y, lighting.z, 1.0);
+    let texture_color = textures.texture_2d(mesh_texture, uvs);
+
+    let frag_color = texture_color.component_mul(&lighting);
+
+    [frag_color.x, frag_color.y, frag_color.z, frag_color.w]
+}
+
+/// mesh-non-skinned-vertex.glsl and mesh-non-skinned-fragment.glsl
+struct NonSkinnedMeshShader {
+    view: Matrix4<f32>,
+    perspective: Matrix4<f32>,
+    camera_pos: Vector3<f32>,
+    clip_plane: Vector4<f32>,
+    mesh_texture: i32,
+    /// Set when we're drawing one instance at a time, see `model_uniforms`
+    model_uniforms: Option<(Matrix4<f32>, Matrix4<f32>)>,
+}
+
+// Varyings: vNormal (3), vUvs (2), fromFragmentToCamera (3), worldPosition (4)
+const NON_SKINNED_NORMAL: usize = 0;
+const NON_SKINNED_UVS: usize = 3;
+const NON_SKINNED_FROM_FRAGMENT_TO_CAMERA: usize = 5;
+const NON_SKINNED_WORLD_POSITION: usize = 8;
+
+impl NonSkinnedMeshShader {
+    fn new(uniforms: &Uniforms) -> NonSkinnedMeshShader {
+        NonSkinnedMeshShader {
+            view: mat4_uniform(uniforms, "view"),
+            perspective: mat4_uniform(uniforms, "perspective"),
+            camera_pos: vec3_uniform(uniforms, "cameraPos"),
+            clip_plane: vec4_uniform(uniforms, "clipPlane"),
+            mesh_texture: int_uniform(uniforms, "meshTexture"),
+            model_uniforms: model_uniforms(uniforms),
+        }
+    }
+}
+
+impl SoftwareShader for NonSkinnedMeshShader {
+    fn varying_count(&self) -> usize {
+        12
+    }
+
+    fn vertex(&self, attributes: &[[f32; 4]], varyings: &mut [f32]) -> [f32; 4] {
+        let position = Vector4::new(attributes[0][0], attributes[0][1], attributes[0][2], 1.0);
+        let normal = Vector4::new(attributes[1][0], attributes[1][1], attributes[1][2], 0.0);
+        let uvs = &attributes[2][0..2];
+        let (model, normal_matrix) = match self.model_uniforms {
+            Some(model_uniforms) => model_uniforms,
+            None => (mat4_attribute(attributes, 3), mat4_attribute(attributes, 7)),
+        };
+
+        let world_position = model * position;
+        let normal = (normal_matrix * normal).xyz();
+
+        let gl_position = self.perspective * self.view * world_position;
+
+        let from_fragment_to_camera = self.camera_pos - world_position.xyz();
+
+        write_varying(varyings, NON_SKINNED_NORMAL, normal.as_slice());
+        write_varying(varyings, NON_SKINNED_UVS, uvs);
+        write_varying(
+            varyings,
+            NON_SKINNED_FROM_FRAGMENT_TO_CAMERA,
+            from_fragment_to_camera.as_slice(),
+        );
+        write_varying(
+            varyings,
+            NON_SKINNED_WORLD_POSITION,
+            world_position.as_slice(),
+        );
+
+        [gl_position.x, gl_position.y, gl_position.z, gl_position.w]
+    }
+
+    fn fragment(
+        &self,
+        _frag_coord: [f32; 4],
+        varyings: &[f32],
+        textures: &TextureUnits,
+    ) -> Option<[f32; 4]> {
+        let world_position = vec4_varying(varyings, NON_SKINNED_WORLD_POSITION);
+
+        if world_position.dot(&self.clip_plane) < 0.0 {
+            return None;
+        }
+
+        Some(lit_mesh_color(
+            textures,
+            self.mesh_texture,
+            vec3_varying(varyings, NON_SKINNED_NORMAL),
+            vec2_varying(varyings, NON_SKINNED_UVS),
+            vec3_varying(varyings, NON_SKINNED_FROM_FRAGMENT_TO_CAMERA),
+        ))
+    }
+}
+
+/// A set of bones' rotation quaternions and translation quaternions
+type Bones = (Vec<Vector4<f32>>, Vec<Vector4<f32>>);
+
+/// mesh-skinned-vertex.glsl and mesh-skinned-fragment.glsl
+struct SkinnedMeshShader {
+    view: Matrix4<f32>,
+    perspective: Matrix4<f32>,
+    camera_pos: Vector3<f32>,
+    clip_plane: Vector4<f32>,
+    mesh_texture: i32,
+    /// Set when we're drawing one instance at a time, see `model_uniforms`
+    model_uniforms: Option<(Matrix4<f32>, Matrix4<f32>)>,
+    bone_storage: BoneStorage,
+    /// Each instance's bones. There's only one set of bones when they're in our uniforms.
+    instance_bones: Vec<Bones>,
+}
+
+// Varyings: shouldClip (1), vNormal (3), vUvs (2), fromFragmentToCamera (3)
+const SKINNED_SHOULD_CLIP: usize = 0;
+const SKINNED_NORMAL: usize = 1;
+const SKINNED_UVS: usize = 4;
+const SKINNED_FROM_FRAGMENT_TO_CAMERA: usize = 6;
+
+impl SkinnedMeshShader {
+    /// We read every bone up front instead of once per vertex like the GLSL does
+    fn new(
+        uniforms: &Uniforms,
+        textures: &TextureUnits,
+        bone_storage: BoneStorage,
+    ) -> SkinnedMeshShader {
+        let instance_bones = match bone_storage {
+            BoneStorage::Uniforms(bone_count) => {
+                let bones = |name: &str| {
+                    (0..bone_count)
+                        .map(|idx| vec4_uniform(uniforms, &format!("{}[{}]", name, idx)))
+                        .collect()
+                };
+
+                vec![(bones("boneRotQuaternions"), bones("boneTransQuaternions"))]
+            }
+            BoneStorage::Texture => {
+                let bone_texture = int_uniform(uniforms, "boneTexture");
+                let bone_count = float_uniform(uniforms, "boneCount");
+                let instance_count = float_uniform(uniforms, "instanceCount");
+
+                let bones = |instance: usize, texel: f32| {
+                    let u = (instance as f32 * 2.0 + texel) / (instance_count * 2.0);
+
+                    (0..bone_count as usize)
+                        .map(|idx| {
+                            let v = (idx as f32 + 0.5) / bone_count;
+                            textures.texture_2d(bone_texture, Vector2::new(u, v))
+                        })
+                        .collect()
+                };
+
+                (0..instance_count as usize)
+                    .map(|instance| (bones(instance, 0.5), bones(instance, 1.5)))
+                    .collect()
+            }
+        };
+
+        SkinnedMeshShader {
+            view: mat4_uniform(uniforms, "view"),
+            perspective: mat4_uniform(uniforms, "perspective"),
+            camera_pos: vec3_uniform(uniforms, "cameraPos"),
+            clip_plane: vec4_uniform(uniforms, "clipPlane"),
+            mesh_texture: int_uniform(uniforms, "meshTexture"),
+            model_uniforms: model_uniforms(uniforms),
+            bone_storage,
+            instance_bones,
+        }
+    }
+
+    fn blend(
+        &self,
+        quaternions: &[Vector4<f32>],
+        indices: [f32; 4],
+        weights: [f32; 4],
+    ) -> Vector4<f32> {
+        // Joints past the end of the armature have no bone to read
+        (0..4).fold(Vector4::zeros(), |blended, idx| {
+            let quaternion = quaternions
+                .get(indices[idx] as usize)
+                .cloned()
+                .unwrap_or_else(Vector4::zeros);
+
+            blended + quaternion * weights[idx]
+        })
+    }
+}
+
+impl SoftwareShader for SkinnedMeshShader {
+    fn varying_count(&self) -> usize {
+        9
+    }
+
+    fn vertex(&self, attributes: &[[f32; 4]], varyings: &mut [f32]) -> [f32; 4] {
+        let position = Vector4::new(attributes[0][0], attributes[0][1], attributes[0][2], 1.0);
+        let normal = Vector4::new(attributes[1][0], attributes[1][1], attributes[1][2], 0.0);
+        let uvs = &attributes[2][0..2];
+        let joint_indices = attributes[3];
+        let joint_weights = attributes[4];
+        let (model, normal_matrix, instance) = match self.model_uniforms {
+            Some((model, normal_matrix)) => (model, normal_matrix, 0),
+            None => {
+                let instance = match self.bone_storage {
+                    BoneStorage::Uniforms(_) => 0,
+                    BoneStorage::Texture => attributes[13][0] as usize,
+                };
+
+                (
+                    mat4_attribute(attributes, 5),
+                    mat4_attribute(attributes, 9),
+                    instance,
+                )
+            }
+        };
+        let (bone_rot_quaternions, bone_trans_quaternions) = &self.instance_bones[instance];
+
+        // Blend our dual quaternion
+        let weighted_rot_quats = self.blend(bone_rot_quaternions, joint_indices, joint_weights);
+        let weighted_trans_quats = self.blend(bone_trans_quaternions, joint_indices, joint_weights);
+
+        // Normalize our dual quaternion (necessary for nlerp)
+        let magnitude = weighted_rot_quats.norm();
+        let weighted_rot_quats = weighted_rot_quats / magnitude;
+        let weighted_trans_quats = weighted_trans_quats / magnitude;
+
+        // Convert our dual quaternion into a 4x4 matrix
+        let (w_r, x_r, y_r, z_r) = (
+            weighted_rot_quats[0],
+            weighted_rot_quats[1],
+            weighted_rot_quats[2],
+            weighted_rot_quats[3],
+        );
+        let (w_t, x_t, y_t, z_t) = (
+            weighted_trans_quats[0],
+            weighted_trans_quats[1],
+            weighted_trans_quats[2],
+            weighted_trans_quats[3],
+        );
+
+        let t0 = 2.0 * (-w_t * x_r + x_t * w_r - y_t * z_r + z_t * y_r);
+        let t1 = 2.0 * (-w_t * y_r + x_t * z_r + y_t * w_r - z_t * x_r);
+        let t2 = 2.0 * (-w_t * z_r - x_t * y_r + y_t * x_r + z_t * w_r);
+
+        #[rustfmt::skip]
+        let converted_matrix = Matrix4::from_column_slice(&[
+            1.0 - (2.0 * y_r * y_r) - (2.0 * z_r * z_r),
+            (2.0 * x_r * y_r) + (2.0 * w_r * z_r),
+            (2.0 * x_r * z_r) - (2.0 * w_r * y_r),
+            0.,
+            (2.0 * x_r * y_r) - (2.0 * w_r * z_r),
+            1.0 - (2.0 * x_r * x_r) - (2.0 * z_r * z_r),
+            (2.0 * y_r * z_r) + (2.0 * w_r * x_r),
+            0.,
+            (2.0 * x_r * z_r) + (2.0 * w_r * y_r),
+            (2.0 * y_r * z_r) - (2.0 * w_r * x_r),
+            1.0 - (2.0 * x_r * x_r) - (2.0 * y_r * y_r),
+            0.,
+            t0,
+            t1,
+            t2,
+            1.,
+        ]);
+
+        // Swap our normal's y and z axis since Blender uses a right handed coordinate system
+        let transformed_normal = (converted_matrix * normal).xyz();
+        let transformed_normal = Vector3::new(
+            transformed_normal.x,
+            transformed_normal.z,
+            -transformed_normal.y,
+        );
+
+        let model_space_pos = converted_matrix * position;
+        let left_model_space_pos = Vector4::new(
+            model_space_pos.x,
+            model_space_pos.z,
+            -model_space_pos.y,
+            model_space_pos.w,
+        );
+
+        let left_world_space = model * left_model_space_pos;
+
+        let gl_position = self.perspective * self.view * left_world_space;
+
+        let should_clip = if left_world_space.dot(&self.clip_plane) < 0.0 {
+            1.0
+        } else {
+            0.0
+        };
+        let from_fragment_to_camera = self.camera_pos - left_world_space.xyz();
+
+        let transformed_normal = (normal_matrix * transformed_normal.push(0.0)).xyz();
+
+        write_varying(varyings, SKINNED_SHOULD_CLIP, &[should_clip]);
+        write_varying(varyings, SKINNED_NORMAL, transformed_normal.as_slice());
+        write_varying(varyings, SKINNED_UVS, uvs);
+        write_varying(
+            varyings,
+            SKINNED_FROM_FRAGMENT_TO_CAMERA,
+            from_fragment_to_camera.as_slice(),
+        );
+
+        [gl_position.x, gl_position.y, gl_position.z, gl_position.w]
+    }
+
+    fn fragment(
+        &self,
+        _frag_coord: [f32; 4],
+        varyings: &[f32],
+        textures: &TextureUnits,
+    ) -> Option<[f32; 4]> {
+        if varyings[SKINNED_SHOULD_CLIP] == 1.0 {
+            return None;
+        }
+
+        Some(lit_mesh_color(
+            textures,
+            self.mesh_texture,
+            vec3_varying(varyings, SKINNED_NORMAL),
+            vec2_varying(varyings, SKINNED_UVS),
+            vec3_varying(varyings, SKINNED_FROM_FRAGMENT_TO_CAMERA),
+        ))
+    }
+}
+
+/// textured-quad-vertex.glsl and textured-quad-fragment.glsl
+struct TexturedQuadShader {
+    texture: i32,
+}
+
+impl TexturedQuadShader {
+    fn new(uniforms: &Uniforms) -> TexturedQuadShader {
+        TexturedQuadShader {
+            texture: int_uniform(uniforms, "texture"),
+        }
+    }
+}
+
+impl SoftwareShader for TexturedQuadShader {
+    fn varying_count(&self) -> usize {
+        2
+    }
+
+    fn vertex(&self, attributes: &[[f32; 4]], varyings: &mut [f32]) -> [f32; 4] {
+        let vertex_data = attributes[0];
+
+        write_varying(varyings, 0, &vertex_data[2..4]);
+
+        [vertex_data[0], vertex_data[1], 0.0, 1.0]
+    }
+
+    fn fragment(
+        &self,
+        _frag_coord: [f32; 4],
+        varyings: &[f32],
+        textures: &TextureUnits,
+    ) -> Option<[f32; 4]> {
+        let color = textures.texture_2d(self.texture, vec2_varying(varyings, 0));
+
+        Some([color.x, color.y, color.z, color.w])
+    }
+}
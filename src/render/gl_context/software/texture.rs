@@ -0,0 +1,73 @@
+use web_sys::WebGlRenderingContext as GL;
+
+/// A texture or renderbuffer that lives in CPU memory.
+///
+/// Texels are stored row by row starting from the bottom of the image, the same way that
+/// GL addresses them. Depth textures and depth renderbuffers store the depth in every channel
+/// so that sampling a depth texture's `.r` works like it does in WebGL.
+#[derive(Debug, Clone, Default)]
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    pub texels: Vec<[f32; 4]>,
+    /// `GL::NEAREST` or `GL::LINEAR`
+    pub mag_filter: u32,
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, fill: [f32; 4]) -> Texture {
+        Texture {
+            width,
+            height,
+            texels: vec![fill; width * height],
+            mag_filter: GL::LINEAR,
+        }
+    }
+
+    /// Sample the texture the same way that `texture2D` would.
+    ///
+    /// We always use `GL::REPEAT` wrapping since that's the WebGL default and we never change it.
+    pub fn sample(&self, uv: [f32; 2]) -> [f32; 4] {
+        if self.texels.is_empty() {
+            return [0., 0., 0., 1.];
+        }
+
+        let x = uv[0] * self.width as f32;
+        let y = uv[1] * self.height as f32;
+
+        if self.mag_filter == GL::NEAREST {
+            return self.texel(x.floor() as i64, y.floor() as i64);
+        }
+
+        // Texel centers are at 0.5, 1.5, 2.5 ...
+        let x = x - 0.5;
+        let y = y - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let bottom = mix(self.texel(x0, y0), self.texel(x0 + 1, y0), tx);
+        let top = mix(self.texel(x0, y0 + 1), self.texel(x0 + 1, y0 + 1), tx);
+
+        mix(bottom, top, ty)
+    }
+
+    pub fn texel_mut(&mut self, x: usize, y: usize) -> &mut [f32; 4] {
+        &mut self.texels[y * self.width + x]
+    }
+
+    fn texel(&self, x: i64, y: i64) -> [f32; 4] {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+
+        self.texels[y * self.width + x]
+    }
+}
+
+fn mix(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    let mut mixed = [0.; 4];
+    for idx in 0..4 {
+        mixed[idx] = a[idx] * (1. - t) + b[idx] * t;
+    }
+    mixed
+}
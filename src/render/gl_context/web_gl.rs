@@ -0,0 +1,441 @@
+use crate::render::GlContext;
+use js_sys::{Reflect, WebAssembly};
+use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::*;
+
+/// A vertex array object created through the `OES_vertex_array_object` extension.
+///
+/// WebGL 1 doesn't have VAOs built in, so we hold onto the extension that created the VAO
+/// in order to be able to bind it later.
+pub struct WebGlVao {
+    oes_vao_ext: js_sys::Object,
+    vao: WebGlVertexArrayObject,
+}
+
+impl GlContext for WebGlRenderingContext {
+    type Shader = WebGlShader;
+    type Program = WebGlProgram;
+    type UniformLocation = WebGlUniformLocation;
+    type Buffer = WebGlBuffer;
+    type Texture = WebGlTexture;
+    type Framebuffer = WebGlFramebuffer;
+    type Renderbuffer = WebGlRenderbuffer;
+    type Vao = WebGlVao;
+
+    fn enable_extension(&self, name: &str) -> bool {
+        match GL::get_extension(self, name) {
+            Ok(Some(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn get_parameter_i32(&self, pname: u32) -> i32 {
+        GL::get_parameter(self, pname)
+            .ok()
+            .and_then(|value| value.as_f64())
+            .unwrap_or(0.) as i32
+    }
+
+    fn create_vertex_array(&self) -> Option<WebGlVao> {
+        let oes_vao_ext = GL::get_extension(self, "OES_vertex_array_object").ok()??;
+
+        let create_vao_ext = Reflect::get(&oes_vao_ext, &"createVertexArrayOES".into())
+            .ok()?
+            .into();
+
+        let vao = Reflect::apply(&create_vao_ext, &oes_vao_ext, &js_sys::Array::new()).ok()?;
+
+        Some(WebGlVao {
+            oes_vao_ext,
+            vao: vao.unchecked_into(),
+        })
+    }
+
+    fn bind_vertex_array(&self, vao: Option<&WebGlVao>) {
+        let (oes_vao_ext, vao): (js_sys::Object, JsValue) = match vao {
+            Some(vao) => (vao.oes_vao_ext.clone(), vao.vao.clone().into()),
+            None => (
+                GL::get_extension(self, "OES_vertex_array_object")
+                    .expect("Get OES vao ext")
+                    .expect("OES vao ext"),
+                JsValue::NULL,
+            ),
+        };
+
+        let bind_vao_ext = Reflect::get(&oes_vao_ext, &"bindVertexArrayOES".into())
+            .expect("Bind vao func")
+            .into();
+
+        let args = js_sys::Array::new();
+        args.push(&vao);
+
+        Reflect::apply(&bind_vao_ext, &oes_vao_ext, &args).expect("Bound VAO");
+    }
+
+    fn create_shader(&self, shader_type: u32) -> Option<WebGlShader> {
+        GL::create_shader(self, shader_type)
+    }
+
+    fn shader_source(&self, shader: &WebGlShader, source: &str) {
+        GL::shader_source(self, shader, source)
+    }
+
+    fn compile_shader(&self, shader: &WebGlShader) {
+        GL::compile_shader(self, shader)
+    }
+
+    fn shader_compiled(&self, shader: &WebGlShader) -> bool {
+        GL::get_shader_parameter(self, shader, GL::COMPILE_STATUS)
+            .as_bool()
+            .unwrap_or(false)
+    }
+
+    fn get_shader_info_log(&self, shader: &WebGlShader) -> Option<String> {
+        GL::get_shader_info_log(self, shader)
+    }
+
+    fn create_program(&self) -> Option<WebGlProgram> {
+        GL::create_program(self)
+    }
+
+    fn attach_shader(&self, program: &WebGlProgram, shader: &WebGlShader) {
+        GL::attach_shader(self, program, shader)
+    }
+
+    fn link_program(&self, program: &WebGlProgram) {
+        GL::link_program(self, program)
+    }
+
+    fn program_linked(&self, program: &WebGlProgram) -> bool {
+        GL::get_program_parameter(self, program, GL::LINK_STATUS)
+            .as_bool()
+            .unwrap_or(false)
+    }
+
+    fn get_program_info_log(&self, program: &WebGlProgram) -> Option<String> {
+        GL::get_program_info_log(self, program)
+    }
+
+    fn use_program(&self, program: Option<&WebGlProgram>) {
+        GL::use_program(self, program)
+    }
+
+    fn get_uniform_location(
+        &self,
+        program: &WebGlProgram,
+        name: &str,
+    ) -> Option<WebGlUniformLocation> {
+        GL::get_uniform_location(self, program, name)
+    }
+
+    fn get_attrib_location(&self, program: &WebGlProgram, name: &str) -> i32 {
+        GL::get_attrib_location(self, program, name)
+    }
+
+    fn create_buffer(&self) -> Option<WebGlBuffer> {
+        GL::create_buffer(self)
+    }
+
+    fn bind_buffer(&self, target: u32, buffer: Option<&WebGlBuffer>) {
+        GL::bind_buffer(self, target, buffer)
+    }
+
+    fn buffer_data_f32(&self, target: u32, data: &[f32], usage: u32) {
+        let memory_buffer = wasm_memory_buffer();
+
+        let data_location = data.as_ptr() as u32 / 4;
+
+        let data_array = js_sys::Float32Array::new(&memory_buffer)
+            .subarray(data_location, data_location + data.len() as u32);
+
+        GL::buffer_data_with_array_buffer_view(self, target, &data_array, usage);
+    }
+
+    fn buffer_data_u8(&self, target: u32, data: &[u8], usage: u32) {
+        let memory_buffer = wasm_memory_buffer();
+
+        let data_location = data.as_ptr() as u32;
+
+        let data_array = js_sys::Uint8Array::new(&memory_buffer)
+            .subarray(data_location, data_location + data.len() as u32);
+
+        GL::buffer_data_with_array_buffer_view(self, target, &data_array, usage);
+    }
+
+    fn buffer_data_u16(&self, target: u32, data: &[u16], usage: u32) {
+        let memory_buffer = wasm_memory_buffer();
+
+        let data_location = data.as_ptr() as u32 / 2;
+
+        let data_array = js_sys::Uint16Array::new(&memory_buffer)
+            .subarray(data_location, data_location + data.len() as u32);
+
+        GL::buffer_data_with_array_buffer_view(self, target, &data_array, usage);
+    }
+
+    fn enable_vertex_attrib_array(&self, index: u32) {
+        GL::enable_vertex_attrib_array(self, index)
+    }
+
+    fn vertex_attrib_pointer_with_i32(
+        &self,
+        index: u32,
+        size: i32,
+        type_: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    ) {
+        GL::vertex_attrib_pointer_with_i32(self, index, size, type_, normalized, stride, offset)
+    }
+
+    fn vertex_attrib_divisor_angle(&self, index: u32, divisor: u32) {
+        let args = js_sys::Array::new();
+        args.push(&JsValue::from_f64(index as f64));
+        args.push(&JsValue::from_f64(divisor as f64));
+
+        call_instanced_arrays_ext(self, "vertexAttribDivisorANGLE", &args);
+    }
+
+    fn create_texture(&self) -> Option<WebGlTexture> {
+        GL::create_texture(self)
+    }
+
+    fn delete_texture(&self, texture: Option<&WebGlTexture>) {
+        GL::delete_texture(self, texture)
+    }
+
+    fn active_texture(&self, texture: u32) {
+        GL::active_texture(self, texture)
+    }
+
+    fn bind_texture(&self, target: u32, texture: Option<&WebGlTexture>) {
+        GL::bind_texture(self, target, texture)
+    }
+
+    fn tex_parameteri(&self, target: u32, pname: u32, param: i32) {
+        GL::tex_parameteri(self, target, pname, param)
+    }
+
+    fn tex_image_2d(
+        &self,
+        target: u32,
+        level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        border: i32,
+        format: u32,
+        type_: u32,
+        pixels: Option<&[u8]>,
+    ) -> Result<(), JsValue> {
+        let pixels = pixels.map(|pixels| {
+            let memory_buffer = wasm_memory_buffer();
+            let pixels_location = pixels.as_ptr() as u32;
+
+            js_sys::Uint8Array::new(&memory_buffer)
+                .subarray(pixels_location, pixels_location + pixels.len() as u32)
+        });
+
+        GL::tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+            self,
+            target,
+            level,
+            internal_format,
+            width,
+            height,
+            border,
+            format,
+            type_,
+            pixels.as_ref().map(|pixels| pixels.as_ref()),
+        )
+    }
+
+    fn tex_image_2d_f32(
+        &self,
+        target: u32,
+        level: i32,
+        internal_format: i32,
+        width: i32,
+        height: i32,
+        border: i32,
+        format: u32,
+        pixels: &[f32],
+    ) -> Result<(), JsValue> {
+        let memory_buffer = wasm_memory_buffer();
+        let pixels_location = pixels.as_ptr() as u32 / 4;
+
+        let pixels = js_sys::Float32Array::new(&memory_buffer)
+            .subarray(pixels_location, pixels_location + pixels.len() as u32);
+
+        GL::tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+            self,
+            target,
+            level,
+            internal_format,
+            width,
+            height,
+            border,
+            format,
+            GL::FLOAT,
+            Some(pixels.as_ref()),
+        )
+    }
+
+    fn create_framebuffer(&self) -> Option<WebGlFramebuffer> {
+        GL::create_framebuffer(self)
+    }
+
+    fn delete_framebuffer(&self, framebuffer: Option<&WebGlFramebuffer>) {
+        GL::delete_framebuffer(self, framebuffer)
+    }
+
+    fn bind_framebuffer(&self, target: u32, framebuffer: Option<&WebGlFramebuffer>) {
+        GL::bind_framebuffer(self, target, framebuffer)
+    }
+
+    fn framebuffer_texture_2d(
+        &self,
+        target: u32,
+        attachment: u32,
+        textarget: u32,
+        texture: Option<&WebGlTexture>,
+        level: i32,
+    ) {
+        GL::framebuffer_texture_2d(self, target, attachment, textarget, texture, level)
+    }
+
+    fn create_renderbuffer(&self) -> Option<WebGlRenderbuffer> {
+        GL::create_renderbuffer(self)
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: Option<&WebGlRenderbuffer>) {
+        GL::delete_renderbuffer(self, renderbuffer)
+    }
+
+    fn bind_renderbuffer(&self, target: u32, renderbuffer: Option<&WebGlRenderbuffer>) {
+        GL::bind_renderbuffer(self, target, renderbuffer)
+    }
+
+    fn renderbuffer_storage(&self, target: u32, internal_format: u32, width: i32, height: i32) {
+        GL::renderbuffer_storage(self, target, internal_format, width, height)
+    }
+
+    fn framebuffer_renderbuffer(
+        &self,
+        target: u32,
+        attachment: u32,
+        renderbuffer_target: u32,
+        renderbuffer: Option<&WebGlRenderbuffer>,
+    ) {
+        GL::framebuffer_renderbuffer(self, target, attachment, renderbuffer_target, renderbuffer)
+    }
+
+    fn uniform1i(&self, location: Option<&WebGlUniformLocation>, x: i32) {
+        GL::uniform1i(self, location, x)
+    }
+
+    fn uniform1f(&self, location: Option<&WebGlUniformLocation>, x: f32) {
+        GL::uniform1f(self, location, x)
+    }
+
+    fn uniform2f(&self, location: Option<&WebGlUniformLocation>, x: f32, y: f32) {
+        GL::uniform2f(self, location, x, y)
+    }
+
+    fn uniform4f(&self, location: Option<&WebGlUniformLocation>, x: f32, y: f32, z: f32, w: f32) {
+        GL::uniform4f(self, location, x, y, z, w)
+    }
+
+    fn uniform3fv_with_f32_array(&self, location: Option<&WebGlUniformLocation>, data: &mut [f32]) {
+        GL::uniform3fv_with_f32_array(self, location, data)
+    }
+
+    fn uniform4fv_with_f32_array(&self, location: Option<&WebGlUniformLocation>, data: &mut [f32]) {
+        GL::uniform4fv_with_f32_array(self, location, data)
+    }
+
+    fn uniform_matrix4fv_with_f32_array(
+        &self,
+        location: Option<&WebGlUniformLocation>,
+        transpose: bool,
+        data: &mut [f32],
+    ) {
+        GL::uniform_matrix4fv_with_f32_array(self, location, transpose, data)
+    }
+
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        GL::viewport(self, x, y, width, height)
+    }
+
+    fn clear_color(&self, red: f32, green: f32, blue: f32, alpha: f32) {
+        GL::clear_color(self, red, green, blue, alpha)
+    }
+
+    fn clear(&self, mask: u32) {
+        GL::clear(self, mask)
+    }
+
+    fn enable(&self, cap: u32) {
+        GL::enable(self, cap)
+    }
+
+    fn disable(&self, cap: u32) {
+        GL::disable(self, cap)
+    }
+
+    fn blend_func(&self, sfactor: u32, dfactor: u32) {
+        GL::blend_func(self, sfactor, dfactor)
+    }
+
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32) {
+        GL::draw_arrays(self, mode, first, count)
+    }
+
+    fn draw_elements_with_i32(&self, mode: u32, count: i32, type_: u32, offset: i32) {
+        GL::draw_elements_with_i32(self, mode, count, type_, offset)
+    }
+
+    fn draw_elements_instanced_angle_with_i32(
+        &self,
+        mode: u32,
+        count: i32,
+        type_: u32,
+        offset: i32,
+        primcount: i32,
+    ) {
+        let args = js_sys::Array::new();
+        args.push(&JsValue::from_f64(mode as f64));
+        args.push(&JsValue::from_f64(count as f64));
+        args.push(&JsValue::from_f64(type_ as f64));
+        args.push(&JsValue::from_f64(offset as f64));
+        args.push(&JsValue::from_f64(primcount as f64));
+
+        call_instanced_arrays_ext(self, "drawElementsInstancedANGLE", &args);
+    }
+}
+
+/// Call one of the `ANGLE_instanced_arrays` extension's methods.
+///
+/// Like `OES_vertex_array_object`, we reach the extension through `Reflect` since it isn't a
+/// type that `web_sys` knows about.
+fn call_instanced_arrays_ext(gl: &WebGlRenderingContext, method: &str, args: &js_sys::Array) {
+    let instanced_arrays_ext = GL::get_extension(gl, "ANGLE_instanced_arrays")
+        .expect("Get ANGLE instanced arrays ext")
+        .expect("ANGLE instanced arrays ext");
+
+    let method = Reflect::get(&instanced_arrays_ext, &method.into())
+        .expect("Instanced arrays func")
+        .into();
+
+    Reflect::apply(&method, &instanced_arrays_ext, args).expect("Called instanced arrays func");
+}
+
+fn wasm_memory_buffer() -> JsValue {
+    wasm_bindgen::memory()
+        .dyn_into::<WebAssembly::Memory>()
+        .unwrap()
+        .buffer()
+}
@@ -0,0 +1,48 @@
+use crate::app::AnimationController;
+use nalgebra::Matrix4;
+
+/// The most instances that we draw in one call. Skinned instances sit side by side in our bone
+/// texture two texels apart, and this keeps it within the 4096 texels wide that practically
+/// every device supports.
+pub const MAX_INSTANCES_PER_DRAW: usize = 1024;
+
+/// How many floats each instance takes up in the instance buffer, see `Render::draw_instances`
+pub const INSTANCE_FLOATS: usize = 33;
+
+/// The per-instance attributes in our mesh shaders, along with how many locations and floats
+/// they take up and where in an instance they start. A mat4 attribute takes up four locations,
+/// one per column.
+pub const INSTANCE_ATTRIBUTES: [(&str, u32, i32, i32); 3] = [
+    ("model", 4, 4, 0),
+    ("normalMatrix", 4, 4, 16),
+    ("instanceIndex", 1, 1, 32),
+];
+
+/// One of the copies of a mesh that a single draw call renders, ex: one bird in a flock
+pub struct MeshInstance<'a> {
+    /// The entity's world matrix from the `SceneGraph`
+    pub model: Matrix4<f32>,
+    /// Which of the armature's actions are playing. None for meshes without an armature, which
+    /// stay in their bind pose.
+    pub animation: Option<&'a AnimationController>,
+}
+
+impl<'a> MeshInstance<'a> {
+    /// Our model matrix, along with the inverse transpose of it that our normals get multiplied
+    /// by so that they stay perpendicular to surfaces that have been scaled unevenly
+    pub fn model_matrices(&self) -> ([f32; 16], [f32; 16]) {
+        let normal_matrix = self
+            .model
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity)
+            .transpose();
+
+        let mut model_array = [0.; 16];
+        model_array.copy_from_slice(self.model.as_slice());
+
+        let mut normal_matrix_array = [0.; 16];
+        normal_matrix_array.copy_from_slice(normal_matrix.as_slice());
+
+        (model_array, normal_matrix_array)
+    }
+}
@@ -1,5 +1,25 @@
+mod mesh_instance;
 mod non_skinned_mesh;
+mod placeholder;
 mod skinned_mesh;
 
+pub use self::mesh_instance::*;
 pub use self::non_skinned_mesh::*;
+pub use self::placeholder::*;
 pub use self::skinned_mesh::*;
+
+use crate::app::AssetError;
+
+/// Get vertex data that a mesh's exporter might have left out, ex: its "uvs"
+fn vertex_data<'a, T>(
+    mesh_name: &str,
+    data: &'a Option<Vec<T>>,
+    data_name: &'static str,
+) -> Result<&'a [T], AssetError> {
+    data.as_ref()
+        .map(|data| &data[..])
+        .ok_or_else(|| AssetError::MissingVertexData {
+            mesh: mesh_name.to_string(),
+            data: data_name,
+        })
+}
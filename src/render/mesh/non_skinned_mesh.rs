@@ -1,39 +1,51 @@
+use crate::app::AssetError;
 use crate::app::State;
+use crate::render::mesh::vertex_data;
+use crate::render::GlContext;
+use crate::render::MeshInstance;
 use crate::render::Render;
 use crate::render::TextureUnit;
+use crate::render::VertexBuffers;
 use crate::shader::Shader;
 use crate::shader::ShaderKind;
 use blender_mesh::BlenderMesh;
-use nalgebra;
-use nalgebra::{Isometry3, Vector3};
-use web_sys::WebGlRenderingContext as GL;
-use web_sys::*;
 
-pub struct NonSkinnedMesh<'a> {
+pub struct NonSkinnedMesh<'a, G: GlContext> {
+    /// Which mesh this is, for reporting vertex data that it's missing
+    pub name: &'a str,
     pub mesh: &'a BlenderMesh,
-    pub shader: &'a Shader,
+    pub shader: &'a Shader<G>,
+    /// Every copy of the mesh to draw
+    pub instances: &'a [MeshInstance<'a>],
+    /// Holds the instances' per-instance attributes, see `Render::draw_instances`. None if we
+    /// can't draw instances.
+    pub instance_buffer: Option<&'a G::Buffer>,
     pub opts: &'a MeshRenderOpts,
 }
 
+/// The options that every instance in a draw call shares
 pub struct MeshRenderOpts {
-    pub pos: (f32, f32, f32),
+    pub texture: TextureUnit,
     pub clip_plane: [f32; 4],
-    pub flip_camera_y: bool,
+    /// Reflect the camera across the horizontal plane at this height
+    pub flip_camera_y: Option<f32>,
 }
 
-impl<'a> Render<'a> for NonSkinnedMesh<'a> {
-    fn shader_kind() -> ShaderKind {
+impl<'a, G: GlContext> Render<'a, G> for NonSkinnedMesh<'a, G> {
+    fn shader_kind(&self) -> ShaderKind {
         ShaderKind::NonSkinnedMesh
     }
 
-    fn shader(&'a self) -> &'a Shader {
+    fn shader(&'a self) -> &'a Shader<G> {
         &self.shader
     }
 
-    fn buffer_attributes(&self, gl: &WebGlRenderingContext) {
+    fn buffer_attributes(&self, gl: &G, buffers: &mut VertexBuffers<G>) -> Result<(), AssetError> {
         let shader = self.shader();
         let mesh = self.mesh;
 
+        let uvs = vertex_data(self.name, &mesh.vertex_uvs, "uvs")?;
+
         let pos_attrib = gl.get_attrib_location(&shader.program, "position");
         let normal_attrib = gl.get_attrib_location(&shader.program, "normal");
         let uv_attrib = gl.get_attrib_location(&shader.program, "uvs");
@@ -42,55 +54,66 @@ impl<'a> Render<'a> for NonSkinnedMesh<'a> {
         gl.enable_vertex_attrib_array(normal_attrib as u32);
         gl.enable_vertex_attrib_array(uv_attrib as u32);
 
-        NonSkinnedMesh::buffer_f32_data(&gl, &mesh.vertex_positions[..], pos_attrib as u32, 3);
-        NonSkinnedMesh::buffer_f32_data(&gl, &mesh.vertex_normals[..], normal_attrib as u32, 3);
-        NonSkinnedMesh::buffer_f32_data(
-            &gl,
-            &mesh.vertex_uvs.as_ref().expect("Mesh uvs")[..],
-            uv_attrib as u32,
-            2,
-        );
-        NonSkinnedMesh::buffer_u16_indices(&gl, &mesh.vertex_position_indices[..]);
+        Self::buffer_f32_data(
+            gl,
+            buffers,
+            &mesh.vertex_positions[..],
+            pos_attrib as u32,
+            3,
+        )?;
+        Self::buffer_f32_data(
+            gl,
+            buffers,
+            &mesh.vertex_normals[..],
+            normal_attrib as u32,
+            3,
+        )?;
+        Self::buffer_f32_data(gl, buffers, uvs, uv_attrib as u32, 2)?;
+        Self::buffer_u16_indices(gl, buffers, &mesh.vertex_position_indices[..])?;
+
+        Self::buffer_instance_attributes(gl, &shader.program, self.instance_buffer);
+
+        Ok(())
     }
 
-    fn render(&self, gl: &WebGlRenderingContext, state: &State) {
+    fn render(&self, gl: &G, state: &State) -> Result<(), AssetError> {
+        gl.marker(format_args!("NonSkinnedMesh::render"));
+
         let shader = self.shader();
 
         let mesh = self.mesh;
         let opts = self.opts;
-        let pos = opts.pos;
 
-        let model_uni = shader.get_uniform_location(gl, "model");
-        let view_uni = shader.get_uniform_location(gl, "view");
-        let camera_pos_uni = shader.get_uniform_location(gl, "cameraPos");
-        let perspective_uni = shader.get_uniform_location(gl, "perspective");
-        let clip_plane_uni = shader.get_uniform_location(gl, "clipPlane");
-        let mesh_texture_uni = shader.get_uniform_location(gl, "meshTexture");
+        let view_uni = shader.get_uniform_location(gl, "view")?;
+        let camera_pos_uni = shader.get_uniform_location(gl, "cameraPos")?;
+        let perspective_uni = shader.get_uniform_location(gl, "perspective")?;
+        let clip_plane_uni = shader.get_uniform_location(gl, "clipPlane")?;
+        let mesh_texture_uni = shader.get_uniform_location(gl, "meshTexture")?;
 
-        gl.uniform4fv_with_f32_array(clip_plane_uni.as_ref(), &mut opts.clip_plane.clone()[..]);
+        gl.uniform4fv_with_f32_array(Some(&clip_plane_uni), &mut opts.clip_plane.clone()[..]);
 
-        let mut view = if opts.flip_camera_y {
-            state.camera().view_flipped_y()
-        } else {
-            state.camera().view()
+        let mut view = match opts.flip_camera_y {
+            Some(water_height) => state.camera().view_flipped_y(water_height),
+            None => state.camera().view(),
         };
-        gl.uniform_matrix4fv_with_f32_array(view_uni.as_ref(), false, &mut view);
-
-        let model = Isometry3::new(Vector3::new(pos.0, pos.1, pos.2), nalgebra::zero());
-        let mut model_array = [0.; 16];
-        model_array.copy_from_slice(model.to_homogeneous().as_slice());
-        gl.uniform_matrix4fv_with_f32_array(model_uni.as_ref(), false, &mut model_array);
+        gl.uniform_matrix4fv_with_f32_array(Some(&view_uni), false, &mut view);
 
         let camera_pos = state.camera().get_eye_pos();
         let mut camera_pos = [camera_pos.x, camera_pos.y, camera_pos.z];
-        gl.uniform3fv_with_f32_array(camera_pos_uni.as_ref(), &mut camera_pos);
+        gl.uniform3fv_with_f32_array(Some(&camera_pos_uni), &mut camera_pos);
 
-        gl.uniform1i(mesh_texture_uni.as_ref(), TextureUnit::Stone.texture_unit());
+        gl.uniform1i(Some(&mesh_texture_uni), opts.texture.texture_unit());
 
         let mut perspective = state.camera().projection();
-        gl.uniform_matrix4fv_with_f32_array(perspective_uni.as_ref(), false, &mut perspective);
+        gl.uniform_matrix4fv_with_f32_array(Some(&perspective_uni), false, &mut perspective);
 
         let num_indices = mesh.vertex_position_indices.len();
-        gl.draw_elements_with_i32(GL::TRIANGLES, num_indices as i32, GL::UNSIGNED_SHORT, 0);
+        Self::draw_instances(
+            gl,
+            shader,
+            self.instance_buffer,
+            self.instances,
+            num_indices,
+        )
     }
 }
@@ -0,0 +1,43 @@
+use blender_mesh::BlenderMesh;
+
+/// A cube that gets drawn in place of any entity whose mesh or armature can't be rendered, so
+/// that a broken model shows up as a box instead of taking down the whole page.
+///
+/// The cube is one unit wide and centered on the entity's origin. Like our other non-skinned
+/// meshes it is already y up.
+pub fn placeholder_mesh() -> BlenderMesh {
+    let mut mesh = BlenderMesh::default();
+    let mut uvs = vec![];
+
+    // Each face's normal followed by the directions that its u and v coordinates run in
+    let faces = [
+        ([1., 0., 0.], [0., 0., -1.], [0., 1., 0.]),
+        ([-1., 0., 0.], [0., 0., 1.], [0., 1., 0.]),
+        ([0., 1., 0.], [1., 0., 0.], [0., 0., -1.]),
+        ([0., -1., 0.], [1., 0., 0.], [0., 0., 1.]),
+        ([0., 0., 1.], [1., 0., 0.], [0., 1., 0.]),
+        ([0., 0., -1.], [-1., 0., 0.], [0., 1., 0.]),
+    ];
+
+    for (normal, u, v) in faces.iter() {
+        let first_vertex = (mesh.vertex_positions.len() / 3) as u16;
+
+        for (s, t) in [(0., 0.), (1., 0.), (1., 1.), (0., 1.)].iter() {
+            for axis in 0..3 {
+                let position = normal[axis] * 0.5 + u[axis] * (s - 0.5) + v[axis] * (t - 0.5);
+                mesh.vertex_positions.push(position);
+            }
+
+            mesh.vertex_normals.extend_from_slice(normal);
+            uvs.extend_from_slice(&[*s, *t]);
+        }
+
+        let quad = [0, 1, 2, 0, 2, 3];
+        mesh.vertex_position_indices
+            .extend(quad.iter().map(|corner| first_vertex + corner));
+    }
+
+    mesh.vertex_uvs = Some(uvs);
+
+    mesh
+}
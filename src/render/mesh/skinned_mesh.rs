@@ -1,38 +1,60 @@
+use crate::app::AssetError;
 use crate::app::State;
 use crate::render::mesh::non_skinned_mesh::MeshRenderOpts;
+use crate::render::mesh::vertex_data;
+use crate::render::GlContext;
+use crate::render::MeshInstance;
 use crate::render::Render;
 use crate::render::TextureUnit;
+use crate::render::VertexBuffers;
+use crate::shader::BoneStorage;
 use crate::shader::Shader;
 use crate::shader::ShaderKind;
-use blender_armature::ActionSettings;
-use blender_armature::BlenderArmature;
-use blender_armature::InterpolationSettings;
+use blender_armature::{BlenderArmature, Bone};
 use blender_mesh::BlenderMesh;
-use nalgebra;
-use nalgebra::{Isometry3, Vector3};
+use std::collections::BTreeMap;
 use web_sys::WebGlRenderingContext as GL;
-use web_sys::*;
 
-pub struct SkinnedMesh<'a> {
+pub struct SkinnedMesh<'a, G: GlContext> {
+    /// Which mesh this is, for reporting vertex data that it's missing
+    pub name: &'a str,
     pub mesh: &'a BlenderMesh,
+    /// Which armature this is, for reporting it if it has more bones than we can skin
+    pub armature_name: &'a str,
     pub armature: &'a BlenderArmature,
-    pub shader: &'a Shader,
+    /// Every copy of the mesh to draw, each playing its own animation
+    pub instances: &'a [MeshInstance<'a>],
+    /// Holds the instances' per-instance attributes, see `Render::draw_instances`. None if we
+    /// can't draw instances.
+    pub instance_buffer: Option<&'a G::Buffer>,
+    pub shader: &'a Shader<G>,
+    /// Where our shader reads the bones from
+    pub bone_storage: BoneStorage,
+    /// Holds the bones when they're stored in a texture
+    pub bone_texture: Option<&'a G::Texture>,
+    /// How many instances' bones fit in our bone texture, see
+    /// `ShaderSystem::max_bone_texture_instances`
+    pub max_bone_texture_instances: usize,
     pub opts: &'a MeshRenderOpts,
 }
 
-impl<'a> Render<'a> for SkinnedMesh<'a> {
-    fn shader_kind() -> ShaderKind {
-        ShaderKind::SkinnedMesh
+impl<'a, G: GlContext> Render<'a, G> for SkinnedMesh<'a, G> {
+    fn shader_kind(&self) -> ShaderKind {
+        ShaderKind::SkinnedMesh(self.bone_storage)
     }
 
-    fn shader(&'a self) -> &'a Shader {
+    fn shader(&'a self) -> &'a Shader<G> {
         &self.shader
     }
 
-    fn buffer_attributes(&self, gl: &WebGlRenderingContext) {
+    fn buffer_attributes(&self, gl: &G, buffers: &mut VertexBuffers<G>) -> Result<(), AssetError> {
         let shader = self.shader();
         let mesh = self.mesh;
 
+        let uvs = vertex_data(self.name, &mesh.vertex_uvs, "uvs")?;
+        let joint_indices = vertex_data(self.name, &mesh.vertex_group_indices, "joint indices")?;
+        let joint_weights = vertex_data(self.name, &mesh.vertex_group_weights, "joint weights")?;
+
         let pos_attrib = gl.get_attrib_location(&shader.program, "position");
         let normal_attrib = gl.get_attrib_location(&shader.program, "normal");
         let uv_attrib = gl.get_attrib_location(&shader.program, "uvs");
@@ -45,107 +67,207 @@ impl<'a> Render<'a> for SkinnedMesh<'a> {
         let joint_weights_attrib = gl.get_attrib_location(&shader.program, "jointWeights");
         gl.enable_vertex_attrib_array(joint_indices_attrib as u32);
         gl.enable_vertex_attrib_array(joint_weights_attrib as u32);
-        SkinnedMesh::buffer_u8_data(
-            &gl,
-            &mesh.vertex_group_indices.as_ref().expect("Group indices")[..],
-            joint_indices_attrib as u32,
-            4,
-        );
-        SkinnedMesh::buffer_f32_data(
-            &gl,
-            &mesh.vertex_group_weights.as_ref().expect("Group weights")[..],
-            joint_weights_attrib as u32,
-            4,
-        );
-
-        SkinnedMesh::buffer_f32_data(&gl, &mesh.vertex_positions[..], pos_attrib as u32, 3);
-        SkinnedMesh::buffer_f32_data(&gl, &mesh.vertex_normals[..], normal_attrib as u32, 3);
-        SkinnedMesh::buffer_f32_data(
-            &gl,
-            &mesh.vertex_uvs.as_ref().expect("Mesh uvs")[..],
-            uv_attrib as u32,
-            2,
-        );
-        SkinnedMesh::buffer_u16_indices(&gl, &mesh.vertex_position_indices[..]);
+        Self::buffer_u8_data(gl, buffers, joint_indices, joint_indices_attrib as u32, 4)?;
+        Self::buffer_f32_data(gl, buffers, joint_weights, joint_weights_attrib as u32, 4)?;
+
+        Self::buffer_f32_data(
+            gl,
+            buffers,
+            &mesh.vertex_positions[..],
+            pos_attrib as u32,
+            3,
+        )?;
+        Self::buffer_f32_data(
+            gl,
+            buffers,
+            &mesh.vertex_normals[..],
+            normal_attrib as u32,
+            3,
+        )?;
+        Self::buffer_f32_data(gl, buffers, uvs, uv_attrib as u32, 2)?;
+        Self::buffer_u16_indices(gl, buffers, &mesh.vertex_position_indices[..])?;
+
+        Self::buffer_instance_attributes(gl, &shader.program, self.instance_buffer);
+
+        Ok(())
     }
 
-    fn render(&self, gl: &WebGlRenderingContext, state: &State) {
+    fn render(&self, gl: &G, state: &State) -> Result<(), AssetError> {
+        gl.marker(format_args!("SkinnedMesh::render"));
+
         let shader = self.shader();
 
         let mesh = self.mesh;
         let opts = self.opts;
-        let pos = opts.pos;
 
-        let model_uni = shader.get_uniform_location(gl, "model");
-        let view_uni = shader.get_uniform_location(gl, "view");
-        let camera_pos_uni = shader.get_uniform_location(gl, "cameraPos");
-        let perspective_uni = shader.get_uniform_location(gl, "perspective");
-        let clip_plane_uni = shader.get_uniform_location(gl, "clipPlane");
-        let mesh_texture_uni = shader.get_uniform_location(gl, "meshTexture");
+        let view_uni = shader.get_uniform_location(gl, "view")?;
+        let camera_pos_uni = shader.get_uniform_location(gl, "cameraPos")?;
+        let perspective_uni = shader.get_uniform_location(gl, "perspective")?;
+        let clip_plane_uni = shader.get_uniform_location(gl, "clipPlane")?;
+        let mesh_texture_uni = shader.get_uniform_location(gl, "meshTexture")?;
 
-        gl.uniform4fv_with_f32_array(clip_plane_uni.as_ref(), &mut opts.clip_plane.clone()[..]);
+        gl.uniform4fv_with_f32_array(Some(&clip_plane_uni), &mut opts.clip_plane.clone()[..]);
 
-        let mut view = if opts.flip_camera_y {
-            state.camera().view_flipped_y()
-        } else {
-            state.camera().view()
+        let mut view = match opts.flip_camera_y {
+            Some(water_height) => state.camera().view_flipped_y(water_height),
+            None => state.camera().view(),
         };
-        gl.uniform_matrix4fv_with_f32_array(view_uni.as_ref(), false, &mut view);
-
-        let model = Isometry3::new(Vector3::new(pos.0, pos.1, pos.2), nalgebra::zero());
-        let mut model_array = [0.; 16];
-        model_array.copy_from_slice(model.to_homogeneous().as_slice());
-        gl.uniform_matrix4fv_with_f32_array(model_uni.as_ref(), false, &mut model_array);
+        gl.uniform_matrix4fv_with_f32_array(Some(&view_uni), false, &mut view);
 
         let mut perspective = state.camera().projection();
-        gl.uniform_matrix4fv_with_f32_array(perspective_uni.as_ref(), false, &mut perspective);
+        gl.uniform_matrix4fv_with_f32_array(Some(&perspective_uni), false, &mut perspective);
 
         let camera_pos = state.camera().get_eye_pos();
         let mut camera_pos = [camera_pos.x, camera_pos.y, camera_pos.z];
-        gl.uniform3fv_with_f32_array(camera_pos_uni.as_ref(), &mut camera_pos);
-
-        gl.uniform1i(mesh_texture_uni.as_ref(), TextureUnit::Stone.texture_unit());
+        gl.uniform3fv_with_f32_array(Some(&camera_pos_uni), &mut camera_pos);
 
-        self.set_armature_uniforms(gl, state);
+        gl.uniform1i(Some(&mesh_texture_uni), opts.texture.texture_unit());
 
         let num_indices = mesh.vertex_position_indices.len();
-        gl.draw_elements_with_i32(GL::TRIANGLES, num_indices as i32, GL::UNSIGNED_SHORT, 0);
+        self.draw_skinned_instances(gl, num_indices)
     }
 }
 
-impl<'a> SkinnedMesh<'a> {
-    fn set_armature_uniforms(&self, gl: &WebGlRenderingContext, state: &State) {
+impl<'a, G: GlContext> SkinnedMesh<'a, G> {
+    /// Upload our instances' bones and draw them.
+    ///
+    /// A bone texture holds as many instances' bones as fit in it, so they can be drawn at once.
+    /// Our uniforms only hold one set of bones, so with those we draw one instance at a time.
+    fn draw_skinned_instances(&self, gl: &G, num_indices: usize) -> Result<(), AssetError> {
         let shader = self.shader();
-        let armature = &self.armature;
+        let instance_buffer = self.instance_buffer;
 
-        let clock = state.clock();
-        let current_time_secs = clock / 1000.0;
+        match self.bone_storage {
+            BoneStorage::Uniforms(max_bones) => {
+                for instance in self.instances.chunks(1) {
+                    self.upload_bone_uniforms(gl, &self.bones(&instance[0])?, max_bones)?;
+                    Self::draw_instances(gl, shader, instance_buffer, instance, num_indices)?;
+                }
+            }
+            BoneStorage::Texture => {
+                // Without instancing every instance reads the first two columns of bones, so
+                // each one needs its own upload
+                let chunk_size = match instance_buffer {
+                    Some(_) => self.max_bone_texture_instances,
+                    None => 1,
+                };
 
-        let interp_opts = InterpolationSettings {
-            current_time: current_time_secs,
-            joint_indices: vec![0, 1, 2, 3, 4, 5, 6, 7, 8],
-            blend_fn: None,
-            current_action: ActionSettings::new("Fly.001", 0.0, true),
-            previous_action: None,
+                for instances in self.instances.chunks(chunk_size) {
+                    let bones: Vec<Vec<[f32; 8]>> = instances
+                        .iter()
+                        .map(|instance| self.bones(instance))
+                        .collect::<Result<_, _>>()?;
+
+                    self.upload_bone_texture(gl, &bones)?;
+                    Self::draw_instances(gl, shader, instance_buffer, instances, num_indices)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The instance's bones for the current frame of its animation, see `dual_quaternions`
+    fn bones(&self, instance: &MeshInstance) -> Result<Vec<[f32; 8]>, AssetError> {
+        let armature = self.armature;
+
+        let bones = match instance.animation {
+            Some(animation) => animation.interpolate_bones(self.armature_name, armature)?,
+            None => BTreeMap::new(),
         };
-        let bones = armature.interpolate_bones(&interp_opts);
 
-        let bone_count = bones.len() as u8;
+        Ok(dual_quaternions(&bones, armature.inverse_bind_poses.len()))
+    }
 
-        for index in 0..bone_count {
-            let bone = bones.get(&index).expect("Interpolated bone");
-            let bone = bone.as_slice();
+    /// Upload every bone into our shader's uniform arrays, which `ShaderSystem::bone_storage`
+    /// made room for `max_bones` in
+    fn upload_bone_uniforms(
+        &self,
+        gl: &G,
+        bones: &[[f32; 8]],
+        max_bones: usize,
+    ) -> Result<(), AssetError> {
+        let shader = self.shader();
 
+        for (index, bone) in bones.iter().enumerate().take(max_bones) {
             let (rot_quat, trans_quat) = bone.split_at(4);
             let (rq, tq) = (rot_quat, trans_quat);
 
             let rot_quat_uni =
-                shader.get_uniform_location(gl, &format!("boneRotQuaternions[{}]", index));
-            gl.uniform4f(rot_quat_uni.as_ref(), rq[0], rq[1], rq[2], rq[3]);
+                shader.get_uniform_location(gl, &format!("boneRotQuaternions[{}]", index))?;
+            gl.uniform4f(Some(&rot_quat_uni), rq[0], rq[1], rq[2], rq[3]);
 
             let trans_quat_uni =
-                shader.get_uniform_location(gl, &format!("boneTransQuaternions[{}]", index));
-            gl.uniform4f(trans_quat_uni.as_ref(), tq[0], tq[1], tq[2], tq[3]);
+                shader.get_uniform_location(gl, &format!("boneTransQuaternions[{}]", index))?;
+            gl.uniform4f(Some(&trans_quat_uni), tq[0], tq[1], tq[2], tq[3]);
         }
+
+        Ok(())
     }
+
+    /// Upload every instance's bones into a float texture with one row per bone and two
+    /// columns per instance. A bone's rotation quaternion goes in the first of its instance's
+    /// texels and its translation quaternion in the second.
+    fn upload_bone_texture(
+        &self,
+        gl: &G,
+        instance_bones: &[Vec<[f32; 8]>],
+    ) -> Result<(), AssetError> {
+        let shader = self.shader();
+
+        let bone_count = instance_bones.iter().map(Vec::len).max().unwrap_or(0);
+
+        let mut texels = Vec::with_capacity(bone_count * instance_bones.len() * 8);
+        for bone in 0..bone_count {
+            for bones in instance_bones.iter() {
+                let bone = bones.get(bone).unwrap_or(&IDENTITY_DUAL_QUATERNION);
+                texels.extend_from_slice(bone);
+            }
+        }
+
+        gl.active_texture(TextureUnit::Bones.TEXTURE_N());
+        gl.bind_texture(GL::TEXTURE_2D, self.bone_texture);
+        gl.tex_image_2d_f32(
+            GL::TEXTURE_2D,
+            0,
+            GL::RGBA as i32,
+            instance_bones.len() as i32 * 2,
+            bone_count as i32,
+            0,
+            GL::RGBA,
+            &texels,
+        )
+        .map_err(|_| AssetError::BoneTextureUpload(self.armature_name.to_string()))?;
+
+        let bone_texture_uni = shader.get_uniform_location(gl, "boneTexture")?;
+        gl.uniform1i(Some(&bone_texture_uni), TextureUnit::Bones.texture_unit());
+
+        let bone_count_uni = shader.get_uniform_location(gl, "boneCount")?;
+        gl.uniform1f(Some(&bone_count_uni), bone_count as f32);
+
+        let instance_count_uni = shader.get_uniform_location(gl, "instanceCount")?;
+        gl.uniform1f(Some(&instance_count_uni), instance_bones.len() as f32);
+
+        Ok(())
+    }
+}
+
+/// Every bone's dual quaternion, with the bone for joint `n` at index `n`.
+///
+/// An action doesn't have to key every bone, so we fill in the joints that it skips (and any
+/// up to `bone_count`) with the identity, which leaves their vertices in the bind pose.
+fn dual_quaternions(bones: &BTreeMap<u8, Bone>, bone_count: usize) -> Vec<[f32; 8]> {
+    let last_joint = bones.keys().next_back().map(|joint| *joint as usize + 1);
+    let bone_count = last_joint.unwrap_or(0).max(bone_count);
+
+    let mut dual_quaternions = vec![IDENTITY_DUAL_QUATERNION; bone_count];
+    // Armatures are converted to dual quaternions when they're loaded, see `preprocess_armature`
+    for (joint, bone) in bones.iter() {
+        dual_quaternions[*joint as usize].copy_from_slice(bone.as_slice());
+    }
+
+    dual_quaternions
 }
+
+/// No rotation (w, x, y, z) followed by no translation
+const IDENTITY_DUAL_QUATERNION: [f32; 8] = [1., 0., 0., 0., 0., 0., 0., 0.];
@@ -1,23 +1,25 @@
 use self::framebuffer::*;
+pub use self::gl_context::*;
 pub(self) use self::mesh::*;
 pub(self) use self::render_trait::*;
 pub use self::texture_unit::*;
 use self::water_tile::*;
+use crate::app::AssetError;
 use crate::app::Assets;
 use crate::app::State;
-use crate::canvas::{CANVAS_HEIGHT, CANVAS_WIDTH};
+use crate::app::WaterBody;
+use crate::app::RIPPLE_GRID_SIZE;
 use crate::render::textured_quad::TexturedQuad;
 use crate::shader::ShaderKind;
 use crate::shader::ShaderSystem;
-use js_sys::Reflect;
-use std::cell::RefCell;
+use blender_mesh::BlenderMesh;
+use std::cell::{Ref, RefCell};
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use web_sys::WebGlRenderingContext as GL;
-use web_sys::*;
-
-pub static WATER_TILE_Y_POS: f32 = 0.0;
 
 mod framebuffer;
+mod gl_context;
 mod mesh;
 mod render_meshes;
 mod render_trait;
@@ -25,53 +27,92 @@ mod texture_unit;
 mod textured_quad;
 mod water_tile;
 
-struct VaoExtension {
-    oes_vao_ext: js_sys::Object,
-    vaos: RefCell<HashMap<String, Vao>>,
-}
-
-struct Vao(js_sys::Object);
-
-pub struct WebRenderer {
-    shader_sys: ShaderSystem,
-    #[allow(unused)]
-    depth_texture_ext: Option<js_sys::Object>,
-    refraction_framebuffer: Framebuffer,
-    reflection_framebuffer: Framebuffer,
-    vao_ext: VaoExtension,
+/// The WebGL 1 extensions that we can't render without
+const REQUIRED_EXTENSIONS: [&str; 2] = ["WEBGL_depth_texture", "OES_vertex_array_object"];
+
+/// The most vertex attributes that one of our shaders uses when we draw instances. The skinned
+/// mesh shader's per-vertex attributes plus its per-instance ones (see `INSTANCE_ATTRIBUTES`)
+/// need more than the 8 that WebGL guarantees, so devices with fewer draw one instance at a time.
+const INSTANCED_VERTEX_ATTRIBS: i32 = 14;
+
+/// Each vertex array object along with the buffers behind it and the version of the data that we
+/// buffered into them, or None if buffering it failed, see `WebRenderer::prepare_version_for_render`
+type VertexArrays<G> = HashMap<String, (<G as GlContext>::Vao, VertexBuffers<G>, Option<u32>)>;
+
+pub struct WebRenderer<G: GlContext> {
+    shader_sys: ShaderSystem<G>,
+    refraction_framebuffer: Framebuffer<G>,
+    reflection_framebuffer: Framebuffer<G>,
+    ripple_texture: Option<G::Texture>,
+    /// Holds the bones of skinned meshes whose rigs don't fit in our uniforms
+    bone_texture: Option<G::Texture>,
+    /// Holds the per-instance attributes of the meshes in each draw call. None on devices that
+    /// can't draw instances, where we upload each instance's model matrix as a uniform instead.
+    instance_buffer: Option<G::Buffer>,
+    /// Drawn in place of entities whose assets are broken
+    placeholder_mesh: BlenderMesh,
+    /// Every asset that we couldn't render in the last frame
+    asset_errors: RefCell<Vec<AssetError>>,
+    vaos: RefCell<VertexArrays<G>>,
 }
 
-impl WebRenderer {
-    pub fn new(gl: &WebGlRenderingContext) -> WebRenderer {
-        let shader_sys = ShaderSystem::new(&gl);
-
-        let depth_texture_ext = gl
-            .get_extension("WEBGL_depth_texture")
-            .expect("Depth texture extension");
-
-        let oes_vao_ext = gl
-            .get_extension("OES_vertex_array_object")
-            .expect("Get OES vao ext")
-            .expect("OES vao ext");
+impl<G: GlContext> WebRenderer<G> {
+    /// Create a renderer, failing if the device is missing an extension that we can't render
+    /// without
+    pub fn new(gl: &G, state: &State) -> Result<WebRenderer<G>, String> {
+        // Needed for our refraction framebuffer's depth texture and our vertex array objects
+        for extension in REQUIRED_EXTENSIONS.iter() {
+            if !gl.enable_extension(extension) {
+                return Err(format!("This device does not support {}", extension));
+            }
+        }
 
-        let vao_ext = VaoExtension {
-            oes_vao_ext,
-            vaos: RefCell::new(HashMap::new()),
+        // Drawing many copies of a mesh at once is only an optimization, so devices that can't
+        // do it get one draw call per entity
+        let instanced = gl.get_parameter_i32(GL::MAX_VERTEX_ATTRIBS) >= INSTANCED_VERTEX_ATTRIBS
+            && gl.enable_extension("ANGLE_instanced_arrays");
+
+        let shader_sys = ShaderSystem::new(gl, instanced)?;
+
+        let refraction_framebuffer =
+            WebRenderer::create_refraction_framebuffer(gl, state.refraction_size())
+                .map_err(|err| err.to_string())?;
+        let reflection_framebuffer =
+            WebRenderer::create_reflection_framebuffer(gl, state.reflection_size())
+                .map_err(|err| err.to_string())?;
+
+        let ripple_texture = WebRenderer::create_ripple_texture(gl);
+        let bone_texture = WebRenderer::create_bone_texture(gl);
+        let instance_buffer = if instanced {
+            let instance_buffer = gl
+                .create_buffer()
+                .ok_or_else(|| "Could not create our instance buffer".to_string())?;
+            Some(instance_buffer)
+        } else {
+            None
         };
 
-        let refraction_framebuffer = WebRenderer::create_refraction_framebuffer(&gl).unwrap();
-        let reflection_framebuffer = WebRenderer::create_reflection_framebuffer(&gl).unwrap();
-
-        WebRenderer {
-            depth_texture_ext,
+        Ok(WebRenderer {
             shader_sys,
             refraction_framebuffer,
             reflection_framebuffer,
-            vao_ext,
-        }
+            ripple_texture,
+            bone_texture,
+            instance_buffer,
+            placeholder_mesh: placeholder_mesh(),
+            asset_errors: RefCell::new(vec![]),
+            vaos: RefCell::new(HashMap::new()),
+        })
     }
 
-    pub fn render(&mut self, gl: &WebGlRenderingContext, state: &State, assets: &Assets) {
+    pub fn render(&mut self, gl: &G, state: &State, assets: &Assets) {
+        self.asset_errors.borrow_mut().clear();
+
+        self.resize_framebuffers(gl, state);
+        self.prepare_skinned_shaders(gl, state, assets);
+
+        let (canvas_width, canvas_height) = state.canvas_size();
+
         gl.clear_color(0.53, 0.8, 0.98, 1.);
         gl.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT);
 
@@ -79,150 +120,276 @@ impl WebRenderer {
         // Position is positive instead of negative for.. mathematical reasons..
         let clip_plane = [0., 1., 0., above];
 
-        self.render_refraction_fbo(gl, state, assets);
-        self.render_reflection_fbo(gl, state, assets);
+        for water_body in state.water_bodies() {
+            self.render_refraction_fbo(gl, state, assets, water_body);
+            self.render_reflection_fbo(gl, state, assets, water_body);
+
+            gl.viewport(0, 0, canvas_width as i32, canvas_height as i32);
 
-        gl.viewport(0, 0, CANVAS_WIDTH, CANVAS_HEIGHT);
+            self.render_water(gl, state, water_body);
+        }
 
-        self.render_water(gl, state);
-        self.render_meshes(gl, state, assets, clip_plane, false);
+        gl.viewport(0, 0, canvas_width as i32, canvas_height as i32);
+        self.render_meshes(gl, state, assets, clip_plane, None);
 
         self.render_refraction_visual(gl, state);
         self.render_reflection_visual(gl, state);
     }
 
-    fn render_water(&mut self, gl: &WebGlRenderingContext, state: &State) {
+    /// The assets that we couldn't render in the last frame. Each entity whose mesh or armature
+    /// was broken was drawn as a placeholder cube instead.
+    pub fn asset_errors(&self) -> Ref<Vec<AssetError>> {
+        self.asset_errors.borrow()
+    }
+
+    /// Re-create any framebuffer whose resolution setting, render scale or canvas has changed
+    /// size, releasing the old one.
+    ///
+    /// If the device can't fit a framebuffer at its new size we report it and keep rendering
+    /// into the old one, trying again next frame.
+    fn resize_framebuffers(&mut self, gl: &G, state: &State) {
+        let refraction_size = state.refraction_size();
+        let reflection_size = state.reflection_size();
+
+        let refraction = &self.refraction_framebuffer;
+        if (refraction.width, refraction.height) != refraction_size {
+            match WebRenderer::create_refraction_framebuffer(gl, refraction_size) {
+                Ok(resized) => {
+                    refraction.delete(gl);
+                    self.refraction_framebuffer = resized;
+                }
+                Err(err) => self.report_asset_error(err),
+            }
+        }
+
+        let reflection = &self.reflection_framebuffer;
+        if (reflection.width, reflection.height) != reflection_size {
+            match WebRenderer::create_reflection_framebuffer(gl, reflection_size) {
+                Ok(resized) => {
+                    reflection.delete(gl);
+                    self.reflection_framebuffer = resized;
+                }
+                Err(err) => self.report_asset_error(err),
+            }
+        }
+    }
+
+    fn render_water(&mut self, gl: &G, state: &State, water_body: &WaterBody) {
         gl.bind_framebuffer(GL::FRAMEBUFFER, None);
 
-        let water_shader = self.shader_sys.get_shader(&ShaderKind::Water).unwrap();
+        let water_shader = match self.shader_sys.get_shader(&ShaderKind::Water) {
+            Some(water_shader) => water_shader,
+            // `ShaderSystem::new` fails without it, so we never get here
+            None => return,
+        };
         self.shader_sys.use_program(gl, ShaderKind::Water);
 
-        let water_tile = RenderableWaterTile::new(water_shader);
+        let grid_resolution = state.water_grid_resolution();
+        let water_tile = RenderableWaterTile::new(water_shader, water_body, grid_resolution);
+
+        let rendered = self
+            .upload_ripples(gl, water_body)
+            .and_then(|_| self.prepare_for_render(gl, &water_tile, &water_tile.vao_key()))
+            .and_then(|_| water_tile.render(gl, state));
+        if let Err(err) = rendered {
+            self.report_asset_error(err);
+        }
+    }
+
+    fn create_ripple_texture(gl: &G) -> Option<G::Texture> {
+        let ripple_texture = gl.create_texture();
+
+        gl.active_texture(TextureUnit::Ripples.TEXTURE_N());
+        gl.bind_texture(GL::TEXTURE_2D, ripple_texture.as_ref());
+
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::LINEAR as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::LINEAR as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
 
-        self.prepare_for_render(gl, &water_tile, "water");
-        water_tile.render(gl, state);
+        ripple_texture
+    }
+
+    fn create_bone_texture(gl: &G) -> Option<G::Texture> {
+        let bone_texture = gl.create_texture();
+
+        gl.active_texture(TextureUnit::Bones.TEXTURE_N());
+        gl.bind_texture(GL::TEXTURE_2D, bone_texture.as_ref());
+
+        // Float textures can't be filtered without another extension, and we never want to blend
+        // one bone into the next anyways
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::NEAREST as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::NEAREST as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
+
+        bone_texture
+    }
+
+    /// Every water body has its own ripples, so we re-upload them before rendering each one
+    fn upload_ripples(&self, gl: &G, water_body: &WaterBody) -> Result<(), AssetError> {
+        let slopes = water_body.ripples().slopes_rgba();
+
+        gl.active_texture(TextureUnit::Ripples.TEXTURE_N());
+        gl.bind_texture(GL::TEXTURE_2D, self.ripple_texture.as_ref());
+        gl.tex_image_2d(
+            GL::TEXTURE_2D,
+            0,
+            GL::RGBA as i32,
+            RIPPLE_GRID_SIZE as i32,
+            RIPPLE_GRID_SIZE as i32,
+            0,
+            GL::RGBA as u32,
+            GL::UNSIGNED_BYTE,
+            Some(&slopes),
+        )
+        .map_err(|_| AssetError::GpuResource("the ripple texture"))
     }
 
     fn render_refraction_fbo(
         &mut self,
-        gl: &WebGlRenderingContext,
+        gl: &G,
         state: &State,
         assets: &Assets,
+        water_body: &WaterBody,
     ) {
-        let Framebuffer { framebuffer, .. } = &self.refraction_framebuffer;
+        let Framebuffer {
+            framebuffer,
+            width,
+            height,
+            ..
+        } = &self.refraction_framebuffer;
         gl.bind_framebuffer(GL::FRAMEBUFFER, framebuffer.as_ref());
 
-        gl.viewport(0, 0, REFRACTION_TEXTURE_WIDTH, REFRACTION_TEXTURE_HEIGHT);
+        gl.viewport(0, 0, *width, *height);
 
         gl.clear_color(0.53, 0.8, 0.98, 1.);
         gl.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT);
 
         if state.water().use_refraction {
-            let clip_plane = [0., -1., 0., WATER_TILE_Y_POS];
-            self.render_meshes(gl, state, assets, clip_plane, false);
+            let clip_plane = water_body.refraction_clip_plane();
+            self.render_meshes(gl, state, assets, clip_plane, None);
         }
     }
 
     fn render_reflection_fbo(
         &mut self,
-        gl: &WebGlRenderingContext,
+        gl: &G,
         state: &State,
         assets: &Assets,
+        water_body: &WaterBody,
     ) {
-        let Framebuffer { framebuffer, .. } = &self.reflection_framebuffer;
+        let Framebuffer {
+            framebuffer,
+            width,
+            height,
+            ..
+        } = &self.reflection_framebuffer;
         gl.bind_framebuffer(GL::FRAMEBUFFER, framebuffer.as_ref());
 
-        gl.viewport(0, 0, REFLECTION_TEXTURE_WIDTH, REFLECTION_TEXTURE_HEIGHT);
+        gl.viewport(0, 0, *width, *height);
 
         gl.clear_color(0.53, 0.8, 0.98, 1.);
         gl.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT);
 
         if state.water().use_reflection {
-            let clip_plane = [0., 1., 0., -WATER_TILE_Y_POS];
-            self.render_meshes(gl, state, assets, clip_plane, true);
+            let clip_plane = water_body.reflection_clip_plane();
+            self.render_meshes(gl, state, assets, clip_plane, Some(water_body.height));
         }
     }
 
-    fn render_refraction_visual(&self, gl: &WebGlRenderingContext, state: &State) {
-        let quad_shader = self
-            .shader_sys
-            .get_shader(&ShaderKind::TexturedQuad)
-            .unwrap();
+    fn render_refraction_visual(&self, gl: &G, state: &State) {
+        let quad_shader = match self.shader_sys.get_shader(&ShaderKind::TexturedQuad) {
+            Some(quad_shader) => quad_shader,
+            // `ShaderSystem::new` fails without it, so we never get here
+            None => return,
+        };
         self.shader_sys.use_program(gl, ShaderKind::TexturedQuad);
+        let (_, canvas_height) = state.canvas_size();
         let textured_quad = TexturedQuad::new(
             0,
-            CANVAS_HEIGHT as u16,
+            canvas_height as u16,
             75,
             75,
             TextureUnit::Refraction as u8,
             quad_shader,
         );
-        self.prepare_for_render(gl, &textured_quad, "RefractionVisual");
-        textured_quad.render(gl, state);
+        let rendered = self
+            .prepare_for_render(gl, &textured_quad, "RefractionVisual")
+            .and_then(|_| textured_quad.render(gl, state));
+        if let Err(err) = rendered {
+            self.report_asset_error(err);
+        }
     }
 
-    fn render_reflection_visual(&self, gl: &WebGlRenderingContext, state: &State) {
-        let quad_shader = self
-            .shader_sys
-            .get_shader(&ShaderKind::TexturedQuad)
-            .unwrap();
+    fn render_reflection_visual(&self, gl: &G, state: &State) {
+        let quad_shader = match self.shader_sys.get_shader(&ShaderKind::TexturedQuad) {
+            Some(quad_shader) => quad_shader,
+            // `ShaderSystem::new` fails without it, so we never get here
+            None => return,
+        };
         self.shader_sys.use_program(gl, ShaderKind::TexturedQuad);
+        let (canvas_width, canvas_height) = state.canvas_size();
         let textured_quad = TexturedQuad::new(
-            CANVAS_WIDTH as u16 - 75,
-            CANVAS_HEIGHT as u16,
+            canvas_width.saturating_sub(75) as u16,
+            canvas_height as u16,
             75,
             75,
             TextureUnit::Reflection as u8,
             quad_shader,
         );
 
-        self.prepare_for_render(gl, &textured_quad, "ReflectionVisual");
-        textured_quad.render(gl, state);
-    }
-
-    fn create_vao(&self) -> Vao {
-        let oes_vao_ext = &self.vao_ext.oes_vao_ext;
-
-        let create_vao_ext = Reflect::get(oes_vao_ext, &"createVertexArrayOES".into())
-            .expect("Create vao func")
-            .into();
-
-        Vao(
-            Reflect::apply(&create_vao_ext, oes_vao_ext, &js_sys::Array::new())
-                .expect("Created vao")
-                .into(),
-        )
+        let rendered = self
+            .prepare_for_render(gl, &textured_quad, "ReflectionVisual")
+            .and_then(|_| textured_quad.render(gl, state));
+        if let Err(err) = rendered {
+            self.report_asset_error(err);
+        }
     }
 
     fn prepare_for_render<'a>(
         &self,
-        gl: &WebGlRenderingContext,
-        renderable: &impl Render<'a>,
+        gl: &G,
+        renderable: &impl Render<'a, G>,
         key: &str,
-    ) {
-        if self.vao_ext.vaos.borrow().get(key).is_none() {
-            let vao = self.create_vao();
-            self.bind_vao(&vao);
-            renderable.buffer_attributes(gl);
-            self.vao_ext.vaos.borrow_mut().insert(key.to_string(), vao);
-            return;
-        }
-
-        let vaos = self.vao_ext.vaos.borrow();
-        let vao = vaos.get(key).unwrap();
-        self.bind_vao(vao);
+    ) -> Result<(), AssetError> {
+        self.prepare_version_for_render(gl, renderable, key, 0)
     }
 
-    fn bind_vao(&self, vao: &Vao) {
-        let oes_vao_ext = &self.vao_ext.oes_vao_ext;
-
-        let bind_vao_ext = Reflect::get(&oes_vao_ext, &"bindVertexArrayOES".into())
-            .expect("Create vao func")
-            .into();
-
-        let args = js_sys::Array::new();
-        args.push(&vao.0);
+    /// Bind the vertex array object for `key`, buffering the renderable's attributes into it if
+    /// they were last buffered from a different `version` of its data (ex: a mesh that a later
+    /// bundle replaced, see `Assets::mesh_version`).
+    fn prepare_version_for_render<'a>(
+        &self,
+        gl: &G,
+        renderable: &impl Render<'a, G>,
+        key: &str,
+        version: u32,
+    ) -> Result<(), AssetError> {
+        gl.marker(format_args!("prepare_for_render {}", key));
+
+        let mut vaos = self.vaos.borrow_mut();
+
+        let (vao, buffers, buffered_version) = match vaos.entry(key.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let vao = gl
+                    .create_vertex_array()
+                    .ok_or(AssetError::GpuResource("a vertex array object"))?;
+                entry.insert((vao, VertexBuffers::default(), None))
+            }
+        };
+        gl.bind_vertex_array(Some(vao));
+
+        // We keep the vertex array of a renderable that failed to buffer so that we don't create
+        // a new one every frame, and try again the next time it gets rendered
+        if *buffered_version != Some(version) {
+            *buffered_version = None;
+            buffers.rewind();
+            renderable.buffer_attributes(gl, buffers)?;
+            *buffered_version = Some(version);
+        }
 
-        Reflect::apply(&bind_vao_ext, oes_vao_ext, &args).expect("Bound VAO");
+        Ok(())
     }
 }
@@ -1,77 +1,278 @@
+use crate::render::GlContext;
+use crate::render::MeshInstance;
 use crate::render::MeshRenderOpts;
 use crate::render::NonSkinnedMesh;
 use crate::render::Render;
 use crate::render::SkinnedMesh;
+use crate::render::TextureUnit;
 use crate::render::WebRenderer;
+use crate::render::MAX_INSTANCES_PER_DRAW;
+use crate::shader::BoneStorage;
 use crate::shader::ShaderKind;
+use crate::AssetError;
 use crate::Assets;
 use crate::State;
-use web_sys::WebGlRenderingContext as GL;
+use blender_armature::BlenderArmature;
+use blender_mesh::BlenderMesh;
 
-static BIRD_SPEED: f32 = 3.5;
-static BIRD_START_Z: f32 = -30.0;
-static BIRD_END_Z: f32 = 30.0;
+/// Keeps our placeholder cube's vertex array object apart from the ones for our meshes
+const PLACEHOLDER_VAO_KEY: &str = "placeholder";
 
-impl WebRenderer {
+/// Entities that we draw together, since they share a mesh, an armature and a texture
+struct MeshBatch<'a> {
+    /// The mesh's name and the mesh. None for entities whose assets we couldn't render, which
+    /// get drawn as our placeholder cube.
+    mesh: Option<(&'a str, &'a BlenderMesh)>,
+    /// The armature's name, the armature and where our shader reads its bones from
+    skin: Option<(&'a str, &'a BlenderArmature, BoneStorage)>,
+    texture: TextureUnit,
+    instances: Vec<MeshInstance<'a>>,
+}
+
+impl<G: GlContext> WebRenderer<G> {
     pub(in crate::render) fn render_meshes(
         &self,
-        gl: &GL,
+        gl: &G,
         state: &State,
         assets: &Assets,
         clip_plane: [f32; 4],
-        flip_camera_y: bool,
+        flip_camera_y: Option<f32>,
     ) {
         if !state.show_scenery() {
             return;
         }
 
-        let (skin, no_skin) = (ShaderKind::SkinnedMesh, ShaderKind::NonSkinnedMesh);
+        let no_skin = ShaderKind::NonSkinnedMesh;
+        let non_skinned_shader = self.shader_sys.get_shader(&no_skin).unwrap();
 
-        // Render Terrain
+        for batch in self.batch_meshes(state, assets) {
+            let mesh_opts = MeshRenderOpts {
+                texture: batch.texture,
+                clip_plane,
+                flip_camera_y,
+            };
 
-        let non_skinned_shader = self.shader_sys.get_shader(&no_skin).unwrap();
-        self.shader_sys.use_program(gl, ShaderKind::NonSkinnedMesh);
+            let (mesh_name, mesh) = match batch.mesh {
+                Some(mesh) => mesh,
+                None => {
+                    for instances in batch.instances.chunks(MAX_INSTANCES_PER_DRAW) {
+                        self.render_placeholder(gl, state, instances, &mesh_opts);
+                    }
+                    continue;
+                }
+            };
 
-        let mesh_opts = MeshRenderOpts {
-            pos: (0., 0., 0.),
-            clip_plane,
-            flip_camera_y,
-        };
+            // Re-buffer the mesh if a bundle replaced it since we last did
+            let mesh_version = assets.mesh_version(mesh_name);
 
-        let mesh_name = "Terrain";
-        let terrain = NonSkinnedMesh {
-            mesh: assets.get_mesh(mesh_name).expect("Terrain mesh"),
-            shader: non_skinned_shader,
-            opts: &mesh_opts,
-        };
+            for instances in batch.instances.chunks(MAX_INSTANCES_PER_DRAW) {
+                let rendered = match batch.skin {
+                    Some((armature_name, armature, bone_storage)) => {
+                        let skin = ShaderKind::SkinnedMesh(bone_storage);
+                        let vao_key = vao_key(mesh_name, skin);
 
-        self.prepare_for_render(gl, &terrain, mesh_name);
-        terrain.render(gl, state);
+                        let skinned_mesh = SkinnedMesh {
+                            name: mesh_name,
+                            mesh,
+                            armature_name,
+                            armature,
+                            instances,
+                            instance_buffer: self.instance_buffer.as_ref(),
+                            shader: self.shader_sys.get_shader(&skin).unwrap(),
+                            bone_storage,
+                            bone_texture: self.bone_texture.as_ref(),
+                            max_bone_texture_instances: self
+                                .shader_sys
+                                .max_bone_texture_instances(),
+                            opts: &mesh_opts,
+                        };
 
-        // Render Bird
+                        self.shader_sys.use_program(gl, skin);
 
-        let skinned_shader = self.shader_sys.get_shader(&skin).unwrap();
-        self.shader_sys.use_program(gl, ShaderKind::SkinnedMesh);
+                        self.prepare_version_for_render(gl, &skinned_mesh, &vao_key, mesh_version)
+                            .and_then(|_| skinned_mesh.render(gl, state))
+                    }
+                    None => {
+                        self.shader_sys.use_program(gl, ShaderKind::NonSkinnedMesh);
+                        let vao_key = vao_key(mesh_name, ShaderKind::NonSkinnedMesh);
 
-        let bird_traveled = (state.clock() / 1000.0) * BIRD_SPEED;
-        let z = BIRD_START_Z + (bird_traveled % (BIRD_END_Z - BIRD_START_Z));
+                        let non_skinned_mesh = NonSkinnedMesh {
+                            name: mesh_name,
+                            mesh,
+                            shader: non_skinned_shader,
+                            instances,
+                            instance_buffer: self.instance_buffer.as_ref(),
+                            opts: &mesh_opts,
+                        };
 
-        let mesh_opts = MeshRenderOpts {
-            pos: (0., 6., z),
-            clip_plane,
-            flip_camera_y,
-        };
+                        self.prepare_version_for_render(
+                            gl,
+                            &non_skinned_mesh,
+                            &vao_key,
+                            mesh_version,
+                        )
+                        .and_then(|_| non_skinned_mesh.render(gl, state))
+                    }
+                };
+
+                // Meshes that `batch_meshes` checked can still fail to render, ex: if our shader
+                // is missing one of the uniforms that we upload
+                if let Err(err) = rendered {
+                    self.report_asset_error(err);
+                    self.render_placeholder(gl, state, instances, &mesh_opts);
+                }
+            }
+        }
+    }
+
+    /// Group the scene's entities into the batches that we draw them in, reporting every entity
+    /// whose assets we can't render and putting it in a batch of placeholders instead
+    fn batch_meshes<'a>(&self, state: &'a State, assets: &'a Assets) -> Vec<MeshBatch<'a>> {
+        let mut batches: Vec<MeshBatch> = vec![];
 
-        let mesh_name = "Bird";
-        let armature_name = "Armature.001";
-        let bird = SkinnedMesh {
-            mesh: assets.get_mesh(mesh_name).expect("Bird mesh"),
-            armature: assets.get_armature(armature_name).expect("Bird armature"),
-            shader: skinned_shader,
-            opts: &mesh_opts,
+        for node in state.scene_graph().nodes() {
+            let entity = node.entity();
+            let mesh_name = entity.mesh.as_str();
+
+            // Scenes are validated when they're loaded, so an armature always comes with an action
+            let skin = match (entity.armature.as_ref(), node.animation()) {
+                (Some(armature_name), Some(animation)) => Some((armature_name, animation)),
+                _ => None,
+            };
+
+            let renderable = assets
+                .renderable_mesh(mesh_name, skin.is_some())
+                .and_then(|mesh| match skin {
+                    Some((armature_name, animation)) => {
+                        let armature = assets.armature(armature_name)?;
+                        let bone_storage = self
+                            .shader_sys
+                            .bone_storage(armature_name, armature.inverse_bind_poses.len())?;
+
+                        let skin = (armature_name.as_str(), armature, bone_storage);
+                        Ok((Some((mesh_name, mesh)), Some(skin), Some(animation)))
+                    }
+                    None => Ok((Some((mesh_name, mesh)), None, None)),
+                });
+
+            let (mesh, skin, animation) = match renderable {
+                Ok(renderable) => renderable,
+                // Entities whose assets haven't finished downloading yet get skipped
+                Err(AssetError::MissingMesh(_)) | Err(AssetError::MissingArmature(_))
+                    if assets.is_downloading() =>
+                {
+                    continue;
+                }
+                Err(err) => {
+                    self.report_asset_error(err);
+                    (None, None, None)
+                }
+            };
+
+            let instance = MeshInstance {
+                model: *node.world_transform(),
+                animation,
+            };
+            let texture = entity.texture.into();
+
+            let batch = batches.iter_mut().find(|batch| {
+                batch.mesh.map(|(name, _)| name) == mesh.map(|(name, _)| name)
+                    && batch.skin.map(|(name, ..)| name) == skin.map(|(name, ..)| name)
+                    && batch.texture == texture
+            });
+
+            match batch {
+                Some(batch) => batch.instances.push(instance),
+                None => batches.push(MeshBatch {
+                    mesh,
+                    skin,
+                    texture,
+                    instances: vec![instance],
+                }),
+            }
+        }
+
+        batches
+    }
+
+    /// Draw our placeholder cube where entities that we couldn't render would have been
+    fn render_placeholder(
+        &self,
+        gl: &G,
+        state: &State,
+        instances: &[MeshInstance],
+        mesh_opts: &MeshRenderOpts,
+    ) {
+        let no_skin = ShaderKind::NonSkinnedMesh;
+        self.shader_sys.use_program(gl, no_skin);
+
+        let placeholder = NonSkinnedMesh {
+            name: PLACEHOLDER_VAO_KEY,
+            mesh: &self.placeholder_mesh,
+            shader: self.shader_sys.get_shader(&no_skin).unwrap(),
+            instances,
+            instance_buffer: self.instance_buffer.as_ref(),
+            opts: mesh_opts,
         };
 
-        self.prepare_for_render(gl, &bird, mesh_name);
-        bird.render(gl, state);
+        let rendered = self
+            .prepare_for_render(gl, &placeholder, PLACEHOLDER_VAO_KEY)
+            .and_then(|_| placeholder.render(gl, state));
+        if let Err(err) = rendered {
+            self.report_asset_error(err);
+        }
+    }
+
+    /// Remember an asset that we couldn't render this frame, see `WebRenderer::asset_errors`
+    pub(in crate::render) fn report_asset_error(&self, err: AssetError) {
+        let mut asset_errors = self.asset_errors.borrow_mut();
+
+        // We render our meshes more than once per frame, for reflections and refractions
+        if !asset_errors.contains(&err) {
+            asset_errors.push(err);
+        }
+    }
+
+    /// Compile the skinned mesh shader for every armature in the scene that needs one that we
+    /// haven't compiled yet. Each one is generated with room for exactly as many bones as the
+    /// armature has.
+    pub(in crate::render) fn prepare_skinned_shaders(
+        &mut self,
+        gl: &G,
+        state: &State,
+        assets: &Assets,
+    ) {
+        for node in state.scene_graph().nodes() {
+            let entity = node.entity();
+
+            // Armatures that are still downloading get their shader once they arrive
+            let (armature_name, armature) = match entity.armature.as_ref() {
+                Some(name) => match assets.get_armature(name) {
+                    Some(armature) => (name, armature),
+                    None => continue,
+                },
+                None => continue,
+            };
+
+            // Armatures with too many bones get reported when we try to render them. A shader
+            // that the driver rejects makes `bone_storage` try a smaller one next time around.
+            let bone_count = armature.inverse_bind_poses.len();
+            while let Ok(bone_storage) = self.shader_sys.bone_storage(armature_name, bone_count) {
+                if self
+                    .shader_sys
+                    .prepare_skinned_shader(gl, bone_storage)
+                    .is_ok()
+                {
+                    break;
+                }
+            }
+        }
     }
 }
+
+/// A vertex array object's attribute locations come from the program that it was buffered for,
+/// so a mesh that gets drawn with more than one program (ex: a rig that's used both with and
+/// without an armature, or by armatures with different bone counts) needs one per program.
+fn vao_key(mesh_name: &str, shader_kind: ShaderKind) -> String {
+    format!("{} {:?}", mesh_name, shader_kind)
+}
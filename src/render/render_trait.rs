@@ -1,71 +1,208 @@
+use crate::app::AssetError;
+use crate::render::GlContext;
+use crate::render::{MeshInstance, INSTANCE_ATTRIBUTES, INSTANCE_FLOATS};
 use crate::shader::Shader;
 use crate::shader::ShaderKind;
 use crate::State;
-use js_sys::WebAssembly;
-use wasm_bindgen::JsCast;
 use web_sys::WebGlRenderingContext as GL;
 
-pub trait Render<'a> {
-    fn shader_kind() -> ShaderKind;
+pub trait Render<'a, G: GlContext> {
+    fn shader_kind(&self) -> ShaderKind;
 
-    fn shader(&'a self) -> &'a Shader;
+    fn shader(&'a self) -> &'a Shader<G>;
 
-    fn buffer_attributes(&self, gl: &GL);
+    /// Buffer our attributes into the bound vertex array object, filling its `buffers`
+    fn buffer_attributes(&self, gl: &G, buffers: &mut VertexBuffers<G>) -> Result<(), AssetError>;
 
-    fn render(&self, gl: &GL, state: &State);
+    fn render(&self, gl: &G, state: &State) -> Result<(), AssetError>;
 
-    fn buffer_f32_data(gl: &GL, data: &[f32], attrib: u32, size: i32) {
-        let memory_buffer = wasm_bindgen::memory()
-            .dyn_into::<WebAssembly::Memory>()
-            .unwrap()
-            .buffer();
+    fn buffer_f32_data(
+        gl: &G,
+        buffers: &mut VertexBuffers<G>,
+        data: &[f32],
+        attrib: u32,
+        size: i32,
+    ) -> Result<(), AssetError> {
+        let buffer = buffers.next(gl)?;
 
-        let data_location = data.as_ptr() as u32 / 4;
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(buffer));
+        gl.buffer_data_f32(GL::ARRAY_BUFFER, data, GL::STATIC_DRAW);
+        gl.vertex_attrib_pointer_with_i32(attrib, size, GL::FLOAT, false, 0, 0);
 
-        let data_array = js_sys::Float32Array::new(&memory_buffer)
-            .subarray(data_location, data_location + data.len() as u32);
+        Ok(())
+    }
 
-        let buffer = gl.create_buffer().unwrap();
+    fn buffer_u8_data(
+        gl: &G,
+        buffers: &mut VertexBuffers<G>,
+        data: &[u8],
+        attrib: u32,
+        size: i32,
+    ) -> Result<(), AssetError> {
+        let buffer = buffers.next(gl)?;
+
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(buffer));
+        gl.buffer_data_u8(GL::ARRAY_BUFFER, data, GL::STATIC_DRAW);
+        gl.vertex_attrib_pointer_with_i32(attrib, size, GL::UNSIGNED_BYTE, false, 0, 0);
 
-        gl.bind_buffer(GL::ARRAY_BUFFER, Some(&buffer));
-        gl.buffer_data_with_array_buffer_view(GL::ARRAY_BUFFER, &data_array, GL::STATIC_DRAW);
-        gl.vertex_attrib_pointer_with_i32(attrib, size, GL::FLOAT, false, 0, 0);
+        Ok(())
     }
 
-    fn buffer_u8_data(gl: &GL, data: &[u8], attrib: u32, size: i32) {
-        let memory_buffer = wasm_bindgen::memory()
-            .dyn_into::<WebAssembly::Memory>()
-            .unwrap()
-            .buffer();
+    fn buffer_u16_indices(
+        gl: &G,
+        buffers: &mut VertexBuffers<G>,
+        indices: &[u16],
+    ) -> Result<(), AssetError> {
+        let index_buffer = buffers.next(gl)?;
+        gl.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(index_buffer));
+        gl.buffer_data_u16(GL::ELEMENT_ARRAY_BUFFER, indices, GL::STATIC_DRAW);
 
-        let data_location = data.as_ptr() as u32;
+        Ok(())
+    }
 
-        let data_array = js_sys::Uint8Array::new(&memory_buffer)
-            .subarray(data_location, data_location + data.len() as u32);
+    /// Point the program's per-instance attributes at our `instance_buffer`, advancing them once
+    /// per instance instead of once per vertex. This only needs to happen once per vertex array
+    /// object since `draw_instances` refills the same buffer before every draw.
+    ///
+    /// Devices that can't draw instances have no instance buffer, and their shaders have
+    /// uniforms instead of per-instance attributes.
+    fn buffer_instance_attributes(
+        gl: &G,
+        program: &G::Program,
+        instance_buffer: Option<&G::Buffer>,
+    ) {
+        let instance_buffer = match instance_buffer {
+            Some(instance_buffer) => instance_buffer,
+            None => return,
+        };
+
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(instance_buffer));
+
+        let stride = INSTANCE_FLOATS as i32 * 4;
+
+        for (name, locations, size, first_float) in INSTANCE_ATTRIBUTES.iter() {
+            let attrib = gl.get_attrib_location(program, name);
+
+            // Only skinned meshes that read their bones from a texture use `instanceIndex`, so
+            // it gets compiled out of the others
+            if attrib < 0 {
+                continue;
+            }
+
+            for location in 0..*locations {
+                let attrib = attrib as u32 + location;
+                let offset = (first_float + location as i32 * size) * 4;
+
+                gl.enable_vertex_attrib_array(attrib);
+                gl.vertex_attrib_pointer_with_i32(attrib, *size, GL::FLOAT, false, stride, offset);
+                gl.vertex_attrib_divisor_angle(attrib, 1);
+            }
+        }
+    }
 
-        let buffer = gl.create_buffer().unwrap();
+    /// Fill our `instance_buffer` with each instance's model matrix, normal matrix and index,
+    /// then draw all of them with one call.
+    ///
+    /// Without an instance buffer we upload each instance's matrices into the shader's uniforms
+    /// and draw them one at a time instead.
+    fn draw_instances(
+        gl: &G,
+        shader: &Shader<G>,
+        instance_buffer: Option<&G::Buffer>,
+        instances: &[MeshInstance],
+        num_indices: usize,
+    ) -> Result<(), AssetError> {
+        let instance_buffer = match instance_buffer {
+            Some(instance_buffer) => instance_buffer,
+            None => return Self::draw_each_instance(gl, shader, instances, num_indices),
+        };
+
+        let mut data = Vec::with_capacity(instances.len() * INSTANCE_FLOATS);
+
+        for (index, instance) in instances.iter().enumerate() {
+            let (model, normal_matrix) = instance.model_matrices();
+
+            data.extend_from_slice(&model);
+            data.extend_from_slice(&normal_matrix);
+            data.push(index as f32);
+        }
+
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(instance_buffer));
+        gl.buffer_data_f32(GL::ARRAY_BUFFER, &data, GL::DYNAMIC_DRAW);
+
+        gl.draw_elements_instanced_angle_with_i32(
+            GL::TRIANGLES,
+            num_indices as i32,
+            GL::UNSIGNED_SHORT,
+            0,
+            instances.len() as i32,
+        );
 
-        gl.bind_buffer(GL::ARRAY_BUFFER, Some(&buffer));
-        gl.buffer_data_with_array_buffer_view(GL::ARRAY_BUFFER, &data_array, GL::STATIC_DRAW);
-        gl.vertex_attrib_pointer_with_i32(attrib, size, GL::UNSIGNED_BYTE, false, 0, 0);
+        Ok(())
     }
 
-    fn buffer_u16_indices(gl: &GL, indices: &[u16]) {
-        let memory_buffer = wasm_bindgen::memory()
-            .dyn_into::<WebAssembly::Memory>()
-            .unwrap()
-            .buffer();
-
-        let indices_location = indices.as_ptr() as u32 / 2;
-        let indices_array = js_sys::Uint16Array::new(&memory_buffer)
-            .subarray(indices_location, indices_location + indices.len() as u32);
-
-        let index_buffer = gl.create_buffer().unwrap();
-        gl.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(&index_buffer));
-        gl.buffer_data_with_array_buffer_view(
-            GL::ELEMENT_ARRAY_BUFFER,
-            &indices_array,
-            GL::STATIC_DRAW,
-        );
+    /// Draw our instances one at a time, for devices that can't draw them all at once
+    fn draw_each_instance(
+        gl: &G,
+        shader: &Shader<G>,
+        instances: &[MeshInstance],
+        num_indices: usize,
+    ) -> Result<(), AssetError> {
+        let model_uni = shader.get_uniform_location(gl, "model")?;
+        let normal_matrix_uni = shader.get_uniform_location(gl, "normalMatrix")?;
+
+        for instance in instances {
+            let (mut model, mut normal_matrix) = instance.model_matrices();
+
+            gl.uniform_matrix4fv_with_f32_array(Some(&model_uni), false, &mut model);
+            gl.uniform_matrix4fv_with_f32_array(
+                Some(&normal_matrix_uni),
+                false,
+                &mut normal_matrix,
+            );
+
+            gl.draw_elements_with_i32(GL::TRIANGLES, num_indices as i32, GL::UNSIGNED_SHORT, 0);
+        }
+
+        Ok(())
+    }
+}
+
+/// The buffers that a vertex array object's attributes live in.
+///
+/// A renderable whose data changes (ex: a mesh that a later bundle replaced) gets re-buffered
+/// into the same buffers, so that we don't leak a new set every time.
+pub struct VertexBuffers<G: GlContext> {
+    buffers: Vec<G::Buffer>,
+    /// How many of our buffers have been filled since the last `rewind`
+    used: usize,
+}
+
+impl<G: GlContext> VertexBuffers<G> {
+    /// Start refilling our buffers from the first one
+    pub fn rewind(&mut self) {
+        self.used = 0;
+    }
+
+    /// The next buffer to fill, only creating one if we've used up all of the old ones
+    fn next(&mut self, gl: &G) -> Result<&G::Buffer, AssetError> {
+        if self.used == self.buffers.len() {
+            let buffer = gl
+                .create_buffer()
+                .ok_or(AssetError::GpuResource("a vertex buffer"))?;
+            self.buffers.push(buffer);
+        }
+
+        self.used += 1;
+        Ok(&self.buffers[self.used - 1])
+    }
+}
+
+impl<G: GlContext> Default for VertexBuffers<G> {
+    fn default() -> Self {
+        VertexBuffers {
+            buffers: vec![],
+            used: 0,
+        }
     }
 }
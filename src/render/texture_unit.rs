@@ -1,6 +1,7 @@
+use crate::app::MeshTexture;
 use web_sys::WebGlRenderingContext as GL;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum TextureUnit {
     Refraction = 0,
     Reflection = 1,
@@ -8,6 +9,8 @@ pub enum TextureUnit {
     NormalMap = 3,
     RefractionDepth = 4,
     Stone = 5,
+    Ripples = 6,
+    Bones = 7,
 }
 
 impl TextureUnit {
@@ -21,6 +24,8 @@ impl TextureUnit {
             TextureUnit::NormalMap => GL::TEXTURE3,
             TextureUnit::RefractionDepth => GL::TEXTURE4,
             TextureUnit::Stone => GL::TEXTURE5,
+            TextureUnit::Ripples => GL::TEXTURE6,
+            TextureUnit::Bones => GL::TEXTURE7,
         }
     }
 
@@ -29,3 +34,11 @@ impl TextureUnit {
         *self as i32
     }
 }
+
+impl From<MeshTexture> for TextureUnit {
+    fn from(texture: MeshTexture) -> TextureUnit {
+        match texture {
+            MeshTexture::Stone => TextureUnit::Stone,
+        }
+    }
+}
@@ -1,15 +1,16 @@
+use crate::app::AssetError;
 use crate::app::State;
-use crate::canvas::{CANVAS_HEIGHT, CANVAS_WIDTH};
+use crate::render::GlContext;
 use crate::render::Render;
+use crate::render::VertexBuffers;
 use crate::shader::Shader;
 use crate::shader::ShaderKind;
 use web_sys::WebGlRenderingContext as GL;
-use web_sys::*;
 
-pub struct TexturedQuad<'a> {
-    /// Left most part of canvas is 0, rightmost is CANVAS_WIDTH
+pub struct TexturedQuad<'a, G: GlContext> {
+    /// Left most part of canvas is 0, rightmost is the canvas' width
     left: u16,
-    /// Bottom of canvas is 0, top is CANVAS_HEIGHT
+    /// Bottom of canvas is 0, top is the canvas' height
     top: u16,
     /// How many pixels wide
     width: u16,
@@ -18,18 +19,18 @@ pub struct TexturedQuad<'a> {
     /// The texture unit to use
     texture_unit: u8,
     /// The shader to use when rendering
-    shader: &'a Shader,
+    shader: &'a Shader<G>,
 }
 
-impl<'a> TexturedQuad<'a> {
+impl<'a, G: GlContext> TexturedQuad<'a, G> {
     pub fn new(
         left: u16,
         top: u16,
         width: u16,
         height: u16,
         texture_unit: u8,
-        shader: &Shader,
-    ) -> TexturedQuad {
+        shader: &Shader<G>,
+    ) -> TexturedQuad<G> {
         TexturedQuad {
             left,
             top,
@@ -41,54 +42,56 @@ impl<'a> TexturedQuad<'a> {
     }
 }
 
-impl<'a> Render<'a> for TexturedQuad<'a> {
-    fn shader_kind() -> ShaderKind {
+impl<'a, G: GlContext> Render<'a, G> for TexturedQuad<'a, G> {
+    fn shader_kind(&self) -> ShaderKind {
         ShaderKind::TexturedQuad
     }
 
-    fn shader(&'a self) -> &'a Shader {
+    fn shader(&'a self) -> &'a Shader<G> {
         &self.shader
     }
 
-    fn buffer_attributes(&self, gl: &GL) {
+    fn buffer_attributes(&self, gl: &G, buffers: &mut VertexBuffers<G>) -> Result<(), AssetError> {
         let shader = self.shader();
 
-        let vertex_data = self.make_textured_quad_vertices(CANVAS_WIDTH, CANVAS_HEIGHT);
+        let vertex_data = self.make_textured_quad_vertices();
 
         let vertex_data_attrib = gl.get_attrib_location(&shader.program, "vertexData");
         gl.enable_vertex_attrib_array(vertex_data_attrib as u32);
 
-        TexturedQuad::buffer_f32_data(&gl, &vertex_data[..], vertex_data_attrib as u32, 4);
+        Self::buffer_f32_data(gl, buffers, &vertex_data[..], vertex_data_attrib as u32, 4)?;
+
+        Ok(())
     }
 
-    fn render(&self, gl: &WebGlRenderingContext, _state: &State) {
+    fn render(&self, gl: &G, _state: &State) -> Result<(), AssetError> {
+        gl.marker(format_args!("TexturedQuad::render"));
+
         let shader = self.shader();
 
-        gl.uniform1i(
-            shader.get_uniform_location(gl, "texture").as_ref(),
-            self.texture_unit as i32,
+        // Our vertices fill the whole viewport, so we shrink the viewport down to our quad.
+        // This way our (cached) vertex data doesn't change when the canvas is resized.
+        gl.viewport(
+            self.left as i32,
+            self.top as i32 - self.height as i32,
+            self.width as i32,
+            self.height as i32,
         );
 
+        let texture_uni = shader.get_uniform_location(gl, "texture")?;
+        gl.uniform1i(Some(&texture_uni), self.texture_unit as i32);
+
         gl.draw_arrays(GL::TRIANGLES, 0, 6);
+
+        Ok(())
     }
 }
 
-impl<'a> TexturedQuad<'a> {
+impl<'a, G: GlContext> TexturedQuad<'a, G> {
     // Combine our vertex data so that we can pass one array to the GPU
-    fn make_textured_quad_vertices(&self, viewport_width: i32, viewport_height: i32) -> Vec<f32> {
-        let viewport_width = viewport_width as f32;
-        let viewport_height = viewport_height as f32;
-
-        let left_x = self.left as f32 / viewport_width;
-        let top_y = self.top as f32 / viewport_height;
-        let right_x = (self.left as f32 + self.width as f32) / viewport_width;
-        let bottom_y = (self.top as f32 - self.height as f32) / viewport_height;
-
-        let left_x = 2.0 * left_x - 1.0;
-        let right_x = 2.0 * right_x - 1.0;
-
-        let bottom_y = 2.0 * bottom_y - 1.0;
-        let top_y = 2.0 * top_y - 1.0;
+    fn make_textured_quad_vertices(&self) -> Vec<f32> {
+        let (left_x, right_x) = (-1.0, 1.0);
+        let (bottom_y, top_y) = (-1.0, 1.0);
 
         // All of the positions of our quad in screen space
         let positions = [
@@ -1,127 +1,232 @@
+use crate::app::AssetError;
 use crate::app::State;
+use crate::app::WaterBody;
+use crate::app::MAX_WAVES;
+use crate::render::GlContext;
 use crate::render::Render;
 use crate::render::TextureUnit;
+use crate::render::VertexBuffers;
 use crate::shader::Shader;
 use crate::shader::ShaderKind;
 use nalgebra;
 use nalgebra::{Isometry3, Matrix4, Vector3};
 use web_sys::WebGlRenderingContext as GL;
-use web_sys::*;
 
-pub struct RenderableWaterTile<'a> {
-    shader: &'a Shader,
+/// The most quads along each side of our water grid. Our grid is indexed with u16s, so it can't
+/// have more than 65536 vertices.
+pub const MAX_WATER_GRID_RESOLUTION: u16 = 255;
+
+pub struct RenderableWaterTile<'a, G: GlContext> {
+    shader: &'a Shader<G>,
+    water_body: &'a WaterBody,
+    /// The number of quads along each side of our water grid, see `Water::grid_resolution`
+    grid_resolution: u16,
 }
 
-impl<'a> RenderableWaterTile<'a> {
-    pub fn new(shader: &'a Shader) -> RenderableWaterTile<'a> {
-        RenderableWaterTile { shader }
+impl<'a, G: GlContext> RenderableWaterTile<'a, G> {
+    pub fn new(
+        shader: &'a Shader<G>,
+        water_body: &'a WaterBody,
+        grid_resolution: u16,
+    ) -> RenderableWaterTile<'a, G> {
+        RenderableWaterTile {
+            shader,
+            water_body,
+            grid_resolution: grid_resolution.max(1).min(MAX_WATER_GRID_RESOLUTION),
+        }
+    }
+
+    /// Our vertices depend on the grid's resolution, so each resolution gets its own vertex array
+    pub fn vao_key(&self) -> String {
+        format!("water-{}", self.grid_resolution)
     }
 }
 
-impl<'a> Render<'a> for RenderableWaterTile<'a> {
-    fn shader_kind() -> ShaderKind {
+impl<'a, G: GlContext> Render<'a, G> for RenderableWaterTile<'a, G> {
+    fn shader_kind(&self) -> ShaderKind {
         ShaderKind::Water
     }
 
-    fn shader(&'a self) -> &'a Shader {
+    fn shader(&'a self) -> &'a Shader<G> {
         &self.shader
     }
 
-    fn buffer_attributes(&self, gl: &WebGlRenderingContext) {
+    fn buffer_attributes(&self, gl: &G, buffers: &mut VertexBuffers<G>) -> Result<(), AssetError> {
         let shader = self.shader();
 
         let pos_attrib = gl.get_attrib_location(&shader.program, "position");
         gl.enable_vertex_attrib_array(pos_attrib as u32);
 
-        // These vertices are the x and z values that create a flat square tile on the `y = 0`
-        // plane. In our render function we'll scale this quad into the water size that we want.
-        // x and z values, y is omitted since this is a flat surface. We set it in the vertex shader
-        let vertices: [f32; 8] = [
-            -0.5, 0.5, // Bottom Left
-            0.5, 0.5, // Bottom Right
-            0.5, -0.5, // Top Right
-            -0.5, -0.5, // Top Left
-        ];
-
-        let mut indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
-
-        RenderableWaterTile::buffer_f32_data(&gl, &vertices, pos_attrib as u32, 2);
-        RenderableWaterTile::buffer_u16_indices(&gl, &mut indices);
+        // These vertices are the x and z values of a grid of quads that make up a flat square
+        // tile on the `y = 0` plane. In our render function we'll scale this grid into the water
+        // size that we want. y is omitted since the vertex shader displaces it with our waves.
+        let resolution = self.grid_resolution;
+        let verts_per_side = resolution + 1;
+
+        let mut vertices = vec![];
+        for row in 0..verts_per_side {
+            for col in 0..verts_per_side {
+                vertices.push(col as f32 / resolution as f32 - 0.5);
+                vertices.push(row as f32 / resolution as f32 - 0.5);
+            }
+        }
+
+        let mut indices = vec![];
+        for row in 0..resolution {
+            for col in 0..resolution {
+                let top_left = row * verts_per_side + col;
+                let top_right = top_left + 1;
+                let bottom_left = top_left + verts_per_side;
+                let bottom_right = bottom_left + 1;
+
+                indices.extend_from_slice(&[
+                    bottom_left,
+                    bottom_right,
+                    top_right,
+                    bottom_left,
+                    top_right,
+                    top_left,
+                ]);
+            }
+        }
+
+        Self::buffer_f32_data(gl, buffers, &vertices, pos_attrib as u32, 2)?;
+        Self::buffer_u16_indices(gl, buffers, &indices)?;
+
+        Ok(())
     }
 
-    fn render(&self, gl: &WebGlRenderingContext, state: &State) {
+    fn render(&self, gl: &G, state: &State) -> Result<(), AssetError> {
+        gl.marker(format_args!("RenderableWaterTile::render"));
+
         let shader = self.shader();
 
-        let model_uni = shader.get_uniform_location(gl, "model");
-        let view_uni = shader.get_uniform_location(gl, "view");
-        let refraction_texture_uni = shader.get_uniform_location(gl, "refractionTexture");
-        let reflection_texture_uni = shader.get_uniform_location(gl, "reflectionTexture");
-        let dudv_texture_uni = shader.get_uniform_location(gl, "dudvTexture");
-        let normal_map_uni = shader.get_uniform_location(gl, "normalMap");
-        let water_depth_texture_uni = shader.get_uniform_location(gl, "waterDepthTexture");
-        let dudv_offset_uni = shader.get_uniform_location(gl, "dudvOffset");
-        let camera_pos_uni = shader.get_uniform_location(gl, "cameraPos");
-        let perspective_uni = shader.get_uniform_location(gl, "perspective");
-        let water_reflectivity_uni = shader.get_uniform_location(gl, "waterReflectivity");
-        let fresnel_strength_unit = shader.get_uniform_location(gl, "fresnelStrength");
-
-        let pos = (0., 0.0, 0.);
-
-        let x_scale = 18.;
-        let z_scale = 18.;
+        let model_uni = shader.get_uniform_location(gl, "model")?;
+        let view_uni = shader.get_uniform_location(gl, "view")?;
+        let refraction_texture_uni = shader.get_uniform_location(gl, "refractionTexture")?;
+        let reflection_texture_uni = shader.get_uniform_location(gl, "reflectionTexture")?;
+        let dudv_texture_uni = shader.get_uniform_location(gl, "dudvTexture")?;
+        let normal_map_uni = shader.get_uniform_location(gl, "normalMap")?;
+        let water_depth_texture_uni = shader.get_uniform_location(gl, "waterDepthTexture")?;
+        let ripple_texture_uni = shader.get_uniform_location(gl, "rippleTexture")?;
+        let dudv_offset_uni = shader.get_uniform_location(gl, "dudvOffset")?;
+        let camera_pos_uni = shader.get_uniform_location(gl, "cameraPos")?;
+        let perspective_uni = shader.get_uniform_location(gl, "perspective")?;
+        let water_reflectivity_uni = shader.get_uniform_location(gl, "waterReflectivity")?;
+        let fresnel_strength_unit = shader.get_uniform_location(gl, "fresnelStrength")?;
+        let seconds_uni = shader.get_uniform_location(gl, "seconds")?;
+        let use_specular_uni = shader.get_uniform_location(gl, "useSpecular")?;
+        let use_depth_tint_uni = shader.get_uniform_location(gl, "useDepthTint")?;
+        let camera_near_uni = shader.get_uniform_location(gl, "cameraNear")?;
+        let camera_far_uni = shader.get_uniform_location(gl, "cameraFar")?;
+
+        let water_body = self.water_body;
+        let pos = (
+            water_body.position.0,
+            water_body.height,
+            water_body.position.1,
+        );
+
+        let (x_scale, z_scale) = water_body.extents;
         let scale = Matrix4::new_nonuniform_scaling(&Vector3::new(x_scale, 1.0, z_scale));
 
         let model = Isometry3::new(Vector3::new(pos.0, pos.1, pos.2), nalgebra::zero());
         let model = model.to_homogeneous();
-        let model = scale * model;
+        // Scale the unit tile first so that the translation isn't scaled along with it
+        let model = model * scale;
         let mut model_array = [0.; 16];
         model_array.copy_from_slice(model.as_slice());
-        gl.uniform_matrix4fv_with_f32_array(model_uni.as_ref(), false, &mut model_array);
+        gl.uniform_matrix4fv_with_f32_array(Some(&model_uni), false, &mut model_array);
 
         let mut view = state.camera().view();
-        gl.uniform_matrix4fv_with_f32_array(view_uni.as_ref(), false, &mut view);
+        gl.uniform_matrix4fv_with_f32_array(Some(&view_uni), false, &mut view);
 
         gl.uniform1i(
-            refraction_texture_uni.as_ref(),
+            Some(&refraction_texture_uni),
             TextureUnit::Refraction.texture_unit(),
         );
         gl.uniform1i(
-            reflection_texture_uni.as_ref(),
+            Some(&reflection_texture_uni),
             TextureUnit::Reflection.texture_unit(),
         );
-        gl.uniform1i(dudv_texture_uni.as_ref(), TextureUnit::Dudv.texture_unit());
+        gl.uniform1i(Some(&dudv_texture_uni), TextureUnit::Dudv.texture_unit());
+        gl.uniform1i(Some(&normal_map_uni), TextureUnit::NormalMap.texture_unit());
         gl.uniform1i(
-            normal_map_uni.as_ref(),
-            TextureUnit::NormalMap.texture_unit(),
+            Some(&water_depth_texture_uni),
+            TextureUnit::RefractionDepth.texture_unit(),
         );
         gl.uniform1i(
-            water_depth_texture_uni.as_ref(),
-            TextureUnit::RefractionDepth.texture_unit(),
+            Some(&ripple_texture_uni),
+            TextureUnit::Ripples.texture_unit(),
         );
 
-        gl.uniform1f(water_reflectivity_uni.as_ref(), state.water().reflectivity);
+        gl.uniform1f(Some(&water_reflectivity_uni), state.water().reflectivity);
 
-        gl.uniform1f(
-            fresnel_strength_unit.as_ref(),
-            state.water().fresnel_strength,
+        gl.uniform1f(Some(&fresnel_strength_unit), state.water().fresnel_strength);
+
+        gl.uniform1i(Some(&use_specular_uni), state.water().use_specular as i32);
+        gl.uniform1i(
+            Some(&use_depth_tint_uni),
+            state.water().use_depth_tint as i32,
         );
 
         let seconds_elapsed = state.clock() / 1000.;
         let dudv_offset = (state.water().wave_speed * seconds_elapsed) % 1.;
-        gl.uniform1f(dudv_offset_uni.as_ref(), dudv_offset);
+        gl.uniform1f(Some(&dudv_offset_uni), dudv_offset);
+
+        gl.uniform1f(Some(&seconds_uni), seconds_elapsed);
+        self.set_wave_uniforms(gl, state)?;
 
         let camera_pos = state.camera().get_eye_pos();
         let mut camera_pos = [camera_pos.x, camera_pos.y, camera_pos.z];
-        gl.uniform3fv_with_f32_array(camera_pos_uni.as_ref(), &mut camera_pos);
+        gl.uniform3fv_with_f32_array(Some(&camera_pos_uni), &mut camera_pos);
+
+        let camera_config = state.camera().config();
+        gl.uniform1f(Some(&camera_near_uni), camera_config.near);
+        gl.uniform1f(Some(&camera_far_uni), camera_config.far);
 
         let mut perspective = state.camera().projection();
-        gl.uniform_matrix4fv_with_f32_array(perspective_uni.as_ref(), false, &mut perspective);
+        gl.uniform_matrix4fv_with_f32_array(Some(&perspective_uni), false, &mut perspective);
 
         gl.enable(GL::BLEND);
         gl.blend_func(GL::SRC_ALPHA, GL::ONE_MINUS_SRC_ALPHA);
 
-        gl.draw_elements_with_i32(GL::TRIANGLES, 6, GL::UNSIGNED_SHORT, 0);
+        let num_indices = self.grid_resolution as i32 * self.grid_resolution as i32 * 6;
+        gl.draw_elements_with_i32(GL::TRIANGLES, num_indices, GL::UNSIGNED_SHORT, 0);
 
         gl.disable(GL::BLEND);
+
+        Ok(())
+    }
+}
+
+impl<'a, G: GlContext> RenderableWaterTile<'a, G> {
+    fn set_wave_uniforms(&self, gl: &G, state: &State) -> Result<(), AssetError> {
+        let shader = self.shader();
+        let waves = &state.water().waves;
+        let wave_count = waves.len().min(MAX_WAVES);
+
+        let wave_count_uni = shader.get_uniform_location(gl, "waveCount")?;
+        gl.uniform1i(Some(&wave_count_uni), wave_count as i32);
+
+        for (index, wave) in waves.iter().take(wave_count).enumerate() {
+            let amplitude_uni =
+                shader.get_uniform_location(gl, &format!("waves[{}].amplitude", index))?;
+            let wavelength_uni =
+                shader.get_uniform_location(gl, &format!("waves[{}].wavelength", index))?;
+            let direction_uni =
+                shader.get_uniform_location(gl, &format!("waves[{}].direction", index))?;
+            let steepness_uni =
+                shader.get_uniform_location(gl, &format!("waves[{}].steepness", index))?;
+
+            let direction = wave.normalized_direction();
+
+            gl.uniform1f(Some(&amplitude_uni), wave.amplitude);
+            gl.uniform1f(Some(&wavelength_uni), wave.wavelength);
+            gl.uniform2f(Some(&direction_uni), direction.0, direction.1);
+            gl.uniform1f(Some(&steepness_uni), wave.steepness);
+        }
+
+        Ok(())
     }
 }
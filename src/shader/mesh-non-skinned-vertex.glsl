@@ -4,7 +4,17 @@ attribute vec3 normal;
 attribute vec2 uvs;
 varying vec2 vUvs;
 
+// `ShaderSystem` defines INSTANCED above this line on devices that can draw many instances of a
+// mesh at once, in which case every instance has its own model matrix, see `MeshInstance`.
+// Otherwise we draw one instance at a time and upload its model matrix as a uniform.
+#ifdef INSTANCED
+attribute mat4 model;
+// The inverse transpose of our model matrix, so that scaling doesn't skew our normals
+attribute mat4 normalMatrix;
+#else
 uniform mat4 model;
+uniform mat4 normalMatrix;
+#endif
 uniform mat4 view;
 uniform mat4 perspective;
 
@@ -20,7 +30,7 @@ void main (void) {
 
   gl_Position = perspective * view * worldPosition;
 
-  vNormal = normal;
+  vNormal = (normalMatrix * vec4(normal, 0.0)).xyz;
   vWorldPos = worldPosition.xyz;
   fromFragmentToCamera = cameraPos - worldPosition.xyz;
 
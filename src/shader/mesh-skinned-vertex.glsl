@@ -4,7 +4,17 @@ attribute vec3 normal;
 attribute vec2 uvs;
 varying vec2 vUvs;
 
+// `ShaderSystem` defines INSTANCED above this line on devices that can draw many instances of a
+// mesh at once, in which case every instance has its own model matrix, see `MeshInstance`.
+// Otherwise we draw one instance at a time and upload its model matrix as a uniform.
+#ifdef INSTANCED
+attribute mat4 model;
+// The inverse transpose of our model matrix, so that scaling doesn't skew our normals
+attribute mat4 normalMatrix;
+#else
 uniform mat4 model;
+uniform mat4 normalMatrix;
+#endif
 uniform mat4 view;
 uniform mat4 perspective;
 
@@ -20,20 +30,55 @@ uniform vec4 clipPlane;
 attribute vec4 jointIndices;
 attribute vec4 jointWeights;
 
-uniform vec4 boneRotQuaternions[15];
-uniform vec4 boneTransQuaternions[15];
+// `ShaderSystem` also defines either BONE_COUNT or BONE_TEXTURE above this line, depending on
+// whether or not the armature's bones fit in our uniforms.
+#ifdef BONE_TEXTURE
+// One row per bone and two columns per instance. Each instance's bone has its rotation
+// quaternion in the first of the instance's columns and its translation quaternion in the second.
+uniform sampler2D boneTexture;
+uniform float boneCount;
+uniform float instanceCount;
+
+// Which of the instances in the draw call we are
+#ifdef INSTANCED
+attribute float instanceIndex;
+#else
+const float instanceIndex = 0.0;
+#endif
+
+vec4 boneRotQuaternion(float index) {
+  float column = (instanceIndex * 2.0 + 0.5) / (instanceCount * 2.0);
+  return texture2D(boneTexture, vec2(column, (index + 0.5) / boneCount));
+}
+
+vec4 boneTransQuaternion(float index) {
+  float column = (instanceIndex * 2.0 + 1.5) / (instanceCount * 2.0);
+  return texture2D(boneTexture, vec2(column, (index + 0.5) / boneCount));
+}
+#else
+uniform vec4 boneRotQuaternions[BONE_COUNT];
+uniform vec4 boneTransQuaternions[BONE_COUNT];
+
+vec4 boneRotQuaternion(float index) {
+  return boneRotQuaternions[int(index)];
+}
+
+vec4 boneTransQuaternion(float index) {
+  return boneTransQuaternions[int(index)];
+}
+#endif
 
 void main (void) {
   // Blend our dual quaternion
-  vec4 weightedRotQuats = boneRotQuaternions[int(jointIndices.x)] * jointWeights.x +
-    boneRotQuaternions[int(jointIndices.y)] * jointWeights.y +
-    boneRotQuaternions[int(jointIndices.z)] * jointWeights.z +
-    boneRotQuaternions[int(jointIndices.w)] * jointWeights.w;
+  vec4 weightedRotQuats = boneRotQuaternion(jointIndices.x) * jointWeights.x +
+    boneRotQuaternion(jointIndices.y) * jointWeights.y +
+    boneRotQuaternion(jointIndices.z) * jointWeights.z +
+    boneRotQuaternion(jointIndices.w) * jointWeights.w;
 
-  vec4 weightedTransQuats = boneTransQuaternions[int(jointIndices.x)] * jointWeights.x +
-    boneTransQuaternions[int(jointIndices.y)] * jointWeights.y +
-    boneTransQuaternions[int(jointIndices.z)] * jointWeights.z +
-    boneTransQuaternions[int(jointIndices.w)] * jointWeights.w;
+  vec4 weightedTransQuats = boneTransQuaternion(jointIndices.x) * jointWeights.x +
+    boneTransQuaternion(jointIndices.y) * jointWeights.y +
+    boneTransQuaternion(jointIndices.z) * jointWeights.z +
+    boneTransQuaternion(jointIndices.w) * jointWeights.w;
 
   // Normalize our dual quaternion (necessary for nlerp)
   float wRot = weightedRotQuats[0];
@@ -107,7 +152,7 @@ void main (void) {
   gl_Position = perspective * view * leftWorldSpace;
 
   shouldClip = dot(leftWorldSpace, clipPlane) < 0.0 ? 1.0 : 0.0;
-  vNormal = transformedNormal;
+  vNormal = (normalMatrix * vec4(transformedNormal, 0.0)).xyz;
   vWorldPos = leftWorldSpace.xyz;
   vUvs = uvs;
   fromFragmentToCamera = cameraPos - leftWorldSpace.xyz;
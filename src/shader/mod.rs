@@ -1,59 +1,190 @@
+use crate::app::AssetError;
+use crate::render::GlContext;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
-use web_sys::*;
+use web_sys::WebGlRenderingContext as GL;
 
-static TEXTURED_QUAD_VS: &'static str = include_str!("./textured-quad-vertex.glsl");
-static TEXTURED_QUAD_FS: &'static str = include_str!("./textured-quad-fragment.glsl");
+pub(crate) static TEXTURED_QUAD_VS: &'static str = include_str!("./textured-quad-vertex.glsl");
+pub(crate) static TEXTURED_QUAD_FS: &'static str = include_str!("./textured-quad-fragment.glsl");
 
-static MESH_SKINNED_VS: &'static str = include_str!("./mesh-skinned-vertex.glsl");
-static MESH_SKINNED_FS: &'static str = include_str!("./mesh-skinned-fragment.glsl");
+pub(crate) static MESH_SKINNED_VS: &'static str = include_str!("./mesh-skinned-vertex.glsl");
+pub(crate) static MESH_SKINNED_FS: &'static str = include_str!("./mesh-skinned-fragment.glsl");
 
-static MESH_NON_SKINNED_VS: &'static str = include_str!("./mesh-non-skinned-vertex.glsl");
-static MESH_NON_SKINNED_FS: &'static str = include_str!("./mesh-non-skinned-fragment.glsl");
+pub(crate) static MESH_NON_SKINNED_VS: &'static str =
+    include_str!("./mesh-non-skinned-vertex.glsl");
+pub(crate) static MESH_NON_SKINNED_FS: &'static str =
+    include_str!("./mesh-non-skinned-fragment.glsl");
 
-static WATER_VS: &'static str = include_str!("./water-vertex.glsl");
-static WATER_FS: &'static str = include_str!("./water-fragment.glsl");
+pub(crate) static WATER_VS: &'static str = include_str!("./water-vertex.glsl");
+pub(crate) static WATER_FS: &'static str = include_str!("./water-fragment.glsl");
+
+/// The vec4 uniforms in mesh-skinned-vertex.glsl that aren't bones: `view` and `perspective`
+/// take four each, plus `cameraPos` and `clipPlane`.
+const SKINNED_NON_BONE_UNIFORM_VECTORS: i32 = 10;
+
+/// The vec4 uniforms that `model` and `normalMatrix` take up when we can't draw instances and
+/// upload them as uniforms instead
+const PER_ENTITY_UNIFORM_VECTORS: i32 = 8;
 
 /// Powers retrieving and using our shaders
-pub struct ShaderSystem {
-    programs: HashMap<ShaderKind, Shader>,
+pub struct ShaderSystem<G: GlContext> {
+    programs: HashMap<ShaderKind, Shader<G>>,
     active_program: RefCell<ShaderKind>,
+    /// The most bones that fit in the skinned mesh shader's uniforms on this device
+    max_uniform_bones: usize,
+    /// Whether or not this device can read bones from a float texture in a vertex shader
+    bone_textures: bool,
+    /// The widest and tallest texture that this device can allocate, which limits how many
+    /// bones and instances fit in a bone texture
+    max_texture_size: usize,
+    /// Whether our mesh shaders read each instance's model matrix from a per-instance attribute
+    /// or from a uniform, see `WebRenderer::new`
+    instanced: bool,
 }
 
-impl ShaderSystem {
-    /// Create  a new ShaderSystem
-    pub fn new(gl: &WebGlRenderingContext) -> ShaderSystem {
+impl<G: GlContext> ShaderSystem<G> {
+    /// Create  a new ShaderSystem. `instanced` is whether or not the device can draw many
+    /// instances of a mesh with one call.
+    ///
+    /// Fails if the device can't compile one of the shaders that every scene needs.
+    pub fn new(gl: &G, instanced: bool) -> Result<ShaderSystem<G>, String> {
         let mut programs = HashMap::new();
 
-        let water_shader = Shader::new(&gl, WATER_VS, WATER_FS).unwrap();
-        let non_skinned_shader =
-            Shader::new(&gl, MESH_NON_SKINNED_VS, MESH_NON_SKINNED_FS).unwrap();
-        let skinned_mesh_shader = Shader::new(&gl, MESH_SKINNED_VS, MESH_SKINNED_FS).unwrap();
-        let textured_quad_shader = Shader::new(&gl, TEXTURED_QUAD_VS, TEXTURED_QUAD_FS).unwrap();
+        let water_shader = Shader::new(gl, ShaderKind::Water, WATER_VS, WATER_FS)?;
+        let non_skinned_shader = Shader::new(
+            gl,
+            ShaderKind::NonSkinnedMesh,
+            &mesh_vertex_shader(MESH_NON_SKINNED_VS, instanced),
+            MESH_NON_SKINNED_FS,
+        )?;
+        let textured_quad_shader = Shader::new(
+            gl,
+            ShaderKind::TexturedQuad,
+            TEXTURED_QUAD_VS,
+            TEXTURED_QUAD_FS,
+        )?;
 
         let active_program = RefCell::new(ShaderKind::TexturedQuad);
         gl.use_program(Some(&textured_quad_shader.program));
 
         programs.insert(ShaderKind::Water, water_shader);
         programs.insert(ShaderKind::NonSkinnedMesh, non_skinned_shader);
-        programs.insert(ShaderKind::SkinnedMesh, skinned_mesh_shader);
         programs.insert(ShaderKind::TexturedQuad, textured_quad_shader);
 
-        ShaderSystem {
+        let non_bone_uniform_vectors = if instanced {
+            SKINNED_NON_BONE_UNIFORM_VECTORS
+        } else {
+            SKINNED_NON_BONE_UNIFORM_VECTORS + PER_ENTITY_UNIFORM_VECTORS
+        };
+
+        // Every bone takes two vec4's, one for each half of its dual quaternion
+        let max_uniform_bones =
+            gl.get_parameter_i32(GL::MAX_VERTEX_UNIFORM_VECTORS) - non_bone_uniform_vectors;
+        let max_uniform_bones = max_uniform_bones.max(0) as usize / 2;
+
+        let bone_textures = gl.get_parameter_i32(GL::MAX_VERTEX_TEXTURE_IMAGE_UNITS) > 0
+            && gl.enable_extension("OES_texture_float");
+        let max_texture_size = gl.get_parameter_i32(GL::MAX_TEXTURE_SIZE).max(0) as usize;
+
+        Ok(ShaderSystem {
             programs,
             active_program,
+            max_uniform_bones,
+            bone_textures,
+            max_texture_size,
+            instanced,
+        })
+    }
+
+    /// Where the skinned mesh shader should read the `bone_count` bones of the armature named
+    /// `armature_name` from.
+    ///
+    /// We prefer uniforms since not every device can sample textures in a vertex shader. A rig
+    /// with more bones than fit in our uniforms on a device that can't use a texture either, or
+    /// with more bones than a texture is tall, can't be skinned, so its entities get drawn as
+    /// placeholders.
+    pub fn bone_storage(
+        &self,
+        armature_name: &str,
+        bone_count: usize,
+    ) -> Result<BoneStorage, AssetError> {
+        // GLSL doesn't allow arrays with no elements
+        let uniform_bones = bone_count.max(1);
+        if uniform_bones <= self.max_uniform_bones {
+            return Ok(BoneStorage::Uniforms(uniform_bones));
+        }
+
+        // Every bone gets its own row of the bone texture
+        let max_texture_bones = if self.bone_textures {
+            self.max_texture_size
+        } else {
+            0
+        };
+
+        if bone_count <= max_texture_bones {
+            return Ok(BoneStorage::Texture);
+        }
+
+        Err(AssetError::TooManyBones {
+            armature: armature_name.to_string(),
+            bone_count,
+            max_bones: self.max_uniform_bones.max(max_texture_bones),
+        })
+    }
+
+    /// How many instances' bones fit side by side in a bone texture, with two columns each
+    pub fn max_bone_texture_instances(&self) -> usize {
+        (self.max_texture_size / 2).max(1)
+    }
+
+    /// Compile the skinned mesh shader for a `BoneStorage` if we haven't already.
+    ///
+    /// A driver can still reject the shader that we generate, ex: if it can't actually fit as
+    /// many bones in its uniforms as it claims. We stop using that `BoneStorage` after it fails,
+    /// so `bone_storage` falls back to a smaller one.
+    pub fn prepare_skinned_shader(
+        &mut self,
+        gl: &G,
+        bone_storage: BoneStorage,
+    ) -> Result<(), String> {
+        let shader_kind = ShaderKind::SkinnedMesh(bone_storage);
+
+        if self.programs.contains_key(&shader_kind) {
+            return Ok(());
         }
+
+        let vertex_shader = skinned_vertex_shader(bone_storage, self.instanced);
+        let skinned_mesh_shader =
+            match Shader::new(gl, shader_kind, &vertex_shader, MESH_SKINNED_FS) {
+                Ok(skinned_mesh_shader) => skinned_mesh_shader,
+                Err(err) => {
+                    match bone_storage {
+                        BoneStorage::Uniforms(bone_count) => {
+                            self.max_uniform_bones = self.max_uniform_bones.min(bone_count - 1);
+                        }
+                        BoneStorage::Texture => self.bone_textures = false,
+                    };
+
+                    return Err(err);
+                }
+            };
+
+        self.programs.insert(shader_kind, skinned_mesh_shader);
+
+        Ok(())
     }
 
     /// Get one of our Shader's
-    pub fn get_shader(&self, shader_kind: &ShaderKind) -> Option<&Shader> {
+    pub fn get_shader(&self, shader_kind: &ShaderKind) -> Option<&Shader<G>> {
         self.programs.get(shader_kind)
     }
 
     /// Use a shader program. We cache the last used shader program to avoid unnecessary
     /// calls to the GPU.
-    pub fn use_program(&self, gl: &WebGlRenderingContext, shader_kind: ShaderKind) {
+    pub fn use_program(&self, gl: &G, shader_kind: ShaderKind) {
+        gl.marker(format_args!("use_program {:?}", shader_kind));
+
         if *self.active_program.borrow() == shader_kind {
             return;
         }
@@ -68,26 +199,62 @@ impl ShaderSystem {
 pub enum ShaderKind {
     Water,
     NonSkinnedMesh,
-    SkinnedMesh,
+    SkinnedMesh(BoneStorage),
     TexturedQuad,
 }
 
+/// Where the skinned mesh shader reads the bones' dual quaternions from
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum BoneStorage {
+    /// `boneRotQuaternions` and `boneTransQuaternions` uniform arrays with room for this many
+    /// bones
+    Uniforms(usize),
+    /// A float texture, for rigs with more bones than fit in our uniforms
+    Texture,
+}
+
+/// One of our mesh vertex shaders, with the `#define` that makes it read its model matrix from a
+/// per-instance attribute if we can draw instances
+fn mesh_vertex_shader(source: &str, instanced: bool) -> String {
+    if instanced {
+        format!("#define INSTANCED\n{}", source)
+    } else {
+        source.to_string()
+    }
+}
+
+/// mesh-skinned-vertex.glsl with the `#define`s that pick where it reads its model matrix and
+/// bones from
+pub(crate) fn skinned_vertex_shader(bone_storage: BoneStorage, instanced: bool) -> String {
+    let define = match bone_storage {
+        BoneStorage::Uniforms(bone_count) => format!("#define BONE_COUNT {}", bone_count),
+        BoneStorage::Texture => "#define BONE_TEXTURE".to_string(),
+    };
+
+    format!(
+        "{}\n{}",
+        define,
+        mesh_vertex_shader(MESH_SKINNED_VS, instanced)
+    )
+}
+
 /// One per ShaderKind
-pub struct Shader {
-    pub program: WebGlProgram,
-    uniforms: RefCell<HashMap<String, WebGlUniformLocation>>,
+pub struct Shader<G: GlContext> {
+    pub program: G::Program,
+    uniforms: RefCell<HashMap<String, G::UniformLocation>>,
 }
 
-impl Shader {
+impl<G: GlContext> Shader<G> {
     /// Create a new Shader program from a vertex and fragment shader
     fn new(
-        gl: &WebGlRenderingContext,
+        gl: &G,
+        shader_kind: ShaderKind,
         vert_shader: &str,
         frag_shader: &str,
-    ) -> Result<Shader, JsValue> {
-        let vert_shader = compile_shader(&gl, WebGlRenderingContext::VERTEX_SHADER, vert_shader)?;
-        let frag_shader = compile_shader(&gl, WebGlRenderingContext::FRAGMENT_SHADER, frag_shader)?;
-        let program = link_program(&gl, &vert_shader, &frag_shader)?;
+    ) -> Result<Shader<G>, String> {
+        let vert_shader = compile_shader(gl, GL::VERTEX_SHADER, vert_shader)?;
+        let frag_shader = compile_shader(gl, GL::FRAGMENT_SHADER, frag_shader)?;
+        let program = link_program(gl, shader_kind, &vert_shader, &frag_shader)?;
 
         let uniforms = RefCell::new(HashMap::new());
 
@@ -99,40 +266,37 @@ impl Shader {
     /// we won't need to query the shader program.
     pub fn get_uniform_location(
         &self,
-        gl: &WebGlRenderingContext,
+        gl: &G,
         uniform_name: &str,
-    ) -> Option<WebGlUniformLocation> {
+    ) -> Result<G::UniformLocation, AssetError> {
         let mut uniforms = self.uniforms.borrow_mut();
 
-        if uniforms.get(uniform_name).is_none() {
-            uniforms.insert(
-                uniform_name.to_string(),
-                gl.get_uniform_location(&self.program, uniform_name)
-                    .expect(&format!(r#"Uniform '{}' not found"#, uniform_name)),
-            );
+        if let Some(location) = uniforms.get(uniform_name) {
+            return Ok(location.clone());
         }
 
-        Some(uniforms.get(uniform_name).expect("loc").clone())
+        let location = gl
+            .get_uniform_location(&self.program, uniform_name)
+            .ok_or_else(|| AssetError::MissingUniform(uniform_name.to_string()))?;
+        uniforms.insert(uniform_name.to_string(), location.clone());
+
+        Ok(location)
     }
 }
 
-/// Create a shader program using the WebGL APIs
-fn compile_shader(
-    gl: &WebGlRenderingContext,
+/// Compile a shader using our GlContext
+fn compile_shader<G: GlContext>(
+    gl: &G,
     shader_type: u32,
     source: &str,
-) -> Result<WebGlShader, String> {
+) -> Result<G::Shader, String> {
     let shader = gl
         .create_shader(shader_type)
         .ok_or_else(|| "Could not create shader".to_string())?;
     gl.shader_source(&shader, source);
     gl.compile_shader(&shader);
 
-    if gl
-        .get_shader_parameter(&shader, WebGlRenderingContext::COMPILE_STATUS)
-        .as_bool()
-        .unwrap_or(false)
-    {
+    if gl.shader_compiled(&shader) {
         Ok(shader)
     } else {
         Err(gl
@@ -141,12 +305,13 @@ fn compile_shader(
     }
 }
 
-/// Link a shader program using the WebGL APIs
-fn link_program(
-    gl: &WebGlRenderingContext,
-    vert_shader: &WebGlShader,
-    frag_shader: &WebGlShader,
-) -> Result<WebGlProgram, String> {
+/// Link a shader program using our GlContext
+fn link_program<G: GlContext>(
+    gl: &G,
+    shader_kind: ShaderKind,
+    vert_shader: &G::Shader,
+    frag_shader: &G::Shader,
+) -> Result<G::Program, String> {
     let program = gl
         .create_program()
         .ok_or_else(|| "Unable to create shader program".to_string())?;
@@ -154,13 +319,10 @@ fn link_program(
     gl.attach_shader(&program, &vert_shader);
     gl.attach_shader(&program, &frag_shader);
 
+    gl.identify_program(&program, shader_kind);
     gl.link_program(&program);
 
-    if gl
-        .get_program_parameter(&program, WebGlRenderingContext::LINK_STATUS)
-        .as_bool()
-        .unwrap_or(false)
-    {
+    if gl.program_linked(&program) {
         Ok(program)
     } else {
         Err(gl
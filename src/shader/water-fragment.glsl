@@ -5,11 +5,14 @@ uniform sampler2D reflectionTexture;
 uniform sampler2D dudvTexture;
 uniform sampler2D normalMap;
 uniform sampler2D waterDepthTexture;
+// The slope of our ripple simulation, see `Ripples::slopes_rgba`
+uniform sampler2D rippleTexture;
 
 vec3 sunlightColor = vec3(1.0, 1.0, 1.0);
 vec3 sunlightDir = normalize(vec3(-1.0, -1.0, 0.5));
 
 varying vec3 fromFragmentToCamera;
+varying vec3 waveNormal;
 
 // Changes over time, making the water look like it's moving
 uniform float dudvOffset;
@@ -17,6 +20,7 @@ uniform float dudvOffset;
 varying vec4 clipSpace;
 
 varying vec2 textureCoords;
+varying vec2 tileCoords;
 
 const float waterDistortionStrength = 0.03;
 const float shineDamper = 20.0;
@@ -24,6 +28,14 @@ const float shineDamper = 20.0;
 uniform float waterReflectivity;
 uniform float fresnelStrength;
 
+// Toggled by our quality presets
+uniform bool useSpecular;
+uniform bool useDepthTint;
+
+// The camera's clipping planes, used to turn depth buffer values back into distances
+uniform float cameraNear;
+uniform float cameraFar;
+
 vec4 shallowWaterColor =  vec4(0.0, 0.1, 0.3, 1.0);
 vec4 deepWaterColor = vec4(0.0, 0.1, 0.2, 1.0);
 
@@ -37,8 +49,8 @@ void main() {
     // Reflections are upside down
     vec2 reflectTexCoords = vec2(ndc.x, -ndc.y);
 
-    float near = 0.1;
-    float far = 50.0;
+    float near = cameraNear;
+    float far = cameraFar;
 
     // Get the distance from our camera to the first thing under this water fragment that a
     // ray would collide with. This might be the ground, the under water walls, a fish, or any
@@ -76,7 +88,9 @@ void main() {
 
     vec4 refractColor = texture2D(refractionTexture, refractTexCoords);
 
-    refractColor = mix(refractColor, deepWaterColor, clamp(angledWaterDepth/10.0, 0.0, 1.0));
+    if (useDepthTint) {
+        refractColor = mix(refractColor, deepWaterColor, clamp(angledWaterDepth/10.0, 0.0, 1.0));
+    }
 
     vec3 toCamera = normalize(fromFragmentToCamera);
 
@@ -89,10 +103,13 @@ void main() {
     // refractive factor will decrease
     refractiveFactor = pow(refractiveFactor, fresnelStrength);
 
-    vec3 reflectedLight = reflect(normalize(sunlightDir), normal);
-    float specular = max(dot(reflectedLight, toCamera), 0.0);
-    specular = pow(specular, shineDamper);
-    vec3 specularHighlights = sunlightColor * specular * waterReflectivity;
+    vec3 specularHighlights = vec3(0.0, 0.0, 0.0);
+    if (useSpecular) {
+        vec3 reflectedLight = reflect(normalize(sunlightDir), normal);
+        float specular = max(dot(reflectedLight, toCamera), 0.0);
+        specular = pow(specular, shineDamper);
+        specularHighlights = sunlightColor * specular * waterReflectivity;
+    }
 
     gl_FragColor = mix(reflectColor, refractColor, refractiveFactor);
     // Mix in a bit of blue so that it looks like water
@@ -109,5 +126,12 @@ vec3 getNormal(vec2 textureCoords) {
     );
     normal = normalize(normal);
 
+    // Tilt the rippled normal along with the slope of our vertex waves
+    normal = normalize(normal + normalize(waveNormal) - vec3(0.0, 1.0, 0.0));
+
+    // Then tilt it away from the slope of any ripples that were made by clicks or objects
+    vec2 rippleSlope = texture2D(rippleTexture, tileCoords).rg * 2.0 - 1.0;
+    normal = normalize(normal - vec3(rippleSlope.x, 0.0, rippleSlope.y));
+
     return normal;
 }
@@ -9,19 +9,75 @@ varying vec3 fromFragmentToCamera;
 
 varying vec4 clipSpace;
 varying vec2 textureCoords;
+// 0.0 - 1.0 across the water
+varying vec2 tileCoords;
+varying vec3 waveNormal;
 
 const float tiling = 4.0;
 
+// Must match MAX_WAVES in src/app/store/water.rs
+const int MAX_WAVES = 4;
+const float PI = 3.14159265;
+const float GRAVITY = 9.8;
+
+struct Wave {
+    float amplitude;
+    float wavelength;
+    // Normalized direction in the xz plane that the wave travels in
+    vec2 direction;
+    // 0 is a rolling sine wave, 1 is the sharpest crest before the wave loops over itself
+    float steepness;
+};
+
+uniform Wave waves[MAX_WAVES];
+uniform int waveCount;
+// Seconds since the application started
+uniform float seconds;
+
 void main() {
     vec4 worldPosition = model * vec4(position.x, 0.0, position.y, 1.0);
 
+    // Sum of Gerstner waves, evaluated in world space so that neighboring water bodies line up.
+    // @see https://developer.nvidia.com/gpugems/GPUGems/gpugems_ch01.html
+    vec2 restingPosition = worldPosition.xz;
+    vec3 displacement = vec3(0.0, 0.0, 0.0);
+    vec3 normal = vec3(0.0, 1.0, 0.0);
+
+    for (int i = 0; i < MAX_WAVES; i++) {
+        if (i >= waveCount) {
+            break;
+        }
+
+        Wave wave = waves[i];
+
+        float frequency = 2.0 * PI / wave.wavelength;
+        // Deep water waves travel at sqrt(g / k)
+        float phaseSpeed = sqrt(GRAVITY / frequency);
+        float phase = frequency * (dot(wave.direction, restingPosition) - phaseSpeed * seconds);
+
+        // Split the steepness between our waves so that their sum never loops over itself
+        float steepnessAmplitude = wave.steepness / (frequency * float(waveCount));
+
+        displacement.x += steepnessAmplitude * wave.direction.x * cos(phase);
+        displacement.y += wave.amplitude * sin(phase);
+        displacement.z += steepnessAmplitude * wave.direction.y * cos(phase);
+
+        float frequencyAmplitude = frequency * wave.amplitude;
+        normal.x -= wave.direction.x * frequencyAmplitude * cos(phase);
+        normal.y -= wave.steepness / float(waveCount) * sin(phase);
+        normal.z -= wave.direction.y * frequencyAmplitude * cos(phase);
+    }
+
+    worldPosition.xyz += displacement;
+    waveNormal = normalize(normal);
+
     clipSpace = perspective * view *  worldPosition;
 
     gl_Position = clipSpace;
 
     // (-0.5 < pos < 0.5) -> (0.0 < pos < 1.0)
-    textureCoords = position + 0.5;
-    textureCoords = textureCoords * tiling;
+    tileCoords = position + 0.5;
+    textureCoords = tileCoords * tiling;
 
     fromFragmentToCamera = cameraPos - worldPosition.xyz;
 }